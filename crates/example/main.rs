@@ -96,8 +96,8 @@ impl ExampleScheme {
 }
 
 impl Scheme for ExampleScheme {
-    fn open(&mut self, path: &str, flags: usize, mode: usize) -> Result<usize> {
-        println!("open {:X} = {}, {:X}, {:X}", path.as_ptr() as usize, path, flags, mode);
+    fn open_as(&mut self, path: &str, flags: usize, mode: usize, uid: u32, gid: u32) -> Result<usize> {
+        println!("open {:X} = {}, {:X}, {:X}, {}, {}", path.as_ptr() as usize, path, flags, mode, uid, gid);
         let id = self.next_id as usize;
         self.next_id += 1;
         if self.next_id < 0 {
@@ -108,14 +108,14 @@ impl Scheme for ExampleScheme {
     }
 
     #[allow(unused_variables)]
-    fn unlink(&mut self, path: &str) -> Result<usize> {
-        println!("unlink {}", path);
+    fn unlink_as(&mut self, path: &str, uid: u32, gid: u32) -> Result<usize> {
+        println!("unlink {}, {}, {}", path, uid, gid);
         Err(Error::new(ENOENT))
     }
 
     #[allow(unused_variables)]
-    fn mkdir(&mut self, path: &str, mode: usize) -> Result<usize> {
-        println!("mkdir {}, {:X}", path, mode);
+    fn mkdir_as(&mut self, path: &str, mode: usize, uid: u32, gid: u32) -> Result<usize> {
+        println!("mkdir {}, {:X}, {}, {}", path, mode, uid, gid);
         Err(Error::new(ENOENT))
     }
 
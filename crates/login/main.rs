@@ -3,6 +3,110 @@ use std::fs::File;
 use std::io::{stdin, stdout, Read, Write};
 use std::process::Command;
 
+mod sha256;
+
+/// An account from /etc/passwd, where each line is `name;password;uid;gid;full name;home;shell`
+struct Passwd {
+    name: String,
+    /// Empty if there is no password, otherwise `salt$hash`, where hash is the SHA-256 of the salt
+    /// followed by the password, in hexadecimal
+    password: String,
+    uid: u32,
+    gid: u32,
+    home: String,
+    shell: String,
+}
+
+impl Passwd {
+    fn parse(line: &str) -> Option<Passwd> {
+        let parts: Vec<&str> = line.split(';').collect();
+        if parts.len() != 7 {
+            return None;
+        }
+
+        Some(Passwd {
+            name: parts[0].to_string(),
+            password: parts[1].to_string(),
+            uid: match parts[2].parse() {
+                Ok(uid) => uid,
+                Err(_) => return None,
+            },
+            gid: match parts[3].parse() {
+                Ok(gid) => gid,
+                Err(_) => return None,
+            },
+            home: parts[5].to_string(),
+            shell: parts[6].to_string(),
+        })
+    }
+
+    /// Check a password against the hash of the account
+    fn verify(&self, password: &str) -> bool {
+        if self.password.is_empty() {
+            return true;
+        }
+
+        let mut parts = self.password.splitn(2, '$');
+        match (parts.next(), parts.next()) {
+            (Some(salt), Some(hash)) => sha256::sha256_hex(format!("{}{}", salt, password).as_bytes()) == hash,
+            _ => false,
+        }
+    }
+
+    /// Find the account of a user
+    fn find(name: &str) -> Option<Passwd> {
+        let mut string = String::new();
+        if let Ok(mut file) = File::open("/etc/passwd") {
+            if let Err(err) = file.read_to_string(&mut string) {
+                println!("login: could not read /etc/passwd: {}", err);
+                return None;
+            }
+        } else {
+            println!("login: could not open /etc/passwd");
+            return None;
+        }
+
+        for line in string.lines() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+
+            if let Some(passwd) = Passwd::parse(line) {
+                if passwd.name == name {
+                    return Some(passwd);
+                }
+            }
+        }
+
+        None
+    }
+}
+
+/// Read a password from the console in raw mode, so that it is not printed
+fn read_password() -> String {
+    print!("password: \x1B[r");
+    stdout().flush().unwrap();
+
+    let mut password = Vec::new();
+    let mut bytes = stdin().bytes();
+    while let Some(Ok(b)) = bytes.next() {
+        match b {
+            b'\n' | b'\r' => break,
+            // Backspace
+            0x7F => {
+                password.pop();
+            },
+            b => password.push(b),
+        }
+    }
+
+    print!("\x1B[R\n");
+    stdout().flush().unwrap();
+
+    String::from_utf8_lossy(&password).into_owned()
+}
+
 fn main() {
     loop {
         print!("redox login: ");
@@ -11,6 +115,33 @@ fn main() {
         let mut buffer = String::new();
         stdin().read_line(&mut buffer).unwrap();
 
+        // An empty read means the line was interrupted with ^C
+        if buffer.is_empty() {
+            continue;
+        }
+
+        let name = buffer.trim();
+        if name.is_empty() {
+            continue;
+        }
+
+        // Always ask for a password, so unknown users and users without one can not be told apart
+        let passwd = Passwd::find(name);
+        let password = read_password();
+
+        let passwd = match passwd {
+            Some(passwd) => if passwd.verify(&password) {
+                passwd
+            } else {
+                println!("login: incorrect password");
+                continue;
+            },
+            None => {
+                println!("login: incorrect password");
+                continue;
+            }
+        };
+
 	if let Ok(mut motd) = File::open("/etc/motd") {
             let mut motd_string = String::new();
             if let Ok(_) = motd.read_to_string(&mut motd_string) {
@@ -18,15 +149,19 @@ fn main() {
             }
         }
 
-        match env::home_dir() {
-            Some(home) => match env::set_current_dir(home) {
-                Ok(()) => (),
-                Err(err) => println!("login: could not set home directory: {}", err)
-            },
-            None => println!("login: could not get home directory")
+        if let Err(err) = env::set_current_dir(&passwd.home) {
+            println!("login: could not set home directory: {}", err);
         }
 
-        let mut child = Command::new("/bin/sh").spawn().unwrap();
-        child.wait().unwrap();
+        env::set_var("USER", &passwd.name);
+        env::set_var("HOME", &passwd.home);
+
+        // The shell runs as the user, login keeps its ids to log in the next user
+        match Command::new(&passwd.shell).uid(passwd.uid).gid(passwd.gid).spawn() {
+            Ok(mut child) => if let Some(sig) = child.wait().unwrap().signal() {
+                println!("login: shell terminated by signal {}", sig);
+            },
+            Err(err) => println!("login: could not run {}: {}", passwd.shell, err),
+        }
     }
 }
@@ -15,7 +15,7 @@ use std::time::Instant;
 
 use system::error::{Error, Result, EBADF};
 use system::scheme::{Packet, Scheme};
-use system::syscall::SYS_READ;
+use system::syscall::{POLLIN, POLLOUT, SYS_READ};
 
 pub use self::color::Color;
 pub use self::event::{Event, EventOption};
@@ -60,6 +60,8 @@ fn schedule(redraws: &mut Vec<Rect>, request: Rect) {
 struct OrbitalScheme {
     start: Instant,
     image: Image,
+    /// The display memory, if it could be mapped
+    framebuffer: Option<&'static mut [u32]>,
     background: Image,
     cursor: Image,
     cursor_x: i32,
@@ -81,6 +83,7 @@ impl OrbitalScheme {
         OrbitalScheme {
             start: Instant::now(),
             image: Image::new(width, height),
+            framebuffer: None,
             background: BmpFile::from_path(&config.background),
             cursor: BmpFile::from_path(&config.cursor),
             cursor_x: 0,
@@ -154,11 +157,15 @@ impl OrbitalScheme {
             if ! rect.is_empty() {
                 let data = self.image.data();
                 for row in rect.top()..rect.bottom() {
-                    let off1 = row * self.image.width() + rect.left();
-                    let off2 = row * self.image.width() + rect.right();
+                    let off1 = (row * self.image.width() + rect.left()) as usize;
+                    let off2 = (row * self.image.width() + rect.right()) as usize;
 
-                    unsafe { display.seek(SeekFrom::Start(off1 as u64)).unwrap(); }
-                    display.send_type(&data[off1 as usize .. off2 as usize]).unwrap();
+                    if let Some(ref mut framebuffer) = self.framebuffer {
+                        framebuffer[off1 .. off2].copy_from_slice(&data[off1 .. off2]);
+                    } else {
+                        unsafe { display.seek(SeekFrom::Start(off1 as u64)).unwrap(); }
+                        display.send_type(&data[off1 .. off2]).unwrap();
+                    }
                 }
             }
         }
@@ -357,6 +364,18 @@ impl Scheme for OrbitalScheme {
         }
     }
 
+    fn poll(&mut self, id: usize, events: usize) -> Result<usize> {
+        if let Some(window) = self.windows.get(&id) {
+            if window.readable() {
+                Ok(events & (POLLIN | POLLOUT) as usize)
+            } else {
+                Ok(events & POLLOUT as usize)
+            }
+        } else {
+            Err(Error::new(EBADF))
+        }
+    }
+
     fn fpath(&self, id: usize, buf: &mut [u8]) -> Result<usize> {
         if let Some(window) = self.windows.get(&id) {
             window.path(buf)
@@ -422,6 +441,11 @@ fn event_loop(scheme_mutex: Arc<Mutex<OrbitalScheme>>, display: Arc<Socket>, soc
                     responses.push(*packet);
                 }
             }
+
+            // Wake contexts polling windows, which may have new events
+            if count > 0 {
+                responses.push(Packet::default());
+            }
         }
         if ! responses.is_empty() {
             socket.send_type(&responses).unwrap();
@@ -490,7 +514,13 @@ fn main() {
 
                     let config = Config::from_path("/etc/orbital.conf");
 
-                    let scheme = Arc::new(Mutex::new(OrbitalScheme::new(width, height, &config)));
+                    let mut scheme = OrbitalScheme::new(width, height, &config);
+                    match unsafe { display.map_type::<u32>((width * height) as usize) } {
+                        Ok(framebuffer) => scheme.framebuffer = Some(framebuffer),
+                        Err(err) => println!("orbital: could not map display, falling back to writes: {}", err)
+                    }
+
+                    let scheme = Arc::new(Mutex::new(scheme));
 
                     *status_daemon.lock().unwrap() = Status::Running;
 
@@ -1,10 +1,13 @@
 use std::cell::UnsafeCell;
-use std::fs::File;
-use std::io::{Read, Write, Result, Seek, SeekFrom};
+use std::fs::{File, OpenOptions};
+use std::io::{Error, Read, Write, Result, Seek, SeekFrom};
 use std::mem;
+use std::os::unix::io::AsRawFd;
 use std::path::PathBuf;
 use std::slice;
 
+use system::syscall::{sys_mmap, MAP_SHARED, PROT_READ, PROT_WRITE};
+
 /// Redox domain socket
 pub struct Socket {
     file: UnsafeCell<File>
@@ -15,7 +18,7 @@ unsafe impl Sync for Socket {}
 
 impl Socket {
     pub fn open(path: &str) -> Result<Socket> {
-        let file = try!(File::open(path));
+        let file = try!(OpenOptions::new().read(true).write(true).open(path));
         Ok(Socket {
             file: UnsafeCell::new(file)
         })
@@ -40,6 +43,13 @@ impl Socket {
         self.receive(unsafe { slice::from_raw_parts_mut(buf.as_mut_ptr() as *mut u8, buf.len() * mem::size_of::<T>()) }).map(|count| count/mem::size_of::<T>())
     }
 
+    /// Map `len` items of the underlying resource, shared and writeable
+    pub unsafe fn map_type<T: Copy>(&self, len: usize) -> Result<&'static mut [T]> {
+        let fd = (*self.file.get()).as_raw_fd();
+        let addr = try!(sys_mmap(len * mem::size_of::<T>(), PROT_READ | PROT_WRITE, MAP_SHARED, fd, 0).map_err(|err| Error::from_sys(err)));
+        Ok(slice::from_raw_parts_mut(addr as *mut T, len))
+    }
+
     pub unsafe fn seek(&self, from: SeekFrom) -> Result<u64> {
         (*self.file.get()).seek(from)
     }
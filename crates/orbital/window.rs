@@ -107,6 +107,11 @@ impl Window {
         self.events.push_back(event);
     }
 
+    /// Check if a read returns without waiting for events
+    pub fn readable(&self) -> bool {
+        self.async || ! self.events.is_empty()
+    }
+
     pub fn read(&mut self, buf: &mut [u8]) -> Result<usize> {
         if buf.len() >= size_of::<Event>() {
             let mut i = 0;
@@ -5,6 +5,9 @@ use super::error::*;
 use super::syscall::*;
 use super::c_string_to_str;
 
+/// A request from the kernel, which is answered by writing it back with the result in `a`. A
+/// packet with an `id` of 0 written to the scheme tells the kernel that the readiness of the
+/// resources of the scheme may have changed, so that contexts waiting in `sys_poll` poll again
 #[derive(Copy, Clone, Debug, Default)]
 #[repr(packed)]
 pub struct Packet {
@@ -12,7 +15,11 @@ pub struct Packet {
     pub a: usize,
     pub b: usize,
     pub c: usize,
-    pub d: usize
+    pub d: usize,
+    /// The effective user id of the caller
+    pub uid: u32,
+    /// The effective group id of the caller
+    pub gid: u32
 }
 
 impl Deref for Packet {
@@ -32,20 +39,74 @@ impl DerefMut for Packet {
     }
 }
 
+/// Read permission in each class of `st_mode`
+const PERM_READ: u16 = 0o4;
+/// Write permission in each class of `st_mode`
+const PERM_WRITE: u16 = 0o2;
+/// Execute, or search for a directory, permission in each class of `st_mode`
+const PERM_EXEC: u16 = 0o1;
+
+/// Check that the user `uid` in the group `gid` has the permissions `access` on a file with
+/// `stat`. Root has every permission
+fn check_access(stat: &Stat, access: u16, uid: u32, gid: u32) -> Result<()> {
+    if uid == 0 {
+        return Ok(());
+    }
+
+    let perm = if stat.st_uid == uid {
+        stat.st_mode >> 6
+    } else if stat.st_gid == gid {
+        stat.st_mode >> 3
+    } else {
+        stat.st_mode
+    };
+
+    if perm & access == access {
+        Ok(())
+    } else {
+        Err(Error::new(EACCES))
+    }
+}
+
+/// Check that the user `uid` in the group `gid` can add or remove entries in the directory
+/// containing `path`
+fn check_parent<S: Scheme + ?Sized>(scheme: &mut S, path: &str, uid: u32, gid: u32) -> Result<()> {
+    if uid == 0 {
+        return Ok(());
+    }
+
+    let trimmed = path.trim_right_matches('/');
+    let parent = match trimmed.rfind('/') {
+        Some(i) => &trimmed[.. i + 1],
+        None => "",
+    };
+
+    let mut stat = Stat::default();
+    if scheme.stat(parent, &mut stat).is_ok() {
+        check_access(&stat, PERM_WRITE | PERM_EXEC, uid, gid)
+    } else {
+        Ok(())
+    }
+}
+
 pub trait Scheme {
     fn handle(&mut self, packet: &mut Packet) {
         packet.a = Error::mux(match packet.a {
-            SYS_OPEN => self.open(c_string_to_str(packet.b as *const u8), packet.c, packet.d),
-            SYS_MKDIR => self.mkdir(c_string_to_str(packet.b as *const u8), packet.c),
+            SYS_OPEN => self.open_as(c_string_to_str(packet.b as *const u8), packet.c, packet.d, packet.uid, packet.gid),
+            SYS_LINK => self.link(c_string_to_str(packet.b as *const u8), c_string_to_str(packet.c as *const u8)),
+            SYS_MKDIR => self.mkdir_as(c_string_to_str(packet.b as *const u8), packet.c, packet.uid, packet.gid),
+            SYS_RENAME => self.rename(c_string_to_str(packet.b as *const u8), c_string_to_str(packet.c as *const u8)),
             SYS_RMDIR => self.rmdir(c_string_to_str(packet.b as *const u8)),
             SYS_STAT => self.stat(c_string_to_str(packet.b as *const u8), unsafe { &mut *(packet.c as *mut Stat) }),
-            SYS_UNLINK => self.unlink(c_string_to_str(packet.b as *const u8)),
+            SYS_UNLINK => self.unlink_as(c_string_to_str(packet.b as *const u8), packet.uid, packet.gid),
 
             SYS_READ => self.read(packet.b, unsafe { slice::from_raw_parts_mut(packet.c as *mut u8, packet.d) }),
             SYS_WRITE => self.write(packet.b, unsafe { slice::from_raw_parts(packet.c as *const u8, packet.d) }),
             SYS_LSEEK => self.seek(packet.b, packet.c, packet.d),
             SYS_FPATH => self.fpath(packet.b, unsafe { slice::from_raw_parts_mut(packet.c as *mut u8, packet.d) }),
             SYS_FSTAT => self.fstat(packet.b, unsafe { &mut *(packet.c as *mut Stat) }),
+            SYS_GETDENTS => self.getdents(packet.b, unsafe { slice::from_raw_parts_mut(packet.c as *mut Dirent, packet.d) }),
+            SYS_POLL => self.poll(packet.b, packet.c),
             SYS_FSYNC => self.fsync(packet.b),
             SYS_FTRUNCATE => self.ftruncate(packet.b, packet.c),
             SYS_CLOSE => self.close(packet.b),
@@ -55,17 +116,55 @@ pub trait Scheme {
     }
 
     /* Scheme operations */
+    // Opening, making directories and unlinking call the `_as` methods with the effective user and
+    // group ids of the caller. By default they check the ids against the permissions that `stat`
+    // returns for the path, or for its parent directory when creating or removing an entry, and
+    // then call the methods without them. Paths that `stat` does not find are not checked
 
     #[allow(unused_variables)]
     fn open(&mut self, path: &str, flags: usize, mode: usize) -> Result<usize> {
         Err(Error::new(ENOENT))
     }
 
+    fn open_as(&mut self, path: &str, flags: usize, mode: usize, uid: u32, gid: u32) -> Result<usize> {
+        let mut stat = Stat::default();
+        if self.stat(path, &mut stat).is_ok() {
+            let mut access = match flags & (O_WRONLY | O_RDWR) {
+                O_WRONLY => PERM_WRITE,
+                O_RDWR => PERM_READ | PERM_WRITE,
+                _ => PERM_READ,
+            };
+            if flags & O_TRUNC == O_TRUNC {
+                access |= PERM_WRITE;
+            }
+            try!(check_access(&stat, access, uid, gid));
+        } else if flags & O_CREAT == O_CREAT {
+            try!(check_parent(self, path, uid, gid));
+        }
+
+        self.open(path, flags, mode)
+    }
+
+    #[allow(unused_variables)]
+    fn link(&mut self, old: &str, new: &str) -> Result<usize> {
+        Err(Error::new(ENOSYS))
+    }
+
     #[allow(unused_variables)]
     fn mkdir(&mut self, path: &str, mode: usize) -> Result<usize> {
         Err(Error::new(ENOENT))
     }
 
+    fn mkdir_as(&mut self, path: &str, mode: usize, uid: u32, gid: u32) -> Result<usize> {
+        try!(check_parent(self, path, uid, gid));
+        self.mkdir(path, mode)
+    }
+
+    #[allow(unused_variables)]
+    fn rename(&mut self, old: &str, new: &str) -> Result<usize> {
+        Err(Error::new(ENOSYS))
+    }
+
     #[allow(unused_variables)]
     fn rmdir(&mut self, path: &str) -> Result<usize> {
         Err(Error::new(ENOENT))
@@ -81,6 +180,11 @@ pub trait Scheme {
         Err(Error::new(ENOENT))
     }
 
+    fn unlink_as(&mut self, path: &str, uid: u32, gid: u32) -> Result<usize> {
+        try!(check_parent(self, path, uid, gid));
+        self.unlink(path)
+    }
+
     /* Resource operations */
     #[allow(unused_variables)]
     fn read(&mut self, id: usize, buf: &mut [u8]) -> Result<usize> {
@@ -107,6 +211,18 @@ pub trait Scheme {
         Err(Error::new(EBADF))
     }
 
+    #[allow(unused_variables)]
+    fn getdents(&mut self, id: usize, dirents: &mut [Dirent]) -> Result<usize> {
+        Err(Error::new(EBADF))
+    }
+
+    /// Return the subset of `events` that would not block. Always ready by default. Schemes that
+    /// report resources as not ready write a packet with an `id` of 0 when that may have changed
+    #[allow(unused_variables)]
+    fn poll(&mut self, id: usize, events: usize) -> Result<usize> {
+        Ok(events & (POLLIN | POLLOUT) as usize)
+    }
+
     #[allow(unused_variables)]
     fn fsync(&mut self, id: usize) -> Result<usize> {
         Err(Error::new(EBADF))
@@ -2,12 +2,19 @@ use syscall::arch::{syscall2, syscall1};
 use error::Result;
 
 pub const SYS_DEBUG: usize = 0;
+pub const SYS_SETNS: usize = 1639;
 pub const SYS_SUPERVISE: usize = 1638; // loominatzi confirmed
 
 pub fn sys_debug(buf: &[u8]) -> Result<usize> {
     unsafe { syscall2(SYS_DEBUG, buf.as_ptr() as usize, buf.len()) }
 }
 
+/// Narrow the schemes the current process, and the children it creates after, can use to
+/// `names`, a null terminated array of C strings. A name not already usable is `EPERM`
+pub unsafe fn sys_setns(names: *const *const u8) -> Result<usize> {
+    syscall1(SYS_SETNS, names as usize)
+}
+
 /// <!-- @MANSTART{supervise} -->
 /// Supervise a given child process' system calls.
 ///
@@ -1,4 +1,6 @@
-use syscall::arch::{syscall0, syscall1, syscall2, syscall3};
+use core::{cmp, str};
+
+use syscall::arch::{syscall0, syscall1, syscall2, syscall3, syscall5};
 use error::Result;
 
 pub const SYS_BRK: usize = 45;
@@ -24,18 +26,60 @@ pub const SYS_CLOCK_GETTIME: usize = 265;
 pub const SYS_DUP: usize = 41;
 pub const SYS_EXECVE: usize = 11;
 pub const SYS_EXIT: usize = 1;
+pub const SYS_FCNTL: usize = 55;
+    pub const F_GETFL: usize = 3;
+    pub const F_SETFL: usize = 4;
 pub const SYS_FPATH: usize = 928;
 pub const SYS_FSTAT: usize = 28;
 pub const SYS_FSYNC: usize = 118;
 pub const SYS_FTRUNCATE: usize = 93;
+pub const SYS_GETDENTS: usize = 141;
+pub const SYS_GETEGID: usize = 50;
+pub const SYS_GETEUID: usize = 49;
+pub const SYS_GETGID: usize = 47;
 pub const SYS_GETPID: usize = 20;
+pub const SYS_GETPRIORITY: usize = 96;
+pub const SYS_GETUID: usize = 24;
 pub const SYS_IOPL: usize = 110;
+pub const SYS_KILL: usize = 37;
+    pub const SIGHUP: usize = 1;
+    pub const SIGINT: usize = 2;
+    pub const SIGQUIT: usize = 3;
+    pub const SIGILL: usize = 4;
+    pub const SIGTRAP: usize = 5;
+    pub const SIGABRT: usize = 6;
+    pub const SIGBUS: usize = 7;
+    pub const SIGFPE: usize = 8;
+    pub const SIGKILL: usize = 9;
+    pub const SIGUSR1: usize = 10;
+    pub const SIGSEGV: usize = 11;
+    pub const SIGUSR2: usize = 12;
+    pub const SIGPIPE: usize = 13;
+    pub const SIGALRM: usize = 14;
+    pub const SIGTERM: usize = 15;
+    pub const SIGCHLD: usize = 17;
+    pub const SIGCONT: usize = 18;
+    pub const SIGSTOP: usize = 19;
+    pub const SIGTSTP: usize = 20;
+    pub const SIGTTIN: usize = 21;
+    pub const SIGTTOU: usize = 22;
+    /// The number of signals, signal numbers are below this
+    pub const NSIG: usize = 32;
 pub const SYS_LINK: usize = 9;
 pub const SYS_LSEEK: usize = 19;
     pub const SEEK_SET: usize = 0;
     pub const SEEK_CUR: usize = 1;
     pub const SEEK_END: usize = 2;
 pub const SYS_MKDIR: usize = 39;
+pub const SYS_MMAP: usize = 90;
+    pub const PROT_READ: usize = 1;
+    pub const PROT_WRITE: usize = 2;
+    pub const PROT_EXEC: usize = 4;
+    pub const MAP_SHARED: usize = 1;
+    pub const MAP_PRIVATE: usize = 2;
+    pub const MAP_ANONYMOUS: usize = 0x20;
+pub const SYS_MPROTECT: usize = 125;
+pub const SYS_MUNMAP: usize = 91;
 pub const SYS_NANOSLEEP: usize = 162;
 pub const SYS_OPEN: usize = 5;
     pub const O_RDONLY: usize = 0;
@@ -51,21 +95,122 @@ pub const SYS_OPEN: usize = 5;
     pub const O_TRUNC: usize = 0x400;
     pub const O_EXCL: usize = 0x800;
 pub const SYS_PIPE2: usize = 331;
+pub const SYS_POLL: usize = 168;
+    pub const POLLIN: u16 = 0x1;
+    pub const POLLOUT: u16 = 0x4;
+    pub const POLLERR: u16 = 0x8;
+    pub const POLLHUP: u16 = 0x10;
+    pub const POLLNVAL: u16 = 0x20;
 pub const SYS_READ: usize = 3;
+pub const SYS_RENAME: usize = 38;
 pub const SYS_RMDIR: usize = 84;
+pub const SYS_SETGID: usize = 46;
+pub const SYS_SETPRIORITY: usize = 97;
+    pub const PRIO_MIN: isize = -20;
+    pub const PRIO_MAX: isize = 19;
+pub const SYS_SETUID: usize = 23;
+pub const SYS_SIGACTION: usize = 67;
+    pub const SIG_DFL: usize = 0;
+    pub const SIG_IGN: usize = 1;
+pub const SYS_SIGPROCMASK: usize = 126;
+    pub const SIG_BLOCK: usize = 0;
+    pub const SIG_UNBLOCK: usize = 1;
+    pub const SIG_SETMASK: usize = 2;
+pub const SYS_SIGRETURN: usize = 119;
 pub const SYS_STAT: usize = 18;
+    /// The bits of a mode that hold the file type
+    pub const MODE_TYPE: u16 = 0xF000;
     pub const MODE_DIR: u16 = 0x4000;
     pub const MODE_FILE: u16 = 0x8000;
+    /// The bits of a mode that hold the permissions, as in Unix
+    pub const MODE_PERM: u16 = 0x0FFF;
 pub const SYS_UNLINK: usize = 10;
 pub const SYS_WAITPID: usize = 7;
+    /// Exit statuses are encoded like Unix: the exit code is in bits 8 to 15, or, if the child
+    /// was terminated by a signal, the signal number is in the low 7 bits
+    pub const WSIGMASK: usize = 0x7F;
 pub const SYS_WRITE: usize = 4;
 pub const SYS_YIELD: usize = 158;
 
 #[derive(Copy, Clone, Debug, Default)]
 #[repr(packed)]
 pub struct Stat {
+    /// The file type and permissions
     pub st_mode: u16,
-    pub st_size: u64
+    pub st_size: u64,
+    // The fields above are the original layout, which schemes built before the rest were added
+    // still fill in. The fields below are left as zero by those schemes
+    /// The device of the scheme holding the file
+    pub st_dev: u64,
+    pub st_ino: u64,
+    pub st_nlink: u32,
+    pub st_uid: u32,
+    pub st_gid: u32,
+    /// The preferred size of reads and writes
+    pub st_blksize: u32,
+    /// The number of 512 byte blocks allocated
+    pub st_blocks: u64,
+    pub st_atime: TimeSpec,
+    pub st_mtime: TimeSpec,
+    pub st_ctime: TimeSpec,
+}
+
+/// The maximum length of a directory entry name
+pub const DIRENT_NAME_LEN: usize = 256;
+
+/// A directory entry, as returned by `sys_getdents`
+#[derive(Copy, Clone)]
+#[repr(packed)]
+pub struct Dirent {
+    pub d_ino: u64,
+    pub d_size: u64,
+    pub d_mode: u16,
+    pub d_namlen: u16,
+    pub d_name: [u8; DIRENT_NAME_LEN],
+}
+
+impl Dirent {
+    /// Create a directory entry, truncating the name to `DIRENT_NAME_LEN` bytes
+    pub fn new(name: &str, mode: u16, size: u64, ino: u64) -> Dirent {
+        let mut dirent = Dirent::default();
+        let mut i = 0;
+        for b in name.bytes().take(DIRENT_NAME_LEN) {
+            dirent.d_name[i] = b;
+            i += 1;
+        }
+        dirent.d_namlen = i as u16;
+        dirent.d_mode = mode;
+        dirent.d_size = size;
+        dirent.d_ino = ino;
+        dirent
+    }
+
+    /// The name of the entry
+    pub fn name(&self) -> &str {
+        let len = cmp::min(self.d_namlen as usize, DIRENT_NAME_LEN);
+        str::from_utf8(&self.d_name[..len]).unwrap_or("")
+    }
+}
+
+impl Default for Dirent {
+    fn default() -> Dirent {
+        Dirent {
+            d_ino: 0,
+            d_size: 0,
+            d_mode: 0,
+            d_namlen: 0,
+            d_name: [0; DIRENT_NAME_LEN],
+        }
+    }
+}
+
+/// A file descriptor to wait on, as passed to `sys_poll`
+#[derive(Copy, Clone, Debug, Default)]
+#[repr(packed)]
+pub struct PollFd {
+    pub fd: usize,
+    pub events: u16,
+    pub revents: u16,
 }
 
 #[derive(Copy, Clone, Debug, Default)]
@@ -107,6 +252,10 @@ pub fn sys_exit(status: usize) -> Result<usize> {
     unsafe { syscall1(SYS_EXIT, status) }
 }
 
+pub fn sys_fcntl(fd: usize, cmd: usize, arg: usize) -> Result<usize> {
+    unsafe { syscall3(SYS_FCNTL, fd, cmd, arg) }
+}
+
 pub fn sys_fpath(fd: usize, buf: &mut [u8]) -> Result<usize> {
     unsafe { syscall3(SYS_FPATH, fd, buf.as_mut_ptr() as usize, buf.len()) }
 }
@@ -123,10 +272,37 @@ pub fn sys_ftruncate(fd: usize, len: usize) -> Result<usize> {
     unsafe { syscall2(SYS_FTRUNCATE, fd, len) }
 }
 
+/// Read up to `dirents.len()` directory entries from `fd`, returning the number read
+pub fn sys_getdents(fd: usize, dirents: &mut [Dirent]) -> Result<usize> {
+    unsafe { syscall3(SYS_GETDENTS, fd, dirents.as_mut_ptr() as usize, dirents.len()) }
+}
+
+pub fn sys_getegid() -> Result<usize> {
+    unsafe { syscall0(SYS_GETEGID) }
+}
+
+pub fn sys_geteuid() -> Result<usize> {
+    unsafe { syscall0(SYS_GETEUID) }
+}
+
+pub fn sys_getgid() -> Result<usize> {
+    unsafe { syscall0(SYS_GETGID) }
+}
+
 pub fn sys_getpid() -> Result<usize> {
     unsafe { syscall0(SYS_GETPID) }
 }
 
+/// Get the scheduling priority of the process `pid`, or of the current process if `pid` is 0
+pub fn sys_getpriority(pid: usize) -> Result<isize> {
+    // The kernel returns 20 - priority, so that it is positive
+    unsafe { syscall1(SYS_GETPRIORITY, pid) }.map(|prio| 20 - prio as isize)
+}
+
+pub fn sys_getuid() -> Result<usize> {
+    unsafe { syscall0(SYS_GETUID) }
+}
+
 pub unsafe fn sys_iopl(level: usize) -> Result<usize> {
     syscall1(SYS_IOPL, level)
 }
@@ -135,6 +311,11 @@ pub unsafe fn sys_link(old: *const u8, new: *const u8) -> Result<usize> {
     syscall2(SYS_LINK, old as usize, new as usize)
 }
 
+/// Send the signal `sig` to the process `pid`. A `sig` of 0 only checks that `pid` can be signalled
+pub fn sys_kill(pid: usize, sig: usize) -> Result<usize> {
+    unsafe { syscall2(SYS_KILL, pid, sig) }
+}
+
 pub fn sys_lseek(fd: usize, offset: isize, whence: usize) -> Result<usize> {
     unsafe { syscall3(SYS_LSEEK, fd, offset as usize, whence) }
 }
@@ -143,6 +324,20 @@ pub unsafe fn sys_mkdir(path: *const u8, mode: usize) -> Result<usize> {
     syscall2(SYS_MKDIR, path as usize, mode)
 }
 
+/// Map `size` bytes of `fd`, starting at `offset`, returning the address chosen by the kernel. With
+/// `MAP_ANONYMOUS`, zeroed memory is mapped instead, and `fd` and `offset` are ignored
+pub unsafe fn sys_mmap(size: usize, prot: usize, flags: usize, fd: usize, offset: usize) -> Result<usize> {
+    syscall5(SYS_MMAP, size, prot, flags, fd, offset)
+}
+
+pub unsafe fn sys_mprotect(addr: usize, size: usize, prot: usize) -> Result<usize> {
+    syscall3(SYS_MPROTECT, addr, size, prot)
+}
+
+pub unsafe fn sys_munmap(addr: usize, size: usize) -> Result<usize> {
+    syscall2(SYS_MUNMAP, addr, size)
+}
+
 pub fn sys_nanosleep(req: &TimeSpec, rem: &mut TimeSpec) -> Result<usize> {
     unsafe { syscall2(SYS_NANOSLEEP, req as *const TimeSpec as usize, rem as *mut TimeSpec as usize) }
 }
@@ -155,14 +350,58 @@ pub unsafe fn sys_pipe2(fds: *mut usize, flags: usize) -> Result<usize> {
     syscall2(SYS_PIPE2, fds as usize, flags)
 }
 
+/// Wait until one of `fds` is ready, or `timeout` milliseconds have passed. A negative timeout
+/// waits forever. Returns the number of entries with a nonzero `revents`
+pub fn sys_poll(fds: &mut [PollFd], timeout: isize) -> Result<usize> {
+    unsafe { syscall3(SYS_POLL, fds.as_mut_ptr() as usize, fds.len(), timeout as usize) }
+}
+
 pub fn sys_read(fd: usize, buf: &mut [u8]) -> Result<usize> {
     unsafe { syscall3(SYS_READ, fd, buf.as_mut_ptr() as usize, buf.len()) }
 }
 
+pub unsafe fn sys_rename(old: *const u8, new: *const u8) -> Result<usize> {
+    syscall2(SYS_RENAME, old as usize, new as usize)
+}
+
 pub unsafe fn sys_rmdir(path: *const u8) -> Result<usize> {
     syscall1(SYS_RMDIR, path as usize)
 }
 
+/// Set the real and effective group of the current process. Only root can change them
+pub fn sys_setgid(gid: usize) -> Result<usize> {
+    unsafe { syscall1(SYS_SETGID, gid) }
+}
+
+/// Set the scheduling priority of the process `pid`, or of the current process if `pid` is 0.
+/// Priorities go from `PRIO_MIN`, the highest, to `PRIO_MAX`, the lowest
+pub fn sys_setpriority(pid: usize, prio: isize) -> Result<usize> {
+    unsafe { syscall2(SYS_SETPRIORITY, pid, prio as usize) }
+}
+
+/// Set the real and effective user of the current process. Only root can change them
+pub fn sys_setuid(uid: usize) -> Result<usize> {
+    unsafe { syscall1(SYS_SETUID, uid) }
+}
+
+/// Set the action for `sig`, returning the previous one. `handler` is `SIG_DFL`, `SIG_IGN`, or the
+/// address of an `extern "C" fn(usize)` which is called with the signal number
+pub unsafe fn sys_sigaction(sig: usize, handler: usize) -> Result<usize> {
+    syscall3(SYS_SIGACTION, sig, handler, sys_sigreturn as usize)
+}
+
+/// Change the blocked signals, with `how` being one of `SIG_BLOCK`, `SIG_UNBLOCK` or
+/// `SIG_SETMASK`. Returns the previous mask
+pub fn sys_sigprocmask(how: usize, mask: usize) -> Result<usize> {
+    unsafe { syscall2(SYS_SIGPROCMASK, how, mask) }
+}
+
+/// Return from a signal handler. Signal handlers return here, so this is never called directly
+pub unsafe extern "C" fn sys_sigreturn() -> ! {
+    let _ = syscall0(SYS_SIGRETURN);
+    unreachable!();
+}
+
 pub unsafe fn sys_stat(path: *const u8, stat: &mut Stat) -> Result<usize> {
     syscall2(SYS_STAT, path as usize, stat as *mut Stat as usize)
 }
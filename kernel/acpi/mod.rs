@@ -1,6 +1,6 @@
 use alloc::boxed::Box;
 use fs::{KScheme, Resource, Url};
-use system::error::{Error, Result, ENOENT};
+use system::error::{Error, Result, EACCES, ENOENT};
 use system::syscall::O_CREAT;
 pub use self::dsdt::DSDT;
 pub use self::fadt::FADT;
@@ -81,10 +81,20 @@ impl KScheme for Acpi {
         "acpi"
     }
 
-    fn open(&mut self, url: Url, flags: usize) -> Result<Box<Resource>> {
+    fn open(&mut self, url: Url, flags: usize, uid: u32, _: u32) -> Result<Box<Resource>> {
+        if uid != 0 {
+            return Err(Error::new(EACCES));
+        }
+
         if url.reference() == "off" && flags & O_CREAT == O_CREAT {
             match self.fadt {
                 Some(fadt) => {
+                    if let Some(ref block_cache) = *::env().block_cache.lock() {
+                        if let Err(err) = block_cache.lock().sync_all() {
+                            debugln!("Failed to write back disks: {}", err);
+                        }
+                    }
+
                     debugln!("Powering Off");
                     unsafe {
                         asm!("out dx, ax" : : "{edx}"(fadt.pm1a_control_block), "{ax}"(0 | 1 << 13) : : "intel", "volatile")
@@ -7,19 +7,22 @@ use arch::memory;
 use arch::paging::Page;
 use arch::regs::Regs;
 
+use collections::{BinaryHeap, VecDeque};
 use collections::string::{String, ToString};
 use collections::vec::Vec;
 
 use common::time::Duration;
 
 use core::cell::UnsafeCell;
+use core::cmp::Ordering;
 use core::slice::{Iter, IterMut};
-use core::{mem, ptr};
+use core::{cmp, mem, ptr, slice};
 use core::ops::DerefMut;
 
-use fs::Resource;
+use fs::{Resource, Url};
 
-use syscall::{do_sys_exit, CLONE_FILES, CLONE_FS, CLONE_VM, CLONE_VFORK, CLONE_SUPERVISE};
+use syscall::{do_sys_exit, CLONE_FILES, CLONE_FS, CLONE_VM, CLONE_VFORK, CLONE_SUPERVISE, NSIG,
+              SIGCHLD, SIGCONT, SIGKILL, SIGSTOP, SIGTSTP, SIGTTIN, SIGTTOU, SIG_DFL, SIG_IGN};
 
 use system::error::{Error, Result, EBADF, EFAULT, ENOMEM, ESRCH, ENOENT, EINVAL};
 
@@ -37,11 +40,64 @@ pub const CONTEXT_MMAP_SIZE: usize = 0x20000000;
 pub const CONTEXT_STACK_ADDR: usize = CONTEXT_MMAP_ADDR + CONTEXT_MMAP_SIZE + memory::CLUSTER_SIZE;
 pub const CONTEXT_STACK_SIZE: usize = 0x100000;
 
+/// Scheduling weights for priorities from -20 to 19, each priority gets about 1.25 times the
+/// processor time of the next
+const PRIORITY_WEIGHTS: [u64; 40] = [
+    88761, 71755, 56483, 46273, 36291, 29154, 23254, 18705, 14949, 11916,
+    9548, 7620, 6100, 4904, 3906, 3121, 2501, 1991, 1586, 1277,
+    1024, 820, 655, 526, 423, 335, 272, 215, 172, 137,
+    110, 87, 70, 56, 45, 36, 29, 23, 18, 15,
+];
+
+/// The number of priorities, from -20 to 19, each with its own run queue
+const PRIORITIES: usize = 40;
+
+/// The virtual time of a time slice at priority 0
+const VTIME_SLICE: u64 = 1024;
+
+/// How far behind the other contexts a context that slept can be, so that it runs soon after
+/// waking without starving the others
+const VTIME_SLEEP_BONUS: u64 = 3 * VTIME_SLICE;
+
+/// A context waiting for its wake time
+#[derive(Copy, Clone, PartialEq, Eq)]
+pub struct Sleeper {
+    pub wake: Duration,
+    pub pid: usize,
+}
+
+impl Ord for Sleeper {
+    /// Reversed, so that the earliest wake time is at the top of the heap
+    fn cmp(&self, other: &Self) -> Ordering {
+        match other.wake.cmp(&self.wake) {
+            Ordering::Equal => other.pid.cmp(&self.pid),
+            ordering => ordering,
+        }
+    }
+}
+
+impl PartialOrd for Sleeper {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
 pub struct ContextManager {
     pub inner: Vec<Box<Context>>,
     pub enabled: bool,
     pub i: usize,
     pub next_pid: usize,
+    /// Contexts that are blocked until a wake time, kept separate from the contexts that can run
+    pub sleepers: BinaryHeap<Sleeper>,
+    /// The lowest virtual time of the contexts that can run
+    pub vtime_min: u64,
+    /// The pids of the contexts that can run, other than the current and the first context, with
+    /// a queue for each priority. Contexts that block, stop, exit or change priority are only
+    /// removed when they reach the front of their queue
+    pub run_queues: Vec<VecDeque<usize>>,
+    /// The pids of contexts made runnable outside of the scheduler, which are queued on the next
+    /// switch
+    pub woken: Vec<usize>,
 }
 
 impl ContextManager {
@@ -51,6 +107,10 @@ impl ContextManager {
             enabled: false,
             i: 0,
             next_pid: 1,
+            sleepers: BinaryHeap::new(),
+            vtime_min: 0,
+            run_queues: (0..PRIORITIES).map(|_| VecDeque::new()).collect(),
+            woken: Vec::new(),
         }
     }
 
@@ -103,90 +163,233 @@ impl ContextManager {
         self.inner.len()
     }
 
+    /// Find the index of the context with a given PID
+    fn position(&self, pid: usize) -> Option<usize> {
+        self.inner.iter().position(|context| context.pid == pid)
+    }
+
     pub unsafe fn push(&mut self, context: Box<Context>) {
         self.inner.push(context);
+        let i = self.inner.len() - 1;
+        self.enqueue(i);
     }
 
+    /// Add the context at `i` to the back of the run queue of its priority, if it can run and is
+    /// not queued yet. The first context is never queued, as it runs when no other context can
+    fn enqueue(&mut self, i: usize) {
+        if i == 0 {
+            return;
+        }
+
+        let context = &mut self.inner[i];
+        if context.queued || ! context.runnable() {
+            return;
+        }
+
+        context.queued = true;
+        self.run_queues[(context.priority + 20) as usize].push_back(context.pid);
+    }
+
+    /// Remove exited contexts, except for the current one, which is still running
     pub unsafe fn clean(&mut self) {
-        loop {
-            if self.i >= self.len() {
-                self.i -= self.len();
+        let current_pid = self.current().map(|current| current.pid).unwrap_or(0);
+
+        let mut i = 0;
+        while i < self.inner.len() {
+            if self.inner[i].exited && self.inner[i].pid != current_pid {
+                let context = self.inner.remove(i);
+                // The pid may be reused, so do not leave it in a run queue
+                if context.queued {
+                    self.run_queues[(context.priority + 20) as usize].retain(|&pid| pid != context.pid);
+                }
+                drop(context);
+                if i < self.i {
+                    self.i -= 1;
+                }
+            } else {
+                i += 1;
             }
+        }
+    }
+
+    /// Unblock the sleepers whose wake time has passed
+    pub fn wake(&mut self) {
+        let now = Duration::monotonic();
 
-            let mut remove = false;
-            if let Ok(next) = self.current() {
-                if next.exited {
-                    remove = true;
+        while let Some(sleeper) = self.sleepers.peek().map(|sleeper| *sleeper) {
+            if sleeper.wake > now {
+                break;
+            }
+            self.sleepers.pop();
+
+            // The context may have been woken early, and be sleeping until another time
+            if let Some(i) = self.position(sleeper.pid) {
+                if self.inner[i].blocked && self.inner[i].wake == Some(sleeper.wake) {
+                    self.inner[i].blocked = false;
+                    self.inner[i].wake = None;
+                    if i != self.i {
+                        self.enqueue(i);
+                    }
                 }
             }
+        }
+    }
 
-            if remove {
-                let i = self.i;
-                drop(self.inner.remove(i));
-            } else {
-                break;
+    /// Pick the context to run next, which is the one that has had the least processor time for
+    /// its priority. Only the front of each run queue is checked, as the contexts in a queue share
+    /// a weight and take turns. Unless `preempt` is set, the current context is yielding, and only
+    /// runs again if no other context can. The first context only runs when no other context can.
+    ///
+    /// If another context is picked, it is removed from its queue, and the current context is
+    /// queued again if it can run
+    pub fn schedule(&mut self, preempt: bool) -> Option<(usize, u64)> {
+        let current_i = self.i;
+
+        for pid in mem::replace(&mut self.woken, Vec::new()) {
+            if let Some(i) = self.position(pid) {
+                if i != current_i {
+                    self.enqueue(i);
+                }
             }
         }
 
-        if self.i >= self.len() {
-            self.i -= self.len();
+        let vtime_floor = self.vtime_min.saturating_sub(VTIME_SLEEP_BONUS);
+
+        let mut next: Option<(usize, usize, u64)> = None;
+        for queue in 0..self.run_queues.len() {
+            while let Some(pid) = self.run_queues[queue].front().map(|pid| *pid) {
+                match self.position(pid) {
+                    Some(i) if i != current_i && self.inner[i].runnable()
+                               && (self.inner[i].priority + 20) as usize == queue => {
+                        let vtime = cmp::max(self.inner[i].vtime, vtime_floor);
+                        if next.map_or(true, |(_, _, next_vtime)| vtime < next_vtime) {
+                            next = Some((queue, i, vtime));
+                        }
+                        break;
+                    },
+                    found => {
+                        // Left the queue since it was added, so drop it, and queue it again at
+                        // its current priority if it can still run
+                        self.run_queues[queue].pop_front();
+                        if let Some(i) = found {
+                            self.inner[i].queued = false;
+                            if i != current_i {
+                                self.enqueue(i);
+                            }
+                        }
+                    }
+                }
+            }
+        }
+
+        let current_runnable = current_i > 0 && self.inner.get(current_i).map_or(false, |current| current.runnable());
+        if current_runnable {
+            let vtime = cmp::max(self.inner[current_i].vtime, vtime_floor);
+            // Contexts with the same time take turns, so the current context only stays if it is
+            // strictly behind
+            if next.map_or(true, |(_, _, next_vtime)| preempt && vtime < next_vtime) {
+                return Some((current_i, vtime));
+            }
+        }
+
+        match next {
+            Some((queue, i, vtime)) => {
+                self.run_queues[queue].pop_front();
+                self.inner[i].queued = false;
+                if current_runnable {
+                    self.enqueue(current_i);
+                }
+                Some((i, vtime))
+            },
+            None => self.inner.get(0).map(|context| (0, context.vtime)),
         }
     }
 }
 
-/// Switch context
+/// Switch context, yielding to any other context that can run
 ///
 /// Unsafe due to interrupt disabling, raw pointers, and unsafe Context functions
 pub unsafe fn context_switch() {
+    context_schedule(false);
+}
+
+/// Switch context if another context has had less processor time, used when a time slice ends
+///
+/// Unsafe due to interrupt disabling, raw pointers, and unsafe Context functions
+pub unsafe fn context_preempt() {
+    context_schedule(true);
+}
+
+/// Block the current context until the monotonic time is reached, or a signal wakes it
+///
+/// Unsafe due to interrupt disabling, raw pointers, and unsafe Context functions
+pub unsafe fn context_sleep(wake: Duration) {
+    {
+        let mut contexts = ::env().contexts.lock();
+        if let Ok(mut context) = contexts.current_mut() {
+            context.blocked = true;
+            context.wake = Some(wake);
+        }
+    }
+
+    context_switch();
+}
+
+unsafe fn context_schedule(preempt: bool) {
     let mut current_ptr: *mut Context = 0 as *mut Context;
     let mut next_ptr: *mut Context = 0 as *mut Context;
 
     {
         let mut contexts = ::env().contexts.lock();
         if contexts.enabled {
+            contexts.clean();
+            contexts.wake();
+
             let current_i = contexts.i;
-            'searching: loop {
-                contexts.i += 1;
-                contexts.clean();
-                if let Ok(mut next) = contexts.current_mut() {
-                    if next.blocked {
-                        if let Some(wake) = next.wake {
-                            if wake <= Duration::monotonic() {
-                                next.blocked = false;
-                                next.wake = None;
-                                break 'searching;
+            if let Some((next_i, vtime)) = contexts.schedule(preempt) {
+                if next_i != current_i {
+                    let mut sleeper = None;
+
+                    if let Ok(mut current) = contexts.get_mut(current_i) {
+                        if current.blocked {
+                            if let Some(wake) = current.wake {
+                                sleeper = Some(Sleeper {
+                                    wake: wake,
+                                    pid: current.pid,
+                                });
                             }
                         }
-                    } else {
-                        break 'searching;
+
+                        current.unmap();
+
+                        current_ptr = current.deref_mut();
                     }
-                }
-                if contexts.i == current_i {
-                    break 'searching;
-                }
-            }
 
-            if contexts.i != current_i {
-                if let Ok(mut current) = contexts.get_mut(current_i) {
-                    current.unmap();
+                    if let Some(sleeper) = sleeper {
+                        contexts.sleepers.push(sleeper);
+                    }
 
-                    current_ptr = current.deref_mut();
-                }
+                    contexts.i = next_i;
+                    if next_i > 0 {
+                        contexts.vtime_min = cmp::max(contexts.vtime_min, vtime);
+                    }
 
-                if let Ok(mut next) = contexts.current_mut() {
-                    next.switch += 1;
+                    if let Ok(mut next) = contexts.current_mut() {
+                        next.switch += 1;
+                        next.vtime = vtime;
 
-                    if let Some(ref mut tss) = ::TSS_PTR {
-                        if next.kernel_stack > 0 {
-                            tss.sp0 = next.kernel_stack + CONTEXT_STACK_SIZE - 128;
-                        } else {
-                            tss.sp0 = 0x800000 - 128;
+                        if let Some(ref mut tss) = ::TSS_PTR {
+                            if next.kernel_stack > 0 {
+                                tss.sp0 = next.kernel_stack + CONTEXT_STACK_SIZE - 128;
+                            } else {
+                                tss.sp0 = 0x800000 - 128;
+                            }
                         }
-                    }
 
-                    next.map();
+                        next.map();
 
-                    next_ptr = next.deref_mut();
+                        next_ptr = next.deref_mut();
+                    }
                 }
             }
         }
@@ -236,6 +439,9 @@ pub unsafe fn context_clone(regs: &Regs) -> Result<usize> {
                 exited: false,
                 switch: 0,
                 time: 0,
+                time_user: 0,
+                priority: parent.priority,
+                vtime: parent.vtime,
                 vfork: if flags & CLONE_VFORK == CLONE_VFORK {
                     parent.blocked = true;
                     Some(parent.deref_mut())
@@ -243,6 +449,15 @@ pub unsafe fn context_clone(regs: &Regs) -> Result<usize> {
                     None
                 },
                 wake: None,
+                stopped: false,
+                queued: false,
+
+                uid: parent.uid,
+                gid: parent.gid,
+                euid: parent.euid,
+                egid: parent.egid,
+
+                namespace: parent.namespace.clone(),
 
                 supervised: flags & CLONE_SUPERVISE == CLONE_SUPERVISE,
                 blocked_syscall: false,
@@ -250,22 +465,8 @@ pub unsafe fn context_clone(regs: &Regs) -> Result<usize> {
                 kernel_stack: kernel_stack,
                 regs: kernel_regs,
                 fx: fx,
-                stack: if let Some(ref entry) = parent.stack {
-                    let physical_address = memory::alloc(entry.virtual_size);
-                    if physical_address > 0 {
-                        ::memcpy(physical_address as *mut u8,
-                                 entry.physical_address as *const u8,
-                                 entry.virtual_size);
-                        Some(ContextMemory {
-                            physical_address: physical_address,
-                            virtual_address: entry.virtual_address,
-                            virtual_size: entry.virtual_size,
-                            writeable: entry.writeable,
-                            allocated: true,
-                        })
-                    } else {
-                        None
-                    }
+                stack: if let Some(ref stack) = parent.stack {
+                    Some(UnsafeCell::new((*stack.get()).dup()))
                 } else {
                     None
                 },
@@ -315,6 +516,8 @@ pub unsafe fn context_clone(regs: &Regs) -> Result<usize> {
 
                                 files.push(ContextFile {
                                     fd: file.fd,
+                                    flags: file.flags,
+                                    path: file.path.clone(),
                                     resource: resource,
                                 });
                             },
@@ -325,6 +528,12 @@ pub unsafe fn context_clone(regs: &Regs) -> Result<usize> {
                 },
 
                 statuses: WaitMap::new(),
+
+                sig_pending: 0,
+                sig_mask: parent.sig_mask,
+                sig_handlers: parent.sig_handlers,
+                sig_restorer: parent.sig_restorer,
+                sig_frames: parent.sig_frames.iter().map(|frame| frame.dup()).collect(),
             }
         };
 
@@ -402,12 +611,14 @@ pub struct ContextMemory {
     pub virtual_size: usize,
     pub writeable: bool,
     pub allocated: bool,
+    /// Memory shared copy-on-write with other contexts. It is mapped read-only until it is copied
+    pub cow: Option<Arc<CowMemory>>,
 }
 
 impl ContextMemory {
     pub unsafe fn map(&mut self) {
         for i in 0..(self.virtual_size + 4095) / 4096 {
-            if self.writeable {
+            if self.writeable && self.cow.is_none() {
                 Page::new(self.virtual_address + i * 4096)
                     .map_user_write(self.physical_address + i * 4096);
             } else {
@@ -423,6 +634,35 @@ impl ContextMemory {
                 .map_kernel_write(self.virtual_address + i * 4096);
         }
     }
+
+    /// Stop sharing copy-on-write memory, copying it if another context or another part of it
+    /// still uses it. The memory has to be mapped again afterwards
+    pub unsafe fn unshare(&mut self) -> Result<()> {
+        if let Some(mut cow) = self.cow.take() {
+            if cow.physical_address == self.physical_address {
+                if let Some(owned) = Arc::get_mut(&mut cow) {
+                    // This is the last user and starts the allocation, so take ownership instead
+                    // of copying
+                    owned.physical_address = 0;
+                    self.allocated = true;
+                    return Ok(());
+                }
+            }
+
+            let physical_address = memory::alloc_aligned(self.virtual_size, 4096);
+            if physical_address == 0 {
+                self.cow = Some(cow);
+                return Err(Error::new(ENOMEM));
+            }
+
+            ::memcpy(physical_address as *mut u8, self.physical_address as *const u8, self.virtual_size);
+
+            self.physical_address = physical_address;
+            self.allocated = true;
+        }
+
+        Ok(())
+    }
 }
 
 impl Drop for ContextMemory {
@@ -433,15 +673,52 @@ impl Drop for ContextMemory {
     }
 }
 
+/// Physical memory shared copy-on-write, which is freed when the last context using it drops it
+pub struct CowMemory {
+    pub physical_address: usize,
+}
+
+impl Drop for CowMemory {
+    fn drop(&mut self) {
+        if self.physical_address > 0 {
+            unsafe { memory::unalloc(self.physical_address) };
+        }
+    }
+}
+
 pub struct ContextFile {
     pub fd: usize,
+    /// The file status flags, such as `O_NONBLOCK`
+    pub flags: usize,
+    /// The path the file was opened with, shown in `context:`
+    pub path: String,
     pub resource: Box<Resource>,
 }
 
+/// Memory reserved in a zone, which is allocated a page at a time when it is first used
+#[derive(Clone)]
+pub struct ContextReserved {
+    pub virtual_address: usize,
+    pub virtual_size: usize,
+    pub writeable: bool,
+}
+
+impl ContextReserved {
+    /// Get the end of the reserved memory, rounded up to the next page
+    pub fn end(&self) -> usize {
+        self.virtual_address + (self.virtual_size + 4095) / 4096 * 4096
+    }
+
+    pub fn contains(&self, ptr: usize) -> bool {
+        ptr >= self.virtual_address && ptr < self.end()
+    }
+}
+
 pub struct ContextZone {
     pub address: usize,
     pub size: usize,
-    pub memory: Vec<ContextMemory>
+    pub memory: Vec<ContextMemory>,
+    pub reserved: Vec<ContextReserved>,
 }
 
 impl ContextZone {
@@ -449,40 +726,40 @@ impl ContextZone {
         ContextZone {
             address: address,
             size: size,
-            memory: Vec::new()
+            memory: Vec::new(),
+            reserved: Vec::new(),
         }
     }
 
-    pub fn dup(&self) -> ContextZone {
+    /// Duplicate the zone for a new process. Memory is shared copy-on-write, and both copies are
+    /// mapped read-only until they are written. The zone must be mapped, as it is remapped here
+    pub fn dup(&mut self) -> ContextZone {
         let mut mem: Vec<ContextMemory> = Vec::new();
-        for entry in self.memory.iter() {
-            let physical_address = unsafe { memory::alloc(entry.virtual_size) };
-            if physical_address > 0 {
-                //TODO: Remap pages during memcpy
-                unsafe {
-                    ::memcpy(physical_address as *mut u8,
-                             entry.physical_address as *const u8,
-                             entry.virtual_size);
-                }
-
-                //debugln!("{}: {}: dup memory {:X}:{:X} for {}", parent.pid, parent.name, entry.virtual_address, entry.virtual_address + entry.virtual_size, clone_pid);
-
-                mem.push(ContextMemory {
-                    physical_address: physical_address,
-                    virtual_address: entry.virtual_address,
-                    virtual_size: entry.virtual_size,
-                    writeable: entry.writeable,
-                    allocated: true,
-                });
-            } else {
-                //debugln!("{}: {}: failed to dup memory {:X}:{:X} for {}", parent.pid, parent.name, entry.virtual_address, entry.virtual_address + entry.virtual_size, clone_pid);
+        for entry in self.memory.iter_mut() {
+            if entry.allocated {
+                entry.cow = Some(Arc::new(CowMemory {
+                    physical_address: entry.physical_address,
+                }));
+                entry.allocated = false;
+                unsafe { entry.map() };
             }
+
+            // Memory that is not owned by the zone, such as a shared file mapping, is shared
+            mem.push(ContextMemory {
+                physical_address: entry.physical_address,
+                virtual_address: entry.virtual_address,
+                virtual_size: entry.virtual_size,
+                writeable: entry.writeable,
+                allocated: false,
+                cow: entry.cow.clone(),
+            });
         }
 
         ContextZone {
             address: self.address,
             size: self.size,
-            memory: mem
+            memory: mem,
+            reserved: self.reserved.clone(),
         }
     }
 
@@ -508,9 +785,45 @@ impl ContextZone {
             }
         }
 
+        for reserved in self.reserved.iter() {
+            if next_mem < reserved.end() {
+                next_mem = reserved.end();
+            }
+        }
+
         return next_mem;
     }
 
+    /// Find the lowest free space of `size` bytes between the memory and reserved memory of the
+    /// zone, first fit, so that the space of removed memory is used again
+    pub fn find_free(&self, size: usize) -> Option<usize> {
+        let mut used: Vec<(usize, usize)> = Vec::new();
+        for mem in self.memory.iter() {
+            let pages = (mem.virtual_size + 4095) / 4096;
+            used.push((mem.virtual_address, mem.virtual_address + pages * 4096));
+        }
+        for reserved in self.reserved.iter() {
+            used.push((reserved.virtual_address, reserved.end()));
+        }
+        used.sort();
+
+        let mut address = self.address;
+        for &(start, end) in used.iter() {
+            if start >= address && start - address >= size {
+                break;
+            }
+            if address < end {
+                address = end;
+            }
+        }
+
+        if address + size <= self.address + self.size {
+            Some(address)
+        } else {
+            None
+        }
+    }
+
     /// Translate to physical if a ptr is inside of the mapped memory
     pub fn translate(&self, ptr: usize, len: usize) -> Option<usize> {
         for mem in self.memory.iter() {
@@ -522,6 +835,171 @@ impl ContextZone {
         None
     }
 
+    /// Split the copy-on-write memory containing `address` in two at it, so that the parts can be
+    /// unshared separately. Both parts use the shared memory until they are unshared
+    fn split_cow(&mut self, address: usize) {
+        let mut split = None;
+
+        for mem in self.memory.iter_mut() {
+            if mem.cow.is_some() && address > mem.virtual_address && address < mem.virtual_address + mem.virtual_size {
+                let offset = address - mem.virtual_address;
+                split = Some(ContextMemory {
+                    physical_address: mem.physical_address + offset,
+                    virtual_address: address,
+                    virtual_size: mem.virtual_size - offset,
+                    writeable: mem.writeable,
+                    allocated: false,
+                    cow: mem.cow.clone(),
+                });
+                mem.virtual_size = offset;
+                break;
+            }
+        }
+
+        if let Some(mem) = split {
+            self.memory.push(mem);
+        }
+    }
+
+    /// Stop sharing the writeable copy-on-write pages between `ptr` and `ptr + len`, and map them
+    /// again. Only the pages in the range are copied. Returns true if any memory was shared
+    pub unsafe fn unshare(&mut self, ptr: usize, len: usize) -> Result<bool> {
+        let start = ptr / 4096 * 4096;
+        let end = (ptr + len + 4095) / 4096 * 4096;
+        self.split_cow(start);
+        self.split_cow(end);
+
+        let mut shared = false;
+
+        for mem in self.memory.iter_mut() {
+            if mem.writeable && mem.cow.is_some()
+               && start < mem.virtual_address + mem.virtual_size && mem.virtual_address < end {
+                try!(mem.unshare());
+                mem.map();
+                shared = true;
+            }
+        }
+
+        Ok(shared)
+    }
+
+    /// Allocate a zeroed page for the reserved memory at `ptr`, if it has not been allocated
+    /// yet, and map it. Returns true if a page was allocated
+    pub unsafe fn fault(&mut self, ptr: usize) -> Result<bool> {
+        let page = ptr / 4096 * 4096;
+
+        if self.memory.iter().any(|mem| page >= mem.virtual_address && page < mem.virtual_address + mem.virtual_size) {
+            return Ok(false);
+        }
+
+        let writeable = match self.reserved.iter().find(|reserved| reserved.contains(page)) {
+            Some(reserved) => reserved.writeable,
+            None => return Ok(false),
+        };
+
+        let physical_address = memory::alloc_aligned(4096, 4096);
+        if physical_address == 0 {
+            return Err(Error::new(ENOMEM));
+        }
+
+        let mut mem = ContextMemory {
+            physical_address: physical_address,
+            virtual_address: page,
+            virtual_size: 4096,
+            writeable: writeable,
+            allocated: true,
+            cow: None,
+        };
+        mem.map();
+        self.memory.push(mem);
+
+        Ok(true)
+    }
+
+    /// Allocate the memory between `ptr` and `ptr + len` as one contiguous block, so that it can
+    /// be translated. Memory that was already used is moved into the block, and the block is mapped.
+    ///
+    /// If the zone is `shared` with other contexts, memory owned by the zone is not moved, as
+    /// they may have translated it and be waiting on a scheme that uses the physical memory.
+    /// Memory shared copy-on-write is only copied, so it can always be moved
+    pub unsafe fn populate(&mut self, ptr: usize, len: usize, shared: bool) -> Result<()> {
+        if self.translate(ptr, len).is_some() {
+            return Ok(());
+        }
+
+        let mut start = ptr / 4096 * 4096;
+        let mut end = (ptr + len + 4095) / 4096 * 4096;
+
+        // Grow the block until it contains all of the memory it overlaps
+        let mut changed = true;
+        while changed {
+            changed = false;
+            for mem in self.memory.iter() {
+                let mem_end = mem.virtual_address + (mem.virtual_size + 4095) / 4096 * 4096;
+                if mem.virtual_size > 0 && mem.virtual_address < end && start < mem_end
+                   && (mem.virtual_address < start || mem_end > end) {
+                    start = cmp::min(start, mem.virtual_address);
+                    end = cmp::max(end, mem_end);
+                    changed = true;
+                }
+            }
+        }
+
+        // Every page must be reserved or used, with the same protection
+        let mut writeable = None;
+        let mut page = start;
+        while page < end {
+            let page_writeable = if let Some(mem) = self.memory.iter().find(|mem| page >= mem.virtual_address && page < mem.virtual_address + mem.virtual_size) {
+                if ! mem.allocated && mem.cow.is_none() {
+                    return Err(Error::new(EFAULT));
+                }
+                if shared && mem.allocated {
+                    return Err(Error::new(EFAULT));
+                }
+                mem.writeable
+            } else if let Some(reserved) = self.reserved.iter().find(|reserved| reserved.contains(page)) {
+                reserved.writeable
+            } else {
+                return Err(Error::new(EFAULT));
+            };
+
+            if writeable.unwrap_or(page_writeable) != page_writeable {
+                return Err(Error::new(EFAULT));
+            }
+            writeable = Some(page_writeable);
+
+            page += 4096;
+        }
+
+        let physical_address = memory::alloc_aligned(end - start, 4096);
+        if physical_address == 0 {
+            return Err(Error::new(ENOMEM));
+        }
+
+        for mem in self.memory.iter_mut() {
+            if mem.virtual_size > 0 && mem.virtual_address >= start && mem.virtual_address < end {
+                ::memcpy((physical_address + mem.virtual_address - start) as *mut u8,
+                         mem.physical_address as *const u8,
+                         mem.virtual_size);
+                mem.virtual_size = 0;
+            }
+        }
+        self.clean_mem();
+
+        let mut mem = ContextMemory {
+            physical_address: physical_address,
+            virtual_address: start,
+            virtual_size: end - start,
+            writeable: writeable.unwrap_or(true),
+            allocated: true,
+            cow: None,
+        };
+        mem.map();
+        self.memory.push(mem);
+
+        Ok(())
+    }
+
     /// Get a memory map from a pointer
     pub fn get_mem<'a>(&'a self, ptr: usize) -> Result<&'a ContextMemory> {
         for mem in self.memory.iter() {
@@ -550,6 +1028,13 @@ impl ContextZone {
     }
 
     pub unsafe fn map(&mut self) {
+        // Reserved pages are not present until they are used, so that using them faults
+        for reserved in self.reserved.iter() {
+            for i in 0..(reserved.end() - reserved.virtual_address) / 4096 {
+                Page::new(reserved.virtual_address + i * 4096).unmap();
+            }
+        }
+
         for entry in self.memory.iter_mut() {
             entry.map();
         }
@@ -559,6 +1044,61 @@ impl ContextZone {
         for entry in self.memory.iter_mut() {
             entry.unmap();
         }
+
+        for reserved in self.reserved.iter() {
+            for i in 0..(reserved.end() - reserved.virtual_address) / 4096 {
+                Page::new(reserved.virtual_address + i * 4096)
+                    .map_kernel_write(reserved.virtual_address + i * 4096);
+            }
+        }
+    }
+}
+
+/// What delivering a signal does
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum SignalAction {
+    /// Discard the signal
+    Ignore,
+    /// Exit, reporting the signal to the parent
+    Terminate,
+    /// Stop until continued
+    Stop,
+    /// Continue if stopped
+    Continue,
+    /// Run the handler at the given address
+    Handle(usize),
+}
+
+/// State saved when entering a signal handler, and restored by sigreturn
+pub struct SignalFrame {
+    /// The registers of the interrupted code
+    pub regs: Regs,
+    /// The signal mask of the interrupted code
+    pub mask: usize,
+    /// The SSE and FPU registers of the interrupted code
+    pub fx: usize,
+}
+
+impl SignalFrame {
+    pub fn dup(&self) -> SignalFrame {
+        let fx = unsafe { memory::alloc(512) };
+        if fx > 0 {
+            unsafe { ::memcpy(fx as *mut u8, self.fx as *const u8, 512) };
+        }
+
+        SignalFrame {
+            regs: self.regs,
+            mask: self.mask,
+            fx: fx,
+        }
+    }
+}
+
+impl Drop for SignalFrame {
+    fn drop(&mut self) {
+        if self.fx > 0 {
+            unsafe { memory::unalloc(self.fx) };
+        }
     }
 }
 
@@ -586,12 +1126,37 @@ pub struct Context {
     pub switch: usize,
     /// The number of time slices used
     pub time: usize,
+    /// The number of time slices used in userspace
+    pub time_user: usize,
+    /// The scheduling priority, from -20 (highest) to 19 (lowest)
+    pub priority: isize,
+    /// The processor time used, weighted by priority, which decides which context runs next
+    pub vtime: u64,
     /// Indicates that the context needs to unblock parent
     pub vfork: Option<*mut Context>,
     /// When to wake up
     pub wake: Option<Duration>,
+    /// Indicates that the context was stopped by a signal, and should not be switched to
+    pub stopped: bool,
+    /// Indicates that the context is in a run queue
+    pub queued: bool,
+    // }
+
+    // These members are the credentials, copied for threads and processes {
+    /// The real user id, who owns the context
+    pub uid: u32,
+    /// The real group id
+    pub gid: u32,
+    /// The effective user id, used to check permissions
+    pub euid: u32,
+    /// The effective group id, used to check permissions
+    pub egid: u32,
     // }
 
+    /// The names of the schemes the context can use, or `None` for every scheme. Copied for
+    /// threads and processes, and narrowed by setns
+    pub namespace: Option<Vec<String>>,
+
     /// Is this process supervised?
     ///
     /// i.e., will the syscalls made by this process block the process until handled by
@@ -609,8 +1174,8 @@ pub struct Context {
     pub regs: Regs,
     /// The location used to save and load SSE and FPU registers
     pub fx: usize,
-    /// The context stack
-    pub stack: Option<ContextMemory>,
+    /// The context stack, copied for threads and processes
+    pub stack: Option<UnsafeCell<ContextZone>>,
     /// Indicates that registers can be loaded (they must be saved first)
     pub loadable: bool,
     // }
@@ -633,6 +1198,19 @@ pub struct Context {
 
     /// Exit statuses of children
     pub statuses: WaitMap<usize, usize>,
+
+    // These members control signals, copied for threads and processes {
+    /// Pending signals, bit `n` is set if signal `n` is pending
+    pub sig_pending: usize,
+    /// Blocked signals, which are left pending until unblocked
+    pub sig_mask: usize,
+    /// Signal handlers, either `SIG_DFL`, `SIG_IGN` or the address of a handler. Reset by exec
+    pub sig_handlers: [usize; NSIG],
+    /// The address handlers return to, which calls sigreturn
+    pub sig_restorer: usize,
+    /// Frames of the handlers being run, innermost last
+    pub sig_frames: Vec<SignalFrame>,
+    // }
 }
 
 impl Context {
@@ -677,8 +1255,20 @@ impl Context {
             exited: false,
             switch: 0,
             time: 0,
+            time_user: 0,
+            priority: 0,
+            vtime: 0,
             vfork: None,
             wake: None,
+            stopped: false,
+            queued: false,
+
+            uid: 0,
+            gid: 0,
+            euid: 0,
+            egid: 0,
+
+            namespace: None,
 
             supervised: false,
             blocked_syscall: false,
@@ -698,6 +1288,12 @@ impl Context {
             files: Arc::new(UnsafeCell::new(Vec::new())),
 
             statuses: WaitMap::new(),
+
+            sig_pending: 0,
+            sig_mask: 0,
+            sig_handlers: [SIG_DFL; NSIG],
+            sig_restorer: 0,
+            sig_frames: Vec::new(),
         }
     }
 
@@ -718,8 +1314,20 @@ impl Context {
             exited: false,
             switch: 0,
             time: 0,
+            time_user: 0,
+            priority: 0,
+            vtime: 0,
             vfork: None,
             wake: None,
+            stopped: false,
+            queued: false,
+
+            uid: 0,
+            gid: 0,
+            euid: 0,
+            egid: 0,
+
+            namespace: None,
 
             supervised: false,
             blocked_syscall: false,
@@ -739,6 +1347,12 @@ impl Context {
             files: Arc::new(UnsafeCell::new(Vec::new())),
 
             statuses: WaitMap::new(),
+
+            sig_pending: 0,
+            sig_mask: 0,
+            sig_handlers: [SIG_DFL; NSIG],
+            sig_restorer: 0,
+            sig_frames: Vec::new(),
         };
 
         for arg in args.iter() {
@@ -771,6 +1385,43 @@ impl Context {
         ret
     }
 
+    /// Check if the context can be switched to
+    pub fn runnable(&self) -> bool {
+        ! self.exited && ! self.blocked && ! self.stopped
+    }
+
+    /// Unblock the context, and queue it to run on the next switch
+    pub fn unblock(&mut self) {
+        self.blocked = false;
+        ::env().contexts.lock().woken.push(self.pid);
+    }
+
+    /// Check if the context may signal or change another, which needs root or a user id that
+    /// matches the real or effective user id of the other
+    pub fn owns(&self, other: &Context) -> bool {
+        self.euid == 0 || self.uid == other.uid || self.uid == other.euid ||
+        self.euid == other.uid || self.euid == other.euid
+    }
+
+    /// Check if a scheme is in the namespace of the context
+    pub fn can_use(&self, scheme: &str) -> bool {
+        match self.namespace {
+            Some(ref namespace) => namespace.iter().any(|name| name == scheme),
+            None => true,
+        }
+    }
+
+    /// Parse a canonical path into a url. Schemes outside of the namespace are not found, and
+    /// the list of schemes, `:`, is outside of every namespace
+    pub fn url<'a>(&self, path: &'a str) -> Result<Url<'a>> {
+        let url = try!(Url::from_str(path));
+        if self.can_use(url.scheme()) {
+            Ok(url)
+        } else {
+            Err(Error::new(ENOENT))
+        }
+    }
+
     pub fn canonicalize(&self, path: &str) -> String {
         if path.find(':').is_none() {
             let cwd = unsafe { &*self.cwd.get() };
@@ -830,6 +1481,40 @@ impl Context {
         Err(Error::new(EBADF))
     }
 
+    /// Get the status flags of a file descriptor
+    pub fn get_file_flags(&self, fd: usize) -> Result<usize> {
+        for file in unsafe { (*self.files.get()).iter() } {
+            if file.fd == fd {
+                return Ok(file.flags);
+            }
+        }
+
+        Err(Error::new(EBADF))
+    }
+
+    /// Get the path a file descriptor was opened with
+    pub fn get_file_path(&self, fd: usize) -> Result<String> {
+        for file in unsafe { (*self.files.get()).iter() } {
+            if file.fd == fd {
+                return Ok(file.path.clone());
+            }
+        }
+
+        Err(Error::new(EBADF))
+    }
+
+    /// Set the status flags of a file descriptor
+    pub fn set_file_flags(&self, fd: usize, flags: usize) -> Result<()> {
+        for file in unsafe { (*self.files.get()).iter_mut() } {
+            if file.fd == fd {
+                file.flags = flags;
+                return Ok(());
+            }
+        }
+
+        Err(Error::new(EBADF))
+    }
+
     /// Get a mutable resource from a file descriptor
     pub fn get_file_mut<'a>(&mut self, fd: usize) -> Result<&'a mut Box<Resource>> {
         for file in unsafe { (*self.files.get()).iter_mut() } {
@@ -848,27 +1533,189 @@ impl Context {
         ptr::write(self.regs.sp as *mut usize, data);
     }
 
-    /// Translate to physical if a ptr is inside of the mapped memory
+    /// Stop sharing the writeable copy-on-write memory between `ptr` and `ptr + len`, so that it
+    /// can be written. Returns true if any memory was shared. Must be called on the current context
+    pub fn unshare(&self, ptr: usize, len: usize) -> Result<bool> {
+        match unsafe { self.zone_at(ptr) } {
+            Some(zone) => unsafe { zone.unshare(ptr, len) },
+            None => Ok(false),
+        }
+    }
+
+    /// Allocate the reserved page at `ptr`, if it has not been allocated yet. Returns true if a
+    /// page was allocated. Must be called on the current context
+    pub fn fault(&self, ptr: usize) -> Result<bool> {
+        match unsafe { self.zone_at(ptr) } {
+            Some(zone) => unsafe { zone.fault(ptr) },
+            None => Ok(false),
+        }
+    }
+
+    /// Translate to physical if a ptr is inside of the mapped memory, for the kernel to read.
+    /// Reserved memory is allocated first, as the kernel accesses the physical memory directly.
+    /// Must be called on the current context
     pub fn translate(&self, ptr: usize, len: usize) -> Result<usize> {
-        if let Some(ref stack) = self.stack {
-            if ptr >= stack.virtual_address && ptr + len <= stack.virtual_address + stack.virtual_size {
-                return Ok(ptr - stack.virtual_address + stack.physical_address);
+        if let Some(zone) = unsafe { self.zone_at(ptr) } {
+            unsafe { try!(zone.populate(ptr, len, self.zone_shared(ptr))) };
+
+            if let Some(address) = zone.translate(ptr, len) {
+                return Ok(address);
             }
         }
 
-        if let Some(address) = unsafe { (*self.image.get()).translate(ptr, len) } {
-            return Ok(address);
+        Err(Error::new(EFAULT))
+    }
+
+    /// Translate to physical like `translate`, for the kernel to write. Memory shared
+    /// copy-on-write is copied first. Must be called on the current context
+    pub fn translate_mut(&self, ptr: usize, len: usize) -> Result<usize> {
+        if let Some(zone) = unsafe { self.zone_at(ptr) } {
+            unsafe {
+                try!(zone.populate(ptr, len, self.zone_shared(ptr)));
+                try!(zone.unshare(ptr, len));
+            }
+
+            if let Some(address) = zone.translate(ptr, len) {
+                return Ok(address);
+            }
         }
 
-        if let Some(address) = unsafe { (*self.heap.get()).translate(ptr, len) } {
-            return Ok(address);
+        Err(Error::new(EFAULT))
+    }
+
+    /// Translate the memory between `ptr` and `ptr + len` a page at a time, for a scheme to map.
+    /// Returns the physical address and size of each run of contiguous pages, starting with the
+    /// page containing `ptr`. Unlike `translate`, the pages do not have to be contiguous, so no
+    /// memory is moved and zones shared with other contexts can be used. Reserved pages are
+    /// allocated, and if `write` is set, memory shared copy-on-write is copied first. Must be
+    /// called on the current context
+    pub fn translate_pages(&self, ptr: usize, len: usize, write: bool) -> Result<Vec<(usize, usize)>> {
+        let zone = match unsafe { self.zone_at(ptr) } {
+            Some(zone) => zone,
+            None => return Err(Error::new(EFAULT)),
+        };
+
+        let end = ptr + cmp::max(len, 1);
+        if end > zone.address + zone.size {
+            return Err(Error::new(EFAULT));
         }
 
-        if let Some(address) = unsafe { (*self.mmap.get()).translate(ptr, len) } {
-            return Ok(address);
+        let mut pages: Vec<(usize, usize)> = Vec::new();
+
+        let mut page = ptr / 4096 * 4096;
+        while page < end {
+            unsafe {
+                try!(zone.fault(page));
+                if write {
+                    try!(zone.unshare(page, 4096));
+                }
+            }
+
+            let physical_address = match zone.translate(page, 1) {
+                Some(physical_address) => physical_address,
+                None => return Err(Error::new(EFAULT)),
+            };
+
+            let mut joined = false;
+            if let Some(last) = pages.last_mut() {
+                if last.0 + last.1 == physical_address {
+                    last.1 += 4096;
+                    joined = true;
+                }
+            }
+            if ! joined {
+                pages.push((physical_address, 4096));
+            }
+
+            page += 4096;
         }
 
-        Err(Error::new(EFAULT))
+        Ok(pages)
+    }
+
+    /// Read a resource to its end for the kernel. Schemes in userspace write to memory of the
+    /// context, so the data is read through a buffer mapped into its heap for the duration of the
+    /// read. Must be called on the current context
+    pub fn read_to_end(&self, resource: &mut Resource) -> Result<Vec<u8>> {
+        let mut vec: Vec<u8> = Vec::new();
+
+        unsafe {
+            let heap = &mut *self.heap.get();
+
+            let virtual_size = 65536;
+            let virtual_address = heap.next_mem();
+
+            let physical_address = memory::alloc_aligned(virtual_size, 4096);
+            if physical_address == 0 {
+                return Err(Error::new(ENOMEM));
+            }
+
+            let mut memory = ContextMemory {
+                physical_address: physical_address,
+                virtual_address: virtual_address,
+                virtual_size: virtual_size,
+                writeable: true,
+                allocated: true,
+                cow: None,
+            };
+
+            memory.map();
+
+            heap.memory.push(memory);
+
+            let mut result = Ok(());
+            loop {
+                let bytes = slice::from_raw_parts_mut(virtual_address as *mut u8, virtual_size);
+                match resource.read(bytes) {
+                    Ok(0) => break,
+                    Ok(count) => vec.extend_from_slice(&bytes[.. count]),
+                    Err(err) => {
+                        result = Err(err);
+                        break;
+                    }
+                }
+            }
+
+            // The read may have faulted other pages into the heap, so find the buffer by address
+            if let Some(i) = heap.memory.iter().position(|mem| mem.virtual_address == virtual_address) {
+                let mut memory = heap.memory.remove(i);
+
+                memory.unmap();
+            }
+
+            try!(result);
+        }
+
+        Ok(vec)
+    }
+
+    /// Check if the zone containing `ptr` is shared with other contexts by `CLONE_VM`. The stack
+    /// is never shared
+    fn zone_shared(&self, ptr: usize) -> bool {
+        for zone in [&self.image, &self.heap, &self.mmap].iter() {
+            let inner = unsafe { &*zone.get() };
+            if ptr >= inner.address && ptr < inner.address + inner.size {
+                return Arc::strong_count(zone) > 1;
+            }
+        }
+
+        false
+    }
+
+    /// Get the zone containing `ptr`
+    unsafe fn zone_at<'a>(&self, ptr: usize) -> Option<&'a mut ContextZone> {
+        let zones = [self.stack.as_ref().map(|stack| stack.get()),
+                     Some(self.image.get()), Some(self.heap.get()), Some(self.mmap.get())];
+
+        for zone in zones.iter() {
+            if let Some(zone) = *zone {
+                if ptr >= (*zone).address && ptr < (*zone).address + (*zone).size {
+                    return Some(&mut *zone);
+                }
+            }
+        }
+
+        None
     }
 
     /// Gets an environment variable. Returns `Err` if the variable is not defined
@@ -918,9 +1765,66 @@ impl Context {
         Err(Error::new(ENOENT))
     }
 
+    /// Get the action taken when `sig` is delivered
+    pub fn signal_action(&self, sig: usize) -> SignalAction {
+        match self.sig_handlers[sig] {
+            SIG_DFL => match sig {
+                SIGCHLD => SignalAction::Ignore,
+                SIGCONT => SignalAction::Continue,
+                SIGSTOP | SIGTSTP | SIGTTIN | SIGTTOU => SignalAction::Stop,
+                _ => SignalAction::Terminate,
+            },
+            SIG_IGN => SignalAction::Ignore,
+            handler => SignalAction::Handle(handler),
+        }
+    }
+
+    /// Get the pending signals which are not blocked
+    fn signal_ready(&self) -> usize {
+        self.sig_pending & !(self.sig_mask & !(1 << SIGKILL | 1 << SIGSTOP))
+    }
+
+    /// Remove and return the lowest pending signal which is not blocked
+    pub fn signal_next(&mut self) -> Option<usize> {
+        let ready = self.signal_ready();
+        if ready > 0 {
+            let sig = ready.trailing_zeros() as usize;
+            self.sig_pending &= !(1 << sig);
+            Some(sig)
+        } else {
+            None
+        }
+    }
+
+    /// Check if a signal is ready which should interrupt a blocking syscall
+    pub fn signal_interrupt(&self) -> bool {
+        let ready = self.signal_ready();
+        (1..NSIG).any(|sig| ready & (1 << sig) > 0 && match self.signal_action(sig) {
+            SignalAction::Terminate | SignalAction::Handle(_) => true,
+            _ => false,
+        })
+    }
+
+    /// Get a ready signal which will terminate the context
+    pub fn signal_fatal(&self) -> Option<usize> {
+        let ready = self.signal_ready();
+        (1..NSIG).find(|&sig| ready & (1 << sig) > 0 && self.signal_action(sig) == SignalAction::Terminate)
+    }
+
+    /// Account for a time slice used by the context
+    pub fn tick(&mut self, user: bool) {
+        self.time += 1;
+        if user {
+            self.time_user += 1;
+        }
+
+        let weight = PRIORITY_WEIGHTS[(self.priority + 20) as usize];
+        self.vtime += VTIME_SLICE * PRIORITY_WEIGHTS[20] / weight;
+    }
+
     pub unsafe fn map(&mut self) {
-        if let Some(ref mut stack) = self.stack {
-            stack.map();
+        if let Some(ref stack) = self.stack {
+            (*stack.get()).map();
         }
         (*self.image.get()).map();
         (*self.heap.get()).map();
@@ -931,8 +1835,8 @@ impl Context {
         (*self.mmap.get()).unmap();
         (*self.heap.get()).unmap();
         (*self.image.get()).unmap();
-        if let Some(ref mut stack) = self.stack {
-            stack.unmap();
+        if let Some(ref stack) = self.stack {
+            (*stack.get()).unmap();
         }
     }
 
@@ -1016,7 +1920,7 @@ impl Context {
 impl Drop for Context {
     fn drop(&mut self) {
         if let Some(vfork) = self.vfork.take() {
-            unsafe { (*vfork).blocked = false; }
+            unsafe { (*vfork).unblock(); }
         }
         if self.kernel_stack > 0 {
             unsafe { memory::unalloc(self.kernel_stack); }
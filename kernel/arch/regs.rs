@@ -18,6 +18,8 @@ impl Into<Packet> for Regs {
             b: self.bx,
             c: self.cx,
             d: self.dx,
+            uid: 0,
+            gid: 0,
         }
     }
 }
@@ -56,6 +56,25 @@ interrupts:
     pop esi
     pop ebp
 
+    ; Some exceptions push an error code after the return frame, which has to be skipped
+    push eax
+    mov eax, [esp - 32] ; The interrupt code, below the registers that were popped
+    cmp eax, 8
+    je .error_code
+    cmp eax, 10
+    jb .return
+    cmp eax, 14
+    jbe .error_code
+    cmp eax, 17
+    je .error_code
+    cmp eax, 30
+    je .error_code
+.return:
+    pop eax
+    iretd
+.error_code:
+    pop eax
+    add esp, 4
     iretd
 
 .handler: dd 0
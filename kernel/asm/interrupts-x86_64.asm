@@ -75,6 +75,25 @@ interrupts:
 	pop r15
 	pop rbp
 
+	; Some exceptions push an error code after the return frame, which has to be skipped
+	push rax
+	mov rax, [rsp - 128] ; The interrupt code, below the registers that were popped
+	cmp rax, 8
+	je .error_code
+	cmp rax, 10
+	jb .return
+	cmp rax, 14
+	jbe .error_code
+	cmp rax, 17
+	je .error_code
+	cmp rax, 30
+	je .error_code
+.return:
+	pop rax
+    iretq
+.error_code:
+	pop rax
+	add rsp, 8
     iretq
 
 .handler: dq 0
@@ -217,7 +217,7 @@ impl KScheme for IntelHda {
         "hda"
     }
 
-    fn open(&mut self, _: Url, _: usize) -> Result<Box<Resource>> {
+    fn open(&mut self, _: Url, _: usize, _: u32, _: u32) -> Result<Box<Resource>> {
         Ok(box IntelHdaResource { base: self.base })
     }
 
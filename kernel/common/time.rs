@@ -1,6 +1,8 @@
 use core::cmp::Ordering;
 use core::ops::{Add, Sub};
 
+use syscall::TimeSpec;
+
 pub const NANOS_PER_MICRO: i32 = 1000;
 pub const NANOS_PER_MILLI: i32 = 1000000;
 pub const NANOS_PER_SEC: i32 = 1000000000;
@@ -42,6 +44,14 @@ impl Duration {
     pub fn realtime() -> Self {
         ::env().clock_realtime.lock().clone()
     }
+
+    /// Convert to a `TimeSpec`, as used by syscalls
+    pub fn timespec(&self) -> TimeSpec {
+        TimeSpec {
+            tv_sec: self.secs,
+            tv_nsec: self.nanos,
+        }
+    }
 }
 
 impl Add for Duration {
@@ -67,6 +77,8 @@ impl PartialEq for Duration {
     }
 }
 
+impl Eq for Duration {}
+
 impl PartialOrd for Duration {
     fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
         let dif = *self - *other;
@@ -83,3 +95,9 @@ impl PartialOrd for Duration {
         }
     }
 }
+
+impl Ord for Duration {
+    fn cmp(&self, other: &Self) -> Ordering {
+        self.partial_cmp(other).unwrap_or(Ordering::Equal)
+    }
+}
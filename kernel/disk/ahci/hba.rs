@@ -205,14 +205,12 @@ impl HbaPort {
         }
     }
 
-    pub fn ata_dma(&mut self, block: u64, sectors: usize, buf: usize, write: bool) -> Result<usize> {
-        // debugln!("AHCI {:X} DMA BLOCK: {:X} SECTORS: {} BUF: {:X} WRITE: {}", (self as *mut HbaPort) as usize, block, sectors, buf, write);
+    /// Transfer `sectors` sectors at `block` to or from `physical_address`, which must be
+    /// physically contiguous, such as kernel memory
+    pub fn ata_dma(&mut self, block: u64, sectors: usize, physical_address: usize, write: bool) -> Result<usize> {
+        // debugln!("AHCI {:X} DMA BLOCK: {:X} SECTORS: {} BUF: {:X} WRITE: {}", (self as *mut HbaPort) as usize, block, sectors, physical_address, write);
 
         if sectors > 0 {
-            let contexts = ::env().contexts.lock();
-            let current = try!(contexts.current());
-            let physical_address = try!(current.translate(buf, sectors * 512));
-
             let mut sector: usize = 0;
             while sectors - sector >= 255 {
                 if let Err(err) = self.ata_dma_small(block + sector as u64, 255, physical_address + sector * 512, write) {
@@ -3,6 +3,8 @@ use alloc::boxed::Box;
 use collections::string::String;
 use collections::vec::Vec;
 
+use arch::memory::Memory;
+
 use disk::Disk;
 
 use drivers::io::Io;
@@ -70,11 +72,29 @@ impl Disk for AhciDisk {
         self.size
     }
 
+    /// The controller transfers to physical memory, so reads go through a bounce buffer in kernel
+    /// memory, whichever memory `buffer` is in
     fn read(&mut self, block: u64, buffer: &mut [u8]) -> Result<usize> {
-        self.port.ata_dma(block, buffer.len() / 512, buffer.as_ptr() as usize, false)
+        let sectors = buffer.len() / 512;
+        let bounce = try!(Memory::<u8>::new_aligned(sectors * 512, 4096));
+        let count = try!(self.port.ata_dma(block, sectors, bounce.address(), false));
+
+        for (b, d) in buffer.iter_mut().zip(bounce.as_slice()[.. count].iter()) {
+            *b = *d;
+        }
+
+        Ok(count)
     }
 
+    /// Writes go through a bounce buffer in kernel memory like reads
     fn write(&mut self, block: u64, buffer: &[u8]) -> Result<usize> {
-        self.port.ata_dma(block, buffer.len() / 512, buffer.as_ptr() as usize, true)
+        let sectors = buffer.len() / 512;
+        let mut bounce = try!(Memory::<u8>::new_aligned(sectors * 512, 4096));
+
+        for (d, b) in bounce.as_mut_slice().iter_mut().zip(buffer.iter()) {
+            *d = *b;
+        }
+
+        self.port.ata_dma(block, sectors, bounce.address(), true)
     }
 }
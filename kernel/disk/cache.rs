@@ -0,0 +1,361 @@
+use alloc::arc::Arc;
+use alloc::boxed::Box;
+
+use collections::{BTreeMap, String, Vec};
+
+use arch::context::context_sleep;
+
+use common::time::Duration;
+
+use core::{cmp, u64};
+
+use sync::Intex;
+
+use system::error::{Error, Result, EIO};
+
+use super::Disk;
+
+/// Blocks kept in the cache, 2 MB
+const CACHE_BLOCKS: usize = 4096;
+/// Blocks read past the end of a read that follows the previous one
+const READ_AHEAD_BLOCKS: u64 = 32;
+/// Seconds a written block may stay in the cache before it is written back
+const DIRTY_AGE: i64 = 5;
+/// Seconds between checks for blocks to write back
+const FLUSH_INTERVAL: i64 = 1;
+
+/// A cached block
+struct CacheBlock {
+    data: [u8; 512],
+    /// The block was written and not yet written back
+    dirty: bool,
+    /// The tick of the last use, the key of the block in the LRU order
+    used: u64,
+}
+
+/// Block cache statistics, in blocks
+#[derive(Copy, Clone, Default)]
+pub struct CacheStats {
+    /// Blocks read from the cache
+    pub hits: u64,
+    /// Blocks read from the disk, not counting read-ahead
+    pub misses: u64,
+    /// Blocks read ahead
+    pub read_ahead: u64,
+    /// Dirty blocks written back
+    pub write_backs: u64,
+    /// Blocks evicted
+    pub evictions: u64,
+}
+
+/// A block cache shared by every disk, with LRU eviction, read-ahead of sequential reads and
+/// write-back of dirty blocks on eviction, sync, and after `DIRTY_AGE` seconds
+pub struct BlockCache {
+    disks: Vec<Box<Disk>>,
+    /// The block after the last read of each disk
+    next: Vec<u64>,
+    /// The time of the first write to each disk since it was last synced
+    dirty_since: Vec<Option<Duration>>,
+    /// The maximum number of cached blocks
+    capacity: usize,
+    blocks: BTreeMap<(usize, u64), CacheBlock>,
+    /// The disk and block of every cached block, by the tick of its last use
+    lru: BTreeMap<u64, (usize, u64)>,
+    tick: u64,
+    pub stats: CacheStats,
+}
+
+impl BlockCache {
+    pub fn new(disks: Vec<Box<Disk>>) -> Self {
+        BlockCache::with_capacity(disks, CACHE_BLOCKS)
+    }
+
+    /// Create a block cache that holds at most `capacity` blocks
+    pub fn with_capacity(disks: Vec<Box<Disk>>, capacity: usize) -> Self {
+        let next = disks.iter().map(|_| 0).collect();
+        let dirty_since = disks.iter().map(|_| None).collect();
+        BlockCache {
+            disks: disks,
+            next: next,
+            dirty_since: dirty_since,
+            capacity: capacity,
+            blocks: BTreeMap::new(),
+            lru: BTreeMap::new(),
+            tick: 0,
+            stats: CacheStats::default(),
+        }
+    }
+
+    /// The number of cached blocks
+    pub fn blocks(&self) -> usize {
+        self.blocks.len()
+    }
+
+    /// The number of cached blocks that are not yet written back
+    pub fn dirty(&self) -> usize {
+        self.blocks.values().filter(|block| block.dirty).count()
+    }
+
+    /// Mark a block as the most recently used
+    fn touch(&mut self, key: (usize, u64)) {
+        self.tick += 1;
+        if let Some(block) = self.blocks.get_mut(&key) {
+            self.lru.remove(&block.used);
+            block.used = self.tick;
+            self.lru.insert(self.tick, key);
+        }
+    }
+
+    /// Cache a block, evicting the least recently used blocks if the cache is full
+    fn insert(&mut self, key: (usize, u64), data: &[u8], dirty: bool) -> Result<()> {
+        while self.blocks.len() >= self.capacity {
+            try!(self.evict());
+        }
+
+        self.tick += 1;
+        let mut block = CacheBlock {
+            data: [0; 512],
+            dirty: dirty,
+            used: self.tick,
+        };
+        for (b, d) in block.data.iter_mut().zip(data.iter()) {
+            *b = *d;
+        }
+
+        self.blocks.insert(key, block);
+        self.lru.insert(self.tick, key);
+        Ok(())
+    }
+
+    /// Remove the least recently used block, writing it back if it is dirty
+    fn evict(&mut self) -> Result<()> {
+        let (tick, key) = match self.lru.iter().next() {
+            Some((&tick, &key)) => (tick, key),
+            None => return Ok(()),
+        };
+
+        if self.blocks.get(&key).map_or(false, |block| block.dirty) {
+            try!(self.write_back(key.0, key.1, key.1 + 1));
+        }
+
+        self.lru.remove(&tick);
+        self.blocks.remove(&key);
+        self.stats.evictions += 1;
+        Ok(())
+    }
+
+    /// Write back the dirty blocks of a disk from `start` to `end`, with one write for every run
+    /// of adjacent blocks
+    fn write_back(&mut self, disk: usize, start: u64, end: u64) -> Result<()> {
+        let mut runs: Vec<(u64, Vec<u8>)> = Vec::new();
+        for (&(block_disk, block), cached) in self.blocks.iter() {
+            if block_disk != disk || block < start || block >= end || !cached.dirty {
+                continue;
+            }
+
+            let joined = if let Some(run) = runs.last_mut() {
+                if run.0 + (run.1.len() / 512) as u64 == block {
+                    run.1.extend_from_slice(&cached.data);
+                    true
+                } else {
+                    false
+                }
+            } else {
+                false
+            };
+
+            if !joined {
+                runs.push((block, cached.data.to_vec()));
+            }
+        }
+
+        for run in runs.iter() {
+            try!(self.disks[disk].write(run.0, &run.1));
+
+            let count = (run.1.len() / 512) as u64;
+            for i in 0..count {
+                if let Some(cached) = self.blocks.get_mut(&(disk, run.0 + i)) {
+                    cached.dirty = false;
+                }
+            }
+            self.stats.write_backs += count;
+        }
+
+        Ok(())
+    }
+
+    /// Check that blocks are on a disk, so that writes do not fail only when written back
+    fn check(&self, disk: usize, block: u64, count: u64) -> Result<()> {
+        match block.checked_add(count) {
+            Some(end) if end <= self.disks[disk].size() / 512 => Ok(()),
+            _ => Err(Error::new(EIO)),
+        }
+    }
+
+    /// Read blocks of a disk. Missing blocks are read together, and a read that follows the last
+    /// one also reads ahead
+    pub fn read(&mut self, disk: usize, block: u64, buffer: &mut [u8]) -> Result<usize> {
+        let count = (buffer.len() / 512) as u64;
+        try!(self.check(disk, block, count));
+
+        let disk_blocks = self.disks[disk].size() / 512;
+        let sequential = block == self.next[disk];
+        self.next[disk] = block + count;
+
+        let mut i = 0;
+        while i < count {
+            if self.blocks.contains_key(&(disk, block + i)) {
+                self.touch((disk, block + i));
+                if let Some(cached) = self.blocks.get(&(disk, block + i)) {
+                    for (b, d) in buffer[(i * 512) as usize .. ((i + 1) * 512) as usize].iter_mut().zip(cached.data.iter()) {
+                        *b = *d;
+                    }
+                }
+                self.stats.hits += 1;
+                i += 1;
+            } else {
+                let mut end = i + 1;
+                while end < count && !self.blocks.contains_key(&(disk, block + end)) {
+                    end += 1;
+                }
+
+                let mut read_end = block + end;
+                if sequential && end == count {
+                    read_end = cmp::min(disk_blocks, read_end + READ_AHEAD_BLOCKS);
+                }
+
+                let mut data: Vec<u8> = vec![0; ((read_end - block - i) * 512) as usize];
+                try!(self.disks[disk].read(block + i, &mut data));
+
+                for j in 0..read_end - block - i {
+                    // Blocks read ahead may already be cached, and newer if they are dirty
+                    let key = (disk, block + i + j);
+                    if !self.blocks.contains_key(&key) {
+                        try!(self.insert(key, &data[(j * 512) as usize .. ((j + 1) * 512) as usize], false));
+                    }
+                }
+
+                for (b, d) in buffer[(i * 512) as usize .. (end * 512) as usize].iter_mut().zip(data.iter()) {
+                    *b = *d;
+                }
+
+                self.stats.misses += end - i;
+                self.stats.read_ahead += read_end - block - end;
+                i = end;
+            }
+        }
+
+        Ok((count * 512) as usize)
+    }
+
+    /// Write blocks of a disk to the cache, to be written back on eviction or sync
+    pub fn write(&mut self, disk: usize, block: u64, buffer: &[u8]) -> Result<usize> {
+        let count = (buffer.len() / 512) as u64;
+        try!(self.check(disk, block, count));
+
+        if count > 0 && self.dirty_since[disk].is_none() {
+            self.dirty_since[disk] = Some(Duration::monotonic());
+        }
+
+        for i in 0..count {
+            let key = (disk, block + i);
+            let data = &buffer[(i * 512) as usize .. ((i + 1) * 512) as usize];
+            if self.blocks.contains_key(&key) {
+                self.touch(key);
+                if let Some(cached) = self.blocks.get_mut(&key) {
+                    for (b, d) in cached.data.iter_mut().zip(data.iter()) {
+                        *b = *d;
+                    }
+                    cached.dirty = true;
+                }
+            } else {
+                try!(self.insert(key, data, true));
+            }
+        }
+
+        Ok((count * 512) as usize)
+    }
+
+    /// Write back every dirty block of a disk
+    pub fn sync(&mut self, disk: usize) -> Result<()> {
+        try!(self.write_back(disk, 0, u64::MAX));
+        self.dirty_since[disk] = None;
+        self.disks[disk].sync()
+    }
+
+    /// Write back every dirty block of every disk
+    pub fn sync_all(&mut self) -> Result<()> {
+        for disk in 0..self.disks.len() {
+            try!(self.sync(disk));
+        }
+        Ok(())
+    }
+
+    /// Write back the disks that were first written to at least `age` ago
+    pub fn sync_older(&mut self, age: Duration) -> Result<()> {
+        let now = Duration::monotonic();
+        for disk in 0..self.disks.len() {
+            if let Some(since) = self.dirty_since[disk] {
+                if since + age <= now {
+                    try!(self.sync(disk));
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Write back dirty blocks once they are `DIRTY_AGE` seconds old
+    pub fn flush_loop(cache: Arc<Intex<BlockCache>>) {
+        loop {
+            if let Err(err) = cache.lock().sync_older(Duration::new(DIRTY_AGE, 0)) {
+                debugln!("Block cache: failed to write back: {}", err);
+            }
+
+            unsafe { context_sleep(Duration::monotonic() + Duration::new(FLUSH_INTERVAL, 0)) };
+        }
+    }
+}
+
+/// A disk whose reads and writes go through a shared block cache
+pub struct CachedDisk {
+    cache: Arc<Intex<BlockCache>>,
+    disk: usize,
+}
+
+impl CachedDisk {
+    /// Create a cached disk for every disk in a cache
+    pub fn all(cache: &Arc<Intex<BlockCache>>) -> Vec<Box<Disk>> {
+        let count = cache.lock().disks.len();
+
+        let mut disks: Vec<Box<Disk>> = Vec::new();
+        for disk in 0..count {
+            disks.push(box CachedDisk {
+                cache: cache.clone(),
+                disk: disk,
+            });
+        }
+        disks
+    }
+}
+
+impl Disk for CachedDisk {
+    fn name(&self) -> String {
+        self.cache.lock().disks[self.disk].name()
+    }
+
+    fn size(&self) -> u64 {
+        self.cache.lock().disks[self.disk].size()
+    }
+
+    fn read(&mut self, block: u64, buffer: &mut [u8]) -> Result<usize> {
+        self.cache.lock().read(self.disk, block, buffer)
+    }
+
+    fn write(&mut self, block: u64, buffer: &[u8]) -> Result<usize> {
+        self.cache.lock().write(self.disk, block, buffer)
+    }
+
+    fn sync(&mut self) -> Result<()> {
+        self.cache.lock().sync(self.disk)
+    }
+}
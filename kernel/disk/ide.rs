@@ -542,7 +542,12 @@ impl IdeDisk {
         if sectors > 0 {
             let contexts = ::env().contexts.lock();
             let current = try!(contexts.current());
-            let physical_address = try!(current.translate(buf, sectors * 512));
+            // Reading from the disk writes to the buffer
+            let physical_address = try!(if write {
+                current.translate(buf, sectors * 512)
+            } else {
+                current.translate_mut(buf, sectors * 512)
+            });
 
             // debugln!("IDE DMA TRANSLATED {:X}", physical_address);
 
@@ -3,11 +3,18 @@ use collections::string::String;
 use system::error::Result;
 
 pub mod ahci;
+pub mod cache;
 pub mod ide;
+pub mod partition;
 
 pub trait Disk {
     fn name(&self) -> String;
     fn size(&self) -> u64;
     fn read(&mut self, block: u64, buffer: &mut [u8]) -> Result<usize>;
     fn write(&mut self, block: u64, buffer: &[u8]) -> Result<usize>;
+
+    /// Write back any writes that were cached
+    fn sync(&mut self) -> Result<()> {
+        Ok(())
+    }
 }
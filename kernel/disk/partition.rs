@@ -0,0 +1,205 @@
+use collections::string::String;
+use collections::vec::Vec;
+
+use core::cmp;
+
+use system::error::{Error, Result, EIO};
+
+use super::Disk;
+
+/// The MBR type of a protective MBR, which covers a disk with a GPT
+const MBR_TYPE_GPT: u8 = 0xEE;
+/// The MBR types of extended partitions, which hold a chain of logical partitions
+const MBR_TYPES_EXTENDED: [u8; 3] = [0x05, 0x0F, 0x85];
+/// Logical partitions read from an extended partition before the chain is considered broken
+const MBR_LOGICAL_MAX: usize = 128;
+
+/// The entries of a GPT read before the rest are ignored
+const GPT_ENTRIES_MAX: u32 = 256;
+
+/// A partition of a disk
+#[derive(Clone)]
+pub struct Partition {
+    /// The number of the partition, from 1. Logical MBR partitions are numbered from 5
+    pub number: usize,
+    /// The first block of the partition
+    pub start: u64,
+    /// The number of blocks in the partition
+    pub blocks: u64,
+    /// The MBR type byte in hexadecimal, or the GPT type GUID
+    pub kind: String,
+    /// The GPT partition name, empty for MBR partitions
+    pub label: String,
+    /// The GPT unique partition GUID, or the MBR disk signature and partition number
+    pub uuid: String,
+}
+
+fn read_u16(bytes: &[u8], offset: usize) -> u16 {
+    bytes[offset] as u16 | (bytes[offset + 1] as u16) << 8
+}
+
+fn read_u32(bytes: &[u8], offset: usize) -> u32 {
+    read_u16(bytes, offset) as u32 | (read_u16(bytes, offset + 2) as u32) << 16
+}
+
+fn read_u64(bytes: &[u8], offset: usize) -> u64 {
+    read_u32(bytes, offset) as u64 | (read_u32(bytes, offset + 4) as u64) << 32
+}
+
+/// Format a GUID, whose first three fields are little endian
+fn guid_string(bytes: &[u8]) -> String {
+    format!("{:08X}-{:04X}-{:04X}-{:02X}{:02X}-{:02X}{:02X}{:02X}{:02X}{:02X}{:02X}",
+            read_u32(bytes, 0),
+            read_u16(bytes, 4),
+            read_u16(bytes, 6),
+            bytes[8], bytes[9],
+            bytes[10], bytes[11], bytes[12], bytes[13], bytes[14], bytes[15])
+}
+
+/// Read a block, which is an error if it can not be read in full
+fn read_block(disk: &mut Disk, block: u64) -> Result<[u8; 512]> {
+    let mut sector = [0; 512];
+    match try!(disk.read(block, &mut sector)) {
+        512 => Ok(sector),
+        _ => Err(Error::new(EIO)),
+    }
+}
+
+/// Check if a partition lies within a disk of the given number of blocks
+fn fits(start: u64, blocks: u64, disk_blocks: u64) -> bool {
+    start > 0 && blocks > 0 && start.checked_add(blocks).map_or(false, |end| end <= disk_blocks)
+}
+
+/// Read the partitions of a GPT, given the header at block 1
+fn gpt_partitions(disk: &mut Disk, header: &[u8; 512], disk_blocks: u64) -> Result<Vec<Partition>> {
+    let mut partitions = Vec::new();
+
+    let entries_block = read_u64(header, 72);
+    let entries = read_u32(header, 80);
+    let entry_size = read_u32(header, 84) as u64;
+    if entry_size < 128 || entry_size > 512 || 512 % entry_size != 0 {
+        return Ok(partitions);
+    }
+
+    let per_block = 512 / entry_size;
+    let mut sector = [0; 512];
+    for i in 0..cmp::min(entries, GPT_ENTRIES_MAX) as u64 {
+        if i % per_block == 0 {
+            sector = try!(read_block(disk, entries_block + i / per_block));
+        }
+
+        let entry = &sector[((i % per_block) * entry_size) as usize ..];
+        if entry[.. 16].iter().all(|&b| b == 0) {
+            continue;
+        }
+
+        let first = read_u64(entry, 32);
+        let last = read_u64(entry, 40);
+        if last < first || !fits(first, last - first + 1, disk_blocks) {
+            continue;
+        }
+
+        let mut name = Vec::new();
+        for j in 0..36 {
+            let c = read_u16(entry, 56 + j * 2);
+            if c == 0 {
+                break;
+            }
+            name.push(c);
+        }
+
+        partitions.push(Partition {
+            number: i as usize + 1,
+            start: first,
+            blocks: last - first + 1,
+            kind: guid_string(&entry[0 .. 16]),
+            label: String::from_utf16_lossy(&name),
+            uuid: guid_string(&entry[16 .. 32]),
+        });
+    }
+
+    Ok(partitions)
+}
+
+/// Read the logical partitions in the chain of extended boot records of an extended partition.
+/// The chain ends at a record without a signature
+fn mbr_logical_partitions(disk: &mut Disk, extended: u64, signature: u32, disk_blocks: u64,
+                          partitions: &mut Vec<Partition>) -> Result<()> {
+    let mut ebr = extended;
+    for number in 5..5 + MBR_LOGICAL_MAX {
+        let sector = try!(read_block(disk, ebr));
+        if sector[510] != 0x55 || sector[511] != 0xAA {
+            return Ok(());
+        }
+
+        // The first entry is relative to this record, the second to the extended partition
+        let kind = sector[446 + 4];
+        let start = ebr + read_u32(&sector, 446 + 8) as u64;
+        let blocks = read_u32(&sector, 446 + 12) as u64;
+        if kind != 0 && fits(start, blocks, disk_blocks) {
+            partitions.push(Partition {
+                number: number,
+                start: start,
+                blocks: blocks,
+                kind: format!("{:02X}", kind),
+                label: String::new(),
+                uuid: format!("{:08X}-{:02X}", signature, number),
+            });
+        }
+
+        let next = read_u32(&sector, 462 + 8) as u64;
+        if sector[462 + 4] == 0 || next == 0 {
+            return Ok(());
+        }
+        ebr = extended + next;
+    }
+
+    Ok(())
+}
+
+/// Read the partition table of a disk. A GPT is used if the MBR is protective, otherwise the
+/// primary and logical MBR partitions are returned. A disk without a table has no partitions,
+/// while a table that can not be read is an error
+pub fn partitions(disk: &mut Disk) -> Result<Vec<Partition>> {
+    let mut partitions = Vec::new();
+
+    let disk_blocks = disk.size() / 512;
+    let mbr = try!(read_block(disk, 0));
+    if mbr[510] != 0x55 || mbr[511] != 0xAA {
+        return Ok(partitions);
+    }
+
+    if (0..4).any(|i| mbr[446 + i * 16 + 4] == MBR_TYPE_GPT) {
+        let header = try!(read_block(disk, 1));
+        if &header[.. 8] == b"EFI PART" {
+            return gpt_partitions(disk, &header, disk_blocks);
+        }
+        return Ok(partitions);
+    }
+
+    let signature = read_u32(&mbr, 440);
+    for i in 0..4 {
+        let entry = &mbr[446 + i * 16 .. 446 + (i + 1) * 16];
+        let kind = entry[4];
+        let start = read_u32(entry, 8) as u64;
+        let blocks = read_u32(entry, 12) as u64;
+        if kind == 0 || !fits(start, blocks, disk_blocks) {
+            continue;
+        }
+
+        if MBR_TYPES_EXTENDED.contains(&kind) {
+            try!(mbr_logical_partitions(disk, start, signature, disk_blocks, &mut partitions));
+        } else {
+            partitions.push(Partition {
+                number: i + 1,
+                start: start,
+                blocks: blocks,
+                kind: format!("{:02X}", kind),
+                label: String::new(),
+                uuid: format!("{:08X}-{:02X}", signature, i + 1),
+            });
+        }
+    }
+
+    Ok(partitions)
+}
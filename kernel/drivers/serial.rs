@@ -1,7 +1,5 @@
 use alloc::boxed::Box;
 
-use collections::string::String;
-
 use common::event;
 
 use drivers::io::{Io, Pio};
@@ -78,12 +76,6 @@ impl KScheme for Serial {
                 }
 
                 c = '\0';
-            } else if c == '\x03' {
-                console.write(b"^C\n");
-                console.commands.send(String::new());
-
-                c = '\0';
-                sc = 0;
             } else if c == '\x1B' {
                 self.escape = true;
                 c = '\0';
@@ -14,6 +14,10 @@ use graphics::display::Display;
 
 use sync::WaitQueue;
 
+use syscall::context_signal;
+
+use system::syscall::SIGINT;
+
 fn ansi_color(value: u8) -> Color {
     match value {
         0 => Color::new(0x00, 0x00, 0x00),
@@ -67,6 +71,10 @@ pub struct Console {
     pub escape_sequence: bool,
     pub sequence: Vec<String>,
     pub raw_mode: bool,
+    /// Is a control key held?
+    pub ctrl: bool,
+    /// The process which last read from the console, whose children form the foreground job
+    pub reader: usize,
 }
 
 impl Console {
@@ -85,6 +93,8 @@ impl Console {
             escape_sequence: false,
             sequence: Vec::new(),
             raw_mode: false,
+            ctrl: false,
+            reader: 0,
         }
     }
 
@@ -309,7 +319,17 @@ RAW MODE
 
     pub fn event(&mut self, event: Event) {
         match event.to_option() {
-            EventOption::Key(key_event) => {
+            EventOption::Key(mut key_event) => {
+                if key_event.scancode == event::K_CTRL {
+                    self.ctrl = key_event.pressed;
+                } else if self.ctrl {
+                    // Control characters, such as ^C
+                    key_event.character = match key_event.character {
+                        c @ 'a' ... 'z' | c @ 'A' ... 'Z' => ((c as u8) & 0x1F) as char,
+                        c => c,
+                    };
+                }
+
                 if key_event.pressed {
                     if self.raw_mode {
                         match key_event.scancode {
@@ -341,6 +361,7 @@ RAW MODE
                             },
                             _ => match key_event.character {
                                 '\0' => (),
+                                '\x03' => self.interrupt(),
                                 c => {
                                     self.redraw = true;
 
@@ -362,6 +383,38 @@ RAW MODE
         }
     }
 
+    /// Interrupt the foreground job, as when ^C is typed
+    ///
+    /// The reader gets an empty line, and SIGINT is sent to the processes it started
+    pub fn interrupt(&mut self) {
+        self.redraw = true;
+        self.write(b"^C\n");
+        self.command.clear();
+        self.commands.send(String::new());
+
+        if self.reader == 0 {
+            return;
+        }
+
+        let mut pids = vec![self.reader];
+        {
+            let contexts = ::env().contexts.lock();
+            let mut i = 0;
+            while i < pids.len() {
+                for context in contexts.iter() {
+                    if context.ppid == pids[i] && ! context.exited && ! pids.contains(&context.pid) {
+                        pids.push(context.pid);
+                    }
+                }
+                i += 1;
+            }
+        }
+
+        for &pid in pids.iter().skip(1) {
+            let _ = context_signal(pid, SIGINT);
+        }
+    }
+
     pub fn write(&mut self, bytes: &[u8]) {
         for byte in bytes.iter() {
             let c = *byte as char;
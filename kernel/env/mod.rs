@@ -1,3 +1,4 @@
+use alloc::arc::Arc;
 use alloc::boxed::Box;
 
 use collections::string::{String, ToString};
@@ -8,11 +9,14 @@ use arch::intex::Intex;
 use common::event::Event;
 use common::time::Duration;
 use disk::Disk;
-use fs::{KScheme, Resource, Scheme, VecResource, Url};
+use disk::cache::BlockCache;
+use fs::{DirResource, KScheme, Resource, Scheme, Url};
+use network::config::NetworkConfig;
+use network::scheme::NetworkResource;
 use sync::WaitQueue;
 
-use system::error::{Error, Result, ENOENT, EEXIST};
-use system::syscall::{O_CREAT, Stat};
+use system::error::{Error, Result, ENOENT, EEXIST, EXDEV};
+use system::syscall::{Dirent, MODE_DIR, O_CREAT, Stat};
 
 use self::console::Console;
 
@@ -33,10 +37,16 @@ pub struct Environment {
     pub console: Intex<Console>,
     /// Disks
     pub disks: Intex<Vec<Box<Disk>>>,
+    /// The block cache of the disks, once they are found
+    pub block_cache: Intex<Option<Arc<Intex<BlockCache>>>>,
     /// Pending events
     pub events: WaitQueue<Event>,
     /// Schemes
     pub schemes: Intex<Vec<Box<KScheme>>>,
+    /// Network interface configurations
+    pub interfaces: Intex<Vec<Arc<Intex<NetworkConfig>>>>,
+    /// Network resources on every interface, which receive frames sent to the local stack
+    pub network_resources: Intex<Vec<*mut NetworkResource>>,
 
     /// Interrupt stats
     pub interrupts: Intex<[u64; 256]>,
@@ -52,8 +62,11 @@ impl Environment {
 
             console: Intex::new(Console::new()),
             disks: Intex::new(Vec::new()),
+            block_cache: Intex::new(None),
             events: WaitQueue::new(),
             schemes: Intex::new(Vec::new()),
+            interfaces: Intex::new(Vec::new()),
+            network_resources: Intex::new(Vec::new()),
 
             interrupts: Intex::new([0; 256]),
         }
@@ -65,26 +78,23 @@ impl Environment {
         }
     }
 
-    /// Open a new resource
-    pub fn open(&self, url: Url, flags: usize) -> Result<Box<Resource>> {
+    /// Open a new resource, for the caller with the effective `uid` and `gid`
+    pub fn open(&self, url: Url, flags: usize, uid: u32, gid: u32) -> Result<Box<Resource>> {
         let url_scheme = url.scheme();
         if url_scheme.is_empty() {
             let url_path = url.reference();
             if url_path.trim_matches('/').is_empty() {
-                let mut list = String::new();
+                let mut entries = Vec::new();
 
                 for scheme in self.schemes.lock().iter() {
                     let scheme_str = scheme.scheme();
                     if !scheme_str.is_empty() {
-                        if !list.is_empty() {
-                            list = list + "\n" + scheme_str;
-                        } else {
-                            list = scheme_str.to_string();
-                        }
+                        let ino = entries.len() as u64;
+                        entries.push(Dirent::new(scheme_str, MODE_DIR, 0, ino));
                     }
                 }
 
-                Ok(box VecResource::new(":".to_string(), list.into_bytes()))
+                Ok(box DirResource::new(":".to_string(), entries))
             } else if flags & O_CREAT == O_CREAT {
                 for scheme in self.schemes.lock().iter_mut() {
                     if scheme.scheme() == url_path {
@@ -105,20 +115,52 @@ impl Environment {
         } else {
             for mut scheme in self.schemes.lock().iter_mut() {
                 if scheme.scheme() == url_scheme {
-                    return scheme.open(url, flags);
+                    return scheme.open(url, flags, uid, gid);
                 }
             }
             Err(Error::new(ENOENT))
         }
     }
 
-    /// Makes a directory
-    pub fn mkdir(&self, url: Url, flags: usize) -> Result<()> {
+    /// Create a new link to an existing resource
+    pub fn link(&self, old: Url, new: Url) -> Result<()> {
+        let url_scheme = old.scheme();
+        if !url_scheme.is_empty() {
+            if new.scheme() != url_scheme {
+                return Err(Error::new(EXDEV));
+            }
+            for mut scheme in self.schemes.lock().iter_mut() {
+                if scheme.scheme() == url_scheme {
+                    return scheme.link(old, new);
+                }
+            }
+        }
+        Err(Error::new(ENOENT))
+    }
+
+    /// Makes a directory, for the caller with the effective `uid` and `gid`
+    pub fn mkdir(&self, url: Url, flags: usize, uid: u32, gid: u32) -> Result<()> {
         let url_scheme = url.scheme();
         if !url_scheme.is_empty() {
             for mut scheme in self.schemes.lock().iter_mut() {
                 if scheme.scheme() == url_scheme {
-                    return scheme.mkdir(url, flags);
+                    return scheme.mkdir(url, flags, uid, gid);
+                }
+            }
+        }
+        Err(Error::new(ENOENT))
+    }
+
+    /// Rename a resource, replacing the target if it exists
+    pub fn rename(&self, old: Url, new: Url) -> Result<()> {
+        let url_scheme = old.scheme();
+        if !url_scheme.is_empty() {
+            if new.scheme() != url_scheme {
+                return Err(Error::new(EXDEV));
+            }
+            for mut scheme in self.schemes.lock().iter_mut() {
+                if scheme.scheme() == url_scheme {
+                    return scheme.rename(old, new);
                 }
             }
         }
@@ -151,13 +193,13 @@ impl Environment {
         Err(Error::new(ENOENT))
     }
 
-    /// Unlink a resource
-    pub fn unlink(&self, url: Url) -> Result<()> {
+    /// Unlink a resource, for the caller with the effective `uid` and `gid`
+    pub fn unlink(&self, url: Url, uid: u32, gid: u32) -> Result<()> {
         let url_scheme = url.scheme();
         if !url_scheme.is_empty() {
             for mut scheme in self.schemes.lock().iter_mut() {
                 if scheme.scheme() == url_scheme {
-                    return scheme.unlink(url);
+                    return scheme.unlink(url, uid, gid);
                 }
             }
         }
@@ -0,0 +1,91 @@
+use super::{Resource, ResourceSeek, VecResource};
+
+use alloc::boxed::Box;
+
+use collections::{String, Vec};
+
+use core::cmp;
+
+use system::error::Result;
+use system::syscall::{Dirent, MODE_DIR, Stat};
+
+/// A directory resource
+///
+/// Reading returns the entries as newline separated names, with directories suffixed by `/`.
+/// `getdents` returns the same entries with their mode, size and inode.
+pub struct DirResource {
+    path: String,
+    entries: Vec<Dirent>,
+    entry: usize,
+    list: VecResource,
+}
+
+impl DirResource {
+    pub fn new(path: String, entries: Vec<Dirent>) -> Self {
+        let mut list = String::new();
+        for entry in entries.iter() {
+            if ! list.is_empty() {
+                list.push('\n');
+            }
+            list.push_str(entry.name());
+            if entry.d_mode & MODE_DIR == MODE_DIR {
+                list.push('/');
+            }
+        }
+
+        DirResource::with_list(path, entries, list)
+    }
+
+    /// Create a directory whose reading returns the given listing instead of the names, for
+    /// schemes that show more about each entry
+    pub fn with_list(path: String, entries: Vec<Dirent>, list: String) -> Self {
+        DirResource {
+            path: path.clone(),
+            entries: entries,
+            entry: 0,
+            list: VecResource::new(path, list.into_bytes()),
+        }
+    }
+}
+
+impl Resource for DirResource {
+    fn dup(&self) -> Result<Box<Resource>> {
+        Ok(box DirResource {
+            path: self.path.clone(),
+            entries: self.entries.clone(),
+            entry: 0,
+            list: VecResource::new(self.path.clone(), self.list.data().clone()),
+        })
+    }
+
+    fn path(&self, buf: &mut [u8]) -> Result<usize> {
+        self.list.path(buf)
+    }
+
+    fn read(&mut self, buf: &mut [u8]) -> Result<usize> {
+        self.list.read(buf)
+    }
+
+    fn seek(&mut self, pos: ResourceSeek) -> Result<usize> {
+        let seek = try!(self.list.seek(pos));
+        if seek == 0 {
+            self.entry = 0;
+        }
+        Ok(seek)
+    }
+
+    fn stat(&self, stat: &mut Stat) -> Result<usize> {
+        try!(self.list.stat(stat));
+        stat.st_mode = MODE_DIR | 0o755;
+        Ok(0)
+    }
+
+    fn getdents(&mut self, dirents: &mut [Dirent]) -> Result<usize> {
+        let count = cmp::min(dirents.len(), self.entries.len() - self.entry);
+        for i in 0..count {
+            dirents[i] = self.entries[self.entry + i];
+        }
+        self.entry += count;
+        Ok(count)
+    }
+}
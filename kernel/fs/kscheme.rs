@@ -2,7 +2,7 @@ use super::{Resource, Url};
 
 use alloc::boxed::Box;
 
-use system::error::{Error, Result, EPERM};
+use system::error::{Error, Result, ENOSYS, EPERM};
 use system::syscall::Stat;
 
 #[allow(unused_variables)]
@@ -15,14 +15,23 @@ pub trait KScheme {
         ""
     }
 
-    fn open(&mut self, path: Url, flags: usize) -> Result<Box<Resource>> {
+    /// Open a resource, for the caller with the effective `uid` and `gid`
+    fn open(&mut self, path: Url, flags: usize, uid: u32, gid: u32) -> Result<Box<Resource>> {
         Err(Error::new(EPERM))
     }
 
-    fn mkdir(&mut self, path: Url, flags: usize) -> Result<()> {
+    fn link(&mut self, old: Url, new: Url) -> Result<()> {
         Err(Error::new(EPERM))
     }
 
+    fn mkdir(&mut self, path: Url, flags: usize, uid: u32, gid: u32) -> Result<()> {
+        Err(Error::new(EPERM))
+    }
+
+    fn rename(&mut self, old: Url, new: Url) -> Result<()> {
+        Err(Error::new(ENOSYS))
+    }
+
     fn rmdir(&mut self, path: Url) -> Result<()> {
         Err(Error::new(EPERM))
     }
@@ -31,7 +40,7 @@ pub trait KScheme {
         Err(Error::new(EPERM))
     }
 
-    fn unlink(&mut self, path: Url) -> Result<()> {
+    fn unlink(&mut self, path: Url, uid: u32, gid: u32) -> Result<()> {
         Err(Error::new(EPERM))
     }
 }
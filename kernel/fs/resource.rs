@@ -1,7 +1,9 @@
 use alloc::boxed::Box;
 
-use system::error::{Error, Result, EPERM, ESPIPE};
-use system::syscall::Stat;
+use sync::WaitCondition;
+
+use system::error::{Error, Result, EAGAIN, EPERM, ESPIPE};
+use system::syscall::{Dirent, Stat, POLLERR, POLLHUP, POLLIN, POLLOUT};
 
 /// Resource seek
 #[derive(Copy, Clone, Debug)]
@@ -41,6 +43,26 @@ pub trait Resource {
         Err(Error::new(EPERM))
     }
 
+    /// Read data to buffer, without blocking
+    /// Returns `EAGAIN` if the read would block. By default, the resource is polled first.
+    fn read_nonblock(&mut self, buf: &mut [u8]) -> Result<usize> {
+        if try!(self.poll(POLLIN)) & (POLLIN | POLLHUP | POLLERR) != 0 {
+            self.read(buf)
+        } else {
+            Err(Error::new(EAGAIN))
+        }
+    }
+
+    /// Write to resource, without blocking
+    /// Returns `EAGAIN` if the write would block. By default, the resource is polled first.
+    fn write_nonblock(&mut self, buf: &[u8]) -> Result<usize> {
+        if try!(self.poll(POLLOUT)) & (POLLOUT | POLLHUP | POLLERR) != 0 {
+            self.write(buf)
+        } else {
+            Err(Error::new(EAGAIN))
+        }
+    }
+
     /// Seek to the given offset
     /// Returns `ESPIPE` if the operation is not supported.
     fn seek(&mut self, pos: ResourceSeek) -> Result<usize> {
@@ -53,6 +75,31 @@ pub trait Resource {
         Err(Error::new(EPERM))
     }
 
+    /// Read directory entries into the buffer, returning the number of entries read
+    /// Returns `EPERM` if the operation is not supported.
+    fn getdents(&mut self, dirents: &mut [Dirent]) -> Result<usize> {
+        Err(Error::new(EPERM))
+    }
+
+    /// Return the subset of `events` that would not block
+    /// Resources are always readable and writable by default.
+    fn poll(&self, events: u16) -> Result<u16> {
+        Ok(events & (POLLIN | POLLOUT))
+    }
+
+    /// Get the condition that is notified when the readiness of the resource may have changed
+    /// Returns `None` if there is no such condition, in which case pollers check again later.
+    fn condition(&self) -> Option<&WaitCondition> {
+        None
+    }
+
+    /// Get the physical address of `size` bytes at `offset`, so that they can be mapped into a
+    /// context. The memory must stay valid for as long as the kernel runs.
+    /// Returns `EPERM` if the operation is not supported.
+    fn mmap(&mut self, offset: usize, size: usize, writeable: bool) -> Result<usize> {
+        Err(Error::new(EPERM))
+    }
+
     /// Sync all buffers
     /// Returns `EPERM` if the operation is not supported.
     fn sync(&mut self) -> Result<()> {
@@ -5,19 +5,20 @@ use collections::String;
 use collections::borrow::ToOwned;
 
 use core::cell::Cell;
+use core::cmp;
 use core::mem::size_of;
 use core::ops::DerefMut;
 use core::{ptr, slice};
 
 use arch::context::{Context, ContextMemory};
 
-use sync::{WaitMap, WaitQueue};
+use sync::{WaitCondition, WaitMap, WaitQueue};
 
-use system::error::{Error, Result, EBADF, EFAULT, EINVAL, ENODEV, ESPIPE};
+use system::error::{Error, Result, EBADF, EINVAL, ENODEV, ESPIPE};
 use system::scheme::Packet;
-use system::syscall::{SYS_CLOSE, SYS_FPATH, SYS_FSTAT, SYS_FSYNC, SYS_FTRUNCATE,
-                    SYS_OPEN, SYS_LSEEK, SEEK_SET, SEEK_CUR, SEEK_END, SYS_MKDIR,
-                    SYS_READ, SYS_WRITE, SYS_RMDIR, SYS_STAT, SYS_UNLINK, Stat};
+use system::syscall::{SYS_CLOSE, SYS_FPATH, SYS_FSTAT, SYS_FSYNC, SYS_FTRUNCATE, SYS_GETDENTS,
+                    SYS_OPEN, SYS_LINK, SYS_LSEEK, SYS_POLL, SEEK_SET, SEEK_CUR, SEEK_END, SYS_MKDIR,
+                    SYS_READ, SYS_RENAME, SYS_WRITE, SYS_RMDIR, SYS_STAT, SYS_UNLINK, Dirent, Stat};
 
 use super::{Resource, ResourceSeek, KScheme, Url};
 
@@ -27,6 +28,9 @@ struct SchemeInner {
     next_id: Cell<usize>,
     todo: WaitQueue<Packet>,
     done: WaitMap<usize, (usize, usize, usize, usize)>,
+    /// Notified when the daemon reports that the readiness of its resources may have changed.
+    /// Resources keep a reference, so that pollers are woken when the daemon goes away
+    condition: Arc<WaitCondition>,
 }
 
 impl SchemeInner {
@@ -37,10 +41,11 @@ impl SchemeInner {
             next_id: Cell::new(1),
             todo: WaitQueue::new(),
             done: WaitMap::new(),
+            condition: Arc::new(WaitCondition::new()),
         }
     }
 
-    fn call(inner: &Weak<SchemeInner>, a: usize, b: usize, c: usize, d: usize) -> Result<usize> {
+    fn call(inner: &Weak<SchemeInner>, uid: u32, gid: u32, a: usize, b: usize, c: usize, d: usize) -> Result<usize> {
         if let Some(scheme) = inner.upgrade() {
             let id = scheme.next_id.get();
 
@@ -56,7 +61,9 @@ impl SchemeInner {
                 a: a,
                 b: b,
                 c: c,
-                d: d
+                d: d,
+                uid: uid,
+                gid: gid
             });
             Error::demux(scheme.done.receive(&id).0)
         } else {
@@ -78,6 +85,7 @@ impl SchemeInner {
                     virtual_size: size,
                     writeable: writeable,
                     allocated: false,
+                    cow: None,
                 });
                 return Ok(virtual_address);
             }
@@ -86,12 +94,51 @@ impl SchemeInner {
         }
     }
 
-    fn release(inner: &Weak<SchemeInner>, virtual_address: usize) {
+    /// Map the buffer between `ptr` and `ptr + len` of the current context into the scheme. The
+    /// buffer is translated a page at a time, so its pages do not have to be contiguous. Returns
+    /// the address of the buffer in the scheme, and the address and size of the mapping to release
+    fn capture_buf(inner: &Weak<SchemeInner>, current: &Context, ptr: usize, len: usize, writeable: bool) -> Result<(usize, usize, usize)> {
+        let pages = match current.translate_pages(ptr, len, writeable) {
+            Ok(pages) => pages,
+            Err(err) => {
+                debugln!("{}:{} fault {:X} {}", file!(), line!(), ptr, len);
+                return Err(err);
+            }
+        };
+
         if let Some(scheme) = inner.upgrade() {
             unsafe {
                 let mmap = &mut *(*scheme.context).mmap.get();
-                if let Ok(mut mem) = mmap.get_mem_mut(virtual_address) {
-                    mem.virtual_size = 0;
+                let virtual_address = mmap.next_mem();
+                let mut offset = 0;
+                for &(physical_address, size) in pages.iter() {
+                    mmap.memory.push(ContextMemory {
+                        physical_address: physical_address,
+                        virtual_address: virtual_address + offset,
+                        virtual_size: size,
+                        writeable: writeable,
+                        allocated: false,
+                        cow: None,
+                    });
+                    offset += size;
+                }
+                Ok((virtual_address + ptr % 4096, virtual_address, offset))
+            }
+        } else {
+            Err(Error::new(ENODEV))
+        }
+    }
+
+    /// Remove the memory mapped between `virtual_address` and `virtual_address + size` by
+    /// `capture` or `capture_buf`
+    fn release(inner: &Weak<SchemeInner>, virtual_address: usize, size: usize) {
+        if let Some(scheme) = inner.upgrade() {
+            unsafe {
+                let mmap = &mut *(*scheme.context).mmap.get();
+                for mem in mmap.memory.iter_mut() {
+                    if mem.virtual_address >= virtual_address && mem.virtual_address < virtual_address + cmp::max(size, 1) {
+                        mem.virtual_size = 0;
+                    }
                 }
                 mmap.clean_mem();
             }
@@ -102,25 +149,32 @@ impl SchemeInner {
 impl Drop for SchemeInner {
     fn drop(&mut self) {
         ::env().schemes.lock().retain(|scheme| scheme.scheme() != self.name);
+        unsafe { self.condition.notify() };
     }
 }
 
 pub struct SchemeResource {
     inner: Weak<SchemeInner>,
     file_id: usize,
+    /// The readiness condition of the scheme
+    condition: Arc<WaitCondition>,
+    /// The effective user id the resource was opened with
+    uid: u32,
+    /// The effective group id the resource was opened with
+    gid: u32,
 }
 
 impl SchemeResource {
     fn call(&self, a: usize, b: usize, c: usize, d: usize) -> Result<usize> {
-        SchemeInner::call(&self.inner, a, b, c, d)
+        SchemeInner::call(&self.inner, self.uid, self.gid, a, b, c, d)
     }
 
-    fn capture(&self, physical_address: usize, size: usize, writeable: bool) -> Result<usize> {
-        SchemeInner::capture(&self.inner, physical_address, size, writeable)
+    fn capture_buf(&self, current: &Context, ptr: usize, len: usize, writeable: bool) -> Result<(usize, usize, usize)> {
+        SchemeInner::capture_buf(&self.inner, current, ptr, len, writeable)
     }
 
-    fn release(&self, virtual_address: usize){
-        SchemeInner::release(&self.inner, virtual_address);
+    fn release(&self, virtual_address: usize, size: usize) {
+        SchemeInner::release(&self.inner, virtual_address, size);
     }
 }
 
@@ -134,66 +188,39 @@ impl Resource for SchemeResource {
     fn path(&self, buf: &mut [u8]) -> Result <usize> {
         let contexts = ::env().contexts.lock();
         let current = try!(contexts.current());
-        if let Ok(physical_address) = current.translate(buf.as_mut_ptr() as usize, buf.len()) {
-            let offset = physical_address % 4096;
-
-            let virtual_address = try!(self.capture(physical_address - offset, buf.len() + offset, true));
+        let (address, virtual_address, size) = try!(self.capture_buf(current, buf.as_mut_ptr() as usize, buf.len(), true));
 
-            let result = self.call(SYS_FPATH, self.file_id, virtual_address + offset, buf.len());
+        let result = self.call(SYS_FPATH, self.file_id, address, buf.len());
 
-            //debugln!("Read {:X} mapped from {:X} to {:X} offset {} length {} size {} result {:?}", physical_address, buf.as_ptr() as usize, virtual_address + offset, offset, buf.len(), virtual_size, result);
+        self.release(virtual_address, size);
 
-            self.release(virtual_address);
-
-            result
-        } else {
-            debugln!("{}:{} fault {:X} {}", file!(), line!(), buf.as_ptr() as usize, buf.len());
-            Err(Error::new(EFAULT))
-        }
+        result
     }
 
     /// Read data to buffer
     fn read(&mut self, buf: &mut [u8]) -> Result<usize> {
         let contexts = ::env().contexts.lock();
         let current = try!(contexts.current());
-        if let Ok(physical_address) = current.translate(buf.as_mut_ptr() as usize, buf.len()) {
-            let offset = physical_address % 4096;
-
-            let virtual_address = try!(self.capture(physical_address - offset, buf.len() + offset, true));
-
-            let result = self.call(SYS_READ, self.file_id, virtual_address + offset, buf.len());
+        let (address, virtual_address, size) = try!(self.capture_buf(current, buf.as_mut_ptr() as usize, buf.len(), true));
 
-            //debugln!("Read {:X} mapped from {:X} to {:X} offset {} length {} size {} result {:?}", physical_address, buf.as_ptr() as usize, virtual_address + offset, offset, buf.len(), virtual_size, result);
+        let result = self.call(SYS_READ, self.file_id, address, buf.len());
 
-            self.release(virtual_address);
+        self.release(virtual_address, size);
 
-            result
-        } else {
-            debugln!("{}:{} fault {:X} {}", file!(), line!(), buf.as_ptr() as usize, buf.len());
-            Err(Error::new(EFAULT))
-        }
+        result
     }
 
     /// Write to resource
     fn write(&mut self, buf: &[u8]) -> Result<usize> {
         let contexts = ::env().contexts.lock();
         let current = try!(contexts.current());
-        if let Ok(physical_address) = current.translate(buf.as_ptr() as usize, buf.len()) {
-            let offset = physical_address % 4096;
+        let (address, virtual_address, size) = try!(self.capture_buf(current, buf.as_ptr() as usize, buf.len(), false));
 
-            let virtual_address = try!(self.capture(physical_address - offset, buf.len() + offset, false));
+        let result = self.call(SYS_WRITE, self.file_id, address, buf.len());
 
-            let result = self.call(SYS_WRITE, self.file_id, virtual_address + offset, buf.len());
+        self.release(virtual_address, size);
 
-            //debugln!("Write {:X} mapped from {:X} to {:X} offset {} length {} size {} result {:?}", physical_address, buf.as_ptr() as usize, virtual_address + offset, offset, buf.len(), virtual_size, result);
-
-            self.release(virtual_address);
-
-            result
-        } else {
-            debugln!("{}:{} fault {:X} {}", file!(), line!(), buf.as_ptr() as usize, buf.len());
-            Err(Error::new(EFAULT))
-        }
+        result
     }
 
     /// Seek
@@ -213,20 +240,38 @@ impl Resource for SchemeResource {
 
         let contexts = ::env().contexts.lock();
         let current = try!(contexts.current());
-        if let Ok(physical_address) = current.translate(buf.as_mut_ptr() as usize, buf.len()) {
-            let offset = physical_address % 4096;
+        let (address, virtual_address, size) = try!(self.capture_buf(current, buf.as_mut_ptr() as usize, buf.len(), true));
 
-            let virtual_address = try!(self.capture(physical_address - offset, buf.len() + offset, true));
+        let result = self.call(SYS_FSTAT, self.file_id, address, buf.len());
 
-            let result = self.call(SYS_FSTAT, self.file_id, virtual_address + offset, buf.len());
+        self.release(virtual_address, size);
 
-            self.release(virtual_address);
+        result
+    }
 
-            result
-        } else {
-            debugln!("{}:{} fault {:X} {}", file!(), line!(), buf.as_ptr() as usize, buf.len());
-            Err(Error::new(EFAULT))
-        }
+    /// Read directory entries
+    fn getdents(&mut self, dirents: &mut [Dirent]) -> Result<usize> {
+        let buf = unsafe { slice::from_raw_parts_mut(dirents.as_mut_ptr() as *mut u8, dirents.len() * size_of::<Dirent>()) };
+
+        let contexts = ::env().contexts.lock();
+        let current = try!(contexts.current());
+        let (address, virtual_address, size) = try!(self.capture_buf(current, buf.as_mut_ptr() as usize, buf.len(), true));
+
+        let result = self.call(SYS_GETDENTS, self.file_id, address, dirents.len());
+
+        self.release(virtual_address, size);
+
+        result
+    }
+
+    /// Poll the resource
+    fn poll(&self, events: u16) -> Result<u16> {
+        self.call(SYS_POLL, self.file_id, events as usize, 0).map(|revents| revents as u16)
+    }
+
+    /// Get the condition that the daemon notifies when readiness may have changed
+    fn condition(&self) -> Option<&WaitCondition> {
+        Some(&self.condition)
     }
 
     /// Sync the resource
@@ -308,7 +353,12 @@ impl Resource for SchemeServerResource {
 
             while i <= buf.len() - size_of::<Packet>() {
                 let packet = unsafe { & *(buf.as_ptr().offset(i as isize) as *const Packet) };
-                self.inner.done.send(packet.id, (packet.a, packet.b, packet.c, packet.d));
+                // Requests never have the id 0, so it is used by the daemon to wake pollers
+                if packet.id == 0 {
+                    unsafe { self.inner.condition.notify() };
+                } else {
+                    self.inner.done.send(packet.id, (packet.a, packet.b, packet.c, packet.d));
+                }
                 i += size_of::<Packet>();
             }
 
@@ -353,16 +403,31 @@ impl Scheme {
         Ok((scheme, server))
     }
 
+    /// Call the scheme with the effective ids of the current context
     fn call(&self, a: usize, b: usize, c: usize, d: usize) -> Result<usize> {
-        SchemeInner::call(&self.inner, a, b, c, d)
+        let (uid, gid) = {
+            let contexts = ::env().contexts.lock();
+            let current = try!(contexts.current());
+            (current.euid, current.egid)
+        };
+        self.call_as(uid, gid, a, b, c, d)
+    }
+
+    /// Call the scheme with the given effective ids
+    fn call_as(&self, uid: u32, gid: u32, a: usize, b: usize, c: usize, d: usize) -> Result<usize> {
+        SchemeInner::call(&self.inner, uid, gid, a, b, c, d)
     }
 
     fn capture(&self, physical_address: usize, size: usize, writeable: bool) -> Result<usize> {
         SchemeInner::capture(&self.inner, physical_address, size, writeable)
     }
 
-    fn release(&self, virtual_address: usize){
-        SchemeInner::release(&self.inner, virtual_address);
+    fn capture_buf(&self, current: &Context, ptr: usize, len: usize, writeable: bool) -> Result<(usize, usize, usize)> {
+        SchemeInner::capture_buf(&self.inner, current, ptr, len, writeable)
+    }
+
+    fn release(&self, virtual_address: usize, size: usize) {
+        SchemeInner::release(&self.inner, virtual_address, size);
     }
 }
 
@@ -375,32 +440,82 @@ impl KScheme for Scheme {
         &self.name
     }
 
-    fn open(&mut self, url: Url, flags: usize) -> Result<Box<Resource>> {
+    fn open(&mut self, url: Url, flags: usize, uid: u32, gid: u32) -> Result<Box<Resource>> {
+        let condition = match self.inner.upgrade() {
+            Some(scheme) => scheme.condition.clone(),
+            None => return Err(Error::new(ENODEV)),
+        };
+
         let c_str = url.to_string() + "\0";
 
         let virtual_address = try!(self.capture(c_str.as_ptr() as usize, c_str.len(), false));
 
-        let result = self.call(SYS_OPEN, virtual_address, flags, 0);
+        let result = self.call_as(uid, gid, SYS_OPEN, virtual_address, flags, 0);
 
-        self.release(virtual_address);
+        self.release(virtual_address, c_str.len());
 
         match result {
             Ok(file_id) => Ok(box SchemeResource {
                 inner: self.inner.clone(),
                 file_id: file_id,
+                condition: condition,
+                uid: uid,
+                gid: gid,
             }),
             Err(err) => Err(err)
         }
     }
 
-    fn mkdir(&mut self, url: Url, flags: usize) -> Result<()> {
+    fn link(&mut self, old: Url, new: Url) -> Result<()> {
+        let old_c_str = old.to_string() + "\0";
+        let new_c_str = new.to_string() + "\0";
+
+        let old_address = try!(self.capture(old_c_str.as_ptr() as usize, old_c_str.len(), false));
+        let new_address = match self.capture(new_c_str.as_ptr() as usize, new_c_str.len(), false) {
+            Ok(address) => address,
+            Err(err) => {
+                self.release(old_address, old_c_str.len());
+                return Err(err);
+            }
+        };
+
+        let result = self.call(SYS_LINK, old_address, new_address, 0);
+
+        self.release(new_address, new_c_str.len());
+        self.release(old_address, old_c_str.len());
+
+        result.and(Ok(()))
+    }
+
+    fn mkdir(&mut self, url: Url, flags: usize, uid: u32, gid: u32) -> Result<()> {
         let c_str = url.to_string() + "\0";
 
         let virtual_address = try!(self.capture(c_str.as_ptr() as usize, c_str.len(), false));
 
-        let result = self.call(SYS_MKDIR, virtual_address, flags, 0);
+        let result = self.call_as(uid, gid, SYS_MKDIR, virtual_address, flags, 0);
+
+        self.release(virtual_address, c_str.len());
+
+        result.and(Ok(()))
+    }
+
+    fn rename(&mut self, old: Url, new: Url) -> Result<()> {
+        let old_c_str = old.to_string() + "\0";
+        let new_c_str = new.to_string() + "\0";
+
+        let old_address = try!(self.capture(old_c_str.as_ptr() as usize, old_c_str.len(), false));
+        let new_address = match self.capture(new_c_str.as_ptr() as usize, new_c_str.len(), false) {
+            Ok(address) => address,
+            Err(err) => {
+                self.release(old_address, old_c_str.len());
+                return Err(err);
+            }
+        };
+
+        let result = self.call(SYS_RENAME, old_address, new_address, 0);
 
-        self.release(virtual_address);
+        self.release(new_address, new_c_str.len());
+        self.release(old_address, old_c_str.len());
 
         result.and(Ok(()))
     }
@@ -412,7 +527,7 @@ impl KScheme for Scheme {
 
         let result = self.call(SYS_RMDIR, virtual_address, 0, 0);
 
-        self.release(virtual_address);
+        self.release(virtual_address, c_str.len());
 
         result.and(Ok(()))
     }
@@ -422,34 +537,34 @@ impl KScheme for Scheme {
 
         let contexts = ::env().contexts.lock();
         let current = try!(contexts.current());
-        if let Ok(physical_address) = current.translate(buf.as_mut_ptr() as usize, buf.len()) {
-            let offset = physical_address % 4096;
-
-            let virtual_address = try!(self.capture(physical_address - offset, buf.len() + offset, true));
+        let (address, virtual_address, size) = try!(self.capture_buf(current, buf.as_mut_ptr() as usize, buf.len(), true));
 
-            let c_str = url.to_string() + "\0";
+        let c_str = url.to_string() + "\0";
 
-            let c_str_address = try!(self.capture(c_str.as_ptr() as usize, c_str.len(), false));
+        let c_str_address = match self.capture(c_str.as_ptr() as usize, c_str.len(), false) {
+            Ok(c_str_address) => c_str_address,
+            Err(err) => {
+                self.release(virtual_address, size);
+                return Err(err);
+            }
+        };
 
-            let result = self.call(SYS_STAT, c_str_address, virtual_address + offset, buf.len());
+        let result = self.call(SYS_STAT, c_str_address, address, buf.len());
 
-            self.release(c_str_address);
+        self.release(c_str_address, c_str.len());
+        self.release(virtual_address, size);
 
-            result.and(Ok(()))
-        } else {
-            debugln!("{}:{} fault {:X} {}", file!(), line!(), buf.as_ptr() as usize, buf.len());
-            Err(Error::new(EFAULT))
-        }
+        result.and(Ok(()))
     }
 
-    fn unlink(&mut self, url: Url) -> Result<()> {
+    fn unlink(&mut self, url: Url, uid: u32, gid: u32) -> Result<()> {
         let c_str = url.to_string() + "\0";
 
         let virtual_address = try!(self.capture(c_str.as_ptr() as usize, c_str.len(), false));
 
-        let result = self.call(SYS_UNLINK, virtual_address, 0, 0);
+        let result = self.call_as(uid, gid, SYS_UNLINK, virtual_address, 0, 0);
 
-        self.release(virtual_address);
+        self.release(virtual_address, c_str.len());
 
         result.and(Ok(()))
     }
@@ -54,7 +54,7 @@ impl Resource for SupervisorResource {
             ctx.regs.ax |= i as usize;
         }
 
-        ctx.blocked = false;
+        ctx.unblock();
 
         Ok(cmp::min(mem::size_of::<usize>(), buf.len()))
     }
@@ -52,14 +52,14 @@ impl<'a> Url<'a> {
         self.scheme.len() + self.reference.len() + 1
     }
 
-    /// Open this URL (returns a resource)
+    /// Open this URL (returns a resource). The kernel opens it as root
     pub fn open(self) -> Result<Box<Resource>> {
-        ::env().open(self, O_RDWR)
+        ::env().open(self, O_RDWR, 0, 0)
     }
 
-    /// Create this URL (returns a resource)
+    /// Create this URL (returns a resource). The kernel creates it as root
     pub fn create(self) -> Result<Box<Resource>> {
-        ::env().open(self, O_CREAT | O_RDWR | O_TRUNC)
+        ::env().open(self, O_CREAT | O_RDWR | O_TRUNC, 0, 0)
     }
 
     /// Return the scheme of this url
@@ -4,26 +4,53 @@ use alloc::boxed::Box;
 
 use collections::{String, Vec};
 
+use common::time::Duration;
+
 use core::cmp::{max, min};
 
 use system::error::Result;
+use system::syscall::{MODE_FILE, Stat};
 
 /// A vector resource
 pub struct VecResource {
     path: String,
     data: Vec<u8>,
     seek: usize,
+    /// The inode, mode, owner and times returned by `stat`
+    stat: Stat,
 }
 
 impl VecResource {
+    /// Create a resource, which is a file readable by everyone, created now
     pub fn new(path: String, data: Vec<u8>) -> Self {
+        let time = Duration::realtime().timespec();
+        VecResource::with_stat(path, data, Stat {
+            st_mode: MODE_FILE | 0o644,
+            st_nlink: 1,
+            st_atime: time,
+            st_mtime: time,
+            st_ctime: time,
+            .. Stat::default()
+        })
+    }
+
+    /// Create a resource with the given stat. The size and blocks are taken from the data
+    pub fn with_stat(path: String, data: Vec<u8>, stat: Stat) -> Self {
         VecResource {
             path: path,
             data: data,
             seek: 0,
+            stat: stat,
         }
     }
 
+    /// Set the modification and status change times to now
+    fn touch(&mut self) {
+        let time = Duration::realtime().timespec();
+        self.stat.st_mtime = time;
+        self.stat.st_ctime = time;
+    }
+
     pub fn data(&self) -> &Vec<u8> {
         return &self.data;
     }
@@ -35,6 +62,7 @@ impl Resource for VecResource {
             path: self.path.clone(),
             data: self.data.clone(),
             seek: self.seek,
+            stat: self.stat,
         })
     }
 
@@ -75,6 +103,7 @@ impl Resource for VecResource {
             self.seek += 1;
             i += 1;
         }
+        self.touch();
         return Ok(i);
     }
 
@@ -92,6 +121,14 @@ impl Resource for VecResource {
         return Ok(self.seek);
     }
 
+    fn stat(&self, stat: &mut Stat) -> Result<usize> {
+        *stat = self.stat;
+        stat.st_size = self.data.len() as u64;
+        stat.st_blksize = 512;
+        stat.st_blocks = (self.data.len() as u64 + 511) / 512;
+        Ok(0)
+    }
+
     fn sync(&mut self) -> Result<()> {
         Ok(())
     }
@@ -102,6 +139,7 @@ impl Resource for VecResource {
         }
         self.data.truncate(len);
         self.seek = min(self.seek, self.data.len());
+        self.touch();
         Ok(())
     }
 }
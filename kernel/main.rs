@@ -35,9 +35,10 @@ extern crate system;
 
 use acpi::Acpi;
 
+use alloc::arc::Arc;
 use alloc::boxed::Box;
 
-use arch::context::{context_switch, Context};
+use arch::context::{context_preempt, context_switch, Context};
 use arch::memory;
 use arch::paging::Page;
 use arch::regs::Regs;
@@ -51,6 +52,8 @@ use core::slice::SliceExt;
 
 use common::time::Duration;
 
+use disk::cache::BlockCache;
+
 use drivers::pci;
 use drivers::io::{Io, Pio};
 use drivers::ps2::*;
@@ -61,7 +64,10 @@ use env::Environment;
 
 use graphics::display;
 
-use network::schemes::{ArpScheme, EthernetScheme, IcmpScheme, IpScheme, TcpScheme, UdpScheme};
+use network::dhcp;
+use network::loopback::Loopback;
+use network::schemes::{ArpCache, ArpScheme, DnsScheme, EthernetScheme, IcmpScheme, Icmpv6Scheme, IpScheme, Ip6Scheme,
+                       NdpCache, NdpScheme, NetcfgScheme, TcpScheme, UdpScheme};
 
 use schemes::context::ContextScheme;
 use schemes::debug::DebugScheme;
@@ -74,8 +80,10 @@ use schemes::interrupt::InterruptScheme;
 use schemes::memory::MemoryScheme;
 use schemes::test::TestScheme;
 
+use sync::Intex;
+
 use syscall::execute::execute;
-use syscall::{do_sys_chdir, do_sys_exit, do_sys_open, syscall_handle};
+use syscall::{do_sys_chdir, do_sys_exit, do_sys_open, signal_handle, syscall_handle};
 
 pub use externs::*;
 
@@ -217,7 +225,7 @@ fn idle_loop() {
         let mut halt = true;
 
         for context in env().contexts.lock().iter().skip(1) {
-            if !context.blocked {
+            if !context.blocked && !context.stopped {
                 halt = false;
                 break;
             }
@@ -374,26 +382,50 @@ unsafe fn init(tss_data: usize) {
             env.schemes.lock().push(box DisplayScheme);
             env.schemes.lock().push(box EnvScheme);
             env.schemes.lock().push(box InterruptScheme);
-            env.schemes.lock().push(box MemoryScheme);
             env.schemes.lock().push(box TestScheme);
 
             //TODO: Do not do this! Find a better way
             let mut disks = Vec::new();
             disks.append(&mut env.disks.lock());
-            env.schemes.lock().push(DiskScheme::new(disks));
+            let block_cache = Arc::new(Intex::new(BlockCache::new(disks)));
+            env.schemes.lock().push(DiskScheme::new(&block_cache));
+            env.schemes.lock().push(box MemoryScheme {
+                block_cache: block_cache.clone()
+            });
+            *env.block_cache.lock() = Some(block_cache.clone());
+
+            let arp_cache = Arc::new(Intex::new(ArpCache::new()));
+            let neighbor_cache = Arc::new(Intex::new(NdpCache::new()));
 
+            env.schemes.lock().push(Loopback::new());
             env.schemes.lock().push(box EthernetScheme);
-            //env.schemes.lock().push(box ArpScheme);
+            env.schemes.lock().push(box ArpScheme {
+                cache: arp_cache.clone()
+            });
+            env.schemes.lock().push(box DnsScheme);
             //env.schemes.lock().push(box IcmpScheme);
             env.schemes.lock().push(box IpScheme {
-                arp: Vec::new()
+                arp: arp_cache.clone(),
+                routes: Arc::new(Intex::new(Vec::new())),
+            });
+            env.schemes.lock().push(box Ip6Scheme {
+                cache: neighbor_cache.clone(),
+            });
+            env.schemes.lock().push(box NdpScheme {
+                cache: neighbor_cache.clone(),
+            });
+            env.schemes.lock().push(box NetcfgScheme);
+            env.schemes.lock().push(TcpScheme::new());
+            env.schemes.lock().push(UdpScheme::new());
+
+            Context::spawn("kcache".to_string(),
+            box move || {
+                BlockCache::flush_loop(block_cache);
             });
-            env.schemes.lock().push(box TcpScheme);
-            env.schemes.lock().push(box UdpScheme);
 
             Context::spawn("karp".to_string(),
             box move || {
-                ArpScheme::reply_loop();
+                ArpScheme::reply_loop(arp_cache);
             });
 
             Context::spawn("kicmp".to_string(),
@@ -401,6 +433,16 @@ unsafe fn init(tss_data: usize) {
                 IcmpScheme::reply_loop();
             });
 
+            Context::spawn("kicmp6".to_string(),
+            box move || {
+                Icmpv6Scheme::reply_loop(neighbor_cache);
+            });
+
+            Context::spawn("kdhcp".to_string(),
+            box move || {
+                dhcp::client_loop();
+            });
+
             env.contexts.lock().enabled = true;
 
             Context::spawn("kinit".to_string(),
@@ -542,10 +584,10 @@ pub extern "cdecl" fn kernel(interrupt: usize, mut regs: &mut Regs) {
             }
 
             if let Ok(mut current) = env().contexts.lock().current_mut() {
-                current.time += 1;
+                current.tick(regs.cs & 3 == 3);
             }
 
-            unsafe { context_switch(); }
+            unsafe { context_preempt(); }
         }
         i @ 0x21 ... 0x2F => {
             env().on_irq(i as u8 - 0x20);
@@ -571,7 +613,33 @@ pub extern "cdecl" fn kernel(interrupt: usize, mut regs: &mut Regs) {
         0xB => exception_error!("Segment not present exception"),
         0xC => exception_error!("Stack-segment fault"),
         0xD => exception_error!("General protection fault"),
-        0xE => exception_error!("Page fault"),
+        0xE => {
+            let address: usize;
+            unsafe { asm!("mov $0, cr2" : "=r"(address) : : : "intel", "volatile"); }
+
+            // The error code is where the instruction pointer would be. A page that is not
+            // present may be reserved memory, which is allocated when it is first used. A write to
+            // a present page may be to memory shared copy-on-write, which is copied
+            let error = regs.ip;
+            {
+                let contexts = ::env().contexts.lock();
+                if let Ok(current) = contexts.current() {
+                    let handled = if error & 1 == 0 {
+                        current.fault(address)
+                    } else if error & 2 == 2 {
+                        current.unshare(address, 1)
+                    } else {
+                        Ok(false)
+                    };
+
+                    if let Ok(true) = handled {
+                        return;
+                    }
+                }
+            }
+
+            exception_error!("Page fault")
+        },
         0x10 => exception!("x87 floating-point exception"),
         0x11 => exception_error!("Alignment check exception"),
         0x12 => exception!("Machine check exception"),
@@ -588,4 +656,9 @@ pub extern "cdecl" fn kernel(interrupt: usize, mut regs: &mut Regs) {
 
         Pio::<u8>::new(0x20).write(0x20);
     }
+
+    // Act on pending signals before returning to userspace
+    if regs.cs & 3 == 3 {
+        signal_handle(regs);
+    }
 }
@@ -71,6 +71,11 @@ impl MacAddr {
         true
     }
 
+    /// Check if this is a multicast address, including the broadcast address
+    pub fn is_multicast(&self) -> bool {
+        self.bytes[0] & 1 == 1
+    }
+
     pub fn from_str(string: &str) -> Self {
         let mut addr = MacAddr { bytes: [0, 0, 0, 0, 0, 0] };
 
@@ -92,6 +97,29 @@ impl MacAddr {
         addr
     }
 
+    /// Parse an address in the format of `to_string`, failing if it is malformed
+    pub fn parse(string: &str) -> Option<Self> {
+        let mut addr = MacAddr { bytes: [0; 6] };
+
+        let mut i = 0;
+        for part in string.split('.') {
+            if i >= 6 {
+                return None;
+            }
+            match u8::from_str_radix(part, 16) {
+                Ok(octet) => addr.bytes[i] = octet,
+                Err(_) => return None,
+            }
+            i += 1;
+        }
+
+        if i == 6 {
+            Some(addr)
+        } else {
+            None
+        }
+    }
+
     pub fn to_string(&self) -> String {
         let mut string = String::new();
         for i in 0..6 {
@@ -106,8 +134,6 @@ impl MacAddr {
 
 pub static BROADCAST_MAC_ADDR: MacAddr = MacAddr { bytes: [0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF] };
 
-pub static mut MAC_ADDR: MacAddr = MacAddr { bytes: [0x00, 0x00, 0x00, 0x00, 0x00, 0x00] };
-
 #[derive(Copy, Clone)]
 pub struct Ipv4Addr {
     pub bytes: [u8; 4],
@@ -142,6 +168,29 @@ impl Ipv4Addr {
         addr
     }
 
+    /// Parse an address in dotted decimal notation, failing if it is malformed
+    pub fn parse(string: &str) -> Option<Self> {
+        let mut addr = Ipv4Addr { bytes: [0; 4] };
+
+        let mut i = 0;
+        for part in string.split('.') {
+            if i >= 4 {
+                return None;
+            }
+            match part.parse::<u8>() {
+                Ok(octet) => addr.bytes[i] = octet,
+                Err(_) => return None,
+            }
+            i += 1;
+        }
+
+        if i == 4 {
+            Some(addr)
+        } else {
+            None
+        }
+    }
+
     pub fn to_string(&self) -> String {
         let mut string = String::new();
 
@@ -162,23 +211,218 @@ pub struct Ipv6Addr {
 }
 
 impl Ipv6Addr {
+    pub fn equals(&self, other: Self) -> bool {
+        self.bytes == other.bytes
+    }
+
+    /// Parse an address in hexadecimal groups, where `::` stands for a run of zero groups, failing
+    /// if it is malformed
+    pub fn parse(string: &str) -> Option<Self> {
+        fn groups(string: &str, groups: &mut Vec<u16>) -> bool {
+            if string.is_empty() {
+                return true;
+            }
+            for group in string.split(':') {
+                if group.is_empty() || group.len() > 4 {
+                    return false;
+                }
+                match u16::from_str_radix(group, 16) {
+                    Ok(group) => groups.push(group),
+                    Err(_) => return false,
+                }
+            }
+            true
+        }
+
+        let mut head = Vec::new();
+        let mut tail = Vec::new();
+        match string.find("::") {
+            Some(i) => {
+                if !groups(&string[.. i], &mut head) || !groups(&string[i + 2 ..], &mut tail) ||
+                   head.len() + tail.len() > 7 {
+                    return None;
+                }
+            },
+            None => {
+                if !groups(string, &mut head) || head.len() != 8 {
+                    return None;
+                }
+            }
+        }
+
+        let mut addr = Ipv6Addr { bytes: [0; 16] };
+        for (i, group) in head.iter().enumerate() {
+            addr.bytes[i * 2] = (*group >> 8) as u8;
+            addr.bytes[i * 2 + 1] = *group as u8;
+        }
+        for (i, group) in tail.iter().enumerate() {
+            let j = 8 - tail.len() + i;
+            addr.bytes[j * 2] = (*group >> 8) as u8;
+            addr.bytes[j * 2 + 1] = *group as u8;
+        }
+        Some(addr)
+    }
+
+    /// The address in hexadecimal groups, with the longest run of zero groups shortened to `::`
     pub fn to_string(&self) -> String {
-        let mut string = String::new();
+        let mut groups = [0u16; 8];
+        for i in 0..8 {
+            groups[i] = (self.bytes[i * 2] as u16) << 8 | self.bytes[i * 2 + 1] as u16;
+        }
 
-        for i in 0..16 {
-            if i > 0 && i % 2 == 0 {
-                string = string + ".";
+        let mut zeros_start = 8;
+        let mut zeros_len = 1;
+        let mut i = 0;
+        while i < 8 {
+            if groups[i] == 0 {
+                let start = i;
+                while i < 8 && groups[i] == 0 {
+                    i += 1;
+                }
+                if i - start > zeros_len {
+                    zeros_start = start;
+                    zeros_len = i - start;
+                }
+            } else {
+                i += 1;
+            }
+        }
+
+        let mut string = String::new();
+        let mut i = 0;
+        while i < 8 {
+            if i == zeros_start {
+                string = string + "::";
+                i += zeros_len;
+            } else {
+                if i > 0 && i != zeros_start + zeros_len {
+                    string = string + ":";
+                }
+                string = string + &format!("{:x}", groups[i]);
+                i += 1;
             }
-            string = string + &format!("{}", self.bytes[i]);
         }
 
         string
     }
+
+    pub fn is_unspecified(&self) -> bool {
+        self.bytes == [0; 16]
+    }
+
+    pub fn is_loopback(&self) -> bool {
+        self.equals(LOOPBACK_IPV6_ADDR)
+    }
+
+    pub fn is_multicast(&self) -> bool {
+        self.bytes[0] == 0xFF
+    }
+
+    pub fn is_link_local(&self) -> bool {
+        self.bytes[0] == 0xFE && self.bytes[1] & 0xC0 == 0x80
+    }
+
+    /// The link-local address of an interface, with the modified EUI-64 identifier of its hardware
+    /// address
+    pub fn link_local(mac: MacAddr) -> Self {
+        Ipv6Addr {
+            bytes: [0xFE, 0x80, 0, 0, 0, 0, 0, 0,
+                    mac.bytes[0] ^ 0x02, mac.bytes[1], mac.bytes[2], 0xFF,
+                    0xFE, mac.bytes[3], mac.bytes[4], mac.bytes[5]]
+        }
+    }
+
+    /// The solicited-node multicast address, which neighbor solicitations for this address are
+    /// sent to
+    pub fn solicited_node(&self) -> Self {
+        Ipv6Addr {
+            bytes: [0xFF, 0x02, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0x01, 0xFF,
+                    self.bytes[13], self.bytes[14], self.bytes[15]]
+        }
+    }
+
+    /// The hardware address that frames to this multicast address are sent to
+    pub fn multicast_mac(&self) -> MacAddr {
+        MacAddr { bytes: [0x33, 0x33, self.bytes[12], self.bytes[13], self.bytes[14], self.bytes[15]] }
+    }
+}
+
+pub static LOOPBACK_IPV6_ADDR: Ipv6Addr = Ipv6Addr { bytes: [0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 1] };
+pub static ALL_NODES_IPV6_ADDR: Ipv6Addr = Ipv6Addr {
+    bytes: [0xFF, 0x02, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 1]
+};
+
+/// An IPv4 or IPv6 address, for the protocols that run over both
+#[derive(Copy, Clone)]
+pub enum IpAddr {
+    V4(Ipv4Addr),
+    V6(Ipv6Addr),
+}
+
+impl IpAddr {
+    pub fn equals(&self, other: Self) -> bool {
+        match (*self, other) {
+            (IpAddr::V4(a), IpAddr::V4(b)) => a.equals(b),
+            (IpAddr::V6(a), IpAddr::V6(b)) => a.equals(b),
+            _ => false,
+        }
+    }
+
+    /// Parse an IPv4 address, or an IPv6 address which may be in brackets
+    pub fn parse(string: &str) -> Option<Self> {
+        if string.starts_with('[') && string.ends_with(']') {
+            Ipv6Addr::parse(&string[1 .. string.len() - 1]).map(|addr| IpAddr::V6(addr))
+        } else if string.contains(':') {
+            Ipv6Addr::parse(string).map(|addr| IpAddr::V6(addr))
+        } else {
+            Ipv4Addr::parse(string).map(|addr| IpAddr::V4(addr))
+        }
+    }
+
+    /// The bytes of the address, which transport checksums include
+    pub fn as_bytes(&self) -> &[u8] {
+        match *self {
+            IpAddr::V4(ref addr) => &addr.bytes,
+            IpAddr::V6(ref addr) => &addr.bytes,
+        }
+    }
+
+    /// The URL of the IP resource for a protocol to this address
+    pub fn ip_url(&self, proto: u8) -> String {
+        match *self {
+            IpAddr::V4(addr) => format!("ip:{}/{:X}", addr.to_string(), proto),
+            IpAddr::V6(addr) => format!("ip6:{}/{:X}", addr.to_string(), proto),
+        }
+    }
+
+    pub fn to_string(&self) -> String {
+        match *self {
+            IpAddr::V4(addr) => addr.to_string(),
+            IpAddr::V6(addr) => addr.to_string(),
+        }
+    }
+
+    /// The address as the host of a URL with a port, where IPv6 addresses are in brackets
+    pub fn to_host_string(&self) -> String {
+        match *self {
+            IpAddr::V4(addr) => addr.to_string(),
+            IpAddr::V6(addr) => format!("[{}]", addr.to_string()),
+        }
+    }
 }
 
-pub static BROADCAST_IP_ADDR: Ipv4Addr = Ipv4Addr { bytes: [10, 85, 85, 255] };
+/// Split `<host>:<port>`, where the host may be an IPv6 address in brackets
+pub fn split_host_port(string: &str) -> (&str, &str) {
+    if string.starts_with('[') {
+        if let Some(end) = string.find(']') {
+            let port = &string[end + 1 ..];
+            return (&string[.. end + 1], port.trim_left_matches(':'));
+        }
+    }
 
-pub static IP_ADDR: Ipv4Addr = Ipv4Addr { bytes: [10, 85, 85, 2] };
+    let mut parts = string.splitn(2, ':');
+    (parts.next().unwrap_or(""), parts.next().unwrap_or(""))
+}
 
 #[derive(Copy, Clone)]
 pub struct Checksum {
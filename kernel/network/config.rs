@@ -0,0 +1,105 @@
+use alloc::arc::Arc;
+
+use collections::vec::Vec;
+
+use sync::Intex;
+
+use network::common::{Ipv4Addr, Ipv6Addr, MacAddr, ALL_NODES_IPV6_ADDR};
+
+/// The number of DNS servers an interface can have
+pub const DNS_SERVERS: usize = 3;
+
+/// The configuration of a network interface
+#[derive(Copy, Clone)]
+pub struct NetworkConfig {
+    /// The hardware address
+    pub mac: MacAddr,
+    /// The IP address
+    pub ip: Ipv4Addr,
+    /// The subnet mask
+    pub netmask: Ipv4Addr,
+    /// The default gateway
+    pub gateway: Ipv4Addr,
+    /// The DNS servers, in order of preference. Unused entries are unspecified
+    pub dns: [Ipv4Addr; DNS_SERVERS],
+}
+
+impl NetworkConfig {
+    /// Create the default configuration for an interface with the given hardware address
+    pub fn new(mac: MacAddr) -> Self {
+        NetworkConfig {
+            mac: mac,
+            ip: Ipv4Addr { bytes: [10, 85, 85, 2] },
+            netmask: Ipv4Addr { bytes: [255, 255, 255, 0] },
+            gateway: Ipv4Addr { bytes: [10, 85, 85, 1] },
+            dns: [Ipv4Addr { bytes: [10, 85, 85, 1] }, Ipv4Addr { bytes: [0; 4] }, Ipv4Addr { bytes: [0; 4] }],
+        }
+    }
+
+    /// The DNS servers that are set, in order of preference
+    pub fn dns_servers(&self) -> Vec<Ipv4Addr> {
+        self.dns.iter().filter(|dns| !dns.equals(Ipv4Addr { bytes: [0; 4] })).map(|dns| *dns).collect()
+    }
+
+    /// Replace the DNS servers. Only the first `DNS_SERVERS` are kept
+    pub fn set_dns_servers(&mut self, servers: &[Ipv4Addr]) {
+        for i in 0..DNS_SERVERS {
+            self.dns[i] = servers.get(i).map(|dns| *dns).unwrap_or(Ipv4Addr { bytes: [0; 4] });
+        }
+    }
+
+    /// The broadcast address of the subnet
+    pub fn broadcast(&self) -> Ipv4Addr {
+        let mut addr = self.ip;
+        for i in 0..4 {
+            addr.bytes[i] |= !self.netmask.bytes[i];
+        }
+        addr
+    }
+
+    /// Check if an address is on the subnet of this interface
+    pub fn on_subnet(&self, addr: Ipv4Addr) -> bool {
+        for i in 0..4 {
+            if addr.bytes[i] & self.netmask.bytes[i] != self.ip.bytes[i] & self.netmask.bytes[i] {
+                return false;
+            }
+        }
+        true
+    }
+
+    /// Check if a packet with the destination address should be received on this interface
+    pub fn accepts(&self, addr: Ipv4Addr) -> bool {
+        addr.equals(self.ip) || addr.equals(self.broadcast()) ||
+        addr.equals(Ipv4Addr { bytes: [255, 255, 255, 255] })
+    }
+
+    /// The IPv6 link-local address, which is formed from the hardware address. This is the only
+    /// IPv6 address, the prefixes in router advertisements are not used for global addresses
+    pub fn ip6(&self) -> Ipv6Addr {
+        Ipv6Addr::link_local(self.mac)
+    }
+
+    /// Check if an IPv6 packet with the destination address should be received on this interface
+    pub fn accepts6(&self, addr: Ipv6Addr) -> bool {
+        let ip6 = self.ip6();
+        addr.equals(ip6) || addr.equals(ip6.solicited_node()) || addr.equals(ALL_NODES_IPV6_ADDR)
+    }
+}
+
+/// Register the configuration of a new interface
+pub fn register(config: Arc<Intex<NetworkConfig>>) {
+    ::env().interfaces.lock().push(config);
+}
+
+/// The primary interface, which `network:` opens
+pub fn primary_interface() -> Option<Arc<Intex<NetworkConfig>>> {
+    ::env().interfaces.lock().get(0).map(|config| config.clone())
+}
+
+/// The configuration of the primary interface
+pub fn primary() -> NetworkConfig {
+    match primary_interface() {
+        Some(config) => *config.lock(),
+        None => NetworkConfig::new(MacAddr { bytes: [0; 6] }),
+    }
+}
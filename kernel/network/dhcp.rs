@@ -0,0 +1,463 @@
+use alloc::boxed::Box;
+
+use collections::vec::Vec;
+
+use core::{cmp, mem, slice};
+
+use arch::context::context_sleep;
+
+use common::random::rand;
+use common::time::Duration;
+
+use fs::{Resource, Url};
+
+use network::common::*;
+use network::config;
+use network::ipv4::{Ipv4, Ipv4Header};
+use network::schemes::udp::{Udp, UdpHeader};
+
+use system::error::EAGAIN;
+
+const DHCP_SERVER_PORT: u16 = 67;
+const DHCP_CLIENT_PORT: u16 = 68;
+
+const DHCP_MAGIC: u32 = 0x63825363;
+
+const DHCP_DISCOVER: u8 = 1;
+const DHCP_OFFER: u8 = 2;
+const DHCP_REQUEST: u8 = 3;
+const DHCP_ACK: u8 = 5;
+const DHCP_NAK: u8 = 6;
+
+const OPT_PAD: u8 = 0;
+const OPT_SUBNET_MASK: u8 = 1;
+const OPT_ROUTER: u8 = 3;
+const OPT_DNS: u8 = 6;
+const OPT_REQUESTED_IP: u8 = 50;
+const OPT_LEASE_TIME: u8 = 51;
+const OPT_MESSAGE_TYPE: u8 = 53;
+const OPT_SERVER_ID: u8 = 54;
+const OPT_PARAMETERS: u8 = 55;
+const OPT_RENEWAL_TIME: u8 = 58;
+const OPT_REBINDING_TIME: u8 = 59;
+const OPT_END: u8 = 255;
+
+/// Seconds to wait for a reply before sending a message again
+const REPLY_TIMEOUT: i64 = 4;
+/// Times to send a message before giving up
+const ATTEMPTS: usize = 3;
+/// Seconds to wait before discovering again when no server answered
+const DISCOVER_INTERVAL: i64 = 30;
+
+/// The fixed part of a DHCP message, up to the server name
+#[derive(Copy, Clone)]
+#[repr(packed)]
+pub struct DhcpHeader {
+    pub op: u8,
+    pub htype: u8,
+    pub hlen: u8,
+    pub hops: u8,
+    pub xid: n32,
+    pub secs: n16,
+    pub flags: n16,
+    pub ciaddr: Ipv4Addr,
+    pub yiaddr: Ipv4Addr,
+    pub siaddr: Ipv4Addr,
+    pub giaddr: Ipv4Addr,
+    pub chaddr: [u8; 16],
+}
+
+/// The length of the server name and boot file fields, which are not used
+const DHCP_LEGACY_LEN: usize = 64 + 128;
+
+/// A DHCP message
+pub struct Dhcp {
+    pub header: DhcpHeader,
+    pub options: Vec<u8>,
+}
+
+impl Dhcp {
+    /// Create a client message of the given type, without an end option
+    fn new(message_type: u8, xid: u32, mac: MacAddr, ciaddr: Ipv4Addr) -> Self {
+        let mut chaddr = [0; 16];
+        for i in 0..6 {
+            chaddr[i] = mac.bytes[i];
+        }
+
+        Dhcp {
+            header: DhcpHeader {
+                op: 1,
+                htype: 1,
+                hlen: 6,
+                hops: 0,
+                xid: n32::new(xid),
+                secs: n16::new(0),
+                flags: n16::new(0x8000), // Ask the server to broadcast replies
+                ciaddr: ciaddr,
+                yiaddr: Ipv4Addr { bytes: [0; 4] },
+                siaddr: Ipv4Addr { bytes: [0; 4] },
+                giaddr: Ipv4Addr { bytes: [0; 4] },
+                chaddr: chaddr,
+            },
+            options: vec![OPT_MESSAGE_TYPE, 1, message_type],
+        }
+    }
+
+    /// Append an option
+    fn push_option(&mut self, code: u8, data: &[u8]) {
+        self.options.push(code);
+        self.options.push(data.len() as u8);
+        self.options.extend_from_slice(data);
+    }
+
+    /// Find the data of an option
+    fn option(&self, code: u8) -> Option<&[u8]> {
+        let mut i = 0;
+        while i < self.options.len() {
+            match self.options[i] {
+                OPT_PAD => i += 1,
+                OPT_END => break,
+                option => {
+                    if i + 1 >= self.options.len() {
+                        break;
+                    }
+                    let start = i + 2;
+                    let end = cmp::min(start + self.options[i + 1] as usize, self.options.len());
+                    if option == code {
+                        return Some(&self.options[start .. end]);
+                    }
+                    i = end;
+                }
+            }
+        }
+        None
+    }
+
+    fn option_addr(&self, code: u8) -> Option<Ipv4Addr> {
+        match self.option(code) {
+            Some(data) if data.len() >= 4 => Some(Ipv4Addr { bytes: [data[0], data[1], data[2], data[3]] }),
+            _ => None,
+        }
+    }
+
+    /// The addresses of an option that has a list of them
+    fn option_addrs(&self, code: u8) -> Vec<Ipv4Addr> {
+        match self.option(code) {
+            Some(data) => data.chunks(4).filter(|chunk| chunk.len() == 4)
+                              .map(|chunk| Ipv4Addr { bytes: [chunk[0], chunk[1], chunk[2], chunk[3]] })
+                              .collect(),
+            None => Vec::new(),
+        }
+    }
+
+    fn option_secs(&self, code: u8) -> Option<i64> {
+        match self.option(code) {
+            Some(data) if data.len() >= 4 => Some(n32 { bytes: [data[0], data[1], data[2], data[3]] }.get() as i64),
+            _ => None,
+        }
+    }
+
+    fn message_type(&self) -> u8 {
+        match self.option(OPT_MESSAGE_TYPE) {
+            Some(data) if !data.is_empty() => data[0],
+            _ => 0,
+        }
+    }
+}
+
+impl FromBytes for Dhcp {
+    fn from_bytes(bytes: Vec<u8>) -> Option<Self> {
+        let options_start = mem::size_of::<DhcpHeader>() + DHCP_LEGACY_LEN + 4;
+        if bytes.len() >= options_start {
+            let magic_start = options_start - 4;
+            let magic = n32 {
+                bytes: [bytes[magic_start], bytes[magic_start + 1], bytes[magic_start + 2], bytes[magic_start + 3]],
+            };
+            if magic.get() == DHCP_MAGIC {
+                unsafe {
+                    return Some(Dhcp {
+                        header: *(bytes.as_ptr() as *const DhcpHeader),
+                        options: bytes[options_start ..].to_vec(),
+                    });
+                }
+            }
+        }
+        None
+    }
+}
+
+impl ToBytes for Dhcp {
+    fn to_bytes(&self) -> Vec<u8> {
+        unsafe {
+            let header_ptr: *const DhcpHeader = &self.header;
+            let mut ret = Vec::from(slice::from_raw_parts(header_ptr as *const u8,
+                                                          mem::size_of::<DhcpHeader>()));
+            ret.extend_from_slice(&[0; DHCP_LEGACY_LEN]);
+            ret.extend_from_slice(&n32::new(DHCP_MAGIC).bytes);
+            ret.extend_from_slice(&self.options);
+            ret.push(OPT_END);
+            ret
+        }
+    }
+}
+
+/// An address leased from a DHCP server. Times are monotonic
+struct Lease {
+    addr: Ipv4Addr,
+    netmask: Option<Ipv4Addr>,
+    router: Option<Ipv4Addr>,
+    dns: Vec<Ipv4Addr>,
+    renew: Duration,
+    rebind: Duration,
+    expire: Duration,
+}
+
+impl Lease {
+    fn from_ack(ack: &Dhcp) -> Self {
+        let now = Duration::monotonic();
+        let lease = ack.option_secs(OPT_LEASE_TIME).unwrap_or(3600);
+        let renew = ack.option_secs(OPT_RENEWAL_TIME).unwrap_or(lease / 2);
+        let rebind = ack.option_secs(OPT_REBINDING_TIME).unwrap_or(lease * 7 / 8);
+
+        Lease {
+            addr: ack.header.yiaddr,
+            netmask: ack.option_addr(OPT_SUBNET_MASK),
+            router: ack.option_addr(OPT_ROUTER),
+            dns: ack.option_addrs(OPT_DNS),
+            renew: now + Duration::new(renew, 0),
+            rebind: now + Duration::new(rebind, 0),
+            expire: now + Duration::new(lease, 0),
+        }
+    }
+
+    /// Configure the primary interface with the leased address
+    fn apply(&self) {
+        if let Some(interface) = config::primary_interface() {
+            let mut config = interface.lock();
+            config.ip = self.addr;
+            if let Some(netmask) = self.netmask {
+                config.netmask = netmask;
+            }
+            if let Some(router) = self.router {
+                config.gateway = router;
+            }
+            if !self.dns.is_empty() {
+                config.set_dns_servers(&self.dns);
+            }
+        }
+        debugln!("DHCP: Leased {}", self.addr.to_string());
+    }
+
+    /// Remove the leased address, gateway and DNS servers from the primary interface, once the
+    /// lease is lost
+    fn clear(&self) {
+        if let Some(interface) = config::primary_interface() {
+            let mut config = interface.lock();
+            if config.ip.equals(self.addr) {
+                config.ip = Ipv4Addr { bytes: [0; 4] };
+                config.gateway = Ipv4Addr { bytes: [0; 4] };
+                config.set_dns_servers(&[]);
+            }
+        }
+        debugln!("DHCP: Lost {}", self.addr.to_string());
+    }
+}
+
+/// Send a DHCP message to the broadcast address
+fn send(link: &mut Box<Resource>, src: Ipv4Addr, dhcp: &Dhcp) {
+    let dst = Ipv4Addr { bytes: [255, 255, 255, 255] };
+
+    let mut udp = Udp {
+        header: UdpHeader {
+            src: n16::new(DHCP_CLIENT_PORT),
+            dst: n16::new(DHCP_SERVER_PORT),
+            len: n16::new(0),
+            checksum: Checksum { data: 0 },
+        },
+        data: dhcp.to_bytes(),
+    };
+    udp.header.len.set((mem::size_of::<UdpHeader>() + udp.data.len()) as u16);
+
+    unsafe {
+        let proto = n16::new(0x11);
+        let datagram_len = udp.header.len;
+        udp.header.checksum.data =
+            Checksum::compile(Checksum::sum((&src as *const Ipv4Addr) as usize, mem::size_of::<Ipv4Addr>()) +
+                              Checksum::sum((&dst as *const Ipv4Addr) as usize, mem::size_of::<Ipv4Addr>()) +
+                              Checksum::sum((&proto as *const n16) as usize, mem::size_of::<n16>()) +
+                              Checksum::sum((&datagram_len as *const n16) as usize, mem::size_of::<n16>()) +
+                              Checksum::sum((&udp.header as *const UdpHeader) as usize, mem::size_of::<UdpHeader>()) +
+                              Checksum::sum(udp.data.as_ptr() as usize, udp.data.len()));
+    }
+
+    let ip_data = udp.to_bytes();
+    let mut ip = Ipv4 {
+        header: Ipv4Header {
+            ver_hlen: 0x40 | (mem::size_of::<Ipv4Header>() / 4 & 0xF) as u8, // No Options
+            services: 0,
+            len: n16::new((mem::size_of::<Ipv4Header>() + ip_data.len()) as u16), // No Options
+            id: n16::new(rand() as u16),
+            flags_fragment: n16::new(0),
+            ttl: 64,
+            proto: 0x11,
+            checksum: Checksum { data: 0 },
+            src: src,
+            dst: dst,
+        },
+        options: Vec::new(),
+        data: ip_data,
+    };
+
+    unsafe {
+        let header_ptr: *const Ipv4Header = &ip.header;
+        ip.header.checksum.data = Checksum::compile(Checksum::sum(header_ptr as usize, mem::size_of::<Ipv4Header>()));
+    }
+
+    if let Err(err) = link.write(&ip.to_bytes()) {
+        debugln!("DHCP: Write Failed: {}", err);
+    }
+}
+
+/// Wait for a reply to the transaction with one of the given types, until the deadline
+fn receive(link: &mut Box<Resource>, xid: u32, types: &[u8], deadline: Duration) -> Option<Dhcp> {
+    loop {
+        let mut bytes = [0; 8192];
+        match link.read_nonblock(&mut bytes) {
+            Ok(count) => {
+                if let Some(packet) = Ipv4::from_bytes(bytes[.. count].to_vec()) {
+                    if packet.header.proto != 0x11 {
+                        continue;
+                    }
+                    if let Some(datagram) = Udp::from_bytes(packet.data) {
+                        if datagram.header.dst.get() != DHCP_CLIENT_PORT {
+                            continue;
+                        }
+                        if let Some(dhcp) = Dhcp::from_bytes(datagram.data) {
+                            if dhcp.header.op == 2 && dhcp.header.xid.get() == xid &&
+                               types.contains(&dhcp.message_type()) {
+                                return Some(dhcp);
+                            }
+                        }
+                    }
+                }
+            },
+            Err(ref err) if err.errno == EAGAIN => {
+                let now = Duration::monotonic();
+                if now >= deadline {
+                    return None;
+                }
+                unsafe { context_sleep(cmp::min(now + Duration::new(0, 100 * 1000000), deadline)) };
+            },
+            Err(_) => return None,
+        }
+    }
+}
+
+/// Send a message and wait for a reply, retrying a few times
+fn transact(src: Ipv4Addr, dhcp: &Dhcp, types: &[u8]) -> Option<Dhcp> {
+    let url = format!("ethernet:{}/800", BROADCAST_MAC_ADDR.to_string());
+    match Url::from_str(&url).unwrap().open() {
+        Ok(mut link) => {
+            for _ in 0..ATTEMPTS {
+                send(&mut link, src, dhcp);
+                let deadline = Duration::monotonic() + Duration::new(REPLY_TIMEOUT, 0);
+                if let Some(reply) = receive(&mut link, dhcp.header.xid.get(), types, deadline) {
+                    return Some(reply);
+                }
+            }
+        },
+        Err(err) => debugln!("DHCP: Failed to open {}: {}", url, err),
+    }
+    None
+}
+
+/// The options requested from the server
+fn push_parameters(dhcp: &mut Dhcp) {
+    dhcp.push_option(OPT_PARAMETERS, &[OPT_SUBNET_MASK, OPT_ROUTER, OPT_DNS, OPT_LEASE_TIME]);
+}
+
+/// Discover a server and request an address from it
+fn discover() -> Option<Lease> {
+    let mac = config::primary().mac;
+    let unspecified = Ipv4Addr { bytes: [0; 4] };
+    let xid = rand() as u32;
+
+    let mut discover = Dhcp::new(DHCP_DISCOVER, xid, mac, unspecified);
+    push_parameters(&mut discover);
+
+    let offer = match transact(unspecified, &discover, &[DHCP_OFFER]) {
+        Some(offer) => offer,
+        None => return None,
+    };
+
+    let mut request = Dhcp::new(DHCP_REQUEST, xid, mac, unspecified);
+    request.push_option(OPT_REQUESTED_IP, &offer.header.yiaddr.bytes);
+    if let Some(server) = offer.option(OPT_SERVER_ID) {
+        request.push_option(OPT_SERVER_ID, server);
+    }
+    push_parameters(&mut request);
+
+    match transact(unspecified, &request, &[DHCP_ACK, DHCP_NAK]) {
+        Some(ref ack) if ack.message_type() == DHCP_ACK => Some(Lease::from_ack(ack)),
+        _ => None,
+    }
+}
+
+/// Extend a lease, until it expires or the server refuses
+fn renew(lease: &Lease) -> Option<Lease> {
+    let mac = config::primary().mac;
+
+    loop {
+        let now = Duration::monotonic();
+        if now >= lease.expire {
+            debugln!("DHCP: Lease of {} expired", lease.addr.to_string());
+            return None;
+        }
+
+        let mut request = Dhcp::new(DHCP_REQUEST, rand() as u32, mac, lease.addr);
+        push_parameters(&mut request);
+
+        match transact(lease.addr, &request, &[DHCP_ACK, DHCP_NAK]) {
+            Some(ref ack) if ack.message_type() == DHCP_ACK => return Some(Lease::from_ack(ack)),
+            Some(_) => return None,
+            None => {
+                // Wait half of the remaining time to the next deadline, but at least a minute
+                let next = if now < lease.rebind {
+                    lease.rebind
+                } else {
+                    lease.expire
+                };
+                let wait = cmp::max((next.secs - now.secs) / 2, 60);
+                unsafe { context_sleep(cmp::min(now + Duration::new(wait, 0), lease.expire)) };
+            }
+        }
+    }
+}
+
+/// Configure the primary interface by DHCP, renewing the lease before it expires
+pub fn client_loop() {
+    let mut lease = None;
+    loop {
+        lease = match lease {
+            Some(current) => {
+                unsafe { context_sleep(current.renew) };
+                let renewed = renew(&current);
+                if renewed.is_none() {
+                    current.clear();
+                }
+                renewed
+            },
+            None => match discover() {
+                Some(new) => Some(new),
+                None => {
+                    unsafe { context_sleep(Duration::monotonic() + Duration::new(DISCOVER_INTERVAL, 0)) };
+                    None
+                }
+            },
+        };
+
+        if let Some(ref current) = lease {
+            current.apply();
+        }
+    }
+}
@@ -0,0 +1,439 @@
+use collections::string::{String, ToString};
+use collections::vec::Vec;
+
+use core::{cmp, mem, slice, str};
+
+use arch::context::context_sleep;
+
+use common::random::rand;
+use common::time::{self, Duration};
+
+use fs::Url;
+
+use network::common::*;
+use network::config;
+
+use system::error::{Error, Result, EAGAIN, EINVAL, EIO, ENOENT, ETIMEDOUT};
+
+const DNS_PORT: u16 = 53;
+
+/// The hosts file, with an address followed by its names on each line
+const HOSTS_FILE: &'static str = "file:/etc/hosts";
+/// The nameservers, one address per line. The DNS server of the primary interface is used if
+/// the file is missing
+const NAMESERVERS_FILE: &'static str = "file:/etc/net/dns";
+
+/// Seconds to wait for a response before sending a query again
+const REPLY_TIMEOUT: i64 = 2;
+/// Times to send a query to a nameserver before trying the next one
+const ATTEMPTS: usize = 2;
+/// CNAME records to follow before giving up
+const MAX_CNAMES: usize = 8;
+
+pub const TYPE_A: u16 = 1;
+pub const TYPE_CNAME: u16 = 5;
+pub const TYPE_AAAA: u16 = 28;
+const CLASS_IN: u16 = 1;
+
+const FLAG_RESPONSE: u16 = 1 << 15;
+const FLAG_RECURSION_DESIRED: u16 = 1 << 8;
+const RCODE_MASK: u16 = 0xF;
+const RCODE_NAME_ERROR: u16 = 3;
+
+#[derive(Copy, Clone)]
+#[repr(packed)]
+pub struct DnsHeader {
+    pub id: n16,
+    pub flags: n16,
+    pub qdcount: n16,
+    pub ancount: n16,
+    pub nscount: n16,
+    pub arcount: n16,
+}
+
+/// The data of a resource record
+pub enum DnsData {
+    A(Ipv4Addr),
+    Aaaa(Ipv6Addr),
+    Cname(String),
+    Other,
+}
+
+/// A resource record in the answer of a response
+pub struct DnsRecord {
+    pub name: String,
+    pub data: DnsData,
+}
+
+/// Compare names, which are case insensitive
+fn name_eq(a: &str, b: &str) -> bool {
+    fn lower(b: u8) -> u8 {
+        if b >= b'A' && b <= b'Z' {
+            b + (b'a' - b'A')
+        } else {
+            b
+        }
+    }
+
+    let a = a.trim_right_matches('.');
+    let b = b.trim_right_matches('.');
+    a.len() == b.len() && a.bytes().zip(b.bytes()).all(|(a, b)| lower(a) == lower(b))
+}
+
+fn read_u16(bytes: &[u8], offset: usize) -> Option<u16> {
+    if offset + 2 <= bytes.len() {
+        Some((bytes[offset] as u16) << 8 | bytes[offset + 1] as u16)
+    } else {
+        None
+    }
+}
+
+/// Read a name, which may be compressed, returning it and the offset after it
+fn read_name(bytes: &[u8], offset: usize) -> Option<(String, usize)> {
+    let mut name = String::new();
+    let mut i = offset;
+    let mut end = None;
+    let mut jumps = 0;
+
+    loop {
+        let len = match bytes.get(i) {
+            Some(&len) => len as usize,
+            None => return None,
+        };
+
+        if len == 0 {
+            i += 1;
+            break;
+        }
+
+        if len & 0xC0 == 0xC0 {
+            let pointer = match read_u16(bytes, i) {
+                Some(pointer) => (pointer & 0x3FFF) as usize,
+                None => return None,
+            };
+
+            if end.is_none() {
+                end = Some(i + 2);
+            }
+
+            // Stop pointer loops
+            jumps += 1;
+            if jumps > 32 {
+                return None;
+            }
+
+            i = pointer;
+            continue;
+        }
+
+        if i + 1 + len > bytes.len() {
+            return None;
+        }
+
+        if !name.is_empty() {
+            name.push('.');
+        }
+        match str::from_utf8(&bytes[i + 1 .. i + 1 + len]) {
+            Ok(label) => name.push_str(label),
+            Err(_) => return None,
+        }
+
+        i += 1 + len;
+    }
+
+    Some((name, end.unwrap_or(i)))
+}
+
+/// Create a query for the records of a type of a name
+pub fn query(id: u16, name: &str, kind: u16) -> Result<Vec<u8>> {
+    let name = name.trim_right_matches('.');
+    if name.is_empty() || name.len() > 253 {
+        return Err(Error::new(EINVAL));
+    }
+
+    let header = DnsHeader {
+        id: n16::new(id),
+        flags: n16::new(FLAG_RECURSION_DESIRED),
+        qdcount: n16::new(1),
+        ancount: n16::new(0),
+        nscount: n16::new(0),
+        arcount: n16::new(0),
+    };
+
+    let mut bytes = unsafe {
+        Vec::from(slice::from_raw_parts(&header as *const DnsHeader as *const u8, mem::size_of::<DnsHeader>()))
+    };
+
+    for label in name.split('.') {
+        if label.is_empty() || label.len() > 63 {
+            return Err(Error::new(EINVAL));
+        }
+        bytes.push(label.len() as u8);
+        bytes.extend_from_slice(label.as_bytes());
+    }
+    bytes.push(0);
+
+    bytes.extend_from_slice(&n16::new(kind).bytes);
+    bytes.extend_from_slice(&n16::new(CLASS_IN).bytes);
+
+    Ok(bytes)
+}
+
+/// Parse the header and the answer of a response
+pub fn parse(bytes: &[u8]) -> Option<(DnsHeader, Vec<DnsRecord>)> {
+    if bytes.len() < mem::size_of::<DnsHeader>() {
+        return None;
+    }
+
+    let header = unsafe { *(bytes.as_ptr() as *const DnsHeader) };
+    let mut i = mem::size_of::<DnsHeader>();
+
+    for _ in 0..header.qdcount.get() {
+        match read_name(bytes, i) {
+            Some((_, next)) => i = next + 4,
+            None => return None,
+        }
+    }
+
+    let mut answers = Vec::new();
+    for _ in 0..header.ancount.get() {
+        let name = match read_name(bytes, i) {
+            Some((name, next)) => {
+                i = next;
+                name
+            },
+            None => return None,
+        };
+
+        if i + 10 > bytes.len() {
+            return None;
+        }
+
+        let kind = read_u16(bytes, i).unwrap_or(0);
+        let class = read_u16(bytes, i + 2).unwrap_or(0);
+        let len = read_u16(bytes, i + 8).unwrap_or(0) as usize;
+        i += 10;
+
+        if i + len > bytes.len() {
+            return None;
+        }
+
+        let data = if class == CLASS_IN && kind == TYPE_A && len == 4 {
+            DnsData::A(Ipv4Addr { bytes: [bytes[i], bytes[i + 1], bytes[i + 2], bytes[i + 3]] })
+        } else if class == CLASS_IN && kind == TYPE_AAAA && len == 16 {
+            let mut addr = Ipv6Addr { bytes: [0; 16] };
+            for (b, d) in addr.bytes.iter_mut().zip(bytes[i .. i + 16].iter()) {
+                *b = *d;
+            }
+            DnsData::Aaaa(addr)
+        } else if class == CLASS_IN && kind == TYPE_CNAME {
+            match read_name(bytes, i) {
+                Some((target, _)) => DnsData::Cname(target),
+                None => return None,
+            }
+        } else {
+            DnsData::Other
+        };
+
+        answers.push(DnsRecord {
+            name: name,
+            data: data,
+        });
+
+        i += len;
+    }
+
+    Some((header, answers))
+}
+
+/// Send a query to a nameserver and wait for its response, retrying a few times
+fn exchange(nameserver: Ipv4Addr, name: &str, kind: u16) -> Result<(DnsHeader, Vec<DnsRecord>)> {
+    let id = rand() as u16;
+    let request = try!(query(id, name, kind));
+
+    let url = format!("udp:{}:{}", nameserver.to_string(), DNS_PORT);
+    let mut socket = try!(Url::from_str(&url).unwrap().open());
+
+    for _ in 0..ATTEMPTS {
+        try!(socket.write(&request));
+
+        let deadline = Duration::monotonic() + Duration::new(REPLY_TIMEOUT, 0);
+        loop {
+            let mut bytes = [0; 4096];
+            match socket.read_nonblock(&mut bytes) {
+                Ok(count) => if let Some((header, answers)) = parse(&bytes[.. count]) {
+                    if header.id.get() == id && header.flags.get() & FLAG_RESPONSE == FLAG_RESPONSE {
+                        return Ok((header, answers));
+                    }
+                },
+                Err(ref err) if err.errno == EAGAIN => {
+                    let now = Duration::monotonic();
+                    if now >= deadline {
+                        break;
+                    }
+                    unsafe { context_sleep(cmp::min(now + Duration::new(0, 10 * time::NANOS_PER_MILLI), deadline)) };
+                },
+                Err(err) => return Err(err),
+            }
+        }
+    }
+
+    Err(Error::new(ETIMEDOUT))
+}
+
+/// Ask a nameserver for the addresses of a type, A or AAAA, of a name, following CNAME records
+fn lookup(nameserver: Ipv4Addr, name: &str, kind: u16) -> Result<Vec<IpAddr>> {
+    let mut name = name.to_string();
+
+    for _ in 0..MAX_CNAMES {
+        let (header, answers) = try!(exchange(nameserver, &name, kind));
+        match header.flags.get() & RCODE_MASK {
+            0 => (),
+            RCODE_NAME_ERROR => return Err(Error::new(ENOENT)),
+            _ => return Err(Error::new(EIO)),
+        }
+
+        // Follow the CNAME records included in the answer
+        let mut target = name.clone();
+        for _ in 0..MAX_CNAMES {
+            let mut next = None;
+            for record in answers.iter() {
+                if let DnsData::Cname(ref cname) = record.data {
+                    if name_eq(&record.name, &target) {
+                        next = Some(cname.clone());
+                    }
+                }
+            }
+
+            match next {
+                Some(next) => target = next,
+                None => break,
+            }
+        }
+
+        let mut addrs = Vec::new();
+        for record in answers.iter() {
+            if name_eq(&record.name, &target) {
+                match record.data {
+                    DnsData::A(addr) if kind == TYPE_A => addrs.push(IpAddr::V4(addr)),
+                    DnsData::Aaaa(addr) if kind == TYPE_AAAA => addrs.push(IpAddr::V6(addr)),
+                    _ => (),
+                }
+            }
+        }
+
+        if !addrs.is_empty() {
+            return Ok(addrs);
+        }
+
+        // Ask for the addresses of a CNAME that were not included
+        if name_eq(&target, &name) {
+            return Err(Error::new(ENOENT));
+        }
+        name = target;
+    }
+
+    Err(Error::new(ENOENT))
+}
+
+/// Ask a nameserver for the IPv4 and then the IPv6 addresses of a name
+fn lookup_all(nameserver: Ipv4Addr, name: &str) -> Result<Vec<IpAddr>> {
+    let mut addrs = Vec::new();
+    for &kind in [TYPE_A, TYPE_AAAA].iter() {
+        match lookup(nameserver, name, kind) {
+            Ok(found) => addrs.extend(found),
+            // The name may only have addresses of the other type
+            Err(ref err) if err.errno == ENOENT => (),
+            Err(err) => return Err(err),
+        }
+    }
+
+    if addrs.is_empty() {
+        Err(Error::new(ENOENT))
+    } else {
+        Ok(addrs)
+    }
+}
+
+/// Read a whole file. A scheme in userspace can only write to memory of the current context, so
+/// the file is read through its heap
+fn read_file(url: &str) -> Result<String> {
+    let mut resource = try!(Url::from_str(url).unwrap().open());
+
+    let data = {
+        let contexts = ::env().contexts.lock();
+        let current = try!(contexts.current());
+        try!(current.read_to_end(&mut *resource))
+    };
+
+    String::from_utf8(data).or(Err(Error::new(EINVAL)))
+}
+
+/// The addresses of a name in a hosts file
+pub fn parse_hosts(data: &str, name: &str) -> Vec<IpAddr> {
+    let mut addrs = Vec::new();
+
+    for line in data.lines() {
+        let mut fields = line.split('#').next().unwrap_or("").split_whitespace();
+        if let Some(addr) = fields.next().and_then(|field| IpAddr::parse(field)) {
+            if fields.any(|field| name_eq(field, name)) {
+                addrs.push(addr);
+            }
+        }
+    }
+
+    addrs
+}
+
+/// The nameservers in a nameservers file. Only IPv4 nameservers are used
+pub fn parse_nameservers(data: &str) -> Vec<Ipv4Addr> {
+    let mut nameservers = Vec::new();
+
+    for line in data.lines() {
+        if let Some(addr) = Ipv4Addr::parse(line.trim()) {
+            nameservers.push(addr);
+        }
+    }
+
+    nameservers
+}
+
+/// The nameservers to query, in order
+fn nameservers() -> Vec<Ipv4Addr> {
+    let mut nameservers = match read_file(NAMESERVERS_FILE) {
+        Ok(data) => parse_nameservers(&data),
+        Err(_) => Vec::new(),
+    };
+
+    if nameservers.is_empty() {
+        nameservers = config::primary().dns_servers();
+    }
+
+    nameservers
+}
+
+/// Resolve a name to its IPv4 and IPv6 addresses, using the hosts file and then the nameservers
+pub fn resolve(name: &str) -> Result<Vec<IpAddr>> {
+    if let Some(addr) = IpAddr::parse(name) {
+        return Ok(vec![addr]);
+    }
+
+    if let Ok(data) = read_file(HOSTS_FILE) {
+        let addrs = parse_hosts(&data, name);
+        if !addrs.is_empty() {
+            return Ok(addrs);
+        }
+    }
+
+    let mut result = Err(Error::new(ENOENT));
+    for nameserver in nameservers() {
+        result = lookup_all(nameserver, name);
+        match result {
+            Ok(_) => break,
+            // The name does not exist, other nameservers would agree
+            Err(ref err) if err.errno == ENOENT => break,
+            Err(_) => (),
+        }
+    }
+    result
+}
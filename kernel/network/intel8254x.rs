@@ -1,3 +1,4 @@
+use alloc::arc::Arc;
 use alloc::boxed::Box;
 
 use arch::memory;
@@ -13,11 +14,12 @@ use common::debug;
 use drivers::pci::config::PciConfig;
 
 use network::common::*;
+use network::config::{self, NetworkConfig};
 use network::scheme::*;
 
 use fs::{KScheme, Resource, Url};
 
-use system::error::Result;
+use system::error::{Error, Result, EACCES};
 
 use sync::Intex;
 
@@ -113,6 +115,7 @@ pub struct Intel8254x {
     pub resources: Intex<Vec<*mut NetworkResource>>,
     pub inbound: VecDeque<Vec<u8>>,
     pub outbound: VecDeque<Vec<u8>>,
+    pub config: Arc<Intex<NetworkConfig>>,
 }
 
 impl KScheme for Intel8254x {
@@ -120,7 +123,11 @@ impl KScheme for Intel8254x {
         "network"
     }
 
-    fn open(&mut self, _: Url, _: usize) -> Result<Box<Resource>> {
+    fn open(&mut self, _: Url, _: usize, uid: u32, _: u32) -> Result<Box<Resource>> {
+        if uid != 0 {
+            return Err(Error::new(EACCES));
+        }
+
         Ok(NetworkResource::new(self))
     }
 
@@ -201,10 +208,13 @@ impl Intel8254x {
             resources: Intex::new(Vec::new()),
             inbound: VecDeque::new(),
             outbound: VecDeque::new(),
+            config: Arc::new(Intex::new(NetworkConfig::new(MacAddr { bytes: [0; 6] }))),
         };
 
         module.init();
 
+        config::register(module.config.clone());
+
         module
     }
 
@@ -317,7 +327,7 @@ impl Intel8254x {
         debug::d(" MAC: ");
         let mac_low = self.read(RAL0);
         let mac_high = self.read(RAH0);
-        MAC_ADDR = MacAddr {
+        let mac = MacAddr {
             bytes: [mac_low as u8,
                     (mac_low >> 8) as u8,
                     (mac_low >> 16) as u8,
@@ -325,7 +335,8 @@ impl Intel8254x {
                     mac_high as u8,
                     (mac_high >> 8) as u8],
         };
-        debug::d(&MAC_ADDR.to_string());
+        debug::d(&mac.to_string());
+        self.config.lock().mac = mac;
 
         //
         // MTA => 0;
@@ -384,7 +395,8 @@ impl Intel8254x {
 
         self.flag(RCTL, RCTL_EN, true);
         self.flag(RCTL, RCTL_UPE, true);
-        // self.flag(RCTL, RCTL_MPE, true);
+        // Neighbor discovery is multicast
+        self.flag(RCTL, RCTL_MPE, true);
         self.flag(RCTL, RCTL_LPE, true);
         self.flag(RCTL, RCTL_LBM, false);
         // RCTL.RDMTS = Minimum threshold size ???
@@ -34,11 +34,13 @@ impl FromBytes for Ipv4 {
             unsafe {
                 let header = *(bytes.as_ptr() as *const Ipv4Header);
                 let header_len = ((header.ver_hlen & 0xF) << 2) as usize;
+                // Frames may be padded past the end of the packet
+                let total_len = header.len.get() as usize;
 
                 return Some(Ipv4 {
                     header: header,
                     options: bytes.get_slice(mem::size_of::<Ipv4Header>() .. header_len).to_vec(),
-                    data: bytes.get_slice(header_len ..).to_vec(),
+                    data: bytes.get_slice(header_len .. total_len).to_vec(),
                 });
             }
         }
@@ -0,0 +1,112 @@
+use alloc::boxed::Box;
+
+use collections::vec::Vec;
+
+use fs::{KScheme, Resource, Url};
+
+use network::common::{IpAddr, Ipv4Addr, Ipv6Addr};
+use network::config;
+use network::scheme::{NetworkResource, NetworkScheme};
+
+use sync::Intex;
+
+use system::error::Result;
+
+/// Check if an address is on the loopback network, 127.0.0.0/8
+pub fn is_loopback(addr: Ipv4Addr) -> bool {
+    addr.bytes[0] == 127
+}
+
+/// Check if a packet with the destination address is for the local stack
+pub fn accepts(addr: Ipv4Addr) -> bool {
+    is_loopback(addr) || config::primary().accepts(addr)
+}
+
+/// The local address to send packets to the peer from
+pub fn host_addr(peer: Ipv4Addr) -> Ipv4Addr {
+    if is_loopback(peer) {
+        peer
+    } else {
+        config::primary().ip
+    }
+}
+
+/// Check if an IPv6 packet with the destination address is for the local stack
+pub fn accepts6(addr: Ipv6Addr) -> bool {
+    addr.is_loopback() || config::primary().accepts6(addr)
+}
+
+/// The local address to send IPv6 packets to the peer from
+pub fn host_addr6(peer: Ipv6Addr) -> Ipv6Addr {
+    if peer.is_loopback() {
+        peer
+    } else {
+        config::primary().ip6()
+    }
+}
+
+/// The local address to send packets to the peer from, for either version of IP
+pub fn host_ip(peer: IpAddr) -> IpAddr {
+    match peer {
+        IpAddr::V4(peer) => IpAddr::V4(host_addr(peer)),
+        IpAddr::V6(peer) => IpAddr::V6(host_addr6(peer)),
+    }
+}
+
+/// Receive a frame on every network resource, as if it had arrived on the interfaces
+pub fn deliver(bytes: &[u8]) {
+    let resources = ::env().network_resources.lock();
+    for resource in resources.iter() {
+        unsafe { (**resource).inbound.send(Vec::from(bytes)) };
+    }
+}
+
+/// The loopback interface, which receives every frame sent on it. `ethernet:` uses it when there
+/// is no network card
+pub struct Loopback {
+    resources: Intex<Vec<*mut NetworkResource>>,
+}
+
+impl Loopback {
+    pub fn new() -> Box<Self> {
+        box Loopback {
+            resources: Intex::new(Vec::new()),
+        }
+    }
+}
+
+impl KScheme for Loopback {
+    fn scheme(&self) -> &str {
+        "loopback"
+    }
+
+    fn open(&mut self, _: Url, _: usize, _: u32, _: u32) -> Result<Box<Resource>> {
+        Ok(NetworkResource::new(self))
+    }
+}
+
+impl NetworkScheme for Loopback {
+    fn add(&mut self, resource: *mut NetworkResource) {
+        self.resources.lock().push(resource);
+    }
+
+    fn remove(&mut self, resource: *mut NetworkResource) {
+        self.resources.lock().retain(|&ptr| ptr != resource);
+    }
+
+    fn sync(&mut self) {
+        let mut outbound = Vec::new();
+        {
+            let resources = self.resources.lock();
+            for resource in resources.iter() {
+                while let Some(bytes) = unsafe { (**resource).outbound.lock().pop_front() } {
+                    outbound.push(bytes);
+                }
+            }
+        }
+
+        for bytes in outbound.iter() {
+            deliver(bytes);
+        }
+    }
+}
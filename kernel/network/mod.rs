@@ -1,8 +1,14 @@
 pub mod common;
+pub mod config;
+pub mod dhcp;
+pub mod dns;
 pub mod ethernet;
 pub mod intel8254x;
 pub mod ipv4;
 pub mod ipv6;
+pub mod loopback;
+pub mod neighbor;
+pub mod route;
 pub mod rtl8139;
 pub mod scheme;
 pub mod schemes;
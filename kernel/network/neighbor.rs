@@ -0,0 +1,98 @@
+use collections::string::String;
+use collections::vec::Vec;
+
+use common::time::Duration;
+
+use network::common::MacAddr;
+
+/// Entries in a cache before the oldest are replaced
+const NEIGHBOR_CACHE_SIZE: usize = 256;
+
+/// A protocol address that hardware addresses are resolved for, by ARP or NDP
+pub trait NeighborAddr: Copy {
+    fn equals(&self, other: Self) -> bool;
+
+    fn to_string(&self) -> String;
+
+    /// Seconds an entry stays in the cache
+    fn ttl() -> i64;
+}
+
+/// A neighbor entry (MAC + protocol address)
+#[derive(Copy, Clone)]
+pub struct NeighborEntry<A: NeighborAddr> {
+    pub ip: A,
+    pub mac: MacAddr,
+    /// The monotonic time the entry expires
+    pub expire: Duration,
+}
+
+/// A cache of resolved hardware addresses, shared by an IP scheme and a reply loop
+pub struct NeighborCache<A: NeighborAddr> {
+    entries: Vec<NeighborEntry<A>>,
+}
+
+impl<A: NeighborAddr> NeighborCache<A> {
+    pub fn new() -> Self {
+        NeighborCache { entries: Vec::new() }
+    }
+
+    /// Remove expired entries
+    fn expire(&mut self) {
+        let now = Duration::monotonic();
+        self.entries.retain(|entry| entry.expire > now);
+    }
+
+    /// The entries that have not expired
+    pub fn entries(&mut self) -> &Vec<NeighborEntry<A>> {
+        self.expire();
+        &self.entries
+    }
+
+    /// The entries that have not expired, as `<ip> <mac> <seconds to expiry>` lines
+    pub fn list(&mut self) -> String {
+        let now = Duration::monotonic();
+
+        let mut string = String::new();
+        for entry in self.entries().iter() {
+            string.push_str(&format!("{} {} {}\n",
+                                     entry.ip.to_string(),
+                                     entry.mac.to_string(),
+                                     (entry.expire - now).secs));
+        }
+        string
+    }
+
+    /// Find the hardware address of a protocol address
+    pub fn get(&mut self, ip: A) -> Option<MacAddr> {
+        self.expire();
+        self.entries.iter().find(|entry| entry.ip.equals(ip)).map(|entry| entry.mac)
+    }
+
+    /// Check if a protocol address has an entry
+    pub fn contains(&mut self, ip: A) -> bool {
+        self.get(ip).is_some()
+    }
+
+    /// Add or refresh an entry, replacing the oldest if the cache is full
+    pub fn insert(&mut self, ip: A, mac: MacAddr) {
+        self.expire();
+        self.entries.retain(|entry| !entry.ip.equals(ip));
+
+        if self.entries.len() >= NEIGHBOR_CACHE_SIZE {
+            let mut oldest = 0;
+            for i in 1..self.entries.len() {
+                if self.entries[i].expire < self.entries[oldest].expire {
+                    oldest = i;
+                }
+            }
+            self.entries.remove(oldest);
+        }
+
+        self.entries.push(NeighborEntry {
+            ip: ip,
+            mac: mac,
+            expire: Duration::monotonic() + Duration::new(A::ttl(), 0),
+        });
+    }
+}
@@ -0,0 +1,118 @@
+use collections::string::String;
+use collections::vec::Vec;
+
+use network::common::{n32, Ipv4Addr};
+use network::config::NetworkConfig;
+
+/// A route to a network, through a gateway or directly on the link if the gateway is unspecified
+#[derive(Copy, Clone)]
+pub struct Route {
+    pub dest: Ipv4Addr,
+    pub netmask: Ipv4Addr,
+    pub gateway: Ipv4Addr,
+}
+
+impl Route {
+    /// The length of the network prefix
+    pub fn prefix_len(&self) -> u32 {
+        n32 { bytes: self.netmask.bytes }.get().count_ones()
+    }
+
+    /// Check if an address is on the network of this route
+    pub fn matches(&self, addr: Ipv4Addr) -> bool {
+        for i in 0..4 {
+            if addr.bytes[i] & self.netmask.bytes[i] != self.dest.bytes[i] & self.netmask.bytes[i] {
+                return false;
+            }
+        }
+        true
+    }
+
+    /// Check if this route is for the network in CIDR notation
+    pub fn is_network(&self, dest: Ipv4Addr, netmask: Ipv4Addr) -> bool {
+        self.dest.equals(dest) && self.netmask.equals(netmask)
+    }
+
+    /// The address to send packets for the destination to
+    pub fn next_hop(&self, addr: Ipv4Addr) -> Ipv4Addr {
+        if self.gateway.equals(Ipv4Addr { bytes: [0; 4] }) {
+            addr
+        } else {
+            self.gateway
+        }
+    }
+
+    pub fn to_string(&self) -> String {
+        format!("{}/{} {}", self.dest.to_string(), self.prefix_len(), self.gateway.to_string())
+    }
+}
+
+/// Parse a network in CIDR notation, such as `10.85.85.0/24`. The address is masked, so that
+/// `10.85.85.5/24` is the same network
+pub fn parse_network(string: &str) -> Option<(Ipv4Addr, Ipv4Addr)> {
+    let mut parts = string.splitn(2, '/');
+    let mut dest = match Ipv4Addr::parse(parts.next().unwrap_or("")) {
+        Some(dest) => dest,
+        None => return None,
+    };
+    let prefix_len = match parts.next().unwrap_or("32").parse::<u32>() {
+        Ok(prefix_len) if prefix_len <= 32 => prefix_len,
+        _ => return None,
+    };
+
+    let mask = if prefix_len == 0 {
+        0
+    } else {
+        0xFFFFFFFF << (32 - prefix_len)
+    };
+
+    let netmask = Ipv4Addr { bytes: n32::new(mask).bytes };
+    for i in 0..4 {
+        dest.bytes[i] &= netmask.bytes[i];
+    }
+
+    Some((dest, netmask))
+}
+
+/// The routes of an interface, which are the static routes followed by the route to its own subnet
+/// and the default route through its gateway, if it has one
+pub fn routes(table: &[Route], config: &NetworkConfig) -> Vec<Route> {
+    let mut routes = table.to_vec();
+
+    let mut subnet = config.ip;
+    for i in 0..4 {
+        subnet.bytes[i] &= config.netmask.bytes[i];
+    }
+    routes.push(Route {
+        dest: subnet,
+        netmask: config.netmask,
+        gateway: Ipv4Addr { bytes: [0; 4] },
+    });
+
+    if !config.gateway.equals(Ipv4Addr { bytes: [0; 4] }) {
+        routes.push(Route {
+            dest: Ipv4Addr { bytes: [0; 4] },
+            netmask: Ipv4Addr { bytes: [0; 4] },
+            gateway: config.gateway,
+        });
+    }
+
+    routes
+}
+
+/// Find the route with the longest prefix matching the address
+pub fn lookup(table: &[Route], config: &NetworkConfig, addr: Ipv4Addr) -> Option<Route> {
+    let mut best: Option<Route> = None;
+    for route in routes(table, config).iter() {
+        if route.matches(addr) {
+            let better = match best {
+                Some(ref best) => route.prefix_len() > best.prefix_len(),
+                None => true,
+            };
+            if better {
+                best = Some(*route);
+            }
+        }
+    }
+    best
+}
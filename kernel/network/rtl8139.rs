@@ -1,3 +1,4 @@
+use alloc::arc::Arc;
 use alloc::boxed::Box;
 
 use arch::memory;
@@ -15,11 +16,12 @@ use drivers::pci::config::PciConfig;
 use drivers::io::{Io, Pio};
 
 use network::common::*;
+use network::config::{self, NetworkConfig};
 use network::scheme::*;
 
 use fs::{KScheme, Resource, Url};
 
-use system::error::Result;
+use system::error::{Error, Result, EACCES};
 
 use sync::Intex;
 
@@ -58,6 +60,7 @@ struct Txd {
 
 pub struct Rtl8139Port {
     pub idr: [Pio<u8>; 6],
+    pub mar: [Pio<u32>; 2],
     pub rbstart: Pio<u32>,
     pub cr: Pio<u8>,
     pub capr: Pio<u16>,
@@ -78,6 +81,8 @@ impl Rtl8139Port {
                   Pio::<u8>::new(base + 0x03),
                   Pio::<u8>::new(base + 0x04),
                   Pio::<u8>::new(base + 0x05)],
+            mar: [Pio::<u32>::new(base + 0x08),
+                  Pio::<u32>::new(base + 0x0C)],
             rbstart: Pio::<u32>::new(base + 0x30),
             cr: Pio::<u8>::new(base + 0x37),
             capr: Pio::<u16>::new(base + 0x38),
@@ -102,6 +107,7 @@ pub struct Rtl8139 {
     txds: Vec<Txd>,
     txd_i: usize,
     port: Rtl8139Port,
+    config: Arc<Intex<NetworkConfig>>,
 }
 
 impl Rtl8139 {
@@ -126,10 +132,13 @@ impl Rtl8139 {
             txds: Vec::new(),
             txd_i: 0,
             port: Rtl8139Port::new((base & 0xFFFFFFF0) as u16),
+            config: Arc::new(Intex::new(NetworkConfig::new(MacAddr { bytes: [0; 6] }))),
         };
 
         unsafe { module.init() };
 
+        config::register(module.config.clone());
+
         module
     }
 
@@ -145,7 +154,7 @@ impl Rtl8139 {
         while self.port.cr.read() & RTL8139_CR_RST != 0 {}
 
         debug::d("   - MAC: ");
-        MAC_ADDR = MacAddr {
+        let mac = MacAddr {
             bytes: [self.port.idr[0].read(),
                     self.port.idr[1].read(),
                     self.port.idr[2].read(),
@@ -153,7 +162,8 @@ impl Rtl8139 {
                     self.port.idr[4].read(),
                     self.port.idr[5].read()],
         };
-        debug::d(&MAC_ADDR.to_string());
+        debug::d(&mac.to_string());
+        self.config.lock().mac = mac;
 
         let receive_buffer = memory::alloc(10240);
         self.port.rbstart.write(receive_buffer as u32);
@@ -174,6 +184,10 @@ impl Rtl8139 {
         debug::d(" CMD: ");
         debug::dbh(self.port.cr.read());
 
+        // Accept every multicast group, neighbor discovery is multicast
+        self.port.mar[0].write(0xFFFFFFFF);
+        self.port.mar[1].write(0xFFFFFFFF);
+
         self.port.rcr.write(RTL8139_RCR_WRAP | RTL8139_RCR_AR | RTL8139_RCR_AB | RTL8139_RCR_AM |
                             RTL8139_RCR_APM);
         debug::d(" RCR: ");
@@ -239,7 +253,11 @@ impl KScheme for Rtl8139 {
         "network"
     }
 
-    fn open(&mut self, _: Url, _: usize) -> Result<Box<Resource>> {
+    fn open(&mut self, _: Url, _: usize, uid: u32, _: u32) -> Result<Box<Resource>> {
+        if uid != 0 {
+            return Err(Error::new(EACCES));
+        }
+
         Ok(NetworkResource::new(self))
     }
 
@@ -7,9 +7,13 @@ use core::ops::DerefMut;
 
 use fs::Resource;
 
-use system::error::Result;
+use network::config;
+use network::loopback;
 
-use sync::{Intex, WaitQueue};
+use system::error::{Error, Result, EAGAIN};
+use system::syscall::{POLLIN, POLLOUT};
+
+use sync::{Intex, WaitCondition, WaitQueue};
 
 pub trait NetworkScheme {
     fn add(&mut self, resource: *mut NetworkResource);
@@ -38,6 +42,7 @@ impl NetworkResource {
 
             (*ret.nic).add(ret.ptr);
         }
+        ::env().network_resources.lock().push(ret.ptr);
 
         ret
     }
@@ -57,6 +62,7 @@ impl Resource for NetworkResource {
 
             (*ret.nic).add(ret.ptr);
         }
+        ::env().network_resources.lock().push(ret.ptr);
 
         Ok(ret)
     }
@@ -88,7 +94,31 @@ impl Resource for NetworkResource {
         return Ok(bytes.len());
     }
 
+    fn read_nonblock(&mut self, buf: &mut [u8]) -> Result<usize> {
+        let bytes = unsafe {
+            (*self.nic).sync();
+            match (*self.ptr).inbound.inner.lock().pop_front() {
+                Some(bytes) => bytes,
+                None => return Err(Error::new(EAGAIN)),
+            }
+        };
+
+        let mut i = 0;
+        while i < bytes.len() && i < buf.len() {
+            buf[i] = bytes[i];
+            i += 1;
+        }
+
+        return Ok(bytes.len());
+    }
+
     fn write(&mut self, buf: &[u8]) -> Result<usize> {
+        // Frames to the local MAC address are received without going through the interface
+        if buf.len() >= 6 && buf[.. 6] == config::primary().mac.bytes {
+            loopback::deliver(buf);
+            return Ok(buf.len());
+        }
+
         unsafe {
             (*self.ptr).outbound.lock().push_back(Vec::from(buf));
 
@@ -98,6 +128,21 @@ impl Resource for NetworkResource {
         Ok(buf.len())
     }
 
+    fn poll(&self, events: u16) -> Result<u16> {
+        let mut revents = events & POLLOUT;
+        unsafe {
+            (*self.nic).sync();
+            if ! (*self.ptr).inbound.inner.lock().is_empty() {
+                revents |= events & POLLIN;
+            }
+        }
+        Ok(revents)
+    }
+
+    fn condition(&self) -> Option<&WaitCondition> {
+        Some(&self.inbound.condition)
+    }
+
     fn sync(&mut self) -> Result<()> {
         unsafe {
             (*self.nic).sync();
@@ -111,5 +156,6 @@ impl Drop for NetworkResource {
         unsafe {
             (*self.nic).remove(self.ptr);
         }
+        ::env().network_resources.lock().retain(|&resource| resource != self.ptr);
     }
 }
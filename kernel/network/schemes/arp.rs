@@ -1,15 +1,33 @@
+use alloc::arc::Arc;
+use alloc::boxed::Box;
+
 use common::debug;
 use common::slice::GetSlice;
+use common::time::Duration;
 
+use collections::string::{String, ToString};
 use collections::vec::Vec;
 
 use core::{mem, slice};
 
-use arch::context::context_switch;
+use arch::context::{context_sleep, context_switch};
 
 use network::common::*;
+use network::config;
+use network::neighbor::{NeighborAddr, NeighborCache};
+
+use fs::{KScheme, Resource, Url, VecResource};
+
+use sync::Intex;
+
+use system::error::{Error, Result, EAGAIN, EHOSTUNREACH};
 
-use fs::{KScheme, Url};
+/// Seconds an entry stays in the cache
+const ARP_TTL: i64 = 60;
+/// Times to send a request before the host is unreachable
+const ARP_ATTEMPTS: usize = 3;
+/// Milliseconds to wait for a reply to each request
+const ARP_TIMEOUT: i32 = 1000;
 
 #[derive(Copy, Clone)]
 #[repr(packed)]
@@ -56,22 +74,118 @@ impl ToBytes for Arp {
     }
 }
 
-pub struct ArpScheme;
+impl NeighborAddr for Ipv4Addr {
+    fn equals(&self, other: Self) -> bool {
+        Ipv4Addr::equals(self, other)
+    }
+
+    fn to_string(&self) -> String {
+        Ipv4Addr::to_string(self)
+    }
+
+    fn ttl() -> i64 {
+        ARP_TTL
+    }
+}
+
+/// The cache of resolved hardware addresses, shared by the IP scheme and the ARP reply loop
+pub type ArpCache = NeighborCache<Ipv4Addr>;
+
+/// Resolve the hardware address of a host on the link, from the cache or by sending requests
+pub fn resolve(cache: &Intex<ArpCache>, ip: Ipv4Addr) -> Result<MacAddr> {
+    if let Some(mac) = cache.lock().get(ip) {
+        return Ok(mac);
+    }
+
+    let mut link = try!(Url::from_str(&format!("ethernet:{}/806", BROADCAST_MAC_ADDR.to_string())).unwrap().open());
+
+    for _ in 0..ARP_ATTEMPTS {
+        let config = config::primary();
+        let arp = Arp {
+            header: ArpHeader {
+                htype: n16::new(1),
+                ptype: n16::new(0x800),
+                hlen: 6,
+                plen: 4,
+                oper: n16::new(1),
+                src_mac: config.mac,
+                src_ip: config.ip,
+                dst_mac: BROADCAST_MAC_ADDR,
+                dst_ip: ip,
+            },
+            data: Vec::new(),
+        };
+        try!(link.write(&arp.to_bytes()));
+
+        let deadline = Duration::monotonic() + Duration::new(0, ARP_TIMEOUT * 1000000);
+        loop {
+            let mut bytes = [0; 8192];
+            match link.read_nonblock(&mut bytes) {
+                Ok(count) => if let Some(packet) = Arp::from_bytes(bytes[.. count].to_vec()) {
+                    if packet.header.oper.get() == 2 && packet.header.src_ip.equals(ip) {
+                        cache.lock().insert(ip, packet.header.src_mac);
+                        return Ok(packet.header.src_mac);
+                    }
+                },
+                Err(ref err) if err.errno == EAGAIN => {
+                    // The reply loop may have learned the address from a request of the host
+                    if let Some(mac) = cache.lock().get(ip) {
+                        return Ok(mac);
+                    }
+
+                    let now = Duration::monotonic();
+                    if now >= deadline {
+                        break;
+                    }
+                    unsafe { context_sleep(now + Duration::new(0, 10 * 1000000)) };
+                },
+                Err(err) => return Err(err),
+            }
+        }
+    }
+
+    Err(Error::new(EHOSTUNREACH))
+}
+
+/// A ARP scheme. Reading `arp:` lists the cache as `<ip> <mac> <seconds to expiry>` lines
+pub struct ArpScheme {
+    pub cache: Arc<Intex<ArpCache>>,
+}
 
 impl KScheme for ArpScheme {
     fn scheme(&self) -> &str {
         "arp"
     }
+
+    fn open(&mut self, _: Url, _: usize, _: u32, _: u32) -> Result<Box<Resource>> {
+        let string = self.cache.lock().list();
+        Ok(box VecResource::new("arp:".to_string(), string.into_bytes()))
+    }
 }
 
 impl ArpScheme {
-    pub fn reply_loop() {
-        while let Ok(mut link) = Url::from_str("ethernet:/806").unwrap().open() {
+    /// Answer requests for the address of this host, learning the addresses of the senders of
+    /// requests and replies
+    pub fn reply_loop(cache: Arc<Intex<ArpCache>>) {
+        let url = format!("ethernet:{}/806", BROADCAST_MAC_ADDR.to_string());
+        while let Ok(mut link) = Url::from_str(&url).unwrap().open() {
             loop {
                 let mut bytes = [0; 8192];
                 if let Ok(count) = link.read(&mut bytes) {
                     if let Some(packet) = Arp::from_bytes(bytes[.. count].to_vec()) {
-                        if packet.header.oper.get() == 1 && packet.header.dst_ip.equals(IP_ADDR) {
+                        let config = config::primary();
+                        let for_us = packet.header.dst_ip.equals(config.ip);
+
+                        // Learn the sender if it is already known or is talking to this host
+                        let sender = packet.header.src_ip;
+                        if !sender.equals(Ipv4Addr { bytes: [0; 4] }) {
+                            let mut cache = cache.lock();
+                            if for_us || cache.contains(sender) {
+                                cache.insert(sender, packet.header.src_mac);
+                            }
+                        }
+
+                        if packet.header.oper.get() == 1 && for_us {
                             let mut response = Arp {
                                 header: packet.header,
                                 data: packet.data.clone(),
@@ -79,10 +193,13 @@ impl ArpScheme {
                             response.header.oper.set(2);
                             response.header.dst_mac = packet.header.src_mac;
                             response.header.dst_ip = packet.header.src_ip;
-                            response.header.src_mac = unsafe { MAC_ADDR };
-                            response.header.src_ip = IP_ADDR;
+                            response.header.src_mac = config.mac;
+                            response.header.src_ip = config.ip;
 
-                            let _ = link.write(&response.to_bytes());
+                            let reply_url = format!("ethernet:{}/806", packet.header.src_mac.to_string());
+                            if let Ok(mut reply_link) = Url::from_str(&reply_url).unwrap().open() {
+                                let _ = reply_link.write(&response.to_bytes());
+                            }
                         }
                     }
                 } else {
@@ -0,0 +1,34 @@
+use alloc::boxed::Box;
+
+use collections::string::String;
+
+use fs::{KScheme, Resource, Url, VecResource};
+
+use network::dns;
+
+use system::error::{Error, Result, ENOENT};
+
+/// The DNS scheme. Reading `dns:<name>` lists the IPv4 and IPv6 addresses of the name, one per
+/// line
+pub struct DnsScheme;
+
+impl KScheme for DnsScheme {
+    fn scheme(&self) -> &str {
+        "dns"
+    }
+
+    fn open(&mut self, url: Url, _: usize, _: u32, _: u32) -> Result<Box<Resource>> {
+        let name = url.reference().trim_matches('/');
+        if name.is_empty() {
+            return Err(Error::new(ENOENT));
+        }
+
+        let mut data = String::new();
+        for addr in try!(dns::resolve(name)).iter() {
+            data.push_str(&addr.to_string());
+            data.push('\n');
+        }
+
+        Ok(box VecResource::new(format!("dns:{}", name), data.into_bytes()))
+    }
+}
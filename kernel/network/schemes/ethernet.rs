@@ -9,11 +9,15 @@ use common::debug;
 use common::to_num::ToNum;
 
 use network::common::*;
+use network::config;
 use network::ethernet::*;
 
 use fs::{KScheme, Resource, Url};
 
-use system::error::{Error, Result, ENOENT};
+use sync::WaitCondition;
+
+use system::error::{Error, Result, EACCES, ENOENT};
+use system::syscall::POLLIN;
 
 /// A ethernet resource
 pub struct EthernetResource {
@@ -27,31 +31,8 @@ pub struct EthernetResource {
     ethertype: u16,
 }
 
-impl Resource for EthernetResource {
-    fn dup(&self) -> Result<Box<Resource>> {
-        match self.network.dup() {
-            Ok(network) => Ok(box EthernetResource {
-                network: network,
-                data: self.data.clone(),
-                peer_addr: self.peer_addr,
-                ethertype: self.ethertype,
-            }),
-            Err(err) => Err(err),
-        }
-    }
-
-    fn path(&self, buf: &mut [u8]) -> Result<usize> {
-        let path_string = format!("ethernet:{}/{:X}", self.peer_addr.to_string(), self.ethertype);
-        let path = path_string.as_bytes();
-
-        for (b, p) in buf.iter_mut().zip(path.iter()) {
-            *b = *p;
-        }
-
-        Ok(cmp::min(buf.len(), path.len()))
-    }
-
-    fn read(&mut self, buf: &mut [u8]) -> Result<usize> {
+impl EthernetResource {
+    fn read_inner(&mut self, buf: &mut [u8], nonblock: bool) -> Result<usize> {
         if !self.data.is_empty() {
             let mut data: Vec<u8> = Vec::new();
             mem::swap(&mut self.data, &mut data);
@@ -65,11 +46,16 @@ impl Resource for EthernetResource {
 
         loop {
             let mut bytes = [0; 8192];
-            match self.network.read(&mut bytes) {
+            let result = if nonblock {
+                self.network.read_nonblock(&mut bytes)
+            } else {
+                self.network.read(&mut bytes)
+            };
+            match result {
                 Ok(count) => {
                     if let Some(frame) = EthernetII::from_bytes(bytes[.. count].to_vec()) {
-                        if frame.header.ethertype.get() == self.ethertype && (unsafe { frame.header.dst.equals(MAC_ADDR) }
-                            || frame.header.dst.equals(BROADCAST_MAC_ADDR)) && (frame.header.src.equals(self.peer_addr)
+                        if frame.header.ethertype.get() == self.ethertype && (frame.header.dst.equals(config::primary().mac)
+                            || frame.header.dst.is_multicast()) && (frame.header.src.equals(self.peer_addr)
                             || self.peer_addr.equals(BROADCAST_MAC_ADDR))
                         {
                             for (b, d) in buf.iter_mut().zip(frame.data.iter()) {
@@ -84,13 +70,46 @@ impl Resource for EthernetResource {
             }
         }
     }
+}
+
+impl Resource for EthernetResource {
+    fn dup(&self) -> Result<Box<Resource>> {
+        match self.network.dup() {
+            Ok(network) => Ok(box EthernetResource {
+                network: network,
+                data: self.data.clone(),
+                peer_addr: self.peer_addr,
+                ethertype: self.ethertype,
+            }),
+            Err(err) => Err(err),
+        }
+    }
+
+    fn path(&self, buf: &mut [u8]) -> Result<usize> {
+        let path_string = format!("ethernet:{}/{:X}", self.peer_addr.to_string(), self.ethertype);
+        let path = path_string.as_bytes();
+
+        for (b, p) in buf.iter_mut().zip(path.iter()) {
+            *b = *p;
+        }
+
+        Ok(cmp::min(buf.len(), path.len()))
+    }
+
+    fn read(&mut self, buf: &mut [u8]) -> Result<usize> {
+        self.read_inner(buf, false)
+    }
+
+    fn read_nonblock(&mut self, buf: &mut [u8]) -> Result<usize> {
+        self.read_inner(buf, true)
+    }
 
     fn write(&mut self, buf: &[u8]) -> Result<usize> {
         let data = Vec::from(buf);
 
         match self.network.write(&EthernetII {
                                       header: EthernetIIHeader {
-                                          src: unsafe { MAC_ADDR },
+                                          src: config::primary().mac,
                                           dst: self.peer_addr,
                                           ethertype: n16::new(self.ethertype),
                                       },
@@ -102,6 +121,19 @@ impl Resource for EthernetResource {
         }
     }
 
+    fn poll(&self, events: u16) -> Result<u16> {
+        if !self.data.is_empty() {
+            let revents = try!(self.network.poll(events));
+            Ok(revents | (events & POLLIN))
+        } else {
+            self.network.poll(events)
+        }
+    }
+
+    fn condition(&self) -> Option<&WaitCondition> {
+        self.network.condition()
+    }
+
     fn sync(&mut self) -> Result<()> {
         self.network.sync()
     }
@@ -114,11 +146,18 @@ impl KScheme for EthernetScheme {
         "ethernet"
     }
 
-    fn open(&mut self, url: Url, _: usize) -> Result<Box<Resource>> {
+    fn open(&mut self, url: Url, _: usize, uid: u32, _: u32) -> Result<Box<Resource>> {
+        if uid != 0 {
+            return Err(Error::new(EACCES));
+        }
+
         let parts: Vec<&str> = url.reference().split("/").collect();
         if let Some(host_string) = parts.get(0) {
             if let Some(ethertype_string) = parts.get(1) {
-                if let Ok(mut network) = Url::from_str("network:").unwrap().open() {
+                // Without a network card, frames are only sent to the local stack
+                let network = Url::from_str("network:").unwrap().open()
+                                  .or_else(|_| Url::from_str("loopback:").unwrap().open());
+                if let Ok(mut network) = network {
                     let ethertype = ethertype_string.to_num_radix(16) as u16;
 
                     if !host_string.is_empty() {
@@ -135,8 +174,8 @@ impl KScheme for EthernetScheme {
                                 Ok(count) => {
                                     if let Some(frame) = EthernetII::from_bytes(bytes[.. count].to_vec()) {
                                         if frame.header.ethertype.get() == ethertype &&
-                                           (unsafe { frame.header.dst.equals(MAC_ADDR) } ||
-                                            frame.header.dst.equals(BROADCAST_MAC_ADDR)) {
+                                           (frame.header.dst.equals(config::primary().mac) ||
+                                            frame.header.dst.is_multicast()) {
                                             return Ok(box EthernetResource {
                                                 network: network,
                                                 data: frame.data,
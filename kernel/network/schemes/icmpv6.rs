@@ -0,0 +1,163 @@
+use alloc::arc::Arc;
+
+use common::debug;
+use common::slice::GetSlice;
+
+use collections::vec::Vec;
+
+use core::{mem, slice};
+
+use network::common::*;
+use network::ipv6::Ipv6;
+use network::loopback;
+
+use fs::Url;
+
+use sync::Intex;
+
+use super::ndp::{self, NdpCache};
+
+pub const ICMPV6_ECHO_REQUEST: u8 = 128;
+pub const ICMPV6_ECHO_REPLY: u8 = 129;
+pub const ICMPV6_NEIGHBOR_SOLICITATION: u8 = 135;
+pub const ICMPV6_NEIGHBOR_ADVERTISEMENT: u8 = 136;
+
+/// The next header value of ICMPv6
+pub const ICMPV6_NEXT_HEADER: u8 = 0x3A;
+
+#[derive(Copy, Clone)]
+#[repr(packed)]
+pub struct Icmpv6Header {
+    pub _type: u8,
+    pub code: u8,
+    pub checksum: Checksum,
+    pub data: [u8; 4],
+}
+
+pub struct Icmpv6 {
+    pub header: Icmpv6Header,
+    pub data: Vec<u8>,
+}
+
+impl Icmpv6 {
+    /// Create a message, with the checksum for the given addresses
+    pub fn new(_type: u8, header_data: [u8; 4], data: Vec<u8>, src: &Ipv6Addr, dst: &Ipv6Addr) -> Self {
+        let mut message = Icmpv6 {
+            header: Icmpv6Header {
+                _type: _type,
+                code: 0,
+                checksum: Checksum { data: 0 },
+                data: header_data,
+            },
+            data: data,
+        };
+
+        unsafe {
+            let next_header = n16::new(ICMPV6_NEXT_HEADER as u16);
+            let len = n16::new((mem::size_of::<Icmpv6Header>() + message.data.len()) as u16);
+            message.header.checksum.data =
+                Checksum::compile(Checksum::sum(src.bytes.as_ptr() as usize, src.bytes.len()) +
+                                  Checksum::sum(dst.bytes.as_ptr() as usize, dst.bytes.len()) +
+                                  Checksum::sum((&next_header as *const n16) as usize, mem::size_of::<n16>()) +
+                                  Checksum::sum((&len as *const n16) as usize, mem::size_of::<n16>()) +
+                                  Checksum::sum((&message.header as *const Icmpv6Header) as usize,
+                                                mem::size_of::<Icmpv6Header>()) +
+                                  Checksum::sum(message.data.as_ptr() as usize, message.data.len()));
+        }
+
+        message
+    }
+
+    /// Check the checksum of a message received from `src` at `dst`
+    pub fn verify(bytes: &[u8], src: &Ipv6Addr, dst: &Ipv6Addr) -> bool {
+        unsafe {
+            let next_header = n16::new(ICMPV6_NEXT_HEADER as u16);
+            let len = n16::new(bytes.len() as u16);
+            Checksum::compile(Checksum::sum(src.bytes.as_ptr() as usize, src.bytes.len()) +
+                              Checksum::sum(dst.bytes.as_ptr() as usize, dst.bytes.len()) +
+                              Checksum::sum((&next_header as *const n16) as usize, mem::size_of::<n16>()) +
+                              Checksum::sum((&len as *const n16) as usize, mem::size_of::<n16>()) +
+                              Checksum::sum(bytes.as_ptr() as usize, bytes.len())) == 0
+        }
+    }
+}
+
+impl FromBytes for Icmpv6 {
+    fn from_bytes(bytes: Vec<u8>) -> Option<Self> {
+        if bytes.len() >= mem::size_of::<Icmpv6Header>() {
+            unsafe {
+                return Some(Icmpv6 {
+                    header: *(bytes.as_ptr() as *const Icmpv6Header),
+                    data: bytes.get_slice(mem::size_of::<Icmpv6Header>() ..).to_vec(),
+                });
+            }
+        }
+        None
+    }
+}
+
+impl ToBytes for Icmpv6 {
+    fn to_bytes(&self) -> Vec<u8> {
+        unsafe {
+            let header_ptr: *const Icmpv6Header = &self.header;
+            let mut ret = Vec::from(slice::from_raw_parts(header_ptr as *const u8,
+                                                          mem::size_of::<Icmpv6Header>()));
+            ret.extend_from_slice(&self.data);
+            ret
+        }
+    }
+}
+
+/// Send a message to an address, from the matching local address
+pub fn send(dst: Ipv6Addr, message: &Icmpv6) {
+    let url = format!("ip6:{}/{:X}", dst.to_string(), ICMPV6_NEXT_HEADER);
+    if let Ok(mut ip) = Url::from_str(&url).unwrap().open() {
+        let _ = ip.write(&message.to_bytes());
+    }
+}
+
+pub struct Icmpv6Scheme;
+
+impl Icmpv6Scheme {
+    /// Answer echo requests and neighbor solicitations, and learn the addresses in neighbor
+    /// advertisements
+    pub fn reply_loop(cache: Arc<Intex<NdpCache>>) {
+        if let Ok(mut link) = Url::from_str("ethernet:FF.FF.FF.FF.FF.FF/86DD").unwrap().open() {
+            loop {
+                let mut bytes = [0; 8192];
+                if let Ok(count) = link.read(&mut bytes) {
+                    let packet = match Ipv6::from_bytes(bytes[.. count].to_vec()) {
+                        Some(packet) => packet,
+                        None => continue,
+                    };
+
+                    if packet.header.next_header != ICMPV6_NEXT_HEADER || !loopback::accepts6(packet.header.dst) ||
+                       !Icmpv6::verify(&packet.data, &packet.header.src, &packet.header.dst) {
+                        continue;
+                    }
+
+                    if let Some(message) = Icmpv6::from_bytes(packet.data.clone()) {
+                        match message.header._type {
+                            ICMPV6_ECHO_REQUEST => if !packet.header.src.is_unspecified() {
+                                let src = loopback::host_addr6(packet.header.src);
+                                let response = Icmpv6::new(ICMPV6_ECHO_REPLY,
+                                                           message.header.data,
+                                                           message.data,
+                                                           &src,
+                                                           &packet.header.src);
+                                send(packet.header.src, &response);
+                            },
+                            ICMPV6_NEIGHBOR_SOLICITATION | ICMPV6_NEIGHBOR_ADVERTISEMENT => {
+                                ndp::receive(&cache, &packet, &message);
+                            },
+                            _ => (),
+                        }
+                    }
+                } else {
+                    break;
+                }
+            }
+        }
+        debug::d("ICMPv6: Failed to open ethernet:\n");
+    }
+}
@@ -1,20 +1,27 @@
+use alloc::arc::Arc;
 use alloc::boxed::Box;
 
-use collections::string::ToString;
+use collections::string::{String, ToString};
 use collections::vec::Vec;
 
-use core::{cmp, mem};
+use core::{cmp, mem, str};
 
 use network::common::*;
+use network::config;
 use network::ipv4::*;
+use network::loopback;
+use network::route::{self, Route};
 
 use common::{debug, random};
 use common::to_num::ToNum;
 
-use super::arp::{Arp, ArpHeader};
-use fs::{KScheme, Resource, Url};
+use super::arp::{self, ArpCache};
+use fs::{KScheme, Resource, ResourceSeek, Url};
 
-use system::error::{Error, Result, ENOENT};
+use sync::{Intex, WaitCondition};
+
+use system::error::{Error, Result, EACCES, EHOSTUNREACH, EINVAL, ENOENT};
+use system::syscall::{Stat, MODE_FILE, POLLIN};
 
 /// A IP (internet protocole) resource
 pub struct IpResource {
@@ -25,32 +32,8 @@ pub struct IpResource {
     id: u16,
 }
 
-impl Resource for IpResource {
-    fn dup(&self) -> Result<Box<Resource>> {
-        match self.link.dup() {
-            Ok(link) => Ok(box IpResource {
-                link: link,
-                data: self.data.clone(),
-                peer_addr: self.peer_addr,
-                proto: self.proto,
-                id: self.id,
-            }),
-            Err(err) => Err(err),
-        }
-    }
-
-    fn path(&self, buf: &mut [u8]) -> Result<usize> {
-        let path_string = format!("ip:{}/{:X}", self.peer_addr.to_string(), self.proto);
-        let path = path_string.as_bytes();
-
-        for (b, p) in buf.iter_mut().zip(path.iter()) {
-            *b = *p;
-        }
-
-        Ok(cmp::min(buf.len(), path.len()))
-    }
-
-    fn read(&mut self, buf: &mut [u8]) -> Result<usize> {
+impl IpResource {
+    fn read_inner(&mut self, buf: &mut [u8], nonblock: bool) -> Result<usize> {
         if !self.data.is_empty() {
             let mut data: Vec<u8> = Vec::new();
             mem::swap(&mut self.data, &mut data);
@@ -64,10 +47,15 @@ impl Resource for IpResource {
 
         loop {
             let mut bytes = [0; 8192];
-            match self.link.read(&mut bytes) {
+            let result = if nonblock {
+                self.link.read_nonblock(&mut bytes)
+            } else {
+                self.link.read(&mut bytes)
+            };
+            match result {
                 Ok(count) => {
                     if let Some(packet) = Ipv4::from_bytes(bytes[.. count].to_vec()) {
-                        if packet.header.proto == self.proto && packet.header.dst.equals(IP_ADDR) &&
+                        if packet.header.proto == self.proto && loopback::accepts(packet.header.dst) &&
                            packet.header.src.equals(self.peer_addr) {
                             for (b, d) in buf.iter_mut().zip(packet.data.iter()) {
                                 *b = *d;
@@ -81,6 +69,40 @@ impl Resource for IpResource {
             }
         }
     }
+}
+
+impl Resource for IpResource {
+    fn dup(&self) -> Result<Box<Resource>> {
+        match self.link.dup() {
+            Ok(link) => Ok(box IpResource {
+                link: link,
+                data: self.data.clone(),
+                peer_addr: self.peer_addr,
+                proto: self.proto,
+                id: self.id,
+            }),
+            Err(err) => Err(err),
+        }
+    }
+
+    fn path(&self, buf: &mut [u8]) -> Result<usize> {
+        let path_string = format!("ip:{}/{:X}", self.peer_addr.to_string(), self.proto);
+        let path = path_string.as_bytes();
+
+        for (b, p) in buf.iter_mut().zip(path.iter()) {
+            *b = *p;
+        }
+
+        Ok(cmp::min(buf.len(), path.len()))
+    }
+
+    fn read(&mut self, buf: &mut [u8]) -> Result<usize> {
+        self.read_inner(buf, false)
+    }
+
+    fn read_nonblock(&mut self, buf: &mut [u8]) -> Result<usize> {
+        self.read_inner(buf, true)
+    }
 
     fn write(&mut self, buf: &[u8]) -> Result<usize> {
         let ip_data = Vec::from(buf);
@@ -96,7 +118,7 @@ impl Resource for IpResource {
                 ttl: 128,
                 proto: self.proto,
                 checksum: Checksum { data: 0 },
-                src: IP_ADDR,
+                src: loopback::host_addr(self.peer_addr),
                 dst: self.peer_addr,
             },
             options: Vec::new(),
@@ -116,20 +138,162 @@ impl Resource for IpResource {
         }
     }
 
+    fn poll(&self, events: u16) -> Result<u16> {
+        if !self.data.is_empty() {
+            let revents = try!(self.link.poll(events));
+            Ok(revents | (events & POLLIN))
+        } else {
+            self.link.poll(events)
+        }
+    }
+
+    fn condition(&self) -> Option<&WaitCondition> {
+        self.link.condition()
+    }
+
     fn sync(&mut self) -> Result<()> {
         self.link.sync()
     }
 }
 
-/// A ARP entry (MAC + IP)
-pub struct ArpEntry {
-    ip: Ipv4Addr,
-    mac: MacAddr,
+/// The routing table, `ip:/route`. Reading lists the routes of the primary interface as
+/// `<network>/<prefix> <gateway>` lines, where an unspecified gateway means the network is on the
+/// link. Writing `add <network>/<prefix> <gateway>` or `del <network>/<prefix>` lines changes the
+/// static routes, and is only allowed to root. The routes to the own subnet and through the default
+/// gateway are set in `netcfg:`
+pub struct RouteResource {
+    routes: Arc<Intex<Vec<Route>>>,
+    data: Vec<u8>,
+    seek: usize,
+    /// The resource was opened by root
+    writeable: bool,
+}
+
+impl RouteResource {
+    fn new(routes: Arc<Intex<Vec<Route>>>, writeable: bool) -> Self {
+        let mut ret = RouteResource {
+            routes: routes,
+            data: Vec::new(),
+            seek: 0,
+            writeable: writeable,
+        };
+        ret.refresh();
+        ret
+    }
+
+    fn refresh(&mut self) {
+        let mut string = String::new();
+        for route in route::routes(&self.routes.lock(), &config::primary()).iter() {
+            string.push_str(&route.to_string());
+            string.push('\n');
+        }
+        self.data = string.into_bytes();
+    }
+
+    fn change(&mut self, line: &str) -> Result<()> {
+        let mut args = line.split_whitespace();
+        let command = args.next().unwrap_or("");
+        let (dest, netmask) = try!(route::parse_network(args.next().unwrap_or("")).ok_or(Error::new(EINVAL)));
+
+        let mut routes = self.routes.lock();
+        match command {
+            "add" => {
+                let gateway = try!(Ipv4Addr::parse(args.next().unwrap_or("")).ok_or(Error::new(EINVAL)));
+                routes.retain(|route| !route.is_network(dest, netmask));
+                routes.push(Route {
+                    dest: dest,
+                    netmask: netmask,
+                    gateway: gateway,
+                });
+            },
+            "del" => {
+                let len = routes.len();
+                routes.retain(|route| !route.is_network(dest, netmask));
+                if routes.len() == len {
+                    return Err(Error::new(ENOENT));
+                }
+            },
+            _ => return Err(Error::new(EINVAL)),
+        }
+
+        Ok(())
+    }
+}
+
+impl Resource for RouteResource {
+    fn dup(&self) -> Result<Box<Resource>> {
+        Ok(box RouteResource {
+            routes: self.routes.clone(),
+            data: self.data.clone(),
+            seek: self.seek,
+            writeable: self.writeable,
+        })
+    }
+
+    fn path(&self, buf: &mut [u8]) -> Result<usize> {
+        let path = b"ip:/route";
+
+        for (b, p) in buf.iter_mut().zip(path.iter()) {
+            *b = *p;
+        }
+
+        Ok(cmp::min(buf.len(), path.len()))
+    }
+
+    fn read(&mut self, buf: &mut [u8]) -> Result<usize> {
+        let mut i = 0;
+        while i < buf.len() && self.seek < self.data.len() {
+            buf[i] = self.data[self.seek];
+            self.seek += 1;
+            i += 1;
+        }
+        Ok(i)
+    }
+
+    fn write(&mut self, buf: &[u8]) -> Result<usize> {
+        if !self.writeable {
+            return Err(Error::new(EACCES));
+        }
+
+        let string = try!(str::from_utf8(buf).or(Err(Error::new(EINVAL))));
+        for line in string.lines() {
+            if !line.trim().is_empty() {
+                try!(self.change(line));
+            }
+        }
+
+        self.refresh();
+        self.seek = 0;
+
+        Ok(buf.len())
+    }
+
+    fn seek(&mut self, pos: ResourceSeek) -> Result<usize> {
+        match pos {
+            ResourceSeek::Start(offset) => self.seek = cmp::min(self.data.len(), offset),
+            ResourceSeek::Current(offset) =>
+                self.seek = cmp::max(0, cmp::min(self.data.len() as isize, self.seek as isize + offset)) as usize,
+            ResourceSeek::End(offset) =>
+                self.seek = cmp::max(0, cmp::min(self.data.len() as isize, self.data.len() as isize + offset)) as usize,
+        }
+        Ok(self.seek)
+    }
+
+    fn stat(&self, stat: &mut Stat) -> Result<usize> {
+        stat.st_mode = MODE_FILE | 0o644;
+        stat.st_size = self.data.len() as u64;
+        Ok(0)
+    }
+
+    fn sync(&mut self) -> Result<()> {
+        Ok(())
+    }
 }
 
 /// A IP scheme
 pub struct IpScheme {
-    pub arp: Vec<ArpEntry>,
+    pub arp: Arc<Intex<ArpCache>>,
+    pub routes: Arc<Intex<Vec<Route>>>,
 }
 
 impl KScheme for IpScheme {
@@ -137,7 +301,11 @@ impl KScheme for IpScheme {
         "ip"
     }
 
-    fn open(&mut self, url: Url, _: usize) -> Result<Box<Resource>> {
+    fn open(&mut self, url: Url, _: usize, uid: u32, _: u32) -> Result<Box<Resource>> {
+        if url.reference().trim_matches('/') == "route" {
+            return Ok(box RouteResource::new(self.routes.clone(), uid == 0));
+        }
+
         let parts: Vec<&str> = url.reference().split('/').collect();
         if let Some(host_string) = parts.get(0) {
             if let Some(proto_string) = parts.get(1) {
@@ -145,54 +313,26 @@ impl KScheme for IpScheme {
 
                 if !host_string.is_empty() {
                     let peer_addr = Ipv4Addr::from_string(&host_string.to_string());
-                    let mut peer_mac = BROADCAST_MAC_ADDR;
 
-                    for entry in self.arp.iter() {
-                        if entry.ip.equals(peer_addr) {
-                            peer_mac = entry.mac;
-                            break;
-                        }
-                    }
+                    let config = config::primary();
+                    let broadcast = peer_addr.equals(config.broadcast()) ||
+                                    peer_addr.equals(Ipv4Addr { bytes: [255, 255, 255, 255] });
 
-                    if peer_mac.equals(BROADCAST_MAC_ADDR) {
-                        if let Ok(mut link) = Url::from_str(&format!("ethernet:{}/806", &peer_mac.to_string())).unwrap().open() {
-                            let arp = Arp {
-                                header: ArpHeader {
-                                    htype: n16::new(1),
-                                    ptype: n16::new(0x800),
-                                    hlen: 6,
-                                    plen: 4,
-                                    oper: n16::new(1),
-                                    src_mac: unsafe { MAC_ADDR },
-                                    src_ip: IP_ADDR,
-                                    dst_mac: peer_mac,
-                                    dst_ip: peer_addr,
-                                },
-                                data: Vec::new(),
-                            };
-
-                            match link.write(&arp.to_bytes()) {
-                                Ok(_) => loop {
-                                    let mut bytes = [0; 8192];
-                                    match link.read(&mut bytes) {
-                                        Ok(count) => if let Some(packet) = Arp::from_bytes(bytes[.. count].to_vec()) {
-                                            if packet.header.oper.get() == 2 &&
-                                               packet.header.src_ip.equals(peer_addr) {
-                                                peer_mac = packet.header.src_mac;
-                                                self.arp.push(ArpEntry {
-                                                    ip: peer_addr,
-                                                    mac: peer_mac,
-                                                });
-                                                break;
-                                            }
-                                        },
-                                        Err(_) => (),
-                                    }
-                                },
-                                Err(err) => debugln!("IP: ARP Write Failed: {}", err),
-                            }
-                        }
-                    }
+                    let peer_mac = if loopback::is_loopback(peer_addr) || peer_addr.equals(config.ip) {
+                        // Packets to the local stack are sent to the own hardware address, which
+                        // loops them back instead of sending them on the link
+                        config.mac
+                    } else if broadcast {
+                        BROADCAST_MAC_ADDR
+                    } else {
+                        // Resolve the next hop, not the final destination
+                        let next_hop = match route::lookup(&self.routes.lock(), &config, peer_addr) {
+                            Some(route) => route.next_hop(peer_addr),
+                            None => return Err(Error::new(EHOSTUNREACH)),
+                        };
+
+                        try!(arp::resolve(&self.arp, next_hop))
+                    };
 
                     if let Ok(link) = Url::from_str(&format!("ethernet:{}/800", &peer_mac.to_string())).unwrap().open(){
                         return Ok(box IpResource {
@@ -209,8 +349,7 @@ impl KScheme for IpScheme {
                         match link.read(&mut bytes) {
                             Ok(count) => {
                                 if let Some(packet) = Ipv4::from_bytes(bytes[.. count].to_vec()) {
-                                    if packet.header.proto == proto &&
-                                       packet.header.dst.equals(IP_ADDR) {
+                                    if packet.header.proto == proto && loopback::accepts(packet.header.dst) {
                                         return Ok(box IpResource {
                                             link: link,
                                             data: packet.data,
@@ -0,0 +1,206 @@
+use alloc::arc::Arc;
+use alloc::boxed::Box;
+
+use collections::vec::Vec;
+
+use core::{cmp, mem};
+
+use network::common::*;
+use network::config;
+use network::ipv6::Ipv6;
+use network::loopback;
+
+use common::debug;
+use common::to_num::ToNum;
+
+use super::icmpv6::ICMPV6_NEXT_HEADER;
+use super::ndp::{self, NdpCache};
+use fs::{KScheme, Resource, Url};
+
+use sync::{Intex, WaitCondition};
+
+use system::error::{Error, Result, EINVAL, ENOENT};
+use system::syscall::POLLIN;
+
+/// The hop limit of packets, except for ICMPv6 which neighbor discovery requires to be 255
+const HOP_LIMIT: u8 = 64;
+
+/// A IPv6 resource
+pub struct Ip6Resource {
+    link: Box<Resource>,
+    data: Vec<u8>,
+    peer_addr: Ipv6Addr,
+    next_header: u8,
+}
+
+impl Ip6Resource {
+    fn read_inner(&mut self, buf: &mut [u8], nonblock: bool) -> Result<usize> {
+        if !self.data.is_empty() {
+            let mut data: Vec<u8> = Vec::new();
+            mem::swap(&mut self.data, &mut data);
+
+            for (b, d) in buf.iter_mut().zip(data.iter()) {
+                *b = *d;
+            }
+
+            return Ok(cmp::min(buf.len(), data.len()));
+        }
+
+        loop {
+            let mut bytes = [0; 8192];
+            let count = try!(if nonblock {
+                self.link.read_nonblock(&mut bytes)
+            } else {
+                self.link.read(&mut bytes)
+            });
+
+            if let Some(packet) = Ipv6::from_bytes(bytes[.. count].to_vec()) {
+                if packet.header.next_header == self.next_header && loopback::accepts6(packet.header.dst) &&
+                   packet.header.src.equals(self.peer_addr) {
+                    for (b, d) in buf.iter_mut().zip(packet.data.iter()) {
+                        *b = *d;
+                    }
+
+                    return Ok(cmp::min(buf.len(), packet.data.len()));
+                }
+            }
+        }
+    }
+}
+
+impl Resource for Ip6Resource {
+    fn dup(&self) -> Result<Box<Resource>> {
+        Ok(box Ip6Resource {
+            link: try!(self.link.dup()),
+            data: self.data.clone(),
+            peer_addr: self.peer_addr,
+            next_header: self.next_header,
+        })
+    }
+
+    fn path(&self, buf: &mut [u8]) -> Result<usize> {
+        let path_string = format!("ip6:{}/{:X}", self.peer_addr.to_string(), self.next_header);
+        let path = path_string.as_bytes();
+
+        for (b, p) in buf.iter_mut().zip(path.iter()) {
+            *b = *p;
+        }
+
+        Ok(cmp::min(buf.len(), path.len()))
+    }
+
+    fn read(&mut self, buf: &mut [u8]) -> Result<usize> {
+        self.read_inner(buf, false)
+    }
+
+    fn read_nonblock(&mut self, buf: &mut [u8]) -> Result<usize> {
+        self.read_inner(buf, true)
+    }
+
+    fn write(&mut self, buf: &[u8]) -> Result<usize> {
+        if buf.len() > 65535 {
+            return Err(Error::new(EINVAL));
+        }
+
+        let hop_limit = if self.next_header == ICMPV6_NEXT_HEADER {
+            255
+        } else {
+            HOP_LIMIT
+        };
+
+        let packet = Ipv6::new(loopback::host_addr6(self.peer_addr),
+                               self.peer_addr,
+                               self.next_header,
+                               hop_limit,
+                               Vec::from(buf));
+
+        try!(self.link.write(&packet.to_bytes()));
+        Ok(buf.len())
+    }
+
+    fn poll(&self, events: u16) -> Result<u16> {
+        if !self.data.is_empty() {
+            let revents = try!(self.link.poll(events));
+            Ok(revents | (events & POLLIN))
+        } else {
+            self.link.poll(events)
+        }
+    }
+
+    fn condition(&self) -> Option<&WaitCondition> {
+        self.link.condition()
+    }
+
+    fn sync(&mut self) -> Result<()> {
+        self.link.sync()
+    }
+}
+
+/// A IPv6 scheme. `ip6:<address>/<next header>` sends to and receives from a host, where the next
+/// header is in hexadecimal, and `ip6:/<next header>` waits for the first packet from any host.
+/// Every host is expected to be on the link, there is no routing
+pub struct Ip6Scheme {
+    pub cache: Arc<Intex<NdpCache>>,
+}
+
+impl KScheme for Ip6Scheme {
+    fn scheme(&self) -> &str {
+        "ip6"
+    }
+
+    fn open(&mut self, url: Url, _: usize, _: u32, _: u32) -> Result<Box<Resource>> {
+        let mut parts = url.reference().rsplitn(2, '/');
+        let next_header_string = parts.next().unwrap_or("");
+        let host_string = match parts.next() {
+            Some(host_string) => host_string.trim_left_matches('[').trim_right_matches(']'),
+            None => {
+                debug::d("IPv6: No next header provided\n");
+                return Err(Error::new(ENOENT));
+            }
+        };
+        let next_header = next_header_string.to_num_radix(16) as u8;
+
+        if !host_string.is_empty() {
+            let peer_addr = try!(Ipv6Addr::parse(host_string).ok_or(Error::new(EINVAL)));
+
+            let config = config::primary();
+            let peer_mac = if peer_addr.is_loopback() || peer_addr.equals(config.ip6()) {
+                // Packets to the local stack are looped back by the own hardware address
+                config.mac
+            } else if peer_addr.is_multicast() {
+                peer_addr.multicast_mac()
+            } else {
+                try!(ndp::resolve(&self.cache, peer_addr))
+            };
+
+            let link = try!(Url::from_str(&format!("ethernet:{}/86DD", &peer_mac.to_string())).unwrap().open());
+            Ok(box Ip6Resource {
+                link: link,
+                data: Vec::new(),
+                peer_addr: peer_addr,
+                next_header: next_header,
+            })
+        } else {
+            while let Ok(mut link) = Url::from_str("ethernet:/86DD").unwrap().open() {
+                let mut bytes = [0; 8192];
+                match link.read(&mut bytes) {
+                    Ok(count) => {
+                        if let Some(packet) = Ipv6::from_bytes(bytes[.. count].to_vec()) {
+                            if packet.header.next_header == next_header && loopback::accepts6(packet.header.dst) {
+                                return Ok(box Ip6Resource {
+                                    link: link,
+                                    data: packet.data,
+                                    peer_addr: packet.header.src,
+                                    next_header: next_header,
+                                });
+                            }
+                        }
+                    }
+                    Err(_) => break,
+                }
+            }
+
+            Err(Error::new(ENOENT))
+        }
+    }
+}
@@ -1,13 +1,23 @@
-pub use self::arp::ArpScheme;
+pub use self::arp::{ArpCache, ArpScheme};
+pub use self::dns::DnsScheme;
 pub use self::ethernet::EthernetScheme;
 pub use self::icmp::IcmpScheme;
+pub use self::icmpv6::Icmpv6Scheme;
 pub use self::ip::IpScheme;
+pub use self::ip6::Ip6Scheme;
+pub use self::ndp::{NdpCache, NdpScheme};
+pub use self::netcfg::NetcfgScheme;
 pub use self::tcp::TcpScheme;
 pub use self::udp::UdpScheme;
 
 pub mod arp;
+pub mod dns;
 pub mod ethernet;
 pub mod icmp;
+pub mod icmpv6;
 pub mod ip;
+pub mod ip6;
+pub mod ndp;
+pub mod netcfg;
 pub mod tcp;
 pub mod udp;
@@ -0,0 +1,212 @@
+use alloc::arc::Arc;
+use alloc::boxed::Box;
+
+use common::time::Duration;
+
+use collections::string::{String, ToString};
+use collections::vec::Vec;
+
+use arch::context::context_sleep;
+
+use network::common::*;
+use network::config;
+use network::neighbor::{NeighborAddr, NeighborCache};
+use network::ipv6::Ipv6;
+
+use fs::{KScheme, Resource, Url, VecResource};
+
+use sync::Intex;
+
+use system::error::{Error, Result, EAGAIN, EHOSTUNREACH};
+
+use super::icmpv6::{self, Icmpv6, ICMPV6_NEIGHBOR_ADVERTISEMENT, ICMPV6_NEIGHBOR_SOLICITATION,
+                    ICMPV6_NEXT_HEADER};
+
+/// Seconds an entry stays in the cache
+const NDP_TTL: i64 = 30;
+/// Times to send a solicitation before the host is unreachable
+const NDP_ATTEMPTS: usize = 3;
+/// Milliseconds to wait for an advertisement after each solicitation
+const NDP_TIMEOUT: i32 = 1000;
+
+const NDP_OPT_SOURCE_LINK_ADDR: u8 = 1;
+const NDP_OPT_TARGET_LINK_ADDR: u8 = 2;
+
+const NDP_FLAG_SOLICITED: u8 = 0x40;
+const NDP_FLAG_OVERRIDE: u8 = 0x20;
+
+impl NeighborAddr for Ipv6Addr {
+    fn equals(&self, other: Self) -> bool {
+        Ipv6Addr::equals(self, other)
+    }
+
+    fn to_string(&self) -> String {
+        Ipv6Addr::to_string(self)
+    }
+
+    fn ttl() -> i64 {
+        NDP_TTL
+    }
+}
+
+/// The cache of resolved hardware addresses of neighbors, shared by the IPv6 scheme and the ICMPv6
+/// reply loop
+pub type NdpCache = NeighborCache<Ipv6Addr>;
+
+/// Create a solicitation or advertisement for a target, with a link-layer address option
+fn neighbor_message(_type: u8, flags: u8, target: Ipv6Addr, option: u8, src: &Ipv6Addr, dst: &Ipv6Addr) -> Icmpv6 {
+    let mut data = Vec::from(&target.bytes[..]);
+    data.push(option);
+    data.push(1);
+    data.extend_from_slice(&config::primary().mac.bytes);
+
+    Icmpv6::new(_type, [flags, 0, 0, 0], data, src, dst)
+}
+
+/// The target of a solicitation or advertisement, and the hardware address in its link-layer
+/// address option of the given type
+fn parse(message: &Icmpv6, option: u8) -> Option<(Ipv6Addr, Option<MacAddr>)> {
+    if message.data.len() < 16 {
+        return None;
+    }
+
+    let mut target = Ipv6Addr { bytes: [0; 16] };
+    for i in 0..16 {
+        target.bytes[i] = message.data[i];
+    }
+
+    let mut mac = None;
+    let mut i = 16;
+    while i + 2 <= message.data.len() {
+        let len = message.data[i + 1] as usize * 8;
+        if len == 0 || i + len > message.data.len() {
+            return None;
+        }
+
+        if message.data[i] == option && len >= 8 {
+            mac = Some(MacAddr {
+                bytes: [message.data[i + 2], message.data[i + 3], message.data[i + 4],
+                        message.data[i + 5], message.data[i + 6], message.data[i + 7]]
+            });
+        }
+
+        i += len;
+    }
+
+    Some((target, mac))
+}
+
+/// Resolve the hardware address of a neighbor, from the cache or by sending solicitations
+pub fn resolve(cache: &Intex<NdpCache>, ip: Ipv6Addr) -> Result<MacAddr> {
+    if let Some(mac) = cache.lock().get(ip) {
+        return Ok(mac);
+    }
+
+    let mut link = try!(Url::from_str("ethernet:FF.FF.FF.FF.FF.FF/86DD").unwrap().open());
+
+    // Solicitations are sent on the link directly, as this is called while opening `ip6:`
+    let dst = ip.solicited_node();
+    let url = format!("ethernet:{}/86DD", dst.multicast_mac().to_string());
+    let mut solicit_link = try!(Url::from_str(&url).unwrap().open());
+
+    for _ in 0..NDP_ATTEMPTS {
+        let src = config::primary().ip6();
+        let solicitation = neighbor_message(ICMPV6_NEIGHBOR_SOLICITATION, 0, ip, NDP_OPT_SOURCE_LINK_ADDR, &src, &dst);
+        let packet = Ipv6::new(src, dst, ICMPV6_NEXT_HEADER, 255, solicitation.to_bytes());
+        try!(solicit_link.write(&packet.to_bytes()));
+
+        let deadline = Duration::monotonic() + Duration::new(0, NDP_TIMEOUT * 1000000);
+        loop {
+            let mut bytes = [0; 8192];
+            match link.read_nonblock(&mut bytes) {
+                Ok(count) => if let Some(packet) = Ipv6::from_bytes(bytes[.. count].to_vec()) {
+                    if packet.header.next_header == ICMPV6_NEXT_HEADER && packet.header.hop_limit == 255 &&
+                       Icmpv6::verify(&packet.data, &packet.header.src, &packet.header.dst) {
+                        if let Some(advertisement) = Icmpv6::from_bytes(packet.data) {
+                            if advertisement.header._type == ICMPV6_NEIGHBOR_ADVERTISEMENT {
+                                if let Some((target, Some(mac))) = parse(&advertisement, NDP_OPT_TARGET_LINK_ADDR) {
+                                    if target.equals(ip) {
+                                        cache.lock().insert(ip, mac);
+                                        return Ok(mac);
+                                    }
+                                }
+                            }
+                        }
+                    }
+                },
+                Err(ref err) if err.errno == EAGAIN => {
+                    // The reply loop may have learned the address from a solicitation of the host
+                    if let Some(mac) = cache.lock().get(ip) {
+                        return Ok(mac);
+                    }
+
+                    let now = Duration::monotonic();
+                    if now >= deadline {
+                        break;
+                    }
+                    unsafe { context_sleep(now + Duration::new(0, 10 * 1000000)) };
+                },
+                Err(err) => return Err(err),
+            }
+        }
+    }
+
+    Err(Error::new(EHOSTUNREACH))
+}
+
+/// Answer a solicitation for the address of this host, learning the address of its sender, or
+/// refresh a known neighbor from an advertisement
+pub fn receive(cache: &Intex<NdpCache>, packet: &Ipv6, message: &Icmpv6) {
+    // Neighbor discovery messages can not have been forwarded by a router
+    if packet.header.hop_limit != 255 || message.header.code != 0 {
+        return;
+    }
+
+    let ip6 = config::primary().ip6();
+    match message.header._type {
+        ICMPV6_NEIGHBOR_SOLICITATION => if let Some((target, mac)) = parse(message, NDP_OPT_SOURCE_LINK_ADDR) {
+            if !target.equals(ip6) {
+                return;
+            }
+
+            let src = packet.header.src;
+            if src.is_unspecified() {
+                // Another host is checking if the address is in use
+                icmpv6::send(ALL_NODES_IPV6_ADDR,
+                             &neighbor_message(ICMPV6_NEIGHBOR_ADVERTISEMENT, NDP_FLAG_OVERRIDE, ip6,
+                                      NDP_OPT_TARGET_LINK_ADDR, &ip6, &ALL_NODES_IPV6_ADDR));
+            } else {
+                if let Some(mac) = mac {
+                    cache.lock().insert(src, mac);
+                }
+                icmpv6::send(src,
+                             &neighbor_message(ICMPV6_NEIGHBOR_ADVERTISEMENT, NDP_FLAG_SOLICITED | NDP_FLAG_OVERRIDE, ip6,
+                                      NDP_OPT_TARGET_LINK_ADDR, &ip6, &src));
+            }
+        },
+        ICMPV6_NEIGHBOR_ADVERTISEMENT => if let Some((target, Some(mac))) = parse(message, NDP_OPT_TARGET_LINK_ADDR) {
+            let mut cache = cache.lock();
+            if cache.contains(target) {
+                cache.insert(target, mac);
+            }
+        },
+        _ => (),
+    }
+}
+
+/// A neighbor discovery scheme. Reading `ndp:` lists the neighbor cache as
+/// `<ip6> <mac> <seconds to expiry>` lines
+pub struct NdpScheme {
+    pub cache: Arc<Intex<NdpCache>>,
+}
+
+impl KScheme for NdpScheme {
+    fn scheme(&self) -> &str {
+        "ndp"
+    }
+
+    fn open(&mut self, _: Url, _: usize, _: u32, _: u32) -> Result<Box<Resource>> {
+        let string = self.cache.lock().list();
+        Ok(box VecResource::new("ndp:".to_string(), string.into_bytes()))
+    }
+}
@@ -0,0 +1,205 @@
+use alloc::arc::Arc;
+use alloc::boxed::Box;
+
+use collections::string::{String, ToString};
+use collections::vec::Vec;
+
+use core::{cmp, str};
+
+use fs::{DirResource, KScheme, Resource, ResourceSeek, Url};
+
+use network::common::{Ipv4Addr, MacAddr};
+use network::config::{NetworkConfig, DNS_SERVERS};
+
+use sync::Intex;
+
+use system::error::{Error, Result, EACCES, EINVAL, ENOENT};
+use system::syscall::{Dirent, Stat, MODE_DIR, MODE_FILE};
+
+/// The settings of each interface directory
+const SETTINGS: [&'static str; 6] = ["dns", "gateway", "ip", "ip6", "mac", "netmask"];
+
+/// A setting of a network interface
+pub struct NetcfgResource {
+    config: Arc<Intex<NetworkConfig>>,
+    path: String,
+    name: &'static str,
+    data: Vec<u8>,
+    seek: usize,
+    /// The resource was opened by root
+    writeable: bool,
+}
+
+impl NetcfgResource {
+    fn new(config: Arc<Intex<NetworkConfig>>, path: String, name: &'static str, writeable: bool) -> Self {
+        let data = get(&config.lock(), name).into_bytes();
+        NetcfgResource {
+            config: config,
+            path: path,
+            name: name,
+            data: data,
+            seek: 0,
+            writeable: writeable,
+        }
+    }
+}
+
+impl Resource for NetcfgResource {
+    fn dup(&self) -> Result<Box<Resource>> {
+        Ok(box NetcfgResource {
+            config: self.config.clone(),
+            path: self.path.clone(),
+            name: self.name,
+            data: self.data.clone(),
+            seek: self.seek,
+            writeable: self.writeable,
+        })
+    }
+
+    fn path(&self, buf: &mut [u8]) -> Result<usize> {
+        let path = self.path.as_bytes();
+
+        for (b, p) in buf.iter_mut().zip(path.iter()) {
+            *b = *p;
+        }
+
+        Ok(cmp::min(buf.len(), path.len()))
+    }
+
+    fn read(&mut self, buf: &mut [u8]) -> Result<usize> {
+        let mut i = 0;
+        while i < buf.len() && self.seek < self.data.len() {
+            buf[i] = self.data[self.seek];
+            self.seek += 1;
+            i += 1;
+        }
+        Ok(i)
+    }
+
+    /// Set the value from the whole buffer, which may end in a newline
+    fn write(&mut self, buf: &[u8]) -> Result<usize> {
+        if !self.writeable {
+            return Err(Error::new(EACCES));
+        }
+
+        let value = try!(str::from_utf8(buf).or(Err(Error::new(EINVAL)))).trim();
+
+        let mut config = self.config.lock();
+        try!(set(&mut config, self.name, value));
+
+        self.data = get(&config, self.name).into_bytes();
+        self.seek = 0;
+
+        Ok(buf.len())
+    }
+
+    fn seek(&mut self, pos: ResourceSeek) -> Result<usize> {
+        match pos {
+            ResourceSeek::Start(offset) => self.seek = cmp::min(self.data.len(), offset),
+            ResourceSeek::Current(offset) =>
+                self.seek = cmp::max(0, cmp::min(self.data.len() as isize, self.seek as isize + offset)) as usize,
+            ResourceSeek::End(offset) =>
+                self.seek = cmp::max(0, cmp::min(self.data.len() as isize, self.data.len() as isize + offset)) as usize,
+        }
+        Ok(self.seek)
+    }
+
+    fn stat(&self, stat: &mut Stat) -> Result<usize> {
+        stat.st_mode = MODE_FILE | 0o644;
+        stat.st_size = self.data.len() as u64;
+        Ok(0)
+    }
+
+    fn sync(&mut self) -> Result<()> {
+        Ok(())
+    }
+}
+
+/// Format a setting of a network interface
+fn get(config: &NetworkConfig, name: &str) -> String {
+    match name {
+        "dns" => config.dns_servers().iter().map(|dns| dns.to_string() + "\n").collect(),
+        "gateway" => config.gateway.to_string() + "\n",
+        "ip" => config.ip.to_string() + "\n",
+        "ip6" => config.ip6().to_string() + "\n",
+        "mac" => config.mac.to_string() + "\n",
+        "netmask" => config.netmask.to_string() + "\n",
+        _ => String::new(),
+    }
+}
+
+/// Parse and change a setting of a network interface
+fn set(config: &mut NetworkConfig, name: &str, value: &str) -> Result<()> {
+    match name {
+        "dns" => {
+            let mut servers = Vec::new();
+            for server in value.split_whitespace() {
+                servers.push(try!(Ipv4Addr::parse(server).ok_or(Error::new(EINVAL))));
+            }
+            if servers.len() > DNS_SERVERS {
+                return Err(Error::new(EINVAL));
+            }
+            config.set_dns_servers(&servers);
+        },
+        "gateway" => config.gateway = try!(Ipv4Addr::parse(value).ok_or(Error::new(EINVAL))),
+        "ip" => config.ip = try!(Ipv4Addr::parse(value).ok_or(Error::new(EINVAL))),
+        // The link-local address follows the hardware address
+        "ip6" => return Err(Error::new(EACCES)),
+        "mac" => config.mac = try!(MacAddr::parse(value).ok_or(Error::new(EINVAL))),
+        "netmask" => config.netmask = try!(Ipv4Addr::parse(value).ok_or(Error::new(EINVAL))),
+        _ => return Err(Error::new(ENOENT)),
+    }
+    Ok(())
+}
+
+/// The network configuration scheme. `netcfg:` lists the interfaces, and each `netcfg:/<n>/` has
+/// the settings `ip`, `netmask`, `gateway`, `dns` and `mac`, which can be read by anyone and
+/// written by root, and the IPv6 link-local address `ip6`, which can only be read. `dns` has one
+/// server per line, and up to `DNS_SERVERS` servers separated by whitespace can be written
+pub struct NetcfgScheme;
+
+impl KScheme for NetcfgScheme {
+    fn scheme(&self) -> &str {
+        "netcfg"
+    }
+
+    fn open(&mut self, url: Url, _: usize, uid: u32, _: u32) -> Result<Box<Resource>> {
+        let path = url.reference().trim_matches('/');
+        let interfaces = ::env().interfaces.lock();
+
+        if path.is_empty() {
+            let mut entries = Vec::new();
+            for i in 0..interfaces.len() {
+                entries.push(Dirent::new(&format!("{}", i), MODE_DIR, 0, i as u64));
+            }
+            return Ok(box DirResource::new("netcfg:/".to_string(), entries));
+        }
+
+        let mut parts = path.splitn(2, '/');
+        let index = try!(parts.next().unwrap_or("").parse::<usize>().or(Err(Error::new(ENOENT))));
+        let config = try!(interfaces.get(index).ok_or(Error::new(ENOENT)));
+
+        match parts.next().unwrap_or("") {
+            "" => {
+                let mut entries = Vec::new();
+                for (i, setting) in SETTINGS.iter().enumerate() {
+                    entries.push(Dirent::new(setting, MODE_FILE, 0, i as u64));
+                }
+                Ok(box DirResource::new(format!("netcfg:/{}/", index), entries))
+            },
+            name => match SETTINGS.iter().find(|setting| **setting == name) {
+                Some(setting) => Ok(box NetcfgResource::new(config.clone(),
+                                                           format!("netcfg:/{}/{}", index, setting),
+                                                           setting,
+                                                           uid == 0)),
+                None => Err(Error::new(ENOENT)),
+            },
+        }
+    }
+
+    fn stat(&mut self, url: Url, stat: &mut Stat) -> Result<()> {
+        let resource = try!(self.open(url, 0, 0, 0));
+        try!(resource.stat(stat));
+        Ok(())
+    }
+}
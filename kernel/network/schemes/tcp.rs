@@ -1,19 +1,31 @@
 use alloc::arc::Arc;
 use alloc::boxed::Box;
 
-use collections::Vec;
+use collections::{String, Vec};
 use collections::string::ToString;
+use collections::vec_deque::VecDeque;
 
 use common::random::rand;
+use common::time::{self, Duration};
 
-use core::{cmp, mem, slice, str};
+use core::{cmp, mem, slice};
 use core::cell::UnsafeCell;
+use core::ops::DerefMut;
 
-use fs::{KScheme, Resource, Url};
+use arch::context::{context_switch, Context};
 
-use network::common::{n16, n32, Checksum, Ipv4Addr, IP_ADDR, FromBytes, ToBytes};
+use fs::{KScheme, Resource, Url, VecResource};
 
-use system::error::{Error, Result, ENOENT, EPIPE};
+use network::common::{n16, n32, split_host_port, Checksum, IpAddr, Ipv4Addr, FromBytes, ToBytes};
+use network::ipv4::Ipv4;
+use network::ipv6::Ipv6;
+use network::loopback;
+
+use sync::{Intex, WaitCondition, WaitQueue};
+
+use system::error::{Error, Result, EACCES, EADDRINUSE, EAGAIN, ECONNABORTED, ECONNREFUSED, ECONNRESET, EINTR,
+                     EINVAL, ENOENT, EPIPE, ETIMEDOUT};
+use system::syscall::{POLLIN, POLLOUT};
 
 #[derive(Copy, Clone)]
 #[repr(packed)]
@@ -40,6 +52,106 @@ pub const TCP_RST: u16 = 1 << 2;
 pub const TCP_PSH: u16 = 1 << 3;
 pub const TCP_ACK: u16 = 1 << 4;
 
+/// Option kinds
+const TCP_OPT_END: u8 = 0;
+const TCP_OPT_NOP: u8 = 1;
+const TCP_OPT_MSS: u8 = 2;
+
+/// Maximum segment size announced to peers over IPv4, for a 1500 byte MTU
+const TCP_MSS: usize = 1460;
+/// Maximum segment size for peers that do not announce one
+const TCP_DEFAULT_MSS: usize = 536;
+/// Size of the receive buffer, which limits the announced window
+const TCP_RECV_BUFFER: usize = 65535;
+/// Size of the send buffer, writes block when it is full
+const TCP_SEND_BUFFER: usize = 65536;
+
+/// Retransmission timeouts in milliseconds, from RFC 6298
+const TCP_RTO_INITIAL: u64 = 1000;
+const TCP_RTO_MIN: u64 = 1000;
+const TCP_RTO_MAX: u64 = 60000;
+/// Clock granularity in milliseconds
+const TCP_CLOCK_GRANULARITY: u64 = 10;
+/// Times a segment is retransmitted before the connection is dropped
+const TCP_MAX_RETRIES: usize = 8;
+/// Maximum segment lifetime in milliseconds. TIME_WAIT, and FIN_WAIT_2 after close, last twice this
+const TCP_MSL: u64 = 30000;
+/// Connections of a listening port that are pending or waiting to be accepted
+const TCP_BACKLOG: usize = 16;
+/// Ports tried when picking an ephemeral port to listen on
+const TCP_EPHEMERAL_TRIES: usize = 64;
+
+impl Tcp {
+    /// Calculate the checksum, including the pseudo header with the IP addresses
+    pub fn checksum(&mut self, src: &IpAddr, dst: &IpAddr) {
+        self.header.checksum.data = 0;
+
+        unsafe {
+            let proto = n16::new(0x06);
+            let segment_len = n16::new((mem::size_of::<TcpHeader>() + self.options.len() + self.data.len()) as u16);
+            self.header.checksum.data =
+                Checksum::compile(Checksum::sum(src.as_bytes().as_ptr() as usize, src.as_bytes().len()) +
+                                  Checksum::sum(dst.as_bytes().as_ptr() as usize, dst.as_bytes().len()) +
+                                  Checksum::sum((&proto as *const n16) as usize,
+                                                mem::size_of::<n16>()) +
+                                  Checksum::sum((&segment_len as *const n16) as usize,
+                                                mem::size_of::<n16>()) +
+                                  Checksum::sum((&self.header as *const TcpHeader) as usize,
+                                                mem::size_of::<TcpHeader>()) +
+                                  Checksum::sum(self.options.as_ptr() as usize, self.options.len()) +
+                                  Checksum::sum(self.data.as_ptr() as usize, self.data.len()));
+        }
+    }
+
+    /// Check the checksum of a segment received from `src` at `dst`
+    pub fn verify(bytes: &[u8], src: &IpAddr, dst: &IpAddr) -> bool {
+        unsafe {
+            let proto = n16::new(0x06);
+            let segment_len = n16::new(bytes.len() as u16);
+            Checksum::compile(Checksum::sum(src.as_bytes().as_ptr() as usize, src.as_bytes().len()) +
+                              Checksum::sum(dst.as_bytes().as_ptr() as usize, dst.as_bytes().len()) +
+                              Checksum::sum((&proto as *const n16) as usize, mem::size_of::<n16>()) +
+                              Checksum::sum((&segment_len as *const n16) as usize, mem::size_of::<n16>()) +
+                              Checksum::sum(bytes.as_ptr() as usize, bytes.len())) == 0
+        }
+    }
+
+    /// The maximum segment size option
+    pub fn mss(&self) -> Option<usize> {
+        let mut i = 0;
+        while i < self.options.len() {
+            match self.options[i] {
+                TCP_OPT_END => break,
+                TCP_OPT_NOP => i += 1,
+                kind => {
+                    if i + 1 >= self.options.len() || self.options[i + 1] < 2 {
+                        break;
+                    }
+                    let len = self.options[i + 1] as usize;
+                    if kind == TCP_OPT_MSS && len == 4 && i + 4 <= self.options.len() {
+                        return Some(((self.options[i + 2] as usize) << 8) | self.options[i + 3] as usize);
+                    }
+                    i += len;
+                }
+            }
+        }
+        None
+    }
+
+    /// The length in sequence space, counting SYN and FIN
+    pub fn sequence_len(&self) -> u32 {
+        let flags = self.header.flags.get();
+        let mut len = self.data.len() as u32;
+        if flags & TCP_SYN == TCP_SYN {
+            len += 1;
+        }
+        if flags & TCP_FIN == TCP_FIN {
+            len += 1;
+        }
+        len
+    }
+}
+
 impl FromBytes for Tcp {
     fn from_bytes(bytes: Vec<u8>) -> Option<Self> {
         if bytes.len() >= mem::size_of::<TcpHeader>() {
@@ -47,11 +159,13 @@ impl FromBytes for Tcp {
                 let header = *(bytes.as_ptr() as *const TcpHeader);
                 let header_len = ((header.flags.get() & 0xF000) >> 10) as usize;
 
-                return Some(Tcp {
-                    header: header,
-                    options: bytes[mem::size_of::<TcpHeader>()..header_len].to_vec(),
-                    data: bytes[header_len..bytes.len()].to_vec(),
-                });
+                if header_len >= mem::size_of::<TcpHeader>() && header_len <= bytes.len() {
+                    return Some(Tcp {
+                        header: header,
+                        options: bytes[mem::size_of::<TcpHeader>()..header_len].to_vec(),
+                        data: bytes[header_len..bytes.len()].to_vec(),
+                    });
+                }
             }
         }
         None
@@ -71,18 +185,265 @@ impl ToBytes for Tcp {
     }
 }
 
+/// Compare sequence numbers, which wrap around
+pub fn seq_lt(a: u32, b: u32) -> bool {
+    (a.wrapping_sub(b) as i32) < 0
+}
+
+pub fn seq_le(a: u32, b: u32) -> bool {
+    (a.wrapping_sub(b) as i32) <= 0
+}
+
+pub fn seq_gt(a: u32, b: u32) -> bool {
+    seq_lt(b, a)
+}
+
+/// Queue data received past a gap. The queue is kept in sequence order, with overlapping and
+/// adjacent data merged, so it never holds more bytes than the window the data is trimmed to
+pub fn queue_segment(queue: &mut Vec<(u32, Vec<u8>)>, sequence: u32, data: &[u8]) {
+    let mut start = sequence;
+    let mut merged = data.to_vec();
+
+    let mut i = 0;
+    while i < queue.len() {
+        let end = start.wrapping_add(merged.len() as u32);
+        let queued_end = queue[i].0.wrapping_add(queue[i].1.len() as u32);
+        if seq_le(queue[i].0, end) && seq_le(start, queued_end) {
+            let (queued_start, mut queued) = queue.remove(i);
+            if seq_lt(queued_start, start) {
+                if seq_gt(end, queued_end) {
+                    queued.extend_from_slice(&merged[queued_end.wrapping_sub(start) as usize ..]);
+                }
+                start = queued_start;
+                merged = queued;
+            } else if seq_gt(queued_end, end) {
+                merged.extend_from_slice(&queued[end.wrapping_sub(queued_start) as usize ..]);
+            }
+        } else {
+            i += 1;
+        }
+    }
+
+    let position = queue.iter().position(|&(queued_start, _)| seq_lt(start, queued_start)).unwrap_or(queue.len());
+    queue.insert(position, (start, merged));
+}
+
+/// The monotonic time a number of milliseconds from now
+fn after(millis: u64) -> Duration {
+    Duration::monotonic() + Duration::new((millis / 1000) as i64, (millis % 1000) as i32 * time::NANOS_PER_MILLI)
+}
+
+fn millis(duration: Duration) -> u64 {
+    if duration.secs < 0 {
+        0
+    } else {
+        duration.secs as u64 * 1000 + (duration.nanos / time::NANOS_PER_MILLI) as u64
+    }
+}
+
+/// The earlier of two optional times
+fn earliest(a: Option<Duration>, b: Option<Duration>) -> Option<Duration> {
+    match (a, b) {
+        (Some(a), Some(b)) => Some(if a < b { a } else { b }),
+        (Some(a), None) => Some(a),
+        (None, b) => b,
+    }
+}
+
+/// Block until the resource is readable, one of the conditions is notified, or the time is
+/// reached. Without a condition or a time, the resource is polled again after a short interval
+fn block(resource: &Resource, conditions: &[&WaitCondition], wake: Option<Duration>) -> Result<()> {
+    let mut contexts = ::env().contexts.lock();
+    let mut current = try!(contexts.current_mut());
+
+    if current.signal_interrupt() {
+        return Err(Error::new(EINTR));
+    }
+
+    if try!(resource.poll(POLLIN)) & POLLIN == POLLIN {
+        return Ok(());
+    }
+
+    let mut waits: Vec<*const WaitCondition> = Vec::new();
+    if let Some(condition) = resource.condition() {
+        waits.push(condition);
+    }
+    for &condition in conditions.iter() {
+        waits.push(condition);
+    }
+
+    let context_ptr: *mut Context = current.deref_mut().deref_mut();
+    unsafe {
+        for &condition in waits.iter() {
+            (*condition).subscribe(context_ptr);
+        }
+
+        current.blocked = true;
+        current.wake = match wake {
+            Some(wake) => Some(wake),
+            None if waits.is_empty() => Some(after(TCP_CLOCK_GRANULARITY)),
+            None => None,
+        };
+
+        context_switch();
+
+        current.wake = None;
+
+        for &condition in waits.iter() {
+            (*condition).unsubscribe(context_ptr);
+        }
+    }
+
+    Ok(())
+}
+
+/// The states of a connection, from RFC 793
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum TcpState {
+    Closed,
+    Listen,
+    SynSent,
+    SynReceived,
+    Established,
+    FinWait1,
+    FinWait2,
+    CloseWait,
+    Closing,
+    LastAck,
+    TimeWait,
+}
+
+/// A segment that was sent but not acknowledged
+struct TcpUnacked {
+    sequence: u32,
+    flags: u16,
+    data: Vec<u8>,
+}
+
+impl TcpUnacked {
+    fn end(&self) -> u32 {
+        let mut len = self.data.len() as u32;
+        if self.flags & TCP_SYN == TCP_SYN {
+            len += 1;
+        }
+        if self.flags & TCP_FIN == TCP_FIN {
+            len += 1;
+        }
+        self.sequence.wrapping_add(len)
+    }
+}
+
 pub struct TcpStream {
     ip: Box<Resource>,
-    peer_addr: Ipv4Addr,
+    peer_addr: IpAddr,
     peer_port: u16,
     host_port: u16,
-    sequence: u32,
-    acknowledge: u32,
+    state: TcpState,
+    /// The error that closed the connection
+    error: Option<isize>,
+
+    /// Initial send sequence number
+    iss: u32,
+    /// Oldest unacknowledged sequence number
+    snd_una: u32,
+    /// Next sequence number to send
+    snd_nxt: u32,
+    /// Window announced by the peer
+    snd_wnd: u32,
+    /// Sequence and acknowledgement numbers of the segment that last updated the window
+    snd_wl1: u32,
+    snd_wl2: u32,
+    /// Maximum segment size of the peer
+    snd_mss: usize,
+    /// Next sequence number expected from the peer
+    rcv_nxt: u32,
+    /// Window last announced to the peer
+    rcv_adv: usize,
+
+    /// Data written but not sent yet
+    send_buf: VecDeque<u8>,
+    /// Segments sent but not acknowledged, in sequence order
+    unacked: VecDeque<TcpUnacked>,
+    /// Data received in order, waiting to be read
+    recv_buf: VecDeque<u8>,
+    /// Data received past a gap, with its sequence number, in order and without overlaps
+    out_of_order: Vec<(u32, Vec<u8>)>,
+    /// Sequence number of a FIN from the peer, which may be past a gap
+    fin_sequence: Option<u32>,
+    /// The peer has finished sending
+    fin_received: bool,
+    /// The stream was closed, a FIN is sent after the buffered data
+    close_requested: bool,
+    /// A FIN was sent
+    fin_sent: bool,
+
+    /// Smoothed round trip time and its variation, in milliseconds
+    srtt: Option<u64>,
+    rttvar: u64,
+    /// Retransmission timeout in milliseconds
+    rto: u64,
+    /// The end of the segment being timed for a round trip sample, and when it was sent
+    rtt_sample: Option<(u32, Duration)>,
+    /// Retransmissions of the oldest segment
+    retries: usize,
+    /// Probes of a closed window sent since the peer last acknowledged anything. These are not
+    /// retransmissions, a peer that keeps acknowledging them is probed for as long as it takes
+    probes: usize,
+    /// When the oldest segment is retransmitted, or a window probe is sent
+    retransmit_at: Option<Duration>,
+    /// When a connection in TIME_WAIT or FIN_WAIT_2 is closed
+    close_at: Option<Duration>,
+
+    /// The queue of the context that finishes closing the connection once it is dropped
+    closing: Arc<WaitQueue<TcpStream>>,
 }
 
 impl TcpStream {
+    pub fn new(ip: Box<Resource>, peer_addr: IpAddr, peer_port: u16, host_port: u16,
+               closing: Arc<WaitQueue<TcpStream>>) -> Self {
+        let iss = rand() as u32;
+        TcpStream {
+            ip: ip,
+            peer_addr: peer_addr,
+            peer_port: peer_port,
+            host_port: host_port,
+            state: TcpState::Closed,
+            error: None,
+
+            iss: iss,
+            snd_una: iss,
+            snd_nxt: iss,
+            snd_wnd: 0,
+            snd_wl1: 0,
+            snd_wl2: 0,
+            snd_mss: TCP_DEFAULT_MSS,
+            rcv_nxt: 0,
+            rcv_adv: 0,
+
+            send_buf: VecDeque::new(),
+            unacked: VecDeque::new(),
+            recv_buf: VecDeque::new(),
+            out_of_order: Vec::new(),
+            fin_sequence: None,
+            fin_received: false,
+            close_requested: false,
+            fin_sent: false,
+
+            srtt: None,
+            rttvar: 0,
+            rto: TCP_RTO_INITIAL,
+            rtt_sample: None,
+            retries: 0,
+            probes: 0,
+            retransmit_at: None,
+            close_at: None,
+
+            closing: closing,
+        }
+    }
+
     fn path(&self, buf: &mut [u8]) -> Result<usize> {
-        let path_string = format!("tcp:{}:{}/{}", self.peer_addr.to_string(), self.peer_port, self.host_port);
+        let path_string = format!("tcp:{}:{}/{}", self.peer_addr.to_host_string(), self.peer_port, self.host_port);
         let path = path_string.as_bytes();
 
         for (b, p) in buf.iter_mut().zip(path.iter()) {
@@ -92,456 +453,1110 @@ impl TcpStream {
         Ok(cmp::min(buf.len(), path.len()))
     }
 
-    fn read(&mut self, buf: &mut [u8]) -> Result<usize> {
-        loop {
-            let mut bytes = [0; 8192];
-            match self.ip.read(&mut bytes) {
-                Ok(count) => {
-                    if let Some(segment) = Tcp::from_bytes(bytes[.. count].to_vec()) {
-                        if (segment.header.flags.get() & (TCP_PSH | TCP_SYN | TCP_ACK)) ==
-                           (TCP_PSH | TCP_ACK) &&
-                           segment.header.dst.get() == self.host_port &&
-                           segment.header.src.get() == self.peer_port {
-                            // Send ACK
-                            self.sequence = segment.header.ack_num.get();
-                            self.acknowledge = segment.header.sequence.get() +
-                                               segment.data.len() as u32;
-                            let mut tcp = Tcp {
-                                        header: TcpHeader {
-                                            src: n16::new(self.host_port),
-                                            dst: n16::new(self.peer_port),
-                                            sequence: n32::new(self.sequence),
-                                            ack_num: n32::new(self.acknowledge),
-                                            flags: n16::new(((mem::size_of::<TcpHeader>() << 10) & 0xF000) as u16 | TCP_ACK),
-                                            window_size: n16::new(65535),
-                                            checksum: Checksum {
-                                                data: 0
-                                            },
-                                            urgent_pointer: n16::new(0)
-                                        },
-                                        options: Vec::new(),
-                                        data: Vec::new()
-                                    };
-
-                            unsafe {
-                                let proto = n16::new(0x06);
-                                let segment_len = n16::new((mem::size_of::<TcpHeader>() + tcp.options.len() + tcp.data.len()) as u16);
-                                tcp.header.checksum.data = Checksum::compile(
-                                            Checksum::sum((&IP_ADDR as *const Ipv4Addr) as usize, mem::size_of::<Ipv4Addr>()) +
-                                            Checksum::sum((&self.peer_addr as *const Ipv4Addr) as usize, mem::size_of::<Ipv4Addr>()) +
-                                            Checksum::sum((&proto as *const n16) as usize, mem::size_of::<n16>()) +
-                                            Checksum::sum((&segment_len as *const n16) as usize, mem::size_of::<n16>()) +
-                                            Checksum::sum((&tcp.header as *const TcpHeader) as usize, mem::size_of::<TcpHeader>()) +
-                                            Checksum::sum(tcp.options.as_ptr() as usize, tcp.options.len()) +
-                                            Checksum::sum(tcp.data.as_ptr() as usize, tcp.data.len())
-                                            );
-                            }
+    /// The largest segment this host can receive, which is smaller over IPv6 for its longer header
+    fn host_mss(&self) -> usize {
+        match self.peer_addr {
+            IpAddr::V4(_) => TCP_MSS,
+            IpAddr::V6(_) => TCP_MSS - 20,
+        }
+    }
 
-                            let _ = self.ip.write(&tcp.to_bytes());
+    /// The space left in the receive buffer
+    fn rcv_wnd(&self) -> usize {
+        TCP_RECV_BUFFER.saturating_sub(self.recv_buf.len())
+    }
 
-                            // TODO: Support broken packets (one packet in two buffers)
-                            let mut i = 0;
-                            while i < buf.len() && i < segment.data.len() {
-                                buf[i] = segment.data[i];
-                                i += 1;
-                            }
-                            return Ok(i);
-                        }
-                    }
-                }
-                Err(err) => return Err(err),
-            }
+    /// Send a segment with the given sequence and acknowledgement numbers
+    fn send_raw(&mut self, sequence: u32, ack_num: u32, flags: u16, data: &[u8]) {
+        let mut options = Vec::new();
+        if flags & TCP_SYN == TCP_SYN {
+            options.push(TCP_OPT_MSS);
+            options.push(4);
+            options.extend_from_slice(&n16::new(self.host_mss() as u16).bytes);
         }
-    }
 
-    fn write(&mut self, buf: &[u8]) -> Result<usize> {
-        let tcp_data = Vec::from(buf);
+        let window = cmp::min(self.rcv_wnd(), 65535);
+        self.rcv_adv = window;
 
         let mut tcp = Tcp {
             header: TcpHeader {
                 src: n16::new(self.host_port),
                 dst: n16::new(self.peer_port),
-                sequence: n32::new(self.sequence),
-                ack_num: n32::new(self.acknowledge),
-                flags: n16::new((((mem::size_of::<TcpHeader>()) << 10) & 0xF000) as u16 | TCP_PSH |
-                                TCP_ACK),
-                window_size: n16::new(65535),
+                sequence: n32::new(sequence),
+                ack_num: n32::new(ack_num),
+                flags: n16::new((((mem::size_of::<TcpHeader>() + options.len()) << 10) & 0xF000) as u16 | flags),
+                window_size: n16::new(window as u16),
                 checksum: Checksum { data: 0 },
                 urgent_pointer: n16::new(0),
             },
-            options: Vec::new(),
-            data: tcp_data,
+            options: options,
+            data: Vec::from(data),
         };
 
-        unsafe {
-            let proto = n16::new(0x06);
-            let segment_len = n16::new((mem::size_of::<TcpHeader>() + tcp.data.len()) as u16);
-            tcp.header.checksum.data =
-                Checksum::compile(Checksum::sum((&IP_ADDR as *const Ipv4Addr) as usize,
-                                                mem::size_of::<Ipv4Addr>()) +
-                                  Checksum::sum((&self.peer_addr as *const Ipv4Addr) as usize,
-                                                mem::size_of::<Ipv4Addr>()) +
-                                  Checksum::sum((&proto as *const n16) as usize,
-                                                mem::size_of::<n16>()) +
-                                  Checksum::sum((&segment_len as *const n16) as usize,
-                                                mem::size_of::<n16>()) +
-                                  Checksum::sum((&tcp.header as *const TcpHeader) as usize,
-                                                mem::size_of::<TcpHeader>()) +
-                                  Checksum::sum(tcp.options.as_ptr() as usize, tcp.options.len()) +
-                                  Checksum::sum(tcp.data.as_ptr() as usize, tcp.data.len()));
-        }
+        tcp.checksum(&loopback::host_ip(self.peer_addr), &self.peer_addr);
 
-        match self.ip.write(&tcp.to_bytes()) {
-            Ok(size) => {
-                loop {
-                    // Wait for ACK
-                    let mut bytes = [0; 8192];
-                    match self.ip.read(&mut bytes) {
-                        Ok(count) => {
-                            if let Some(segment) = Tcp::from_bytes(bytes[.. count].to_vec()) {
-                                if segment.header.dst.get() == self.host_port &&
-                                   segment.header.src.get() == self.peer_port {
-                                    return if (segment.header.flags.get() & (TCP_PSH | TCP_SYN | TCP_ACK)) == TCP_ACK {
-                                        self.sequence = segment.header.ack_num.get();
-                                        self.acknowledge = segment.header.sequence.get();
-                                        Ok(size)
-                                    } else {
-                                        Err(Error::new(EPIPE))
-                                    };
-                                }
-                            }
-                        }
-                        Err(err) => return Err(err),
-                    }
-                }
-            }
-            Err(err) => Err(err),
-        }
+        let _ = self.ip.write(&tcp.to_bytes());
     }
 
-    fn sync(&mut self) -> Result<()> {
-        self.ip.sync()
+    /// Send a segment acknowledging everything received
+    fn send_segment(&mut self, sequence: u32, flags: u16, data: &[u8]) {
+        let ack_num = self.rcv_nxt;
+        self.send_raw(sequence, ack_num, flags, data);
     }
 
-    /// Etablish client
-    pub fn client_establish(&mut self) -> bool {
-        // Send SYN
-        let mut tcp = Tcp {
-            header: TcpHeader {
-                src: n16::new(self.host_port),
-                dst: n16::new(self.peer_port),
-                sequence: n32::new(self.sequence),
-                ack_num: n32::new(self.acknowledge),
-                flags: n16::new(((mem::size_of::<TcpHeader>() << 10) & 0xF000) as u16 | TCP_SYN),
-                window_size: n16::new(65535),
-                checksum: Checksum { data: 0 },
-                urgent_pointer: n16::new(0),
-            },
-            options: Vec::new(),
-            data: Vec::new(),
-        };
+    fn send_ack(&mut self) {
+        let sequence = self.snd_nxt;
+        self.send_segment(sequence, TCP_ACK, &[]);
+    }
 
-        unsafe {
-            let proto = n16::new(0x06);
-            let segment_len = n16::new((mem::size_of::<TcpHeader>() + tcp.options.len() +
-                                        tcp.data
-                                           .len()) as u16);
-            tcp.header.checksum.data =
-                Checksum::compile(Checksum::sum((&IP_ADDR as *const Ipv4Addr) as usize,
-                                                mem::size_of::<Ipv4Addr>()) +
-                                  Checksum::sum((&self.peer_addr as *const Ipv4Addr) as usize,
-                                                mem::size_of::<Ipv4Addr>()) +
-                                  Checksum::sum((&proto as *const n16) as usize,
-                                                mem::size_of::<n16>()) +
-                                  Checksum::sum((&segment_len as *const n16) as usize,
-                                                mem::size_of::<n16>()) +
-                                  Checksum::sum((&tcp.header as *const TcpHeader) as usize,
-                                                mem::size_of::<TcpHeader>()) +
-                                  Checksum::sum(tcp.options.as_ptr() as usize, tcp.options.len()) +
-                                  Checksum::sum(tcp.data.as_ptr() as usize, tcp.data.len()));
+    /// Reset the peer of an unacceptable segment
+    fn send_reset(&mut self, segment: &Tcp) {
+        if segment.header.flags.get() & TCP_ACK == TCP_ACK {
+            self.send_raw(segment.header.ack_num.get(), 0, TCP_RST, &[]);
+        } else {
+            let ack_num = segment.header.sequence.get().wrapping_add(segment.sequence_len());
+            self.send_raw(0, ack_num, TCP_RST | TCP_ACK, &[]);
         }
+    }
 
-        match self.ip.write(&tcp.to_bytes()) {
-            Ok(_) => {
-                loop {
-                    // Wait for SYN-ACK
-                    let mut bytes = [0; 8192];
-                    match self.ip.read(&mut bytes) {
-                        Ok(count) => {
-                            if let Some(segment) = Tcp::from_bytes(bytes[.. count].to_vec()) {
-                                if segment.header.dst.get() == self.host_port &&
-                                   segment.header.src.get() == self.peer_port {
-                                    return if (segment.header.flags.get() & (TCP_PSH | TCP_SYN | TCP_ACK)) == (TCP_SYN | TCP_ACK) {
-                                        self.sequence = segment.header.ack_num.get();
-                                        self.acknowledge = segment.header.sequence.get();
-
-                                        self.acknowledge += 1;
-                                        tcp = Tcp {
-                                                header: TcpHeader {
-                                                    src: n16::new(self.host_port),
-                                                    dst: n16::new(self.peer_port),
-                                                    sequence: n32::new(self.sequence),
-                                                    ack_num: n32::new(self.acknowledge),
-                                                    flags: n16::new(((mem::size_of::<TcpHeader>() << 10) & 0xF000) as u16 | TCP_ACK),
-                                                    window_size: n16::new(65535),
-                                                    checksum: Checksum {
-                                                        data: 0
-                                                    },
-                                                    urgent_pointer: n16::new(0)
-                                                },
-                                                options: Vec::new(),
-                                                data: Vec::new()
-                                            };
-
-                                        unsafe {
-                                            let proto = n16::new(0x06);
-                                            let segment_len = n16::new((mem::size_of::<TcpHeader>() + tcp.options.len() + tcp.data.len()) as u16);
-                                            tcp.header.checksum.data = Checksum::compile(
-                                                    Checksum::sum((&IP_ADDR as *const Ipv4Addr) as usize, mem::size_of::<Ipv4Addr>()) +
-                                                    Checksum::sum((&self.peer_addr as *const Ipv4Addr) as usize, mem::size_of::<Ipv4Addr>()) +
-                                                    Checksum::sum((&proto as *const n16) as usize, mem::size_of::<n16>()) +
-                                                    Checksum::sum((&segment_len as *const n16) as usize, mem::size_of::<n16>()) +
-                                                    Checksum::sum((&tcp.header as *const TcpHeader) as usize, mem::size_of::<TcpHeader>()) +
-                                                    Checksum::sum(tcp.options.as_ptr() as usize, tcp.options.len()) +
-                                                    Checksum::sum(tcp.data.as_ptr() as usize, tcp.data.len())
-                                                    );
-                                        }
-
-                                        let _ = self.ip.write(&tcp.to_bytes());
-
-                                        true
-                                    } else {
-                                        false
-                                    };
-                                }
-                            }
-                        }
-                        Err(_) => return false,
-                    }
-                }
-            }
-            Err(_) => false,
+    /// Send a new segment and queue it for retransmission
+    fn transmit(&mut self, flags: u16, data: Vec<u8>) {
+        let segment = TcpUnacked {
+            sequence: self.snd_nxt,
+            flags: flags,
+            data: data,
+        };
+
+        self.resend(&segment);
+
+        self.snd_nxt = segment.end();
+        if self.rtt_sample.is_none() {
+            self.rtt_sample = Some((segment.end(), Duration::monotonic()));
+        }
+        if self.retransmit_at.is_none() {
+            self.retransmit_at = Some(after(self.rto));
         }
+
+        self.unacked.push_back(segment);
     }
 
-    /// Try to establish a server connection
-    pub fn server_establish(&mut self, _: Tcp) -> bool {
-        // Send SYN-ACK
-        self.acknowledge += 1;
-        let mut tcp = Tcp {
-            header: TcpHeader {
-                src: n16::new(self.host_port),
-                dst: n16::new(self.peer_port),
-                sequence: n32::new(self.sequence),
-                ack_num: n32::new(self.acknowledge),
-                flags: n16::new(((mem::size_of::<TcpHeader>() << 10) & 0xF000) as u16 | TCP_SYN |
-                                TCP_ACK),
-                window_size: n16::new(65535),
-                checksum: Checksum { data: 0 },
-                urgent_pointer: n16::new(0),
-            },
-            options: Vec::new(),
-            data: Vec::new(),
-        };
+    fn resend(&mut self, segment: &TcpUnacked) {
+        let mut flags = segment.flags;
+        if self.state != TcpState::SynSent {
+            flags |= TCP_ACK;
+        }
+        self.send_segment(segment.sequence, flags, &segment.data);
+    }
 
-        unsafe {
-            let proto = n16::new(0x06);
-            let segment_len = n16::new((mem::size_of::<TcpHeader>() + tcp.options.len() +
-                                        tcp.data
-                                           .len()) as u16);
-            tcp.header.checksum.data =
-                Checksum::compile(Checksum::sum((&IP_ADDR as *const Ipv4Addr) as usize,
-                                                mem::size_of::<Ipv4Addr>()) +
-                                  Checksum::sum((&self.peer_addr as *const Ipv4Addr) as usize,
-                                                mem::size_of::<Ipv4Addr>()) +
-                                  Checksum::sum((&proto as *const n16) as usize,
-                                                mem::size_of::<n16>()) +
-                                  Checksum::sum((&segment_len as *const n16) as usize,
-                                                mem::size_of::<n16>()) +
-                                  Checksum::sum((&tcp.header as *const TcpHeader) as usize,
-                                                mem::size_of::<TcpHeader>()) +
-                                  Checksum::sum(tcp.options.as_ptr() as usize, tcp.options.len()) +
-                                  Checksum::sum(tcp.data.as_ptr() as usize, tcp.data.len()));
+    /// Send buffered data that fits in the window of the peer, then a FIN if the stream is closed
+    fn output(&mut self) {
+        if self.state != TcpState::Established && self.state != TcpState::CloseWait {
+            return;
         }
 
-        match self.ip.write(&tcp.to_bytes()) {
-            Ok(_) => {
-                loop {
-                    // Wait for ACK
-                    let mut bytes = [0; 8192];
-                    match self.ip.read(&mut bytes) {
-                        Ok(count ) => {
-                            if let Some(segment) = Tcp::from_bytes(bytes[.. count].to_vec()) {
-                                if segment.header.dst.get() == self.host_port &&
-                                   segment.header.src.get() == self.peer_port {
-                                    return if (segment.header.flags.get() & (TCP_PSH | TCP_SYN | TCP_ACK)) == TCP_ACK {
-                                        self.sequence = segment.header.ack_num.get();
-                                        self.acknowledge = segment.header.sequence.get();
-                                        true
-                                    } else {
-                                        false
-                                    };
-                                }
-                            }
-                        }
-                        Err(_) => return false,
-                    }
+        loop {
+            let in_flight = self.snd_nxt.wrapping_sub(self.snd_una);
+            let usable = self.snd_wnd.saturating_sub(in_flight) as usize;
+            if self.send_buf.is_empty() || usable == 0 {
+                break;
+            }
+
+            let len = cmp::min(cmp::min(self.send_buf.len(), self.snd_mss), usable);
+            let mut data = Vec::with_capacity(len);
+            for _ in 0..len {
+                if let Some(b) = self.send_buf.pop_front() {
+                    data.push(b);
                 }
             }
-            Err(_) => false,
+            self.transmit(TCP_PSH, data);
+        }
+
+        if self.send_buf.is_empty() {
+            if self.close_requested && !self.fin_sent {
+                self.fin_sent = true;
+                self.transmit(TCP_FIN, Vec::new());
+                self.state = if self.state == TcpState::Established {
+                    TcpState::FinWait1
+                } else {
+                    TcpState::LastAck
+                };
+            }
+        } else if self.retransmit_at.is_none() {
+            // The window is closed, probe it when the timer expires
+            self.retransmit_at = Some(after(self.rto));
         }
     }
-}
 
-impl Drop for TcpStream {
-    fn drop(&mut self) {
-        // Send FIN-ACK
-        let mut tcp = Tcp {
-            header: TcpHeader {
-                src: n16::new(self.host_port),
-                dst: n16::new(self.peer_port),
-                sequence: n32::new(self.sequence),
-                ack_num: n32::new(self.acknowledge),
-                flags: n16::new((((mem::size_of::<TcpHeader>()) << 10) & 0xF000) as u16 | TCP_FIN | TCP_ACK),
-                window_size: n16::new(65535),
-                checksum: Checksum { data: 0 },
-                urgent_pointer: n16::new(0),
+    /// Update the retransmission timeout with a round trip time, from RFC 6298
+    fn update_rto(&mut self, rtt: u64) {
+        let srtt = match self.srtt {
+            Some(srtt) => {
+                let delta = if srtt > rtt {
+                    srtt - rtt
+                } else {
+                    rtt - srtt
+                };
+                self.rttvar = (3 * self.rttvar + delta) / 4;
+                (7 * srtt + rtt) / 8
             },
-            options: Vec::new(),
-            data: Vec::new(),
+            None => {
+                self.rttvar = rtt / 2;
+                rtt
+            }
         };
+        self.srtt = Some(srtt);
+        self.rto = cmp::max(cmp::min(srtt + cmp::max(TCP_CLOCK_GRANULARITY, 4 * self.rttvar), TCP_RTO_MAX),
+                            TCP_RTO_MIN);
+    }
 
-        unsafe {
-            let proto = n16::new(0x06);
-            let segment_len = n16::new((mem::size_of::<TcpHeader>() + tcp.options.len() +
-                                        tcp.data
-                                           .len()) as u16);
-            tcp.header.checksum.data =
-                Checksum::compile(Checksum::sum((&IP_ADDR as *const Ipv4Addr) as usize,
-                                                mem::size_of::<Ipv4Addr>()) +
-                                  Checksum::sum((&self.peer_addr as *const Ipv4Addr) as usize,
-                                                mem::size_of::<Ipv4Addr>()) +
-                                  Checksum::sum((&proto as *const n16) as usize,
-                                                mem::size_of::<n16>()) +
-                                  Checksum::sum((&segment_len as *const n16) as usize,
-                                                mem::size_of::<n16>()) +
-                                  Checksum::sum((&tcp.header as *const TcpHeader) as usize,
-                                                mem::size_of::<TcpHeader>()) +
-                                  Checksum::sum(tcp.options.as_ptr() as usize, tcp.options.len()) +
-                                  Checksum::sum(tcp.data.as_ptr() as usize, tcp.data.len()));
+    /// Process a new acknowledgement number
+    fn acknowledged(&mut self, ack: u32) {
+        self.snd_una = ack;
+
+        if let Some((sample_end, sent)) = self.rtt_sample {
+            if seq_le(sample_end, ack) {
+                self.update_rto(millis(Duration::monotonic() - sent));
+                self.rtt_sample = None;
+            }
         }
 
-        let _ = self.ip.write(&tcp.to_bytes());
-    }
-}
+        while let Some(mut segment) = self.unacked.pop_front() {
+            if seq_le(segment.end(), ack) {
+                continue;
+            }
 
-/// A TCP resource
-pub struct TcpResource {
-    stream: Arc<UnsafeCell<TcpStream>>
-}
+            // Remove the acknowledged part of the segment
+            if seq_lt(segment.sequence, ack) {
+                let mut acked = ack.wrapping_sub(segment.sequence) as usize;
+                if segment.flags & TCP_SYN == TCP_SYN {
+                    segment.flags &= !TCP_SYN;
+                    acked -= 1;
+                }
+                let acked = cmp::min(acked, segment.data.len());
+                segment.data = segment.data[acked..].to_vec();
+                segment.sequence = ack;
+            }
 
-impl Resource for TcpResource {
-    fn dup(&self) -> Result<Box<Resource>> {
-        Ok(box TcpResource {
-            stream: self.stream.clone()
-        })
-    }
+            self.unacked.push_front(segment);
+            break;
+        }
 
-    fn path(&self, buf: &mut [u8]) -> Result<usize> {
-        unsafe { (*self.stream.get()).path(buf) }
+        self.retries = 0;
+        self.retransmit_at = if self.unacked.is_empty() {
+            None
+        } else {
+            Some(after(self.rto))
+        };
     }
 
-    fn read(&mut self, buf: &mut [u8]) -> Result<usize> {
-        unsafe { (*self.stream.get()).read(buf) }
+    /// All data sent, including a FIN, is acknowledged
+    fn fin_acked(&self) -> bool {
+        self.fin_sent && self.snd_una == self.snd_nxt
     }
 
-    fn write(&mut self, buf: &[u8]) -> Result<usize> {
-        unsafe { (*self.stream.get()).write(buf) }
+    fn time_wait(&mut self) {
+        self.state = TcpState::TimeWait;
+        self.retransmit_at = None;
+        self.close_at = Some(after(2 * TCP_MSL));
     }
 
-    fn sync(&mut self) -> Result<()> {
-        unsafe { (*self.stream.get()).sync() }
+    /// Close the connection because of an error
+    fn abort(&mut self, errno: isize) {
+        self.state = TcpState::Closed;
+        self.error = Some(errno);
+        self.send_buf.clear();
+        self.unacked.clear();
+        self.retransmit_at = None;
+        self.close_at = None;
     }
-}
-
-/// A TCP scheme
-pub struct TcpScheme;
 
-impl KScheme for TcpScheme {
-    fn scheme(&self) -> &str {
-        "tcp"
-    }
+    /// Queue data received at a sequence number, reassembling data received out of order
+    fn receive_data(&mut self, mut sequence: u32, data: &[u8]) {
+        let mut data = data;
 
-    fn open(&mut self, url: Url, _: usize) -> Result<Box<Resource>> {
-        let mut parts = url.reference().split('/');
-        let remote = parts.next().unwrap_or("");
-        let path = parts.next().unwrap_or("");
+        // Trim data that was already received
+        if seq_lt(sequence, self.rcv_nxt) {
+            let duplicate = self.rcv_nxt.wrapping_sub(sequence) as usize;
+            if duplicate >= data.len() {
+                return;
+            }
+            data = &data[duplicate..];
+            sequence = self.rcv_nxt;
+        }
 
-        let mut remote_parts = remote.split(':');
-        let host = remote_parts.next().unwrap_or("");
-        let port = remote_parts.next().unwrap_or("");
+        // Trim data past the window
+        let offset = sequence.wrapping_sub(self.rcv_nxt) as usize;
+        let window = self.rcv_wnd();
+        if offset >= window {
+            return;
+        }
+        if data.len() > window - offset {
+            data = &data[..window - offset];
+        }
 
-        if ! host.is_empty() && ! port.is_empty() {
-            let peer_addr = Ipv4Addr::from_string(&host.to_string());
-            let peer_port = port.parse::<u16>().unwrap_or(0);
-            let host_port = (rand() % 32768 + 32768) as u16;
+        if sequence == self.rcv_nxt {
+            self.recv_buf.extend(data.iter().cloned());
+            self.rcv_nxt = self.rcv_nxt.wrapping_add(data.len() as u32);
 
-            match Url::from_str(&format!("ip:{}/6", peer_addr.to_string())).unwrap().open() {
-                Ok(ip) => {
-                    let mut stream = TcpStream {
-                        ip: ip,
-                        peer_addr: peer_addr,
-                        peer_port: peer_port,
-                        host_port: host_port,
-                        sequence: rand() as u32,
-                        acknowledge: 0,
-                    };
-
-                    if stream.client_establish() {
-                        return Ok(box TcpResource {
-                            stream: Arc::new(UnsafeCell::new(stream))
-                        });
-                    }
+            // Move queued data that is now in order
+            while !self.out_of_order.is_empty() && seq_le(self.out_of_order[0].0, self.rcv_nxt) {
+                let (queued_sequence, queued_data) = self.out_of_order.remove(0);
+                let duplicate = self.rcv_nxt.wrapping_sub(queued_sequence) as usize;
+                if duplicate < queued_data.len() {
+                    let window = self.rcv_wnd();
+                    let end = cmp::min(queued_data.len(), duplicate + window);
+                    self.recv_buf.extend(queued_data[duplicate..end].iter().cloned());
+                    self.rcv_nxt = self.rcv_nxt.wrapping_add((end - duplicate) as u32);
                 }
-                Err(err) => return Err(err),
             }
-        } else if ! path.is_empty() {
-            let host_port = path.parse::<u16>().unwrap_or(0);
+        } else {
+            // The right edge of the window does not move back, so the queue stays within it
+            queue_segment(&mut self.out_of_order, sequence, data);
+        }
+    }
 
-            while let Ok(mut ip) = Url::from_str("ip:/6").unwrap().open() {
-                let mut bytes = [0; 8192];
-                match ip.read(&mut bytes) {
-                    Ok(count) => {
-                        if let Some(segment) = Tcp::from_bytes(bytes[.. count].to_vec()) {
-                            if segment.header.dst.get() == host_port && (segment.header.flags.get() & (TCP_PSH | TCP_SYN | TCP_ACK)) == TCP_SYN {
-                                let mut path = [0; 256];
-                                if let Ok(path_count) = ip.path(&mut path) {
-                                    let ip_reference = unsafe { str::from_utf8_unchecked(&path[.. path_count]) }.split(':').nth(1).unwrap_or("");
-                                    let ip_remote = ip_reference.split('/').next().unwrap_or("");
-                                    let peer_addr = ip_remote.split(':').next().unwrap_or("");
-
-                                    let mut stream = TcpStream {
-                                        ip: ip,
-                                        peer_addr: Ipv4Addr::from_string(&peer_addr.to_string()),
-                                        peer_port: segment.header.src.get(),
-                                        host_port: host_port,
-                                        sequence: rand() as u32,
-                                        acknowledge: segment.header.sequence.get(),
-                                    };
-
-                                    if stream.server_establish(segment) {
-                                        return Ok(box TcpResource {
-                                            stream: Arc::new(UnsafeCell::new(stream))
-                                        });
-                                    }
-                                }
+    /// Process a FIN once all data before it is received
+    fn receive_fin(&mut self) {
+        if let Some(fin_sequence) = self.fin_sequence {
+            if !self.fin_received && fin_sequence == self.rcv_nxt {
+                self.rcv_nxt = self.rcv_nxt.wrapping_add(1);
+                self.fin_received = true;
+
+                match self.state {
+                    TcpState::SynReceived | TcpState::Established => self.state = TcpState::CloseWait,
+                    TcpState::FinWait1 => if self.fin_acked() {
+                        self.time_wait();
+                    } else {
+                        self.state = TcpState::Closing;
+                    },
+                    TcpState::FinWait2 => self.time_wait(),
+                    _ => (),
+                }
+            }
+        }
+    }
+
+    /// Process a segment in the SYN_SENT state
+    fn input_syn_sent(&mut self, segment: &Tcp) {
+        let flags = segment.header.flags.get();
+        let ack = segment.header.ack_num.get();
+
+        if flags & TCP_ACK == TCP_ACK && (seq_le(ack, self.iss) || seq_gt(ack, self.snd_nxt)) {
+            if flags & TCP_RST == 0 {
+                self.send_reset(segment);
+            }
+            return;
+        }
+
+        if flags & TCP_RST == TCP_RST {
+            if flags & TCP_ACK == TCP_ACK {
+                self.abort(ECONNREFUSED);
+            }
+            return;
+        }
+
+        if flags & TCP_SYN == TCP_SYN {
+            self.rcv_nxt = segment.header.sequence.get().wrapping_add(1);
+            if let Some(mss) = segment.mss() {
+                self.snd_mss = cmp::min(mss, self.host_mss());
+            }
+
+            if flags & TCP_ACK == TCP_ACK {
+                self.acknowledged(ack);
+                self.snd_wnd = segment.header.window_size.get() as u32;
+                self.snd_wl1 = segment.header.sequence.get();
+                self.snd_wl2 = ack;
+                self.state = TcpState::Established;
+                self.send_ack();
+            } else {
+                // Simultaneous open, the SYN is retransmitted as a SYN-ACK
+                self.state = TcpState::SynReceived;
+                let sequence = self.iss;
+                self.send_segment(sequence, TCP_SYN | TCP_ACK, &[]);
+            }
+        }
+    }
+
+    /// Process a segment from the peer, from the "segment arrives" section of RFC 793
+    fn input(&mut self, segment: Tcp) {
+        if segment.header.dst.get() != self.host_port || segment.header.src.get() != self.peer_port {
+            return;
+        }
+
+        let flags = segment.header.flags.get();
+        let sequence = segment.header.sequence.get();
+        let ack = segment.header.ack_num.get();
+
+        match self.state {
+            TcpState::Closed => {
+                if flags & TCP_RST == 0 {
+                    self.send_reset(&segment);
+                }
+                return;
+            },
+            TcpState::Listen => return,
+            TcpState::SynSent => {
+                self.input_syn_sent(&segment);
+                return;
+            },
+            _ => (),
+        }
+
+        // Check that the segment is in the receive window
+        let len = segment.sequence_len();
+        let rcv_nxt = self.rcv_nxt;
+        let window = self.rcv_wnd() as u32;
+        let in_window = |sequence: u32| seq_le(rcv_nxt, sequence) && seq_lt(sequence, rcv_nxt.wrapping_add(window));
+        let acceptable = if len == 0 {
+            if window == 0 {
+                sequence == rcv_nxt
+            } else {
+                in_window(sequence)
+            }
+        } else {
+            window > 0 && (in_window(sequence) || in_window(sequence.wrapping_add(len - 1)))
+        };
+
+        if !acceptable {
+            if flags & TCP_RST == 0 {
+                self.send_ack();
+            }
+            return;
+        }
+
+        if flags & TCP_RST == TCP_RST {
+            match self.state {
+                TcpState::SynReceived => self.abort(ECONNREFUSED),
+                TcpState::Established | TcpState::FinWait1 | TcpState::FinWait2 | TcpState::CloseWait =>
+                    self.abort(ECONNRESET),
+                _ => {
+                    self.abort(ECONNRESET);
+                    self.error = None;
+                }
+            }
+            return;
+        }
+
+        if flags & TCP_SYN == TCP_SYN {
+            self.send_reset(&segment);
+            self.abort(ECONNRESET);
+            return;
+        }
+
+        if flags & TCP_ACK == 0 {
+            return;
+        }
+
+        // The peer is still there, even if its window stays closed (RFC 1122 4.2.2.17)
+        self.probes = 0;
+
+        if self.state == TcpState::SynReceived {
+            if seq_lt(self.snd_una, ack) && seq_le(ack, self.snd_nxt) {
+                self.state = TcpState::Established;
+                self.snd_wnd = segment.header.window_size.get() as u32;
+                self.snd_wl1 = sequence;
+                self.snd_wl2 = ack;
+            } else {
+                self.send_reset(&segment);
+                return;
+            }
+        }
+
+        if seq_lt(self.snd_una, ack) && seq_le(ack, self.snd_nxt) {
+            self.acknowledged(ack);
+        } else if seq_gt(ack, self.snd_nxt) {
+            self.send_ack();
+            return;
+        }
+
+        if seq_le(self.snd_una, ack) &&
+           (seq_lt(self.snd_wl1, sequence) || (self.snd_wl1 == sequence && seq_le(self.snd_wl2, ack))) {
+            self.snd_wnd = segment.header.window_size.get() as u32;
+            self.snd_wl1 = sequence;
+            self.snd_wl2 = ack;
+        }
+
+        match self.state {
+            TcpState::FinWait1 => if self.fin_acked() {
+                self.state = TcpState::FinWait2;
+                if self.close_requested {
+                    self.close_at = Some(after(2 * TCP_MSL));
+                }
+            },
+            TcpState::Closing => if self.fin_acked() {
+                self.time_wait();
+            },
+            TcpState::LastAck => if self.fin_acked() {
+                self.state = TcpState::Closed;
+                self.retransmit_at = None;
+                return;
+            },
+            _ => (),
+        }
+
+        let mut ack_needed = false;
+
+        if !segment.data.is_empty() {
+            match self.state {
+                TcpState::Established | TcpState::FinWait1 | TcpState::FinWait2 => {
+                    self.receive_data(sequence, &segment.data);
+                    ack_needed = true;
+                },
+                _ => (),
+            }
+        }
+
+        if flags & TCP_FIN == TCP_FIN {
+            self.fin_sequence = Some(sequence.wrapping_add(segment.data.len() as u32));
+            ack_needed = true;
+        }
+        self.receive_fin();
+
+        if ack_needed {
+            self.send_ack();
+        }
+
+        self.output();
+    }
+
+    /// Process the segments that have arrived
+    fn receive(&mut self) -> Result<()> {
+        loop {
+            let mut bytes = [0; 8192];
+            match self.ip.read_nonblock(&mut bytes) {
+                Ok(count) => {
+                    if Tcp::verify(&bytes[.. count], &self.peer_addr, &loopback::host_ip(self.peer_addr)) {
+                        if let Some(segment) = Tcp::from_bytes(bytes[.. count].to_vec()) {
+                            self.input(segment);
+                        }
+                    }
+                },
+                Err(ref err) if err.errno == EAGAIN => return Ok(()),
+                Err(err) => return Err(err),
+            }
+        }
+    }
+
+    /// Retransmit, probe the window, or close, when the timers expire
+    fn timers(&mut self) {
+        let now = Duration::monotonic();
+
+        if let Some(close_at) = self.close_at {
+            if now >= close_at {
+                self.state = TcpState::Closed;
+                self.close_at = None;
+                self.retransmit_at = None;
+            }
+        }
+
+        if let Some(retransmit_at) = self.retransmit_at {
+            if now >= retransmit_at {
+                // With a closed window, the segment is a probe the peer can not accept, so only
+                // give up if the peer stops acknowledging the probes
+                let probe = self.snd_wnd == 0 && self.state != TcpState::SynSent;
+                if probe {
+                    if self.probes >= TCP_MAX_RETRIES {
+                        self.abort(ETIMEDOUT);
+                        return;
+                    }
+                    self.probes += 1;
+                } else {
+                    if self.retries >= TCP_MAX_RETRIES {
+                        self.abort(ETIMEDOUT);
+                        return;
+                    }
+                    self.retries += 1;
+                }
+
+                self.rto = cmp::min(self.rto * 2, TCP_RTO_MAX);
+                // Do not time retransmitted segments (Karn's algorithm)
+                self.rtt_sample = None;
+                self.retransmit_at = None;
+
+                if let Some(segment) = self.unacked.pop_front() {
+                    self.resend(&segment);
+                    self.unacked.push_front(segment);
+                    self.retransmit_at = Some(after(self.rto));
+                } else if let Some(b) = self.send_buf.pop_front() {
+                    // Probe a closed window with one byte
+                    self.transmit(TCP_PSH, vec![b]);
+                }
+            }
+        }
+    }
+
+    /// Process segments and timers
+    fn update(&mut self) -> Result<()> {
+        try!(self.receive());
+        self.timers();
+        Ok(())
+    }
+
+    /// Block until a segment arrives or a timer expires, then process them
+    fn wait(&mut self) -> Result<()> {
+        let wake = self.deadline();
+        try!(block(&*self.ip, &[], wake));
+        self.update()
+    }
+
+    /// The earliest time a timer expires
+    fn deadline(&self) -> Option<Duration> {
+        earliest(self.retransmit_at, self.close_at)
+    }
+
+    /// The error to return when the connection cannot be used
+    fn closed_error(&self, errno: isize) -> Error {
+        Error::new(self.error.unwrap_or(errno))
+    }
+
+    /// Actively open a connection
+    pub fn connect(&mut self) -> Result<()> {
+        self.state = TcpState::SynSent;
+        self.transmit(TCP_SYN, Vec::new());
+
+        while self.state == TcpState::SynSent || self.state == TcpState::SynReceived {
+            try!(self.wait());
+        }
+
+        match self.state {
+            TcpState::Established | TcpState::CloseWait => Ok(()),
+            _ => Err(self.closed_error(ECONNREFUSED)),
+        }
+    }
+
+    /// Answer the SYN of a connection to a listening port. The handshake completes as segments
+    /// are processed
+    fn syn_received(&mut self, syn: &Tcp) {
+        self.rcv_nxt = syn.header.sequence.get().wrapping_add(1);
+        self.snd_wnd = syn.header.window_size.get() as u32;
+        self.snd_wl1 = syn.header.sequence.get();
+        if let Some(mss) = syn.mss() {
+            self.snd_mss = cmp::min(mss, self.host_mss());
+        }
+
+        self.state = TcpState::SynReceived;
+        self.transmit(TCP_SYN, Vec::new());
+    }
+
+    fn read(&mut self, buf: &mut [u8], nonblock: bool) -> Result<usize> {
+        loop {
+            try!(self.update());
+
+            if !self.recv_buf.is_empty() {
+                let mut i = 0;
+                while i < buf.len() {
+                    match self.recv_buf.pop_front() {
+                        Some(b) => buf[i] = b,
+                        None => break,
+                    }
+                    i += 1;
+                }
+
+                // Announce the window if it opened by a segment or more
+                if self.rcv_wnd() >= self.rcv_adv + self.snd_mss {
+                    self.send_ack();
+                }
+
+                return Ok(i);
+            }
+
+            if self.fin_received {
+                return Ok(0);
+            }
+
+            if self.state == TcpState::Closed {
+                return match self.error {
+                    Some(errno) => Err(Error::new(errno)),
+                    None => Ok(0),
+                };
+            }
+
+            if nonblock {
+                return Err(Error::new(EAGAIN));
+            }
+
+            try!(self.wait());
+        }
+    }
+
+    fn write(&mut self, buf: &[u8]) -> Result<usize> {
+        let mut i = 0;
+        while i < buf.len() {
+            try!(self.update());
+
+            if self.state != TcpState::Established && self.state != TcpState::CloseWait {
+                return Err(self.closed_error(EPIPE));
+            }
+
+            while i < buf.len() && self.send_buf.len() < TCP_SEND_BUFFER {
+                self.send_buf.push_back(buf[i]);
+                i += 1;
+            }
+
+            self.output();
+
+            if i < buf.len() {
+                try!(self.wait());
+            }
+        }
+
+        Ok(i)
+    }
+
+    fn poll(&mut self, events: u16) -> Result<u16> {
+        try!(self.update());
+
+        let mut revents = 0;
+        if !self.recv_buf.is_empty() || self.fin_received || self.state == TcpState::Closed {
+            revents |= events & POLLIN;
+        }
+        if (self.state == TcpState::Established || self.state == TcpState::CloseWait) &&
+           self.send_buf.len() < TCP_SEND_BUFFER {
+            revents |= events & POLLOUT;
+        }
+        Ok(revents)
+    }
+
+    /// Wait until all data written is acknowledged
+    fn sync(&mut self) -> Result<()> {
+        loop {
+            try!(self.update());
+
+            if self.send_buf.is_empty() && self.unacked.is_empty() {
+                return Ok(());
+            }
+
+            if self.state == TcpState::Closed {
+                return Err(self.closed_error(EPIPE));
+            }
+
+            try!(self.wait());
+        }
+    }
+}
+
+impl Drop for TcpStream {
+    fn drop(&mut self) {
+        match self.state {
+            TcpState::Closed | TcpState::Listen | TcpState::SynSent => return,
+            _ => (),
+        }
+
+        // Send a FIN after the buffered data, then hand the connection to the closing context,
+        // which retransmits until the peer has acknowledged everything and TIME_WAIT ends
+        self.close_requested = true;
+        if self.state == TcpState::FinWait2 {
+            self.close_at = Some(after(2 * TCP_MSL));
+        }
+        self.output();
+
+        let closed = TcpStream::new(box VecResource::new(String::new(), Vec::new()),
+                                    IpAddr::V4(Ipv4Addr { bytes: [0; 4] }),
+                                    0,
+                                    0,
+                                    self.closing.clone());
+        let closing = self.closing.clone();
+        closing.send(mem::replace(self, closed));
+    }
+}
+
+/// Finish closing the connections that were dropped, including TIME_WAIT, in one context for all
+/// of them
+fn closing_loop(closing: Arc<WaitQueue<TcpStream>>) {
+    let mut streams: Vec<TcpStream> = Vec::new();
+
+    loop {
+        if streams.is_empty() {
+            streams.extend(closing.receive_all());
+        } else {
+            while let Some(stream) = closing.inner.lock().pop_front() {
+                streams.push(stream);
+            }
+        }
+
+        let mut i = 0;
+        while i < streams.len() {
+            streams[i].output();
+            let _ = streams[i].update();
+            if streams[i].state == TcpState::Closed {
+                streams.remove(i);
+            } else {
+                i += 1;
+            }
+        }
+
+        let mut wake = None;
+        let mut conditions = vec![&closing.condition];
+        for stream in streams.iter() {
+            wake = earliest(wake, stream.deadline());
+            match stream.ip.condition() {
+                Some(condition) => conditions.push(condition),
+                None => wake = earliest(wake, Some(after(TCP_CLOCK_GRANULARITY))),
+            }
+        }
+
+        if ! streams.is_empty() {
+            let _ = block(&NoResource, &conditions, wake);
+        }
+    }
+}
+
+/// A TCP resource
+pub struct TcpResource {
+    stream: Arc<UnsafeCell<TcpStream>>
+}
+
+impl Resource for TcpResource {
+    fn dup(&self) -> Result<Box<Resource>> {
+        Ok(box TcpResource {
+            stream: self.stream.clone()
+        })
+    }
+
+    fn path(&self, buf: &mut [u8]) -> Result<usize> {
+        unsafe { (*self.stream.get()).path(buf) }
+    }
+
+    fn read(&mut self, buf: &mut [u8]) -> Result<usize> {
+        unsafe { (*self.stream.get()).read(buf, false) }
+    }
+
+    fn read_nonblock(&mut self, buf: &mut [u8]) -> Result<usize> {
+        unsafe { (*self.stream.get()).read(buf, true) }
+    }
+
+    fn write(&mut self, buf: &[u8]) -> Result<usize> {
+        unsafe { (*self.stream.get()).write(buf) }
+    }
+
+    fn poll(&self, events: u16) -> Result<u16> {
+        unsafe { (*self.stream.get()).poll(events) }
+    }
+
+    fn condition(&self) -> Option<&WaitCondition> {
+        unsafe { (*self.stream.get()).ip.condition() }
+    }
+
+    fn sync(&mut self) -> Result<()> {
+        unsafe { (*self.stream.get()).sync() }
+    }
+}
+
+/// A listening port, shared by its listening resources and the context that answers its SYNs
+pub struct TcpListener {
+    port: u16,
+    /// The user that listens on the port, connections are only accepted by this user or root
+    uid: u32,
+    /// The queue of the closing context, for the connections of the port
+    closing: Arc<WaitQueue<TcpStream>>,
+    /// Connections that completed the handshake, waiting to be accepted
+    backlog: WaitQueue<TcpStream>,
+    /// Cleared when the last listening resource is closed
+    open: Intex<bool>,
+}
+
+impl TcpListener {
+    fn is_open(&self) -> bool {
+        *self.open.lock()
+    }
+
+    /// Answer a SYN to the port by starting a handshake, unless the backlog is full
+    fn syn(&self, src: IpAddr, dst: IpAddr, data: Vec<u8>, pending: &mut Vec<TcpStream>) {
+        if !Tcp::verify(&data, &src, &dst) {
+            return;
+        }
+
+        let segment = match Tcp::from_bytes(data) {
+            Some(segment) => segment,
+            None => return,
+        };
+
+        if segment.header.dst.get() != self.port ||
+           segment.header.flags.get() & (TCP_RST | TCP_SYN | TCP_ACK) != TCP_SYN {
+            return;
+        }
+
+        let peer_addr = src;
+        let peer_port = segment.header.src.get();
+
+        // A retransmitted SYN is answered by the pending connection
+        if pending.iter().any(|stream| stream.peer_addr.equals(peer_addr) && stream.peer_port == peer_port) {
+            return;
+        }
+
+        // Ignore the SYN when the backlog is full, the peer will retransmit it
+        if pending.len() + self.backlog.inner.lock().len() >= TCP_BACKLOG {
+            return;
+        }
+
+        if let Ok(ip) = Url::from_str(&peer_addr.ip_url(0x06)).unwrap().open() {
+            let mut stream = TcpStream::new(ip, peer_addr, peer_port, self.port, self.closing.clone());
+            stream.syn_received(&segment);
+            pending.push(stream);
+        }
+    }
+
+    /// Answer SYNs over IPv4 and IPv6 and complete handshakes, queueing the established
+    /// connections, until the listener is closed
+    fn run(&self) {
+        let link = Url::from_str("ethernet:FF.FF.FF.FF.FF.FF/800").unwrap().open();
+        let link6 = Url::from_str("ethernet:FF.FF.FF.FF.FF.FF/86DD").unwrap().open();
+        if let (Ok(mut link), Ok(mut link6)) = (link, link6) {
+            let mut pending: Vec<TcpStream> = Vec::new();
+
+            while self.is_open() {
+                loop {
+                    let mut bytes = [0; 8192];
+                    match link.read_nonblock(&mut bytes) {
+                        Ok(count) => if let Some(packet) = Ipv4::from_bytes(bytes[.. count].to_vec()) {
+                            if packet.header.proto == 0x06 && loopback::accepts(packet.header.dst) {
+                                self.syn(IpAddr::V4(packet.header.src),
+                                         IpAddr::V4(packet.header.dst),
+                                         packet.data,
+                                         &mut pending);
                             }
+                        },
+                        Err(_) => break,
+                    }
+                }
+
+                loop {
+                    let mut bytes = [0; 8192];
+                    match link6.read_nonblock(&mut bytes) {
+                        Ok(count) => if let Some(packet) = Ipv6::from_bytes(bytes[.. count].to_vec()) {
+                            if packet.header.next_header == 0x06 && loopback::accepts6(packet.header.dst) {
+                                self.syn(IpAddr::V6(packet.header.src),
+                                         IpAddr::V6(packet.header.dst),
+                                         packet.data,
+                                         &mut pending);
+                            }
+                        },
+                        Err(_) => break,
+                    }
+                }
+
+                let mut i = 0;
+                while i < pending.len() {
+                    let _ = pending[i].update();
+                    match pending[i].state {
+                        TcpState::SynReceived => i += 1,
+                        TcpState::Established | TcpState::CloseWait => self.backlog.send(pending.remove(i)),
+                        _ => {
+                            pending.remove(i);
                         }
                     }
-                    Err(err) => return Err(err),
+                }
+
+                let mut wake = None;
+                for stream in pending.iter() {
+                    wake = earliest(wake, stream.deadline());
+                }
+
+                let mut conditions = vec![&self.backlog.condition];
+                if let Some(condition) = link6.condition() {
+                    conditions.push(condition);
+                }
+                if block(&*link, &conditions, wake).is_err() {
+                    break;
+                }
+            }
+        }
+
+        *self.open.lock() = false;
+        self.backlog.inner.lock().clear();
+    }
+
+    /// Take a connection from the backlog, blocking until there is one
+    fn accept(&self) -> Result<TcpStream> {
+        loop {
+            if let Some(stream) = self.backlog.inner.lock().pop_front() {
+                return Ok(stream);
+            }
+
+            if !self.is_open() {
+                return Err(Error::new(ECONNABORTED));
+            }
+
+            try!(block(&NoResource, &[&self.backlog.condition], None));
+        }
+    }
+}
+
+/// Closes a listener when the last of its resources is dropped
+struct TcpListenerHandle {
+    listener: Arc<TcpListener>,
+}
+
+impl Drop for TcpListenerHandle {
+    fn drop(&mut self) {
+        *self.listener.open.lock() = false;
+        unsafe { self.listener.backlog.condition.notify(); }
+    }
+}
+
+/// A listening TCP resource, `tcp:/<port>`. Connections are accepted by opening `tcp:/<port>/accept`
+pub struct TcpListenerResource {
+    handle: Arc<TcpListenerHandle>,
+}
+
+impl Resource for TcpListenerResource {
+    fn dup(&self) -> Result<Box<Resource>> {
+        Ok(box TcpListenerResource {
+            handle: self.handle.clone()
+        })
+    }
+
+    fn path(&self, buf: &mut [u8]) -> Result<usize> {
+        let path_string = format!("tcp:/{}", self.handle.listener.port);
+        let path = path_string.as_bytes();
+
+        for (b, p) in buf.iter_mut().zip(path.iter()) {
+            *b = *p;
+        }
+
+        Ok(cmp::min(buf.len(), path.len()))
+    }
+
+    fn poll(&self, events: u16) -> Result<u16> {
+        if self.handle.listener.backlog.inner.lock().is_empty() {
+            Ok(0)
+        } else {
+            Ok(events & POLLIN)
+        }
+    }
+
+    fn condition(&self) -> Option<&WaitCondition> {
+        Some(&self.handle.listener.backlog.condition)
+    }
+}
+
+/// A resource that is never readable, for blocking on conditions alone
+struct NoResource;
+
+impl Resource for NoResource {
+    fn poll(&self, _: u16) -> Result<u16> {
+        Ok(0)
+    }
+}
+
+/// A TCP scheme. `tcp:<host>:<port>` connects to a peer, where an IPv6 host is in brackets,
+/// `tcp:/<port>` listens on a port over IPv4 and IPv6, or on an ephemeral port if it is 0, and
+/// `tcp:/<port>/accept` accepts a connection on a port that the same user listens on
+pub struct TcpScheme {
+    listeners: Vec<Arc<TcpListener>>,
+    /// Connections that were dropped and are closing, serviced by one context
+    closing: Arc<WaitQueue<TcpStream>>,
+}
+
+impl TcpScheme {
+    pub fn new() -> Box<Self> {
+        let closing = Arc::new(WaitQueue::new());
+
+        let context_closing = closing.clone();
+        Context::spawn("ktcp".to_string(), box move || {
+            closing_loop(context_closing);
+        });
+
+        box TcpScheme {
+            listeners: Vec::new(),
+            closing: closing,
+        }
+    }
+
+    fn in_use(&self, port: u16) -> bool {
+        self.listeners.iter().any(|listener| listener.port == port)
+    }
+
+    /// Listen on a port, answering SYNs in a new context. Port 0 picks an ephemeral port, which
+    /// is found in the path of the resource
+    fn listen(&mut self, mut port: u16, uid: u32) -> Result<Box<Resource>> {
+        self.listeners.retain(|listener| listener.is_open());
+        if port == 0 {
+            for _ in 0..TCP_EPHEMERAL_TRIES {
+                let ephemeral = (rand() % 32768 + 32768) as u16;
+                if !self.in_use(ephemeral) {
+                    port = ephemeral;
+                    break;
                 }
             }
         }
+        if port == 0 || self.in_use(port) {
+            return Err(Error::new(EADDRINUSE));
+        }
+
+        let listener = Arc::new(TcpListener {
+            port: port,
+            uid: uid,
+            closing: self.closing.clone(),
+            backlog: WaitQueue::new(),
+            open: Intex::new(true),
+        });
+        self.listeners.push(listener.clone());
+
+        let context_listener = listener.clone();
+        Context::spawn(format!("ktcpd {}", port), box move || {
+            context_listener.run();
+        });
+
+        Ok(box TcpListenerResource {
+            handle: Arc::new(TcpListenerHandle {
+                listener: listener
+            })
+        })
+    }
+
+    /// Accept a connection on a listening port, as the user that listens on it or root
+    fn accept(&mut self, port: u16, uid: u32) -> Result<Box<Resource>> {
+        let listener = match self.listeners.iter().find(|listener| listener.port == port && listener.is_open()) {
+            Some(listener) => listener.clone(),
+            None => return Err(Error::new(ENOENT)),
+        };
+
+        if uid != 0 && uid != listener.uid {
+            return Err(Error::new(EACCES));
+        }
+
+        let stream = try!(listener.accept());
+        Ok(box TcpResource {
+            stream: Arc::new(UnsafeCell::new(stream))
+        })
+    }
+}
+
+impl KScheme for TcpScheme {
+    fn scheme(&self) -> &str {
+        "tcp"
+    }
+
+    fn open(&mut self, url: Url, _: usize, uid: u32, _: u32) -> Result<Box<Resource>> {
+        let mut parts = url.reference().split('/');
+        let remote = parts.next().unwrap_or("");
+        let path = parts.next().unwrap_or("");
+        let action = parts.next().unwrap_or("");
+
+        let (host, port) = split_host_port(remote);
+
+        if ! host.is_empty() && ! port.is_empty() {
+            let peer_addr = try!(IpAddr::parse(host).ok_or(Error::new(EINVAL)));
+            let peer_port = port.parse::<u16>().unwrap_or(0);
+            let host_port = (rand() % 32768 + 32768) as u16;
+
+            let ip = try!(Url::from_str(&peer_addr.ip_url(0x06)).unwrap().open());
+            let mut stream = TcpStream::new(ip, peer_addr, peer_port, host_port, self.closing.clone());
+            try!(stream.connect());
+
+            return Ok(box TcpResource {
+                stream: Arc::new(UnsafeCell::new(stream))
+            });
+        } else if ! path.is_empty() {
+            let host_port = try!(path.parse::<u16>().or(Err(Error::new(ENOENT))));
+
+            return match action {
+                "" => self.listen(host_port, uid),
+                "accept" => self.accept(host_port, uid),
+                _ => Err(Error::new(ENOENT)),
+            };
+        }
 
         Err(Error::new(ENOENT))
     }
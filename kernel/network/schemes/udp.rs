@@ -1,17 +1,23 @@
+use alloc::arc::Arc;
 use alloc::boxed::Box;
 
 use collections::Vec;
-use collections::string::ToString;
 
 use common::random::rand;
 
-use core::{cmp, mem, ptr, slice, str};
+use core::{cmp, mem, ptr, slice};
 
 use fs::{KScheme, Resource, Url};
 
-use network::common::{n16, Checksum, Ipv4Addr, IP_ADDR, FromBytes, ToBytes};
+use network::common::{n16, split_host_port, Checksum, IpAddr, Ipv4Addr, Ipv6Addr, FromBytes, ToBytes};
+use network::ipv4::Ipv4;
+use network::ipv6::Ipv6;
+use network::loopback;
 
-use system::error::{Error, Result, ENOENT};
+use sync::{Intex, WaitCondition};
+
+use system::error::{Error, Result, EADDRINUSE, EINVAL, ENOENT};
+use system::syscall::POLLIN;
 
 #[derive(Copy, Clone)]
 #[repr(packed)]
@@ -27,6 +33,75 @@ pub struct Udp {
     pub data: Vec<u8>,
 }
 
+/// The length of the address that precedes each datagram on a bound socket
+const UDP_ADDR_LEN: usize = 6;
+/// The length of the address that precedes each datagram on a bound IPv6 socket
+const UDP_ADDR6_LEN: usize = 18;
+/// Ports tried when picking an ephemeral port
+const UDP_EPHEMERAL_TRIES: usize = 64;
+
+impl Udp {
+    /// Create a datagram, with the checksum for the given IP addresses
+    pub fn new(src: &IpAddr, src_port: u16, dst: &IpAddr, dst_port: u16, data: &[u8]) -> Self {
+        let mut udp = Udp {
+            header: UdpHeader {
+                src: n16::new(src_port),
+                dst: n16::new(dst_port),
+                len: n16::new((mem::size_of::<UdpHeader>() + data.len()) as u16),
+                checksum: Checksum { data: 0 },
+            },
+            data: Vec::from(data),
+        };
+
+        unsafe {
+            let proto = n16::new(0x11);
+            let datagram_len = n16::new((mem::size_of::<UdpHeader>() + udp.data.len()) as u16);
+            udp.header.checksum.data =
+                Checksum::compile(Checksum::sum(src.as_bytes().as_ptr() as usize, src.as_bytes().len()) +
+                                  Checksum::sum(dst.as_bytes().as_ptr() as usize, dst.as_bytes().len()) +
+                                  Checksum::sum((&proto as *const n16) as usize,
+                                                mem::size_of::<n16>()) +
+                                  Checksum::sum((&datagram_len as *const n16) as usize,
+                                                mem::size_of::<n16>()) +
+                                  Checksum::sum((&udp.header as *const UdpHeader) as usize,
+                                                mem::size_of::<UdpHeader>()) +
+                                  Checksum::sum(udp.data.as_ptr() as usize, udp.data.len()));
+        }
+
+        // A zero checksum means there is none, so it is sent as all ones
+        if udp.header.checksum.data == 0 {
+            udp.header.checksum.data = 0xFFFF;
+        }
+
+        udp
+    }
+
+    /// Check the checksum of a datagram received from `src` at `dst`. A zero checksum means the
+    /// sender did not calculate one, which is only allowed over IPv4
+    pub fn verify(bytes: &[u8], src: &IpAddr, dst: &IpAddr) -> bool {
+        if bytes.len() < mem::size_of::<UdpHeader>() {
+            return false;
+        }
+
+        if bytes[6] == 0 && bytes[7] == 0 {
+            return match *src {
+                IpAddr::V4(_) => true,
+                IpAddr::V6(_) => false,
+            };
+        }
+
+        unsafe {
+            let proto = n16::new(0x11);
+            let datagram_len = n16::new(bytes.len() as u16);
+            Checksum::compile(Checksum::sum(src.as_bytes().as_ptr() as usize, src.as_bytes().len()) +
+                              Checksum::sum(dst.as_bytes().as_ptr() as usize, dst.as_bytes().len()) +
+                              Checksum::sum((&proto as *const n16) as usize, mem::size_of::<n16>()) +
+                              Checksum::sum((&datagram_len as *const n16) as usize, mem::size_of::<n16>()) +
+                              Checksum::sum(bytes.as_ptr() as usize, bytes.len())) == 0
+        }
+    }
+}
+
 impl FromBytes for Udp {
     fn from_bytes(bytes: Vec<u8>) -> Option<Self> {
         if bytes.len() >= mem::size_of::<UdpHeader>() {
@@ -58,9 +133,49 @@ impl ToBytes for Udp {
 pub struct UdpResource {
     ip: Box<Resource>,
     data: Vec<u8>,
-    peer_addr: Ipv4Addr,
+    peer_addr: IpAddr,
     peer_port: u16,
     host_port: u16,
+    bound: Arc<UdpBinding>,
+}
+
+impl UdpResource {
+    fn read_inner(&mut self, buf: &mut [u8], nonblock: bool) -> Result<usize> {
+        if !self.data.is_empty() {
+            let mut bytes: Vec<u8> = Vec::new();
+            mem::swap(&mut self.data, &mut bytes);
+
+            for (b, d) in buf.iter_mut().zip(bytes.iter()) {
+                *b = *d;
+            }
+
+            return Ok(cmp::min(buf.len(), bytes.len()));
+        }
+
+        loop {
+            let mut bytes = [0; 8192];
+            let result = if nonblock {
+                self.ip.read_nonblock(&mut bytes)
+            } else {
+                self.ip.read(&mut bytes)
+            };
+            match result {
+                Ok(count) => {
+                    if let Some(datagram) = Udp::from_bytes(bytes[.. count].to_vec()) {
+                        if datagram.header.dst.get() == self.host_port &&
+                           datagram.header.src.get() == self.peer_port {
+                            for (b, d) in buf.iter_mut().zip(datagram.data.iter()) {
+                                *b = *d;
+                            }
+
+                            return Ok(cmp::min(buf.len(), datagram.data.len()));
+                        }
+                    }
+                }
+                Err(err) => return Err(err),
+            }
+        }
+    }
 }
 
 impl Resource for UdpResource {
@@ -73,6 +188,7 @@ impl Resource for UdpResource {
                     peer_addr: self.peer_addr,
                     peer_port: self.peer_port,
                     host_port: self.host_port,
+                    bound: self.bound.clone(),
                 }))
             }
             Err(err) => Err(err),
@@ -80,7 +196,7 @@ impl Resource for UdpResource {
     }
 
     fn path(&self, buf: &mut [u8]) -> Result<usize> {
-        let path_string = format!("udp:{}:{}/{}", self.peer_addr.to_string(), self.peer_port, self.host_port);
+        let path_string = format!("udp:{}:{}/{}", self.peer_addr.to_host_string(), self.peer_port, self.host_port);
         let path = path_string.as_bytes();
 
         for (b, p) in buf.iter_mut().zip(path.iter()) {
@@ -91,137 +207,302 @@ impl Resource for UdpResource {
     }
 
     fn read(&mut self, buf: &mut [u8]) -> Result<usize> {
-        if !self.data.is_empty() {
-            let mut bytes: Vec<u8> = Vec::new();
-            mem::swap(&mut self.data, &mut bytes);
+        self.read_inner(buf, false)
+    }
 
-            // TODO: Allow splitting
-            let i = 0;
-            while i < buf.len() && i < bytes.len() {
-                buf[i] = bytes[i];
-            }
-            return Ok(i);
+    fn read_nonblock(&mut self, buf: &mut [u8]) -> Result<usize> {
+        self.read_inner(buf, true)
+    }
+
+    fn write(&mut self, buf: &[u8]) -> Result<usize> {
+        let udp = Udp::new(&loopback::host_ip(self.peer_addr), self.host_port, &self.peer_addr, self.peer_port, buf);
+
+        match self.ip.write(&udp.to_bytes()) {
+            Ok(_) => Ok(buf.len()),
+            Err(err) => Err(err),
         }
+    }
+
+    fn poll(&self, events: u16) -> Result<u16> {
+        if !self.data.is_empty() {
+            let revents = try!(self.ip.poll(events));
+            Ok(revents | (events & POLLIN))
+        } else {
+            self.ip.poll(events)
+        }
+    }
+
+    fn condition(&self) -> Option<&WaitCondition> {
+        self.ip.condition()
+    }
+
+    fn sync(&mut self) -> Result<()> {
+        self.ip.sync()
+    }
+}
 
+/// A UDP socket bound to a port, receiving from and sending to any peer. Each read returns one
+/// datagram and each write sends one, preceded by the IPv4 address, or the IPv6 address on an IPv6
+/// socket, and the big endian port of the peer
+pub struct UdpSocketResource {
+    link: Box<Resource>,
+    /// The IP resource of the last peer written to, reused while the peer stays the same
+    ip: Option<(IpAddr, Box<Resource>)>,
+    host_port: u16,
+    v6: bool,
+    bound: Arc<UdpBinding>,
+}
+
+impl UdpSocketResource {
+    fn read_inner(&mut self, buf: &mut [u8], nonblock: bool) -> Result<usize> {
         loop {
             let mut bytes = [0; 8192];
-            match self.ip.read(&mut bytes) {
-                Ok(count) => {
-                    if let Some(datagram) = Udp::from_bytes(bytes[.. count].to_vec()) {
-                        if datagram.header.dst.get() == self.host_port &&
-                           datagram.header.src.get() == self.peer_port {
-                            // TODO: Allow splitting
-                            let i = 0;
-                            while i < buf.len() && i < datagram.data.len() {
-                                buf[i] = datagram.data[i];
-                            }
-                            return Ok(i);
+            let count = try!(if nonblock {
+                self.link.read_nonblock(&mut bytes)
+            } else {
+                self.link.read(&mut bytes)
+            });
+
+            let (src, dst, packet_data) = if self.v6 {
+                match Ipv6::from_bytes(bytes[.. count].to_vec()) {
+                    Some(packet) => if packet.header.next_header == 0x11 && loopback::accepts6(packet.header.dst) {
+                        (IpAddr::V6(packet.header.src), IpAddr::V6(packet.header.dst), packet.data)
+                    } else {
+                        continue;
+                    },
+                    None => continue,
+                }
+            } else {
+                match Ipv4::from_bytes(bytes[.. count].to_vec()) {
+                    Some(packet) => if packet.header.proto == 0x11 && loopback::accepts(packet.header.dst) {
+                        (IpAddr::V4(packet.header.src), IpAddr::V4(packet.header.dst), packet.data)
+                    } else {
+                        continue;
+                    },
+                    None => continue,
+                }
+            };
+
+            if Udp::verify(&packet_data, &src, &dst) {
+                if let Some(datagram) = Udp::from_bytes(packet_data) {
+                    if datagram.header.dst.get() == self.host_port {
+                        let mut data = Vec::with_capacity(UDP_ADDR6_LEN + datagram.data.len());
+                        data.extend_from_slice(src.as_bytes());
+                        data.extend_from_slice(&datagram.header.src.bytes);
+                        data.extend_from_slice(&datagram.data);
+
+                        for (b, d) in buf.iter_mut().zip(data.iter()) {
+                            *b = *d;
                         }
+
+                        return Ok(cmp::min(buf.len(), data.len()));
                     }
                 }
-                Err(err) => return Err(err),
             }
         }
     }
+}
+
+impl Resource for UdpSocketResource {
+    fn dup(&self) -> Result<Box<Resource>> {
+        Ok(box UdpSocketResource {
+            link: try!(self.link.dup()),
+            ip: None,
+            host_port: self.host_port,
+            v6: self.v6,
+            bound: self.bound.clone(),
+        })
+    }
+
+    fn path(&self, buf: &mut [u8]) -> Result<usize> {
+        let path_string = if self.v6 {
+            format!("udp:/{}/6", self.host_port)
+        } else {
+            format!("udp:/{}", self.host_port)
+        };
+        let path = path_string.as_bytes();
+
+        for (b, p) in buf.iter_mut().zip(path.iter()) {
+            *b = *p;
+        }
+
+        Ok(cmp::min(buf.len(), path.len()))
+    }
+
+    fn read(&mut self, buf: &mut [u8]) -> Result<usize> {
+        self.read_inner(buf, false)
+    }
+
+    fn read_nonblock(&mut self, buf: &mut [u8]) -> Result<usize> {
+        self.read_inner(buf, true)
+    }
 
     fn write(&mut self, buf: &[u8]) -> Result<usize> {
-        let udp_data = Vec::from(buf);
+        let addr_len = if self.v6 {
+            UDP_ADDR6_LEN
+        } else {
+            UDP_ADDR_LEN
+        };
+        if buf.len() < addr_len {
+            return Err(Error::new(EINVAL));
+        }
 
-        let mut udp = Udp {
-            header: UdpHeader {
-                src: n16::new(self.host_port),
-                dst: n16::new(self.peer_port),
-                len: n16::new((mem::size_of::<UdpHeader>() + udp_data.len()) as u16),
-                checksum: Checksum { data: 0 },
-            },
-            data: udp_data,
+        let peer_addr = if self.v6 {
+            let mut addr = Ipv6Addr { bytes: [0; 16] };
+            for i in 0..16 {
+                addr.bytes[i] = buf[i];
+            }
+            IpAddr::V6(addr)
+        } else {
+            IpAddr::V4(Ipv4Addr { bytes: [buf[0], buf[1], buf[2], buf[3]] })
         };
+        let peer_port = n16 { bytes: [buf[addr_len - 2], buf[addr_len - 1]] }.get();
+        if peer_port == 0 {
+            return Err(Error::new(EINVAL));
+        }
 
-        unsafe {
-            let proto = n16::new(0x11);
-            let datagram_len = n16::new((mem::size_of::<UdpHeader>() + udp.data.len()) as u16);
-            udp.header.checksum.data =
-                Checksum::compile(Checksum::sum((&IP_ADDR as *const Ipv4Addr) as usize,
-                                                mem::size_of::<Ipv4Addr>()) +
-                                  Checksum::sum((&self.peer_addr as *const Ipv4Addr) as usize,
-                                                mem::size_of::<Ipv4Addr>()) +
-                                  Checksum::sum((&proto as *const n16) as usize,
-                                                mem::size_of::<n16>()) +
-                                  Checksum::sum((&datagram_len as *const n16) as usize,
-                                                mem::size_of::<n16>()) +
-                                  Checksum::sum((&udp.header as *const UdpHeader) as usize,
-                                                mem::size_of::<UdpHeader>()) +
-                                  Checksum::sum(udp.data.as_ptr() as usize, udp.data.len()));
+        let udp = Udp::new(&loopback::host_ip(peer_addr), self.host_port, &peer_addr, peer_port, &buf[addr_len ..]);
+
+        let reuse = match self.ip {
+            Some((addr, _)) => addr.equals(peer_addr),
+            None => false,
+        };
+        if !reuse {
+            // Broadcast addresses are sent to the broadcast MAC address by the IP scheme
+            let ip = try!(Url::from_str(&peer_addr.ip_url(0x11)).unwrap().open());
+            self.ip = Some((peer_addr, ip));
         }
 
-        match self.ip.write(&udp.to_bytes()) {
-            Ok(_) => Ok(buf.len()),
-            Err(err) => Err(err),
+        if let Some((_, ref mut ip)) = self.ip {
+            try!(ip.write(&udp.to_bytes()));
         }
+
+        Ok(buf.len())
+    }
+
+    fn poll(&self, events: u16) -> Result<u16> {
+        self.link.poll(events)
+    }
+
+    fn condition(&self) -> Option<&WaitCondition> {
+        self.link.condition()
     }
 
     fn sync(&mut self) -> Result<()> {
-        self.ip.sync()
+        self.link.sync()
     }
 }
 
-/// UDP UdpScheme
-pub struct UdpScheme;
+/// The bound ports, by port number and whether they are IPv6
+type UdpPorts = Arc<Intex<Vec<(u16, bool)>>>;
+
+/// Releases a bound port when the last resource using it is dropped
+struct UdpBinding {
+    ports: UdpPorts,
+    port: u16,
+    v6: bool,
+}
+
+impl Drop for UdpBinding {
+    fn drop(&mut self) {
+        let (port, v6) = (self.port, self.v6);
+        self.ports.lock().retain(|&bound| bound != (port, v6));
+    }
+}
+
+/// UDP UdpScheme. `udp:<host>:<port>` is connected to a peer, where an IPv6 host is in brackets,
+/// and `udp:/<port>` is bound to a port, or `udp:/<port>/6` for IPv6, where port 0 picks an
+/// ephemeral port. A port can only be used by one socket at a time
+pub struct UdpScheme {
+    ports: UdpPorts,
+}
+
+impl UdpScheme {
+    pub fn new() -> Box<Self> {
+        box UdpScheme {
+            ports: Arc::new(Intex::new(Vec::new())),
+        }
+    }
+
+    /// Bind a port, or an ephemeral port if it is 0, which is found in the path of the resource
+    fn bind(&mut self, mut port: u16, v6: bool) -> Result<Arc<UdpBinding>> {
+        let mut ports = self.ports.lock();
+        if port == 0 {
+            for _ in 0..UDP_EPHEMERAL_TRIES {
+                let ephemeral = (rand() % 32768 + 32768) as u16;
+                if !ports.contains(&(ephemeral, v6)) {
+                    port = ephemeral;
+                    break;
+                }
+            }
+        }
+        if port == 0 || ports.contains(&(port, v6)) {
+            return Err(Error::new(EADDRINUSE));
+        }
+
+        ports.push((port, v6));
+        Ok(Arc::new(UdpBinding {
+            ports: self.ports.clone(),
+            port: port,
+            v6: v6,
+        }))
+    }
+}
 
 impl KScheme for UdpScheme {
     fn scheme(&self) -> &str {
         "udp"
     }
 
-    fn open(&mut self, url: Url, _: usize) -> Result<Box<Resource>> {
+    fn open(&mut self, url: Url, _: usize, _: u32, _: u32) -> Result<Box<Resource>> {
         let mut parts = url.reference().split('/');
         let remote = parts.next().unwrap_or("");
         let path = parts.next().unwrap_or("");
+        let version = parts.next().unwrap_or("");
 
-        // Check host and port vs path
-        if ! path.is_empty() {
-            let mut remote_parts = remote.split(':');
-            let host_port = remote_parts.nth(1).unwrap_or("").parse::<usize>().unwrap_or(0);
-            if host_port > 0 && host_port < 65536 {
-                if let Ok(mut ip) = Url::from_str("ip:/11").unwrap().open() {
-                    let mut bytes = [0; 8192];
-                    if let Ok(count) = ip.read(&mut bytes) {
-                        if let Some(datagram) = Udp::from_bytes(bytes[.. count].to_vec()) {
-                            if datagram.header.dst.get() as usize == host_port {
-                                let mut path = [0; 256];
-                                if let Ok(path_count) = ip.path(&mut path) {
-                                    let ip_reference = unsafe { str::from_utf8_unchecked(&path[.. path_count]) }.split(':').nth(1).unwrap_or("");
-                                    let ip_remote = ip_reference.split('/').next().unwrap_or("");
-                                    let peer_addr = ip_remote.split(':').next().unwrap_or("");
-
-                                    return Ok(Box::new(UdpResource {
-                                        ip: ip,
-                                        data: datagram.data,
-                                        peer_addr: Ipv4Addr::from_string(&peer_addr.to_string()),
-                                        peer_port: datagram.header.src.get(),
-                                        host_port: host_port as u16,
-                                    }));
-                                }
-                            }
-                        }
-                    }
-                }
-            }
+        if remote.is_empty() {
+            let port = try!(path.parse::<u16>().or(Err(Error::new(ENOENT))));
+
+            let v6 = match version {
+                "" => false,
+                "6" => true,
+                _ => return Err(Error::new(ENOENT)),
+            };
+
+            let link = if v6 {
+                try!(Url::from_str("ethernet:FF.FF.FF.FF.FF.FF/86DD").unwrap().open())
+            } else {
+                try!(Url::from_str("ethernet:FF.FF.FF.FF.FF.FF/800").unwrap().open())
+            };
+            let bound = try!(self.bind(port, v6));
+            return Ok(box UdpSocketResource {
+                link: link,
+                ip: None,
+                host_port: bound.port,
+                v6: v6,
+                bound: bound,
+            });
         } else {
-            let mut remote_parts = remote.split(':');
-            let peer_addr = remote_parts.next().unwrap_or("");
-            let peer_port = remote_parts.next().unwrap_or("").parse::<usize>().unwrap_or(0);
-            if peer_port > 0 && peer_port < 65536 {
-                let host_port = (rand() % 32768 + 32768) as u16;
-
-                if let Ok(ip) = Url::from_str(&format!("ip:{}/11", peer_addr)).unwrap().open() {
-                    return Ok(Box::new(UdpResource {
-                        ip: ip,
-                        data: Vec::new(),
-                        peer_addr: Ipv4Addr::from_string(&peer_addr.to_string()),
-                        peer_port: peer_port as u16,
-                        host_port: host_port,
-                    }));
+            let (host, port) = split_host_port(remote);
+            let peer_port = port.parse::<usize>().unwrap_or(0);
+            if let Some(peer_addr) = IpAddr::parse(host) {
+                if peer_port > 0 && peer_port < 65536 {
+                    let v6 = match peer_addr {
+                        IpAddr::V4(_) => false,
+                        IpAddr::V6(_) => true,
+                    };
+
+                    if let Ok(ip) = Url::from_str(&peer_addr.ip_url(0x11)).unwrap().open() {
+                        let bound = try!(self.bind(path.parse::<u16>().unwrap_or(0), v6));
+                        return Ok(Box::new(UdpResource {
+                            ip: ip,
+                            data: Vec::new(),
+                            peer_addr: peer_addr,
+                            peer_port: peer_port as u16,
+                            host_port: bound.port,
+                            bound: bound,
+                        }));
+                    }
                 }
             }
         }
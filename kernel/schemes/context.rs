@@ -1,90 +1,308 @@
 use alloc::boxed::Box;
 
 use collections::string::{String, ToString};
+use collections::vec::Vec;
 
-use arch::context;
+use arch::context::{self, Context, ContextMemory, ContextZone};
 
-use fs::{KScheme, Resource, Url, VecResource};
+use fs::{DirResource, KScheme, Resource, Url, VecResource};
 
-use system::error::Result;
+use syscall::{Dirent, MODE_DIR, MODE_FILE, Stat};
 
+use system::error::{Error, Result, EACCES, ENOENT};
+
+/// The entries of each process directory
+const ENTRIES: [&'static str; 5] = ["cwd", "env", "fds", "maps", "status"];
+
+/// Reading `context:` returns a table of all contexts. `context:/` lists the PIDs, and each
+/// `context:/<pid>/` has the entries `status`, `fds`, `maps`, `env` and `cwd`. Only `status` can
+/// be read by anyone, the others need root or a user id that owns the context
 pub struct ContextScheme;
 
+/// An entry of the context scheme. Files have a mode and the user and group ids of their owner
+enum ContextEntry {
+    Dir(String, Vec<Dirent>),
+    File(String, String, u16, u32, u32),
+}
+
+impl ContextScheme {
+    /// Get an entry for the caller with the effective user id `uid`
+    fn entry(&self, url: Url, uid: u32) -> Result<ContextEntry> {
+        if url.reference().is_empty() {
+            return Ok(ContextEntry::File("context:".to_string(), context_list(), 0o644, 0, 0));
+        }
+
+        let path = url.reference().trim_matches('/');
+        let contexts = ::env().contexts.lock();
+        let current = try!(contexts.current());
+
+        if path.is_empty() {
+            let mut entries = Vec::new();
+            for context in contexts.iter() {
+                entries.push(Dirent::new(&format!("{}", context.pid), MODE_DIR, 0, context.pid as u64));
+            }
+            return Ok(ContextEntry::Dir("context:/".to_string(), entries));
+        }
+
+        let mut parts = path.splitn(2, '/');
+        let pid = try!(parts.next().unwrap_or("").parse::<usize>().or(Err(Error::new(ENOENT))));
+        let context = try!(contexts.find(pid).or(Err(Error::new(ENOENT))));
+
+        let name = parts.next().unwrap_or("");
+        if name != "" && name != "status" && uid != 0 && ! current.owns(context) {
+            return Err(Error::new(EACCES));
+        }
+
+        let data = match name {
+            "" => {
+                let mut entries = Vec::new();
+                for (i, entry) in ENTRIES.iter().enumerate() {
+                    entries.push(Dirent::new(entry, MODE_FILE, 0, i as u64));
+                }
+                return Ok(ContextEntry::Dir(format!("context:/{}/", pid), entries));
+            },
+            "cwd" => unsafe { (*context.cwd.get()).clone() },
+            "env" => context_env(context),
+            "fds" => context_fds(context),
+            "maps" => context_maps(context, uid == 0),
+            "status" => context_status(context),
+            _ => return Err(Error::new(ENOENT)),
+        };
+
+        let mode = if name == "status" { 0o644 } else { 0o600 };
+        Ok(ContextEntry::File(format!("context:/{}/{}", pid, name), data, mode, context.uid, context.gid))
+    }
+}
+
 impl KScheme for ContextScheme {
     fn scheme(&self) -> &str {
         "context"
     }
 
-    fn open(&mut self, _: Url, _: usize) -> Result<Box<Resource>> {
-        let mut string = format!("{:<6}{:<6}{:<8}{:<8}{:<8}{:<6}{:<6}{:<6}{}\n",
-                                 "PID",
-                                 "PPID",
-                                 "SWITCH",
-                                 "TIME",
-                                 "MEM",
-                                 "FDS",
-                                 "FLG",
-                                 "IOPL",
-                                 "NAME");
-        {
+    fn open(&mut self, url: Url, _: usize, uid: u32, _: u32) -> Result<Box<Resource>> {
+        match try!(self.entry(url, uid)) {
+            ContextEntry::Dir(path, entries) => Ok(box DirResource::new(path, entries)),
+            ContextEntry::File(path, data, _, _, _) => Ok(box VecResource::new(path, data.into_bytes())),
+        }
+    }
+
+    fn stat(&mut self, url: Url, stat: &mut Stat) -> Result<()> {
+        let uid = {
             let contexts = ::env().contexts.lock();
-            for context in contexts.iter() {
-                let mut memory = 0;
-                if context.kernel_stack > 0 {
-                    memory += context::CONTEXT_STACK_SIZE;
-                }
-                if let Some(ref stack) = context.stack {
-                    memory += stack.virtual_size;
-                }
-                memory += unsafe { (*context.image.get()).size() };
-                memory += unsafe { (*context.heap.get()).size() };
-                memory += unsafe { (*context.mmap.get()).size() };
-
-                let memory_string = if memory >= 1024 * 1024 * 1024 {
-                    format!("{} GB", memory / 1024 / 1024 / 1024)
-                } else if memory >= 1024 * 1024 {
-                    format!("{} MB", memory / 1024 / 1024)
-                } else if memory >= 1024 {
-                    format!("{} KB", memory / 1024)
-                } else {
-                    format!("{} B", memory)
-                };
-
-                let mut flags_string = String::new();
-                if context.stack.is_some() {
-                    flags_string.push('U');
-                } else {
-                    flags_string.push('K');
-                }
-                if context.blocked {
-                    flags_string.push('B');
-                }
-                if context.exited {
-                    flags_string.push('E');
-                }
-                if context.vfork.is_some() {
-                    flags_string.push('V');
-                }
-                if context.wake.is_some() {
-                    flags_string.push('S');
-                }
-                if context.supervised {
-                    flags_string.push('T');
-                }
+            try!(contexts.current()).euid
+        };
 
-                string.push_str(&format!("{:<6}{:<6}{:<8}{:<8}{:<8}{:<6}{:<6}{:<6}{}\n",
-                                   context.pid,
-                                   context.ppid,
-                                   context.switch,
-                                   context.time,
-                                   memory_string,
-                                   unsafe { (*context.files.get()).len() },
-                                   flags_string,
-                                   context.iopl,
-                                   context.name));
+        match try!(self.entry(url, uid)) {
+            ContextEntry::Dir(_, _) => stat.st_mode = MODE_DIR | 0o755,
+            ContextEntry::File(_, data, mode, uid, gid) => {
+                stat.st_mode = MODE_FILE | mode;
+                stat.st_size = data.len() as u64;
+                stat.st_uid = uid;
+                stat.st_gid = gid;
             }
         }
+        Ok(())
+    }
+}
 
-        Ok(box VecResource::new("context:".to_string(), string.into_bytes()))
+/// The table of all contexts
+fn context_list() -> String {
+    let mut string = format!("{:<6}{:<6}{:<5}{:<8}{:<8}{:<8}{:<8}{:<6}{:<6}{:<6}{}\n",
+                             "PID",
+                             "PPID",
+                             "PRI",
+                             "SWITCH",
+                             "TIME",
+                             "UTIME",
+                             "MEM",
+                             "FDS",
+                             "FLG",
+                             "IOPL",
+                             "NAME");
+    {
+        let contexts = ::env().contexts.lock();
+        for context in contexts.iter() {
+            let mut memory = 0;
+            if context.kernel_stack > 0 {
+                memory += context::CONTEXT_STACK_SIZE;
+            }
+            if let Some(ref stack) = context.stack {
+                memory += unsafe { (*stack.get()).size() };
+            }
+            memory += unsafe { (*context.image.get()).size() };
+            memory += unsafe { (*context.heap.get()).size() };
+            memory += unsafe { (*context.mmap.get()).size() };
+
+            let memory_string = if memory >= 1024 * 1024 * 1024 {
+                format!("{} GB", memory / 1024 / 1024 / 1024)
+            } else if memory >= 1024 * 1024 {
+                format!("{} MB", memory / 1024 / 1024)
+            } else if memory >= 1024 {
+                format!("{} KB", memory / 1024)
+            } else {
+                format!("{} B", memory)
+            };
+
+            let mut flags_string = String::new();
+            if context.stack.is_some() {
+                flags_string.push('U');
+            } else {
+                flags_string.push('K');
+            }
+            if context.blocked {
+                flags_string.push('B');
+            }
+            if context.exited {
+                flags_string.push('E');
+            }
+            if context.vfork.is_some() {
+                flags_string.push('V');
+            }
+            if context.wake.is_some() {
+                flags_string.push('S');
+            }
+            if context.supervised {
+                flags_string.push('T');
+            }
+            if context.stopped {
+                flags_string.push('Z');
+            }
+
+            string.push_str(&format!("{:<6}{:<6}{:<5}{:<8}{:<8}{:<8}{:<8}{:<6}{:<6}{:<6}{}\n",
+                               context.pid,
+                               context.ppid,
+                               context.priority,
+                               context.switch,
+                               context.time,
+                               context.time_user,
+                               memory_string,
+                               unsafe { (*context.files.get()).len() },
+                               flags_string,
+                               context.iopl,
+                               context.name));
+        }
     }
+
+    string
+}
+
+/// The state, scheduling and signal information of a context, as `Key: value` lines
+fn context_status(context: &Context) -> String {
+    let state = if context.exited {
+        "exited"
+    } else if context.stopped {
+        "stopped"
+    } else if context.wake.is_some() {
+        "sleeping"
+    } else if context.blocked {
+        "blocked"
+    } else {
+        "running"
+    };
+
+    let mut memory = 0;
+    if let Some(ref stack) = context.stack {
+        memory += unsafe { (*stack.get()).size() };
+    }
+    memory += unsafe { (*context.image.get()).size() };
+    memory += unsafe { (*context.heap.get()).size() };
+    memory += unsafe { (*context.mmap.get()).size() };
+
+    let mut string = String::new();
+    string.push_str(&format!("Name: {}\n", context.name));
+    string.push_str(&format!("State: {}\n", state));
+    string.push_str(&format!("Pid: {}\n", context.pid));
+    string.push_str(&format!("PPid: {}\n", context.ppid));
+    string.push_str(&format!("Kernel: {}\n", context.stack.is_none()));
+    string.push_str(&format!("Supervised: {}\n", context.supervised));
+    string.push_str(&format!("Priority: {}\n", context.priority));
+    string.push_str(&format!("Switches: {}\n", context.switch));
+    string.push_str(&format!("Time: {}\n", context.time));
+    string.push_str(&format!("UserTime: {}\n", context.time_user));
+    string.push_str(&format!("Memory: {}\n", memory));
+    string.push_str(&format!("Files: {}\n", unsafe { (*context.files.get()).len() }));
+    string.push_str(&format!("Iopl: {}\n", context.iopl));
+    string.push_str(&format!("SigPending: {:X}\n", context.sig_pending));
+    string.push_str(&format!("SigMask: {:X}\n", context.sig_mask));
+    string
+}
+
+/// The environment variables of a context, as `NAME=value` lines
+fn context_env(context: &Context) -> String {
+    let mut string = String::new();
+    if let Ok(vars) = context.list_env_vars() {
+        for &(ref name, ref value) in vars.iter() {
+            string.push_str(&format!("{}={}\n", name, value));
+        }
+    }
+    string
+}
+
+/// The file descriptors of a context, with their status flags and the paths they were opened with
+fn context_fds(context: &Context) -> String {
+    let mut string = format!("{:<6}{:<8}{}\n", "FD", "FLAGS", "PATH");
+
+    for file in unsafe { (*context.files.get()).iter() } {
+        string.push_str(&format!("{:<6}{:<8X}{}\n", file.fd, file.flags, file.path));
+    }
+
+    string
+}
+
+/// The memory of a context, with its protection and zone. Protection is `r` for readable, `w` for
+/// writeable, then `p` for private memory, `c` for memory shared copy-on-write, `s` for shared
+/// memory, or `-` for reserved memory that is allocated when used. Physical addresses are only
+/// shown if `physical` is set, for root
+fn context_maps(context: &Context, physical: bool) -> String {
+    let mut string = format!("{:<20}{:<8}{:<10}{}\n", "ADDRESS", "PROT", "PHYSICAL", "ZONE");
+
+    let mut zones: Vec<(&str, &ContextZone)> = Vec::new();
+    unsafe {
+        zones.push(("image", &*context.image.get()));
+        zones.push(("heap", &*context.heap.get()));
+        zones.push(("mmap", &*context.mmap.get()));
+        if let Some(ref stack) = context.stack {
+            zones.push(("stack", &*stack.get()));
+        }
+    }
+
+    for &(name, zone) in zones.iter() {
+        let mut memory: Vec<&ContextMemory> = zone.memory.iter().collect();
+        memory.sort_by(|a, b| a.virtual_address.cmp(&b.virtual_address));
+
+        for mem in memory.iter() {
+            let sharing = if mem.cow.is_some() {
+                'c'
+            } else if mem.allocated {
+                'p'
+            } else {
+                's'
+            };
+
+            let physical_string = if physical {
+                format!("{:X}", mem.physical_address)
+            } else {
+                "-".to_string()
+            };
+
+            string.push_str(&format!("{:08X}-{:08X}   r{}{}     {:<10}{}\n",
+                                     mem.virtual_address,
+                                     mem.virtual_address + mem.virtual_size,
+                                     if mem.writeable { 'w' } else { '-' },
+                                     sharing,
+                                     physical_string,
+                                     name));
+        }
+
+        for reserved in zone.reserved.iter() {
+            string.push_str(&format!("{:08X}-{:08X}   r{}-     {:<10}{}\n",
+                                     reserved.virtual_address,
+                                     reserved.virtual_address + reserved.virtual_size,
+                                     if reserved.writeable { 'w' } else { '-' },
+                                     "-",
+                                     name));
+        }
+    }
+
+    string
 }
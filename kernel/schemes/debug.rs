@@ -5,9 +5,14 @@ use collections::string::String;
 
 use core::cmp;
 
+use env::console::Console;
+
 use fs::{KScheme, Resource, Url};
 
+use sync::WaitCondition;
+
 use system::error::Result;
+use system::syscall::{POLLIN, POLLOUT};
 
 /// A debug resource
 pub struct DebugResource {
@@ -35,7 +40,11 @@ impl Resource for DebugResource {
 
     fn read(&mut self, buf: &mut [u8]) -> Result<usize> {
         if self.command.is_empty() {
-            self.command = ::env().console.lock().commands.receive();
+            let mut console = ::env().console.lock();
+            if let Ok(current) = ::env().contexts.lock().current() {
+                console.reader = current.pid;
+            }
+            self.command = console.commands.receive();
         }
 
         let mut i = 0;
@@ -52,6 +61,20 @@ impl Resource for DebugResource {
         Ok(buf.len())
     }
 
+    fn poll(&self, events: u16) -> Result<u16> {
+        let mut revents = events & POLLOUT;
+        if ! self.command.is_empty() || ! ::env().console.lock().commands.inner.lock().is_empty() {
+            revents |= events & POLLIN;
+        }
+        Ok(revents)
+    }
+
+    fn condition(&self) -> Option<&WaitCondition> {
+        // The console lives in the environment for the lifetime of the kernel
+        let console: *const Console = &*::env().console.lock();
+        Some(unsafe { &(*console).commands.condition })
+    }
+
     fn sync(&mut self) -> Result<()> {
         let mut console = ::env().console.lock();
         console.redraw = true;
@@ -73,7 +96,7 @@ impl KScheme for DebugScheme {
         "debug"
     }
 
-    fn open(&mut self, _: Url, _: usize) -> Result<Box<Resource>> {
+    fn open(&mut self, _: Url, _: usize, _: u32, _: u32) -> Result<Box<Resource>> {
         let console = ::env().console.lock();
         if let Some(ref display) = console.display {
             Ok(box DebugResource {
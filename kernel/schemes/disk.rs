@@ -4,20 +4,51 @@ use alloc::boxed::Box;
 use collections::borrow::ToOwned;
 use collections::{String, Vec};
 
+use common::time::Duration;
+
 use core::cmp;
 use disk::Disk;
-use fs::{KScheme, Resource, ResourceSeek, Url, VecResource};
+use disk::cache::{BlockCache, CachedDisk};
+use disk::partition::{self, Partition};
+use fs::{DirResource, KScheme, Resource, ResourceSeek, Url};
 use sync::Intex;
 
-use syscall::{MODE_DIR, MODE_FILE, Stat};
+use syscall::{Dirent, MODE_FILE, Stat, TimeSpec};
+
+use system::error::{Error, Result, EACCES, ENOENT};
+
+/// The stat of a disk, numbered from 1. Disks are owned by root, and only it can read and write them
+fn disk_stat(ino: u64, size: u64, time: TimeSpec) -> Stat {
+    Stat {
+        st_ino: ino,
+        st_mode: MODE_FILE | 0o600,
+        st_nlink: 1,
+        st_size: size,
+        st_blksize: 512,
+        st_blocks: (size + 511) / 512,
+        st_atime: time,
+        st_mtime: time,
+        st_ctime: time,
+        .. Stat::default()
+    }
+}
 
-use system::error::{Error, Result, ENOENT};
+/// The inode of a partition, after the inodes of the disks
+fn partition_ino(disk: usize, partition: usize) -> u64 {
+    (disk as u64 + 1) << 32 | partition as u64
+}
 
-/// A disk resource
+/// A disk resource, for a whole disk or one of its partitions
 pub struct DiskResource {
     pub path: String,
     pub disk: Arc<Intex<Box<Disk>>>,
+    /// The first block, which offsets every read and write
+    pub start: u64,
+    /// The size in bytes, which bounds every read, write and seek
+    pub size: u64,
     pub seek: u64,
+    pub ino: u64,
+    pub time: TimeSpec,
 }
 
 impl Resource for DiskResource {
@@ -25,7 +56,11 @@ impl Resource for DiskResource {
         Ok(box DiskResource {
             path: self.path.clone(),
             disk: self.disk.clone(),
+            start: self.start,
+            size: self.size,
             seek: self.seek,
+            ino: self.ino,
+            time: self.time,
         })
     }
 
@@ -39,19 +74,29 @@ impl Resource for DiskResource {
     }
 
     fn read(&mut self, buf: &mut [u8]) -> Result<usize> {
-        let count = try!(self.disk.lock().read(self.seek/512, buf));
+        let len = cmp::min(buf.len() as u64, self.size - self.seek) as usize;
+        if len == 0 {
+            return Ok(0);
+        }
+
+        let count = try!(self.disk.lock().read(self.start + self.seek/512, &mut buf[.. len]));
         self.seek += count as u64;
         Ok(count)
     }
 
     fn write(&mut self, buf: &[u8]) -> Result<usize> {
-        let count = try!(self.disk.lock().write(self.seek/512, buf));
+        let len = cmp::min(buf.len() as u64, self.size - self.seek) as usize;
+        if len == 0 {
+            return Ok(0);
+        }
+
+        let count = try!(self.disk.lock().write(self.start + self.seek/512, &buf[.. len]));
         self.seek += count as u64;
         Ok(count)
     }
 
     fn seek(&mut self, pos: ResourceSeek) -> Result<usize> {
-        let size = self.disk.lock().size();
+        let size = self.size;
         match pos {
             ResourceSeek::Start(offset) => self.seek = cmp::min(size, offset as u64),
             ResourceSeek::Current(offset) => self.seek = cmp::min(size, cmp::max(0, self.seek as i64 + offset as i64) as u64),
@@ -60,8 +105,13 @@ impl Resource for DiskResource {
         Ok(self.seek as usize)
     }
 
+    fn stat(&self, stat: &mut Stat) -> Result<usize> {
+        *stat = disk_stat(self.ino, self.size, self.time);
+        Ok(0)
+    }
+
     fn sync(&mut self) -> Result<()> {
-        Ok(())
+        self.disk.lock().sync()
     }
 }
 
@@ -71,24 +121,36 @@ impl Drop for DiskResource {
     }
 }
 
-/// A disk scheme
+/// A disk scheme. `disk:/<n>` is a whole disk, `disk:/<n>/` lists the partitions in its MBR or GPT
+/// as `<number> <type> <uuid> <label>` lines, and `disk:/<n>/<number>` is one of the partitions.
+/// The partition table is read on every open, so a table written through `disk:/<n>` is used by
+/// the next open of a partition
 pub struct DiskScheme {
     disks: Vec<Arc<Intex<Box<Disk>>>>,
+    /// The time the disks were found, used as the time of every disk
+    time: TimeSpec,
 }
 
 impl DiskScheme {
-    /// Create a new disk scheme from an array of Disks
-    pub fn new(mut disks: Vec<Box<Disk>>) -> Box<Self> {
+    /// Create a new disk scheme from the disks of a block cache
+    pub fn new(cache: &Arc<Intex<BlockCache>>) -> Box<Self> {
         let mut scheme = box DiskScheme {
-            disks: Vec::new()
+            disks: Vec::new(),
+            time: Duration::realtime().timespec(),
         };
 
+        let mut disks = CachedDisk::all(cache);
         for disk in disks.drain(..) {
             scheme.disks.push(Arc::new(Intex::new(disk)));
         }
 
         scheme
     }
+
+    /// Read the partition table of a disk, through the block cache
+    fn partitions(&self, number: usize) -> Result<Vec<Partition>> {
+        partition::partitions(&mut **self.disks[number].lock())
+    }
 }
 
 impl KScheme for DiskScheme {
@@ -100,59 +162,90 @@ impl KScheme for DiskScheme {
         "disk"
     }
 
-    fn open(&mut self, url: Url, _flags: usize) -> Result<Box<Resource>> {
-        let path = url.reference().trim_matches('/');
+    fn open(&mut self, url: Url, _flags: usize, uid: u32, _: u32) -> Result<Box<Resource>> {
+        let path = url.reference().trim_left_matches('/');
 
         if path.is_empty() {
-            let mut list = String::new();
-            for i in 0..self.disks.len() {
-                if ! list.is_empty() {
-                    list.push('\n');
-                }
-                list.push_str(&format!("{}", i));
+            let mut entries = Vec::new();
+            for (i, disk) in self.disks.iter().enumerate() {
+                entries.push(Dirent::new(&format!("{}", i), MODE_FILE, disk.lock().size(), i as u64 + 1));
             }
 
-            return Ok(box VecResource::new("disk:/".to_owned(), list.into_bytes()));
-        } else {
-            if let Ok(number) = path.parse::<usize>() {
-                if let Some(disk) = self.disks.get(number) {
-                    return Ok(box DiskResource {
-                        path: format!("disk:/{}", number),
-                        disk: disk.clone(),
-                        seek: 0
-                    });
-                }
-            }
+            return Ok(box DirResource::new("disk:/".to_owned(), entries));
         }
 
-        Err(Error::new(ENOENT))
-    }
+        let mut parts = path.splitn(2, '/');
+        let number = try!(parts.next().unwrap_or("").parse::<usize>().or(Err(Error::new(ENOENT))));
+        let disk = try!(self.disks.get(number).ok_or(Error::new(ENOENT))).clone();
 
-    fn stat(&mut self, url: Url, stat: &mut Stat) -> Result<()> {
-        let path = url.reference().trim_matches('/');
+        match parts.next() {
+            None => {
+                // Disks are owned by root, see disk_stat
+                if uid != 0 {
+                    return Err(Error::new(EACCES));
+                }
 
-        if path.is_empty() {
-            let mut list = String::new();
-            for i in 0..self.disks.len() {
-                if ! list.is_empty() {
-                    list.push('\n');
+                let size = disk.lock().size();
+                Ok(box DiskResource {
+                    path: format!("disk:/{}", number),
+                    disk: disk,
+                    start: 0,
+                    size: size,
+                    seek: 0,
+                    ino: number as u64 + 1,
+                    time: self.time,
+                })
+            },
+            Some("") => {
+                let mut entries = Vec::new();
+                let mut list = String::new();
+                let partitions = try!(self.partitions(number));
+                for partition in partitions.iter() {
+                    entries.push(Dirent::new(&format!("{}", partition.number),
+                                             MODE_FILE,
+                                             partition.blocks * 512,
+                                             partition_ino(number, partition.number)));
+                    if ! list.is_empty() {
+                        list.push('\n');
+                    }
+                    list.push_str(&format!("{} {} {} {}", partition.number, partition.kind, partition.uuid, partition.label));
                 }
-                list.push_str(&format!("{}", i));
-            }
 
-            stat.st_mode = MODE_DIR;
-            stat.st_size = list.len() as u64;
-            return Ok(());
-        } else {
-            if let Ok(number) = path.parse::<usize>() {
-                if let Some(disk) = self.disks.get(number) {
-                    stat.st_mode = MODE_FILE;
-                    stat.st_size = disk.lock().size();
-                    return Ok(());
+                Ok(box DirResource::with_list(format!("disk:/{}/", number), entries, list))
+            },
+            Some(partition_string) => {
+                // Partitions are owned by root like their disk
+                if uid != 0 {
+                    return Err(Error::new(EACCES));
                 }
-            }
+
+                let partition_number = try!(partition_string.trim_right_matches('/').parse::<usize>().or(Err(Error::new(ENOENT))));
+                let partitions = try!(self.partitions(number));
+                let partition = try!(partitions.iter().find(|partition| partition.number == partition_number).ok_or(Error::new(ENOENT)));
+
+                Ok(box DiskResource {
+                    path: format!("disk:/{}/{}", number, partition.number),
+                    disk: disk,
+                    start: partition.start,
+                    size: partition.blocks * 512,
+                    seek: 0,
+                    ino: partition_ino(number, partition.number),
+                    time: self.time,
+                })
+            },
         }
+    }
 
-        Err(Error::new(ENOENT))
+    fn stat(&mut self, url: Url, stat: &mut Stat) -> Result<()> {
+        // Disks and partitions can only be opened by root, so neither can they be stat by others
+        let (uid, gid) = {
+            let contexts = ::env().contexts.lock();
+            let current = try!(contexts.current());
+            (current.euid, current.egid)
+        };
+
+        let resource = try!(self.open(url, 0, uid, gid));
+        try!(resource.stat(stat));
+        Ok(())
     }
 }
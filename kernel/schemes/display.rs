@@ -92,6 +92,19 @@ impl Resource for DisplayResource {
         }
     }
 
+    fn mmap(&mut self, offset: usize, size: usize, _writeable: bool) -> Result<usize> {
+        let console = ::env().console.lock();
+        if let Some(ref display) = console.display {
+            if offset + size <= display.size * 4 {
+                Ok(display.onscreen as usize + offset)
+            } else {
+                Err(Error::new(EINVAL))
+            }
+        } else {
+            Err(Error::new(EBADF))
+        }
+    }
+
     fn sync(&mut self) -> Result<()> {
         Ok(())
     }
@@ -104,7 +117,11 @@ impl KScheme for DisplayScheme {
         "display"
     }
 
-    fn open(&mut self, url: Url, _: usize) -> Result<Box<Resource>> {
+    fn open(&mut self, url: Url, _: usize, uid: u32, _: u32) -> Result<Box<Resource>> {
+        if uid != 0 {
+            return Err(Error::new(EACCES));
+        }
+
         if url.reference() == "manager" {
             let mut console = ::env().console.lock();
             if console.draw {
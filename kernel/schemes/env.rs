@@ -3,6 +3,7 @@ use fs::resource::ResourceSeek;
 use collections::string::String;
 use alloc::boxed::Box;
 use system::error::{Error, Result, EINVAL};
+use system::syscall::{Dirent, MODE_FILE};
 use core::cmp::min;
 
 pub struct EnvScheme;
@@ -12,12 +13,13 @@ impl KScheme for EnvScheme {
         "env"
     }
 
-    fn open(&mut self, url: Url, _: usize) -> Result<Box<Resource>> {
+    fn open(&mut self, url: Url, _: usize, _: u32, _: u32) -> Result<Box<Resource>> {
         let name = url.reference();
         if name.contains('=') { return Err(Error::new(EINVAL)) }
         if name == "" || name == "/" {
             Ok(box EnvListResource {
-                pos: 0
+                pos: 0,
+                entry: 0
             })
         } else {
             Ok(box EnvVariableResource {
@@ -27,7 +29,7 @@ impl KScheme for EnvScheme {
         }
     }
 
-    fn unlink(&mut self, url: Url) -> Result<()> {
+    fn unlink(&mut self, url: Url, _: u32, _: u32) -> Result<()> {
         let name = url.reference();
         let contexts = ::env().contexts.lock();
         let current = try!(contexts.current());
@@ -36,7 +38,8 @@ impl KScheme for EnvScheme {
 }
 
 pub struct EnvListResource {
-    pos: usize
+    pos: usize,
+    entry: usize
 }
 
 impl EnvListResource {
@@ -55,7 +58,7 @@ impl EnvListResource {
 
 impl Resource for EnvListResource {
     fn dup(&self) -> Result<Box<Resource>> {
-        Ok(box EnvListResource { pos: 0 })
+        Ok(box EnvListResource { pos: 0, entry: 0 })
     }
 
     fn read(&mut self, buf: &mut [u8]) -> Result<usize> {
@@ -81,8 +84,27 @@ impl Resource for EnvListResource {
                 self.pos = (string.bytes().count() as isize + offset) as usize;
             }
         }
+        if self.pos == 0 {
+            self.entry = 0;
+        }
         Ok(self.pos)
     }
+
+    fn getdents(&mut self, dirents: &mut [Dirent]) -> Result<usize> {
+        let contexts = ::env().contexts.lock();
+        let current = try!(contexts.current());
+        let values = try!(current.list_env_vars());
+        let mut i = 0;
+        for &(ref name, ref value) in values.iter().skip(self.entry) {
+            if i >= dirents.len() {
+                break;
+            }
+            dirents[i] = Dirent::new(name, MODE_FILE, value.len() as u64, (self.entry + i) as u64);
+            i += 1;
+        }
+        self.entry += i;
+        Ok(i)
+    }
 }
 
 pub struct EnvVariableResource {
@@ -1,8 +1,12 @@
 use alloc::boxed::Box;
 
-use collections::{BTreeMap, String};
+use collections::{BTreeMap, Vec};
 
-use fs::{KScheme, Resource, Url, VecResource};
+use common::time::Duration;
+
+use fs::{DirResource, KScheme, Resource, Url, VecResource};
+
+use syscall::{Dirent, MODE_FILE, Stat, TimeSpec};
 
 use system::error::{Error, Result, ENOENT};
 
@@ -11,15 +15,35 @@ pub mod gen;
 
 /// A memory scheme
 pub struct InitFsScheme {
-    pub files: BTreeMap<&'static str, &'static [u8]>
+    pub files: BTreeMap<&'static str, &'static [u8]>,
+    /// The time the scheme was created, used as the time of every file
+    pub time: TimeSpec,
 }
 
 impl InitFsScheme {
     pub fn new() -> Box<InitFsScheme> {
         Box::new(InitFsScheme {
-            files: gen::gen()
+            files: gen::gen(),
+            time: Duration::realtime().timespec(),
         })
     }
+
+    /// The stat of a file, numbered from 1 in order of name. Files are read only, and
+    /// executable by everyone
+    fn file_stat(&self, ino: u64, size: u64) -> Stat {
+        Stat {
+            st_ino: ino,
+            st_mode: MODE_FILE | 0o555,
+            st_nlink: 1,
+            st_size: size,
+            st_blksize: 512,
+            st_blocks: (size + 511) / 512,
+            st_atime: self.time,
+            st_mtime: self.time,
+            st_ctime: self.time,
+            .. Stat::default()
+        }
+    }
 }
 
 impl KScheme for InitFsScheme {
@@ -27,25 +51,43 @@ impl KScheme for InitFsScheme {
         "initfs"
     }
 
-    fn open(&mut self, url: Url, _: usize) -> Result<Box<Resource>> {
+    fn open(&mut self, url: Url, _: usize, _: u32, _: u32) -> Result<Box<Resource>> {
         let reference = url.reference().trim_matches('/');
         if reference.is_empty() {
-            let mut list = String::new();
+            let mut entries = Vec::new();
 
-            for file in self.files.iter() {
-                if ! list.is_empty() {
-                    list.push('\n');
-                }
-                list.push_str(file.0);
+            for (i, file) in self.files.iter().enumerate() {
+                entries.push(Dirent::new(file.0, MODE_FILE, file.1.len() as u64, i as u64 + 1));
             }
 
-            Ok(box VecResource::new(url.to_string(), list.into_bytes()))
+            Ok(box DirResource::new(url.to_string(), entries))
         }else {
-            if let Some(data) = self.files.get(reference) {
-                Ok(box VecResource::new(url.to_string(), data.to_vec()))
-            } else {
-                Err(Error::new(ENOENT))
+            for (i, file) in self.files.iter().enumerate() {
+                if *file.0 == reference {
+                    let stat = self.file_stat(i as u64 + 1, file.1.len() as u64);
+                    return Ok(box VecResource::with_stat(url.to_string(), file.1.to_vec(), stat));
+                }
+            }
+
+            Err(Error::new(ENOENT))
+        }
+    }
+
+    fn stat(&mut self, url: Url, stat: &mut Stat) -> Result<()> {
+        if url.reference().trim_matches('/').is_empty() {
+            let resource = try!(self.open(url, 0, 0, 0));
+            try!(resource.stat(stat));
+            return Ok(());
+        }
+
+        let reference = url.reference().trim_matches('/');
+        for (i, file) in self.files.iter().enumerate() {
+            if *file.0 == reference {
+                *stat = self.file_stat(i as u64 + 1, file.1.len() as u64);
+                return Ok(());
             }
         }
+
+        Err(Error::new(ENOENT))
     }
 }
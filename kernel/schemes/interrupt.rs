@@ -4,7 +4,7 @@ use collections::string::ToString;
 
 use fs::{KScheme, Resource, Url, VecResource};
 
-use system::error::Result;
+use system::error::{Error, Result, EACCES};
 
 pub struct InterruptScheme;
 
@@ -32,7 +32,11 @@ impl KScheme for InterruptScheme {
         "interrupt"
     }
 
-    fn open(&mut self, _: Url, _: usize) -> Result<Box<Resource>> {
+    fn open(&mut self, _: Url, _: usize, uid: u32, _: u32) -> Result<Box<Resource>> {
+        if uid != 0 {
+            return Err(Error::new(EACCES));
+        }
+
         let mut string = format!("{:<6}{:<16}{}\n", "INT", "COUNT", "DESCRIPTION");
 
         {
@@ -1,25 +1,50 @@
+use alloc::arc::Arc;
 use alloc::boxed::Box;
 
 use arch::memory;
 
 use collections::string::ToString;
 
+use disk::cache::BlockCache;
+
 use fs::{KScheme, Resource, Url, VecResource};
 
-use system::error::Result;
+use sync::Intex;
+
+use system::error::{Error, Result, EACCES};
 
-/// A memory scheme
-pub struct MemoryScheme;
+/// A memory scheme, which also reports the use of the block cache
+pub struct MemoryScheme {
+    pub block_cache: Arc<Intex<BlockCache>>,
+}
 
 impl KScheme for MemoryScheme {
     fn scheme(&self) -> &str {
         "memory"
     }
 
-    fn open(&mut self, _: Url, _: usize) -> Result<Box<Resource>> {
-        let string = format!("Memory Used: {} KB\nMemory Free: {} KB\n",
-                             memory::memory_used() / 1024,
-                             memory::memory_free() / 1024);
+    fn open(&mut self, _: Url, _: usize, uid: u32, _: u32) -> Result<Box<Resource>> {
+        if uid != 0 {
+            return Err(Error::new(EACCES));
+        }
+
+        let mut string = format!("Memory Used: {} KB\nMemory Free: {} KB\n",
+                                 memory::memory_used() / 1024,
+                                 memory::memory_free() / 1024);
+
+        {
+            let block_cache = self.block_cache.lock();
+            let stats = block_cache.stats;
+            string.push_str(&format!("Block Cache: {} KB\nBlock Cache Dirty: {} KB\nBlock Cache Hits: {}\nBlock Cache Misses: {}\nBlock Cache Read Ahead: {}\nBlock Cache Write Backs: {}\nBlock Cache Evictions: {}\n",
+                                     block_cache.blocks() / 2,
+                                     block_cache.dirty() / 2,
+                                     stats.hits,
+                                     stats.misses,
+                                     stats.read_ahead,
+                                     stats.write_backs,
+                                     stats.evictions));
+        }
+
         Ok(box VecResource::new("memory:".to_string(), string.into_bytes()))
     }
 }
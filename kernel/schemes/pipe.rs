@@ -5,9 +5,10 @@ use core::cmp;
 
 use fs::Resource;
 
-use sync::WaitQueue;
+use sync::{WaitCondition, WaitQueue};
 
 use system::error::{Error, Result, EPIPE};
+use system::syscall::{POLLERR, POLLHUP, POLLIN, POLLOUT};
 
 /// Read side of a pipe
 pub struct PipeRead {
@@ -62,6 +63,20 @@ impl Resource for PipeRead {
             Ok(i)
         }
     }
+
+    fn poll(&self, events: u16) -> Result<u16> {
+        let mut revents = 0;
+        if ! self.vec.inner.lock().is_empty() {
+            revents |= events & POLLIN;
+        } else if Arc::weak_count(&self.vec) == 0 {
+            revents |= POLLHUP;
+        }
+        Ok(revents)
+    }
+
+    fn condition(&self) -> Option<&WaitCondition> {
+        Some(&self.vec.condition)
+    }
 }
 
 /// Read side of a pipe
@@ -107,6 +122,14 @@ impl Resource for PipeWrite {
         }
     }
 
+    fn poll(&self, events: u16) -> Result<u16> {
+        if self.vec.upgrade().is_some() {
+            Ok(events & POLLOUT)
+        } else {
+            Ok(POLLERR)
+        }
+    }
+
     fn sync(&mut self) -> Result<()> {
         //TODO: Wait until empty
         Ok(())
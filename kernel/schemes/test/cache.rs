@@ -0,0 +1,150 @@
+use alloc::arc::Arc;
+use alloc::boxed::Box;
+
+use collections::string::{String, ToString};
+use collections::vec::Vec;
+
+use common::time::Duration;
+
+use disk::Disk;
+use disk::cache::BlockCache;
+
+use sync::Intex;
+
+use system::error::Result;
+
+/// A disk in memory, which records the block and length of every write
+struct MemoryDisk {
+    data: Arc<Intex<Vec<u8>>>,
+    writes: Arc<Intex<Vec<(u64, usize)>>>,
+}
+
+impl Disk for MemoryDisk {
+    fn name(&self) -> String {
+        "memory".to_string()
+    }
+
+    fn size(&self) -> u64 {
+        self.data.lock().len() as u64
+    }
+
+    fn read(&mut self, block: u64, buffer: &mut [u8]) -> Result<usize> {
+        let data = self.data.lock();
+        for (b, d) in buffer.iter_mut().zip(data[(block * 512) as usize ..].iter()) {
+            *b = *d;
+        }
+        Ok(buffer.len())
+    }
+
+    fn write(&mut self, block: u64, buffer: &[u8]) -> Result<usize> {
+        let mut data = self.data.lock();
+        for (d, b) in data[(block * 512) as usize ..].iter_mut().zip(buffer.iter()) {
+            *d = *b;
+        }
+        self.writes.lock().push((block, buffer.len()));
+        Ok(buffer.len())
+    }
+}
+
+/// Create a cache of one memory disk with `blocks` blocks, where every byte of a block is its number
+fn memory_cache(blocks: usize, capacity: usize) -> (BlockCache, Arc<Intex<Vec<u8>>>, Arc<Intex<Vec<(u64, usize)>>>) {
+    let mut data = Vec::new();
+    for block in 0..blocks {
+        for _ in 0..512 {
+            data.push(block as u8);
+        }
+    }
+
+    let data = Arc::new(Intex::new(data));
+    let writes = Arc::new(Intex::new(Vec::new()));
+    let mut disks: Vec<Box<Disk>> = Vec::new();
+    disks.push(box MemoryDisk {
+        data: data.clone(),
+        writes: writes.clone(),
+    });
+
+    (BlockCache::with_capacity(disks, capacity), data, writes)
+}
+
+pub fn eviction() -> bool {
+    let (mut cache, _, _) = memory_cache(16, 4);
+    let mut buffer = [0; 512];
+
+    // None of these reads follow the previous one, so nothing is read ahead
+    for &block in [5, 3, 1, 7].iter() {
+        test!(cache.read(0, block, &mut buffer).is_ok());
+        test!(buffer[0] == block as u8);
+    }
+    test!(cache.blocks() == 4);
+    test!(cache.stats.misses == 4);
+
+    // Using block 5 makes block 3 the least recently used
+    test!(cache.read(0, 5, &mut buffer).is_ok());
+    test!(cache.read(0, 9, &mut buffer).is_ok());
+    test!(cache.blocks() == 4);
+    test!(cache.stats.evictions == 1);
+
+    test!(cache.read(0, 5, &mut buffer).is_ok());
+    test!(buffer[0] == 5);
+    test!(cache.stats.hits == 2);
+
+    test!(cache.read(0, 3, &mut buffer).is_ok());
+    test!(buffer[0] == 3);
+    test!(cache.stats.misses == 6);
+    test!(cache.stats.evictions == 2);
+    test!(cache.stats.read_ahead == 0);
+
+    succ!();
+}
+
+pub fn write_back() -> bool {
+    let (mut cache, data, writes) = memory_cache(16, 16);
+
+    test!(cache.write(0, 2, &[0xAA; 1024]).is_ok());
+    test!(cache.write(0, 4, &[0xBB; 512]).is_ok());
+    test!(cache.write(0, 7, &[0xCC; 512]).is_ok());
+    test!(cache.dirty() == 4);
+    test!(writes.lock().is_empty());
+
+    // Blocks are not written back before they are old enough
+    test!(cache.sync_older(Duration::new(3600, 0)).is_ok());
+    test!(writes.lock().is_empty());
+
+    // Adjacent blocks are written back together
+    test!(cache.sync_older(Duration::new(0, 0)).is_ok());
+    test!(cache.dirty() == 0);
+    test!(cache.stats.write_backs == 4);
+    test!(*writes.lock() == vec![(2, 1536), (7, 512)]);
+
+    let data = data.lock();
+    test!(data[2 * 512] == 0xAA);
+    test!(data[4 * 512 + 511] == 0xBB);
+    test!(data[5 * 512] == 5);
+    test!(data[7 * 512] == 0xCC);
+
+    succ!();
+}
+
+pub fn read_ahead() -> bool {
+    let (mut cache, _, writes) = memory_cache(64, 64);
+    let mut buffer = [0; 512];
+
+    test!(cache.write(0, 3, &[0xAA; 512]).is_ok());
+
+    // The first read of a disk follows the previous one, so it reads ahead over block 3
+    test!(cache.read(0, 0, &mut buffer).is_ok());
+    test!(buffer[0] == 0);
+    test!(cache.stats.read_ahead == 32);
+
+    test!(cache.read(0, 3, &mut buffer).is_ok());
+    test!(buffer[0] == 0xAA);
+    test!(cache.dirty() == 1);
+
+    test!(cache.read(0, 1, &mut buffer).is_ok());
+    test!(buffer[0] == 1);
+    test!(cache.stats.misses == 1);
+    test!(cache.stats.hits == 2);
+    test!(writes.lock().is_empty());
+
+    succ!();
+}
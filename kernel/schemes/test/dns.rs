@@ -0,0 +1,95 @@
+use collections::vec::Vec;
+
+use network::common::{IpAddr, Ipv4Addr, Ipv6Addr};
+use network::dns::{self, DnsData, TYPE_A, TYPE_AAAA};
+
+fn addr(string: &str) -> IpAddr {
+    IpAddr::parse(string).unwrap()
+}
+
+pub fn hosts() -> bool {
+    let data = "127.0.0.1 localhost\n\
+                ::1 localhost ip6-localhost\n\
+                # 10.0.0.1 localhost\n\
+                10.0.0.2\tServer server.lan # 10.0.0.3 server\n\
+                invalid server\n";
+
+    let addrs = dns::parse_hosts(data, "localhost");
+    test!(addrs.len() == 2);
+    test!(addrs[0].equals(addr("127.0.0.1")));
+    test!(addrs[1].equals(addr("::1")));
+
+    // Names are case insensitive, and may end with a dot
+    let addrs = dns::parse_hosts(data, "SERVER");
+    test!(addrs.len() == 1);
+    test!(addrs[0].equals(addr("10.0.0.2")));
+    test!(dns::parse_hosts(data, "server.lan.").len() == 1);
+
+    test!(dns::parse_hosts(data, "10.0.0.1").is_empty());
+    test!(dns::parse_hosts(data, "invalid").is_empty());
+    succ!();
+}
+
+pub fn nameservers() -> bool {
+    let nameservers = dns::parse_nameservers("10.85.85.1\n\n  8.8.8.8  \nfe80::1\nnameserver\n");
+    test!(nameservers.len() == 2);
+    test!(nameservers[0].equals(Ipv4Addr { bytes: [10, 85, 85, 1] }));
+    test!(nameservers[1].equals(Ipv4Addr { bytes: [8, 8, 8, 8] }));
+    succ!();
+}
+
+pub fn message() -> bool {
+    test!(dns::query(1, "", TYPE_A).is_err());
+    test!(dns::query(1, "a..b", TYPE_A).is_err());
+
+    let request = match dns::query(0x1234, "www.example.com.", TYPE_AAAA) {
+        Ok(request) => request,
+        Err(_) => { fail!(); },
+    };
+    test!(&request[12 ..] == b"\x03www\x07example\x03com\x00\x00\x1C\x00\x01");
+
+    // A response with a compressed CNAME to example.com, then an A and an AAAA record
+    let mut response = request.clone();
+    response[2] = 0x81;
+    response[3] = 0x80;
+    response[7] = 3;
+    response.extend_from_slice(b"\xC0\x0C\x00\x05\x00\x01\x00\x00\x0E\x10\x00\x02\xC0\x10");
+    response.extend_from_slice(b"\xC0\x10\x00\x01\x00\x01\x00\x00\x0E\x10\x00\x04\x5D\xB8\xD8\x22");
+    response.extend_from_slice(b"\xC0\x10\x00\x1C\x00\x01\x00\x00\x0E\x10\x00\x10\
+                                 \x26\x06\x28\x00\x02\x20\x00\x01\x02\x48\x18\x93\x25\xC8\x19\x46");
+
+    let (header, answers) = match dns::parse(&response) {
+        Some(message) => message,
+        None => { fail!(); },
+    };
+    test!(header.id.get() == 0x1234);
+    test!(answers.len() == 3);
+
+    test!(answers[0].name == "www.example.com");
+    match answers[0].data {
+        DnsData::Cname(ref target) => { test!(target == "example.com"); },
+        _ => { fail!(); },
+    }
+
+    test!(answers[1].name == "example.com");
+    match answers[1].data {
+        DnsData::A(addr) => { test!(addr.equals(Ipv4Addr { bytes: [93, 184, 216, 34] })); },
+        _ => { fail!(); },
+    }
+
+    match answers[2].data {
+        DnsData::Aaaa(addr) => {
+            test!(addr.equals(Ipv6Addr::parse("2606:2800:220:1:248:1893:25c8:1946").unwrap()));
+        },
+        _ => { fail!(); },
+    }
+
+    // Truncated responses and pointer loops are rejected
+    test!(dns::parse(&response[.. response.len() - 1]).is_none());
+
+    let mut looped: Vec<u8> = request[.. 12].to_vec();
+    looped.extend_from_slice(b"\xC0\x0C\x00\x01\x00\x01");
+    test!(dns::parse(&looped).is_none());
+
+    succ!();
+}
@@ -0,0 +1,35 @@
+use arch::context::CONTEXT_MMAP_SIZE;
+
+use syscall::{do_sys_mmap, do_sys_munmap};
+
+use system::syscall::{MAP_ANONYMOUS, MAP_PRIVATE, PROT_READ, PROT_WRITE};
+
+/// Map and unmap more memory than the mmap zone of the caller holds, which only works if the
+/// space of removed mappings is used again
+pub fn reuse() -> bool {
+    let size = 1024 * 1024;
+    let map = || do_sys_mmap(size, PROT_READ | PROT_WRITE, MAP_PRIVATE | MAP_ANONYMOUS, 0, 0);
+
+    let first = match map() {
+        Ok(addr) => addr,
+        Err(_) => { fail!(); },
+    };
+    // The second mapping stays, so the first leaves a gap below it
+    let second = match map() {
+        Ok(addr) => addr,
+        Err(_) => { fail!(); },
+    };
+    test!(second > first);
+
+    for _ in 0..CONTEXT_MMAP_SIZE / size + 1 {
+        test!(do_sys_munmap(first, size).is_ok());
+        match map() {
+            Ok(addr) => { test!(addr == first); },
+            Err(_) => { fail!(); },
+        }
+    }
+
+    test!(do_sys_munmap(first, size).is_ok());
+    test!(do_sys_munmap(second, size).is_ok());
+    succ!();
+}
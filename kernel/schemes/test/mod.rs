@@ -30,8 +30,13 @@ macro_rules! fail {
 }
 
 // Add your test here!
+pub mod cache;
+pub mod dns;
 pub mod get_slice;
 pub mod meta;
+pub mod mmap;
+pub mod route;
+pub mod tcp;
 
 pub struct TestScheme;
 
@@ -40,7 +45,7 @@ impl KScheme for TestScheme {
         "test"
     }
 
-    fn open(&mut self, _: Url, _: usize) -> Result<Box<Resource>> {
+    fn open(&mut self, _: Url, _: usize, _: u32, _: u32) -> Result<Box<Resource>> {
         let mut string = String::new();
 
         macro_rules! reg_test {
@@ -90,6 +95,17 @@ impl KScheme for TestScheme {
         reg_test!(meta::meta_test_woah, "Testing the testing (wut)");
         reg_test!(!meta::meta_test_woah_fail, "Testing the fail testing (wut)");
         reg_test!(get_slice::test, "GetSlice");
+        reg_test!(route::parse_network, "Route network parsing");
+        reg_test!(route::lookup, "Route longest prefix match");
+        reg_test!(dns::hosts, "DNS hosts file parsing");
+        reg_test!(dns::nameservers, "DNS nameservers file parsing");
+        reg_test!(dns::message, "DNS query and response parsing");
+        reg_test!(tcp::sequence, "TCP sequence number comparison");
+        reg_test!(tcp::reassembly, "TCP out of order reassembly");
+        reg_test!(cache::eviction, "Block cache LRU eviction");
+        reg_test!(cache::write_back, "Block cache write-back of dirty runs");
+        reg_test!(cache::read_ahead, "Block cache read-ahead around dirty blocks");
+        reg_test!(mmap::reuse, "Mmap reuse of unmapped space");
 
         Ok(box VecResource::new("test:".to_string(), string.into_bytes()))
     }
@@ -0,0 +1,86 @@
+use collections::vec::Vec;
+
+use network::common::{Ipv4Addr, MacAddr};
+use network::config::NetworkConfig;
+use network::route::{self, Route};
+
+fn addr(string: &str) -> Ipv4Addr {
+    Ipv4Addr::parse(string).unwrap()
+}
+
+pub fn parse_network() -> bool {
+    let (dest, netmask) = match route::parse_network("10.85.85.5/24") {
+        Some(network) => network,
+        None => { fail!(); },
+    };
+    test!(dest.equals(addr("10.85.85.0")));
+    test!(netmask.equals(addr("255.255.255.0")));
+
+    let (dest, netmask) = match route::parse_network("192.168.1.1") {
+        Some(network) => network,
+        None => { fail!(); },
+    };
+    test!(dest.equals(addr("192.168.1.1")));
+    test!(netmask.equals(addr("255.255.255.255")));
+
+    let (dest, netmask) = match route::parse_network("1.2.3.4/0") {
+        Some(network) => network,
+        None => { fail!(); },
+    };
+    test!(dest.equals(addr("0.0.0.0")));
+    test!(netmask.equals(addr("0.0.0.0")));
+
+    test!(route::parse_network("10.85.85.0/33").is_none());
+    test!(route::parse_network("10.85.85/24").is_none());
+    succ!();
+}
+
+pub fn lookup() -> bool {
+    let config = NetworkConfig::new(MacAddr { bytes: [0; 6] });
+
+    let mut table = Vec::new();
+    for &(network, gateway) in [("10.0.0.0/8", "10.85.85.10"), ("10.1.0.0/16", "10.85.85.11")].iter() {
+        let (dest, netmask) = route::parse_network(network).unwrap();
+        table.push(Route {
+            dest: dest,
+            netmask: netmask,
+            gateway: addr(gateway),
+        });
+    }
+
+    // The longest prefix wins, whatever the order of the table
+    match route::lookup(&table, &config, addr("10.1.2.3")) {
+        Some(route) => { test!(route.gateway.equals(addr("10.85.85.11"))); },
+        None => { fail!(); },
+    }
+    match route::lookup(&table, &config, addr("10.2.2.3")) {
+        Some(route) => { test!(route.gateway.equals(addr("10.85.85.10"))); },
+        None => { fail!(); },
+    }
+
+    // The subnet of the interface is on the link, and is more specific than 10.0.0.0/8
+    match route::lookup(&table, &config, addr("10.85.85.20")) {
+        Some(route) => {
+            test!(route.prefix_len() == 24);
+            test!(route.next_hop(addr("10.85.85.20")).equals(addr("10.85.85.20")));
+        },
+        None => { fail!(); },
+    }
+
+    // Everything else goes through the default gateway
+    match route::lookup(&table, &config, addr("8.8.8.8")) {
+        Some(route) => {
+            test!(route.prefix_len() == 0);
+            test!(route.next_hop(addr("8.8.8.8")).equals(addr("10.85.85.1")));
+        },
+        None => { fail!(); },
+    }
+
+    // Without a gateway, such as after a lease is lost, there is no default route
+    let mut config = config;
+    config.gateway = addr("0.0.0.0");
+    test!(route::lookup(&table, &config, addr("8.8.8.8")).is_none());
+    test!(route::lookup(&table, &config, addr("10.2.2.3")).is_some());
+
+    succ!();
+}
@@ -0,0 +1,51 @@
+use collections::vec::Vec;
+
+use network::schemes::tcp::{self, queue_segment};
+
+pub fn sequence() -> bool {
+    test!(tcp::seq_lt(1, 2));
+    test!(!tcp::seq_lt(2, 2));
+    test!(tcp::seq_le(2, 2));
+    test!(tcp::seq_gt(3, 2));
+
+    // Sequence numbers wrap around
+    test!(tcp::seq_lt(0xFFFFFFF0, 0x10));
+    test!(tcp::seq_gt(0x10, 0xFFFFFFF0));
+    test!(tcp::seq_le(0xFFFFFFFF, 0));
+    test!(!tcp::seq_le(0, 0xFFFFFFFF));
+    succ!();
+}
+
+pub fn reassembly() -> bool {
+    let mut queue = Vec::new();
+
+    // Segments are kept in order
+    queue_segment(&mut queue, 20, b"uvwx");
+    queue_segment(&mut queue, 10, b"klmn");
+    test!(queue.len() == 2);
+    test!(queue[0] == (10, b"klmn".to_vec()));
+    test!(queue[1] == (20, b"uvwx".to_vec()));
+
+    // A duplicate does not grow the queue
+    queue_segment(&mut queue, 10, b"klmn");
+    test!(queue.len() == 2);
+    test!(queue[0] == (10, b"klmn".to_vec()));
+
+    // Overlapping and adjacent segments are merged
+    queue_segment(&mut queue, 12, b"mnopqrst");
+    test!(queue.len() == 1);
+    test!(queue[0] == (10, b"klmnopqrstuvwx".to_vec()));
+
+    queue_segment(&mut queue, 8, b"ijklmnop");
+    test!(queue.len() == 1);
+    test!(queue[0] == (8, b"ijklmnopqrstuvwx".to_vec()));
+
+    // Merging works across the wrap of the sequence numbers
+    let mut queue = Vec::new();
+    queue_segment(&mut queue, 2, b"cd");
+    queue_segment(&mut queue, 0xFFFFFFFE, b"yzab");
+    test!(queue.len() == 1);
+    test!(queue[0] == (0xFFFFFFFE, b"yzabcd".to_vec()));
+
+    succ!();
+}
@@ -5,6 +5,8 @@ use collections::Vec;
 use core::mem;
 use core::ops::DerefMut;
 
+use syscall::context_exit;
+
 use super::Intex;
 
 pub struct WaitCondition {
@@ -22,17 +24,45 @@ impl WaitCondition {
         let mut contexts = Vec::new();
         mem::swap(self.contexts.lock().deref_mut(), &mut contexts);
         for &context in contexts.iter() {
-            (*context).blocked = false;
+            (*context).unblock();
         }
     }
 
+    /// Register a context to be unblocked on the next notify, without blocking it
+    pub unsafe fn subscribe(&self, context: *mut Context) {
+        self.contexts.lock().push(context);
+    }
+
+    /// Remove a context registered with `subscribe`
+    pub unsafe fn unsubscribe(&self, context: *mut Context) {
+        self.contexts.lock().retain(|&other| other != context);
+    }
+
     pub unsafe fn wait(&self) {
+        let mut context_ptr = 0 as *mut Context;
         if let Ok(mut context) = ::env().contexts.lock().current_mut() {
-            let mut contexts = self.contexts.lock();
-            contexts.push(context.deref_mut() as *mut Context);
-            (*context).blocked = true;
+            context_ptr = context.deref_mut() as *mut Context;
+        }
+
+        if context_ptr as usize > 0 {
+            if let Some(sig) = (*context_ptr).signal_fatal() {
+                context_exit(sig);
+            }
+
+            self.contexts.lock().push(context_ptr);
+            (*context_ptr).blocked = true;
         }
+
         context_switch();
+
+        // A signal may have woken the context without a notify
+        if context_ptr as usize > 0 {
+            self.unsubscribe(context_ptr);
+
+            if let Some(sig) = (*context_ptr).signal_fatal() {
+                context_exit(sig);
+            }
+        }
     }
 }
 
@@ -2,7 +2,8 @@ use alloc::arc::Arc;
 
 use arch::context::{CONTEXT_IMAGE_ADDR, CONTEXT_IMAGE_SIZE, CONTEXT_HEAP_ADDR, CONTEXT_HEAP_SIZE,
                     CONTEXT_MMAP_ADDR, CONTEXT_MMAP_SIZE, CONTEXT_STACK_SIZE, CONTEXT_STACK_ADDR,
-                    context_switch, context_userspace, Context, ContextMemory, ContextZone};
+                    context_switch, context_userspace, Context, ContextMemory, ContextReserved,
+                    ContextZone};
 use arch::elf::Elf;
 use arch::memory;
 use arch::regs::Regs;
@@ -10,15 +11,12 @@ use arch::regs::Regs;
 use collections::string::{String, ToString};
 use collections::vec::Vec;
 
-use common::slice::GetSlice;
-
 use core::cell::UnsafeCell;
 use core::ops::DerefMut;
-use core::{mem, ptr, slice, str};
-
-use fs::Url;
+use core::{cmp, mem, ptr, str};
 
-use system::error::{Error, Result, ENOEXEC, ENOMEM};
+use system::error::{Error, Result, ENOEXEC};
+use system::syscall::{O_RDONLY, SIG_DFL, SIG_IGN};
 
 pub fn execute_thread(context_ptr: *mut Context, entry: usize, mut args: Vec<String>) -> ! {
     Context::spawn("kexec".to_string(), box move || {
@@ -50,6 +48,7 @@ pub fn execute_thread(context_ptr: *mut Context, entry: usize, mut args: Vec<Str
                     virtual_size: virtual_size,
                     writeable: false,
                     allocated: true,
+                    cow: None,
                 });
             }
 
@@ -60,18 +59,39 @@ pub fn execute_thread(context_ptr: *mut Context, entry: usize, mut args: Vec<Str
 
         context.iopl = 0;
 
+        // Handlers were in the old image, but ignored signals stay ignored
+        for handler in context.sig_handlers.iter_mut() {
+            if *handler != SIG_IGN {
+                *handler = SIG_DFL;
+            }
+        }
+        context.sig_frames.clear();
+
         context.regs = Regs::default();
         context.regs.sp = context.kernel_stack + CONTEXT_STACK_SIZE - 128;
 
-        context.stack = Some(ContextMemory {
-            physical_address: unsafe { memory::alloc_aligned(CONTEXT_STACK_SIZE, 4096) },
+        // The top of the stack holds the arguments, the rest is allocated when it is used
+        let mut stack = ContextZone::new(CONTEXT_STACK_ADDR, CONTEXT_STACK_SIZE);
+        stack.reserved.push(ContextReserved {
             virtual_address: CONTEXT_STACK_ADDR,
             virtual_size: CONTEXT_STACK_SIZE,
             writeable: true,
-            allocated: true,
         });
 
-        let user_sp = if let Some(ref stack) = context.stack {
+        let args_size = (128 + context_args.len() * mem::size_of::<usize>() + 4095) / 4096 * 4096;
+        let physical_address = unsafe { memory::alloc_aligned(args_size, 4096) };
+        if physical_address > 0 {
+            stack.memory.push(ContextMemory {
+                physical_address: physical_address,
+                virtual_address: CONTEXT_STACK_ADDR + CONTEXT_STACK_SIZE - args_size,
+                virtual_size: args_size,
+                writeable: true,
+                allocated: true,
+                cow: None,
+            });
+        }
+
+        let user_sp = if let Some(stack) = stack.memory.last() {
             let mut sp = stack.physical_address + stack.virtual_size - 128;
             for arg in context_args.iter() {
                 sp -= mem::size_of::<usize>();
@@ -82,6 +102,8 @@ pub fn execute_thread(context_ptr: *mut Context, entry: usize, mut args: Vec<Str
             0
         };
 
+        context.stack = Some(UnsafeCell::new(stack));
+
         unsafe {
             context.push(0x20 | 3);
             context.push(user_sp);
@@ -92,7 +114,7 @@ pub fn execute_thread(context_ptr: *mut Context, entry: usize, mut args: Vec<Str
         }
 
         if let Some(vfork) = context.vfork.take() {
-            unsafe { (*vfork).blocked = false; }
+            unsafe { (*vfork).unblock(); }
         }
     });
 
@@ -106,56 +128,22 @@ pub fn execute(mut args: Vec<String>) -> Result<usize> {
     let contexts = ::env().contexts.lock();
     let current = try!(contexts.current());
 
-    let mut vec: Vec<u8> = Vec::new();
+    let vec: Vec<u8>;
 
     let path = current.canonicalize(args.get(0).map_or("", |p| &p));
-    let mut url = try!(Url::from_str(&path)).to_cow();
+    let mut url = try!(current.url(&path)).to_cow();
     {
-        let mut resource = if let Ok(resource) = url.as_url().open() {
+        // The program is opened with the ids of the caller, so it must be able to read it
+        let mut resource = if let Ok(resource) = ::env().open(url.as_url(), O_RDONLY, current.euid, current.egid) {
             resource
         } else {
             let path = "file:/bin/".to_string() + args.get(0).map_or("", |p| &p);
-            url = try!(Url::from_str(&path)).to_owned().into_cow();
-            try!(url.as_url().open())
+            url = try!(current.url(&path)).to_owned().into_cow();
+            try!(::env().open(url.as_url(), O_RDONLY, current.euid, current.egid))
         };
 
-        // Hack to allow file scheme to find memory in context's memory space
-        unsafe {
-            let heap = &mut *current.heap.get();
-
-            let virtual_size = 65536;
-            let virtual_address = heap.next_mem();
-
-            let physical_address = memory::alloc_aligned(virtual_size, 4096);
-            if physical_address == 0 {
-                return Err(Error::new(ENOMEM));
-            }
-
-            let mut memory = ContextMemory {
-                physical_address: physical_address,
-                virtual_address: virtual_address,
-                virtual_size: virtual_size,
-                writeable: true,
-                allocated: true,
-            };
-
-            memory.map();
-
-            heap.memory.push(memory);
-
-            'reading: loop {
-                let mut bytes = slice::from_raw_parts_mut(virtual_address as *mut u8, virtual_size);
-                match resource.read(&mut bytes) {
-                    Ok(0) => break 'reading,
-                    Ok(count) => vec.extend_from_slice(bytes.get_slice(.. count)),
-                    Err(err) => return Err(err)
-                }
-            }
-
-            let mut memory = heap.memory.pop().unwrap();
-
-            memory.unmap();
-        }
+        // The file scheme can only write to memory in the context's memory space
+        vec = try!(current.read_to_end(&mut *resource));
     }
 
     if vec.starts_with(b"#!") {
@@ -180,6 +168,7 @@ pub fn execute(mut args: Vec<String>) -> Result<usize> {
             Ok(executable) => {
                 let entry = unsafe { executable.entry() };
                 let mut memory = Vec::new();
+                let mut reserved = Vec::new();
                 unsafe {
                     for segment in executable.load_segment().iter() {
                         let virtual_address = segment.vaddr as usize;
@@ -187,27 +176,45 @@ pub fn execute(mut args: Vec<String>) -> Result<usize> {
 
                         let offset = virtual_address % 4096;
 
-                        let physical_address = memory::alloc_aligned(virtual_size + offset, 4096);
-
-                        if physical_address > 0 {
-                            //TODO: Use paging to fix collisions
-                            // Copy progbits
-                            ::memcpy((physical_address + offset) as *mut u8,
-                                     (executable.data.as_ptr() as usize + segment.off as usize) as *const u8,
-                                     segment.file_len as usize);
+                        // Only the pages with progbits are allocated now, the rest are zero
+                        // until they are used
+                        let file_size = cmp::min((segment.file_len as usize + offset + 4095) / 4096 * 4096,
+                                                 virtual_size + offset);
+
+                        if file_size > 0 {
+                            let physical_address = memory::alloc_aligned(file_size, 4096);
+
+                            if physical_address > 0 {
+                                //TODO: Use paging to fix collisions
+                                // Copy progbits
+                                ::memcpy((physical_address + offset) as *mut u8,
+                                         (executable.data.as_ptr() as usize + segment.off as usize) as *const u8,
+                                         segment.file_len as usize);
+
+                                memory.push(ContextMemory {
+                                    physical_address: physical_address,
+                                    virtual_address: virtual_address - offset,
+                                    virtual_size: file_size,
+                                    writeable: segment.flags & 2 == 2,
+                                    allocated: true,
+                                    cow: None,
+                                });
+                            } else {
+                                continue;
+                            }
+                        }
 
-                            memory.push(ContextMemory {
-                                physical_address: physical_address,
-                                virtual_address: virtual_address - offset,
-                                virtual_size: virtual_size + offset,
+                        if virtual_size + offset > file_size {
+                            reserved.push(ContextReserved {
+                                virtual_address: virtual_address - offset + file_size,
+                                virtual_size: virtual_size + offset - file_size,
                                 writeable: segment.flags & 2 == 2,
-                                allocated: true,
                             });
                         }
                     }
                 }
 
-                if entry > 0 && ! memory.is_empty() {
+                if entry > 0 && (! memory.is_empty() || ! reserved.is_empty()) {
                     let mut contexts = ::env().contexts.lock();
                     let mut context = try!(contexts.current_mut());
 
@@ -220,6 +227,7 @@ pub fn execute(mut args: Vec<String>) -> Result<usize> {
 
                     let mut image = ContextZone::new(CONTEXT_IMAGE_ADDR, CONTEXT_IMAGE_SIZE);
                     image.memory = memory;
+                    image.reserved = reserved;
 
                     context.image = Arc::new(UnsafeCell::new(image));
                     context.heap = Arc::new(UnsafeCell::new(ContextZone::new(CONTEXT_HEAP_ADDR, CONTEXT_HEAP_SIZE)));
@@ -1,16 +1,25 @@
-use arch::context::ContextFile;
+use arch::context::{context_switch, Context, ContextFile};
+
+use collections::Vec;
+use collections::string::ToString;
+
+use common::time::{self, Duration};
 
 use core::slice;
+use core::ops::DerefMut;
 
-use fs::{ResourceSeek, Url};
+use fs::ResourceSeek;
 
 use schemes::pipe::{PipeRead, PipeWrite};
 
+use sync::WaitCondition;
+
 use system::c_string_to_str;
 
-use syscall::{Stat, SEEK_CUR, SEEK_END, SEEK_SET};
+use syscall::{Dirent, PollFd, Stat, F_GETFL, F_SETFL, O_APPEND, O_CREAT, O_EXCL, O_NONBLOCK, O_TRUNC,
+              POLLERR, POLLNVAL, SEEK_CUR, SEEK_END, SEEK_SET};
 
-use system::error::{Error, Result, EBADF, EFAULT, EINVAL};
+use system::error::{Error, Result, EBADF, EFAULT, EINTR, EINVAL};
 
 /** <!-- @MANSTART{sys_chdir} -->
 NAME
@@ -133,6 +142,8 @@ pub fn do_sys_dup(fd: usize) -> Result<usize> {
     let contexts = ::env().contexts.lock();
     let current = try!(contexts.current());
     let resource = try!(current.get_file(fd));
+    let flags = try!(current.get_file_flags(fd));
+    let path = try!(current.get_file_path(fd));
     let new_resource = try!(resource.dup());
     let new_fd = current.next_fd();
 
@@ -141,12 +152,60 @@ pub fn do_sys_dup(fd: usize) -> Result<usize> {
     unsafe {
         (*current.files.get()).push(ContextFile {
             fd: new_fd,
+            flags: flags,
+            path: path,
             resource: new_resource,
         });
     }
     Ok(new_fd)
 }
 
+/** <!-- @MANSTART{sys_fcntl} -->
+NAME
+    sys_fcntl - manipulate file descriptor
+
+SYNOPSIS
+    sys_fcntl(fd: usize, cmd: usize, arg: usize) -> Result<usize>;
+
+DESCRIPTION
+    sys_fcntl performs the operation cmd on the file descriptor fd
+
+    F_GETFL: 3
+        Return the file access mode and the file status flags
+
+    F_SETFL: 4
+        Set the file status flags to arg. The access mode (O_RDONLY, O_WRONLY, O_RDWR) and the file
+        creation flags (O_CREAT, O_EXCL, O_TRUNC) in arg are ignored
+
+RETURN VALUE
+    On success, F_GETFL returns Ok(flags) and F_SETFL returns Ok(0). On error, Err(err) is returned
+    where err is one of the following errors
+
+ERRORS
+    EBADF
+        fd is not a valid open file decriptor
+
+    EINVAL
+        cmd is not recognized
+
+    ESRCH
+        Currently not running in a process context (rare, would only happen during kernel init)
+<!-- @MANEND --> */
+pub fn do_sys_fcntl(fd: usize, cmd: usize, arg: usize) -> Result<usize> {
+    let contexts = ::env().contexts.lock();
+    let current = try!(contexts.current());
+    let flags = try!(current.get_file_flags(fd));
+    match cmd {
+        F_GETFL => Ok(flags),
+        F_SETFL => {
+            let status_flags = O_NONBLOCK | O_APPEND;
+            try!(current.set_file_flags(fd, (flags & ! status_flags) | (arg & status_flags)));
+            Ok(0)
+        },
+        _ => Err(Error::new(EINVAL)),
+    }
+}
+
 pub fn do_sys_fpath(fd: usize, buf: *mut u8, count: usize) -> Result<usize> {
     let contexts = ::env().contexts.lock();
     let current = try!(contexts.current());
@@ -159,6 +218,8 @@ pub fn do_sys_fstat(fd: usize, stat: *mut Stat) -> Result<usize> {
     let current = try!(contexts.current());
     let resource = try!(current.get_file(fd));
     if stat as usize > 0 {
+        // Fields not known to the resource are left as zero
+        unsafe { *stat = Stat::default() };
         resource.stat(unsafe { &mut *stat })
     } else {
         Err(Error::new(EFAULT))
@@ -235,7 +296,87 @@ pub fn do_sys_ftruncate(fd: usize, length: usize) -> Result<usize> {
     resource.truncate(length).and(Ok(0))
 }
 
-//TODO: Link
+/** <!-- @MANSTART{sys_getdents} -->
+NAME
+    sys_getdents - get directory entries
+
+SYNOPSIS
+    sys_getdents(fd: usize, dirents: *mut Dirent, count: usize) -> Result<usize>;
+
+DESCRIPTION
+    sys_getdents reads up to count Dirent structures from the directory referred to by fd into the
+    buffer pointed to by dirents. Each entry carries the name, mode, size and inode number of the
+    file, so that the type of an entry is known without calling sys_stat
+
+RETURN VALUE
+    On success, Ok(count) is returned, where count is the number of entries read. Ok(0) is
+    returned at the end of the directory. On error, Err(err) is returned where err is one of the
+    following errors
+
+ERRORS
+    EBADF
+        fd is not a valid open file decriptor
+
+    EFAULT
+        dirents is outside of the accessible address space of the process
+
+    EPERM
+        fd does not refer to a directory, or the scheme does not support listing entries
+
+    ESRCH
+        Currently not running in a process context (rare, would only happen during kernel init)
+<!-- @MANEND --> */
+pub fn do_sys_getdents(fd: usize, dirents: *mut Dirent, count: usize) -> Result<usize> {
+    let mut contexts = ::env().contexts.lock();
+    let mut current = try!(contexts.current_mut());
+    let mut resource = try!(current.get_file_mut(fd));
+    if dirents as usize > 0 {
+        resource.getdents(unsafe { slice::from_raw_parts_mut(dirents, count) })
+    } else {
+        Err(Error::new(EFAULT))
+    }
+}
+
+/** <!-- @MANSTART{sys_link} -->
+NAME
+    sys_link - make a new name for a file
+
+SYNOPSIS
+    sys_link(old: *const u8, new: *const u8) -> Result<usize>;
+
+DESCRIPTION
+    sys_link creates a new link (also known as a hard link) named new to the existing file old
+
+RETURN VALUE
+    On success, Ok(0) is returned. On error, Err(err) is returned where err is one of the following
+    errors
+
+ERRORS
+    EEXIST
+        new already exists
+
+    EFAULT
+        old or new points outside of the accessible address space of the process
+
+    ENOENT
+        A directory component in old or new does not exist
+
+    EPERM
+        The filesystem containing old does not support the creation of links
+
+    EXDEV
+        old and new are not on the same scheme
+
+    ESRCH
+        Currently not running in a process context (rare, would only happen during kernel init)
+<!-- @MANEND --> */
+pub fn do_sys_link(old: *const u8, new: *const u8) -> Result<usize> {
+    let contexts = ::env().contexts.lock();
+    let current = try!(contexts.current());
+    let old_string = current.canonicalize(c_string_to_str(old));
+    let new_string = current.canonicalize(c_string_to_str(new));
+    ::env().link(try!(current.url(&old_string)), try!(current.url(&new_string))).and(Ok(0))
+}
 
 /** <!-- @MANSTART{sys_lseek} -->
 NAME
@@ -326,7 +467,7 @@ pub fn do_sys_mkdir(path: *const u8, flags: usize) -> Result<usize> {
     let contexts = ::env().contexts.lock();
     let current = try!(contexts.current());
     let path_string = current.canonicalize(c_string_to_str(path));
-    ::env().mkdir(try!(Url::from_str(&path_string)), flags).and(Ok(0))
+    ::env().mkdir(try!(current.url(&path_string)), flags, current.euid, current.egid).and(Ok(0))
 }
 
 /** <!-- @MANSTART{sys_open} -->
@@ -339,6 +480,12 @@ SYNOPSIS
 DESCRIPTION
     sys_open returns a file descriptor referencing path, creating path if O_CREAT is provided
 
+    If O_NONBLOCK is provided, reads and writes on the file descriptor return EAGAIN instead of
+    blocking. It can be changed later with sys_fcntl
+
+    The scheme is given the effective user and group ids of the calling process, so it can check
+    them against the permissions of the file
+
     TODO: Open is very complicated, and has a lot of flags
 
 RETURN VALUE
@@ -386,19 +533,21 @@ pub fn do_sys_open(path_c: *const u8, flags: usize) -> Result<usize> {
     let current = try!(contexts.current());
     let path = current.canonicalize(c_string_to_str(path_c));
     //debugln!("{}: {}: open {}", current.pid, current.name, path);
-    let url = try!(Url::from_str(&path));
-    let resource = try!(::env().open(url, flags));
+    let url = try!(current.url(&path));
+    let resource = try!(::env().open(url, flags, current.euid, current.egid));
     let fd = current.next_fd();
     unsafe {
         (*current.files.get()).push(ContextFile {
             fd: fd,
+            flags: flags & ! (O_CREAT | O_TRUNC | O_EXCL),
+            path: path.clone(),
             resource: resource,
         });
     }
     Ok(fd)
 }
 
-pub fn do_sys_pipe2(fds: *mut usize, _flags: usize) -> Result<usize> {
+pub fn do_sys_pipe2(fds: *mut usize, flags: usize) -> Result<usize> {
     let contexts = ::env().contexts.lock();
     let current = try!(contexts.current());
     if fds as usize > 0 {
@@ -409,12 +558,16 @@ pub fn do_sys_pipe2(fds: *mut usize, _flags: usize) -> Result<usize> {
             *fds.offset(0) = current.next_fd();
             (*current.files.get()).push(ContextFile {
                 fd: *fds.offset(0),
+                flags: flags & O_NONBLOCK,
+                path: "pipe:r".to_string(),
                 resource: read,
             });
 
             *fds.offset(1) = current.next_fd();
             (*current.files.get()).push(ContextFile {
                 fd: *fds.offset(1),
+                flags: flags & O_NONBLOCK,
+                path: "pipe:w".to_string(),
                 resource: write,
             });
         }
@@ -425,6 +578,124 @@ pub fn do_sys_pipe2(fds: *mut usize, _flags: usize) -> Result<usize> {
     }
 }
 
+/// How often to check resources that cannot notify readiness changes, in nanoseconds
+const POLL_INTERVAL: i32 = 10 * time::NANOS_PER_MILLI;
+
+/** <!-- @MANSTART{sys_poll} -->
+NAME
+    sys_poll - wait for some event on a set of file descriptors
+
+SYNOPSIS
+    sys_poll(fds: *mut PollFd, count: usize, timeout: isize) -> Result<usize>;
+
+DESCRIPTION
+    sys_poll waits for one of the count file descriptors in fds to become ready to perform I/O.
+    For each entry, events is a mask of POLLIN and POLLOUT, and revents is set by the kernel to
+    the events that are ready, along with POLLERR, POLLHUP or POLLNVAL.
+
+    timeout is the number of milliseconds to block. A negative timeout blocks until an event
+    occurs, and a timeout of zero returns immediately.
+
+    Resources of userspace schemes are polled again when their scheme daemon writes a packet
+    with an id of 0 to the scheme, which it does when the readiness of its resources may have
+    changed.
+
+RETURN VALUE
+    On success, Ok(count) is returned, where count is the number of entries with a nonzero
+    revents. Ok(0) is returned if the timeout expired. On error, Err(err) is returned where err
+    is one of the following errors
+
+ERRORS
+    EFAULT
+        fds is outside of the accessible address space of the process
+
+    EINTR
+        A signal arrived before any file descriptor was ready
+
+    ESRCH
+        Currently not running in a process context (rare, would only happen during kernel init)
+<!-- @MANEND --> */
+pub fn do_sys_poll(fds: *mut PollFd, count: usize, timeout: isize) -> Result<usize> {
+    if fds as usize == 0 && count > 0 {
+        return Err(Error::new(EFAULT));
+    }
+
+    let fds = unsafe { slice::from_raw_parts_mut(fds, count) };
+
+    let end = if timeout >= 0 {
+        Some(Duration::monotonic() + Duration::new((timeout / 1000) as i64,
+                                                    (timeout % 1000) as i32 * time::NANOS_PER_MILLI))
+    } else {
+        None
+    };
+
+    loop {
+        let mut contexts = ::env().contexts.lock();
+        let mut current = try!(contexts.current_mut());
+
+        let mut ready = 0;
+        let mut conditions: Vec<*const WaitCondition> = Vec::new();
+        let mut interval = false;
+        for pollfd in fds.iter_mut() {
+            pollfd.revents = match current.get_file(pollfd.fd) {
+                Ok(resource) => {
+                    match resource.condition() {
+                        Some(condition) => conditions.push(condition),
+                        None => interval = true,
+                    }
+                    resource.poll(pollfd.events).unwrap_or(POLLERR)
+                },
+                Err(_) => POLLNVAL,
+            };
+
+            if pollfd.revents != 0 {
+                ready += 1;
+            }
+        }
+
+        if ready > 0 {
+            return Ok(ready);
+        }
+
+        if current.signal_interrupt() {
+            return Err(Error::new(EINTR));
+        }
+
+        let now = Duration::monotonic();
+        let mut wake = end;
+        if let Some(end) = end {
+            if end <= now {
+                return Ok(0);
+            }
+        }
+        if interval {
+            let next = now + Duration::new(0, POLL_INTERVAL);
+            wake = match wake {
+                Some(end) if end < next => Some(end),
+                _ => Some(next),
+            };
+        }
+
+        let context_ptr: *mut Context = current.deref_mut().deref_mut();
+        unsafe {
+            for &condition in conditions.iter() {
+                (*condition).subscribe(context_ptr);
+            }
+
+            current.blocked = true;
+            current.wake = wake;
+
+            context_switch();
+
+            current.wake = None;
+
+            for &condition in conditions.iter() {
+                (*condition).unsubscribe(context_ptr);
+            }
+        }
+    }
+}
+
 /** <!-- @MANSTART{sys_read} -->
 NAME
     sys_read - read from a file descriptor
@@ -441,6 +712,9 @@ RETURN VALUE
     Err(err) is returned where err is one of the following errors
 
 ERRORS
+    EAGAIN
+        fd is marked O_NONBLOCK and the operation would block
+
     EBADF
         fd is not a valid open file decriptor
 
@@ -459,23 +733,74 @@ ERRORS
 pub fn do_sys_read(fd: usize, buf: *mut u8, count: usize) -> Result<usize> {
     let mut contexts = ::env().contexts.lock();
     let mut current = try!(contexts.current_mut());
+    let flags = try!(current.get_file_flags(fd));
     let mut resource = try!(current.get_file_mut(fd));
-    resource.read(unsafe { slice::from_raw_parts_mut(buf, count) })
+    if flags & O_NONBLOCK == O_NONBLOCK {
+        resource.read_nonblock(unsafe { slice::from_raw_parts_mut(buf, count) })
+    } else {
+        resource.read(unsafe { slice::from_raw_parts_mut(buf, count) })
+    }
+}
+
+/** <!-- @MANSTART{sys_rename} -->
+NAME
+    sys_rename - change the name or location of a file
+
+SYNOPSIS
+    sys_rename(old: *const u8, new: *const u8) -> Result<usize>;
+
+DESCRIPTION
+    sys_rename renames old to new. If new already exists, it is atomically replaced, so that there
+    is no point at which another process attempting to access new will find it missing
+
+RETURN VALUE
+    On success, Ok(0) is returned. On error, Err(err) is returned where err is one of the following
+    errors
+
+ERRORS
+    EFAULT
+        old or new points outside of the accessible address space of the process
+
+    EISDIR
+        new is an existing directory, but old is not a directory
+
+    ENOENT
+        old does not exist, or a directory component in new does not exist
+
+    ENOTDIR
+        old is a directory, and new exists but is not a directory
+
+    EPERM
+        The filesystem containing old does not support renaming
+
+    EXDEV
+        old and new are not on the same scheme
+
+    ESRCH
+        Currently not running in a process context (rare, would only happen during kernel init)
+<!-- @MANEND --> */
+pub fn do_sys_rename(old: *const u8, new: *const u8) -> Result<usize> {
+    let contexts = ::env().contexts.lock();
+    let current = try!(contexts.current());
+    let old_string = current.canonicalize(c_string_to_str(old));
+    let new_string = current.canonicalize(c_string_to_str(new));
+    ::env().rename(try!(current.url(&old_string)), try!(current.url(&new_string))).and(Ok(0))
 }
 
 pub fn do_sys_rmdir(path: *const u8) -> Result<usize> {
     let contexts = ::env().contexts.lock();
     let current = try!(contexts.current());
     let path_string = current.canonicalize(c_string_to_str(path));
-    ::env().rmdir(try!(Url::from_str(&path_string))).and(Ok(0))
+    ::env().rmdir(try!(current.url(&path_string))).and(Ok(0))
 }
 
 pub fn do_sys_stat(path: *const u8, stat: *mut Stat) -> Result<usize> {
     let contexts = ::env().contexts.lock();
     let current = try!(contexts.current());
     let path = current.canonicalize(c_string_to_str(path));
-    let url = try!(Url::from_str(&path));
+    let url = try!(current.url(&path));
     if stat as usize > 0 {
+        unsafe { *stat = Stat::default() };
         ::env().stat(url, unsafe { &mut *stat }).and(Ok(0))
     } else {
         Err(Error::new(EFAULT))
@@ -486,7 +811,7 @@ pub fn do_sys_unlink(path: *const u8) -> Result<usize> {
     let contexts = ::env().contexts.lock();
     let current = try!(contexts.current());
     let path_string = current.canonicalize(c_string_to_str(path));
-    ::env().unlink(try!(Url::from_str(&path_string))).and(Ok(0))
+    ::env().unlink(try!(current.url(&path_string)), current.euid, current.egid).and(Ok(0))
 }
 
 /** <!-- @MANSTART{sys_write} -->
@@ -505,6 +830,9 @@ RETURN VALUE
     Err(err) is returned where err is one of the following errors
 
 ERRORS
+    EAGAIN
+        fd is marked O_NONBLOCK and the operation would block
+
     EBADF
         fd is not a valid open file decriptor
 
@@ -529,6 +857,11 @@ ERRORS
 pub fn do_sys_write(fd: usize, buf: *const u8, count: usize) -> Result<usize> {
     let mut contexts = ::env().contexts.lock();
     let mut current = try!(contexts.current_mut());
+    let flags = try!(current.get_file_flags(fd));
     let mut resource = try!(current.get_file_mut(fd));
-    resource.write(unsafe { slice::from_raw_parts(buf, count) })
+    if flags & O_NONBLOCK == O_NONBLOCK {
+        resource.write_nonblock(unsafe { slice::from_raw_parts(buf, count) })
+    } else {
+        resource.write(unsafe { slice::from_raw_parts(buf, count) })
+    }
 }
@@ -1,63 +1,325 @@
-use arch::context::ContextMemory;
+use arch::context::{ContextMemory, ContextReserved, ContextZone};
 use arch::memory;
+use arch::paging::Page;
 
-use system::error::Result;
-
-//TODO: Refactor file to propogate results
+use system::error::{Error, Result, EACCES, EINVAL, ENOMEM};
+use system::syscall::{MAP_ANONYMOUS, MAP_PRIVATE, MAP_SHARED, O_RDWR, O_WRONLY, PROT_READ, PROT_WRITE};
 
 pub fn do_sys_brk(addr: usize) -> Result<usize> {
-    let mut ret = 0;
+    let contexts = ::env().contexts.lock();
+    let current = try!(contexts.current());
+    let heap = unsafe { &mut *current.heap.get() };
+
+    // The heap is reserved up to the break, and pages are only allocated when they are used
+    if heap.reserved.is_empty() {
+        heap.reserved.push(ContextReserved {
+            virtual_address: heap.address,
+            virtual_size: 0,
+            writeable: true,
+        });
+    }
+
+    if addr < heap.address {
+        return Ok(heap.next_mem());
+    }
+
+    if addr > heap.address + heap.size {
+        debugln!("{}: {}", current.pid, current.name);
+        debugln!("BRK: {:X} is past the end of the heap", addr);
+        return Ok(heap.next_mem());
+    }
+
+    let old_end = heap.reserved[0].end();
+    heap.reserved[0].virtual_size = addr - heap.address;
+    let new_end = heap.reserved[0].end();
+
+    unsafe {
+        // Pages that were reserved are not present until they are used
+        for i in 0..new_end.saturating_sub(old_end) / 4096 {
+            Page::new(old_end + i * 4096).unmap();
+        }
+
+        // Free the pages past the new break
+        for mem in heap.memory.iter_mut() {
+            let mem_end = mem.virtual_address + (mem.virtual_size + 4095) / 4096 * 4096;
+            if mem_end > new_end {
+                mem.unmap();
+                mem.virtual_size = if mem.virtual_address < new_end {
+                    new_end - mem.virtual_address
+                } else {
+                    0
+                };
+                mem.map();
+            }
+        }
+        heap.clean_mem();
+
+        for i in 0..old_end.saturating_sub(new_end) / 4096 {
+            let page = new_end + i * 4096;
+            Page::new(page).map_kernel_write(page);
+        }
+    }
+
+    Ok(addr)
+}
+
+/** <!-- @MANSTART{sys_mmap} -->
+NAME
+    sys_mmap - map files or memory into the address space
+
+SYNOPSIS
+    sys_mmap(size: usize, prot: usize, flags: usize, fd: usize, offset: usize) -> Result<usize>;
+
+DESCRIPTION
+    sys_mmap creates a mapping of size bytes, rounded up to the page size, in the mmap region of
+    the calling process. The kernel chooses the address, which is the lowest free space in the
+    region, so space freed by sys_munmap is used again.
+
+    prot must contain PROT_READ, and PROT_WRITE makes the mapping writeable. PROT_EXEC is
+    accepted, but memory is always executable.
+
+    flags must contain exactly one of MAP_SHARED and MAP_PRIVATE. If MAP_ANONYMOUS is set, the
+    mapping is filled with zeros and fd and offset are ignored. Otherwise, size bytes of fd are
+    mapped, starting at offset, which must be a multiple of the page size. Only some resources,
+    such as display:, can be mapped.
+
+    With MAP_SHARED, writes go directly to the resource. With MAP_PRIVATE, the mapping is a copy
+    of the resource taken when sys_mmap is called.
+
+    Mappings are copied on write by sys_clone without CLONE_VM, except for shared file mappings,
+    which refer to the same memory. All mappings are removed by sys_execve and sys_exit.
+
+RETURN VALUE
+    On success, Ok(addr) is returned, where addr is the address of the mapping. On error,
+    Err(err) is returned where err is one of the following errors
+
+ERRORS
+    EACCES
+        MAP_SHARED and PROT_WRITE were requested, but fd is not open for writing
+
+    EBADF
+        fd is not a valid file descriptor
+
+    EINVAL
+        size is 0, prot does not contain PROT_READ, flags is not valid, or offset is not aligned
+
+    ENOMEM
+        There is no room in the mmap region, or memory could not be allocated
+
+    EPERM
+        fd cannot be mapped
+
+    ESRCH
+        Currently not running in a process context (rare, would only happen during kernel init)
+<!-- @MANEND --> */
+pub fn do_sys_mmap(size: usize, prot: usize, flags: usize, fd: usize, offset: usize) -> Result<usize> {
+    let size = (size + 4095) / 4096 * 4096;
+    if size == 0 || prot & PROT_READ != PROT_READ
+       || (flags & MAP_SHARED == MAP_SHARED) == (flags & MAP_PRIVATE == MAP_PRIVATE) {
+        return Err(Error::new(EINVAL));
+    }
+
+    let writeable = prot & PROT_WRITE == PROT_WRITE;
 
     let contexts = ::env().contexts.lock();
-    if let Ok(current) = contexts.current() {
-        ret = unsafe { (*current.heap.get()).next_mem() };
-
-        // TODO: Make this smarter, currently it attempt to resize the entire data segment
-        if let Some(mut mem) = unsafe { (*current.heap.get()).memory.last_mut() } {
-            if mem.writeable && mem.allocated {
-                if addr >= mem.virtual_address {
-                    unsafe { mem.unmap() };
-
-                    let size = addr - mem.virtual_address;
-                    let physical_address = unsafe { memory::realloc_aligned(mem.physical_address, size, 4096) };
-                    if physical_address > 0 {
-                        mem.physical_address = physical_address;
-                        mem.virtual_size = size;
-                        ret = mem.virtual_address + mem.virtual_size;
-                    } else {
-                        debugln!("BRK: Realloc failed {:X}, {}\n", mem.virtual_address, size);
-                    }
-
-                    unsafe { mem.map() };
-                }
-            } else {
-                debugln!("{}: {}", current.pid, current.name);
-                debugln!("BRK: End segment not writeable or allocated");
+    let current = try!(contexts.current());
+
+    let (physical_address, allocated) = if flags & MAP_ANONYMOUS == MAP_ANONYMOUS {
+        let physical_address = unsafe { memory::alloc_aligned(size, 4096) };
+        if physical_address == 0 {
+            return Err(Error::new(ENOMEM));
+        }
+        unsafe { ::memset(physical_address as *mut u8, 0, size) };
+
+        (physical_address, true)
+    } else {
+        if offset % 4096 != 0 {
+            return Err(Error::new(EINVAL));
+        }
+
+        if flags & MAP_SHARED == MAP_SHARED {
+            if writeable && try!(current.get_file_flags(fd)) & (O_WRONLY | O_RDWR) == 0 {
+                return Err(Error::new(EACCES));
             }
-        } else if addr >= ret {
-            let size = addr - ret;
+
+            let resource = try!(current.get_file_mut(fd));
+            (try!(resource.mmap(offset, size, writeable)), false)
+        } else {
+            let resource = try!(current.get_file_mut(fd));
+            let source = try!(resource.mmap(offset, size, false));
+
             let physical_address = unsafe { memory::alloc_aligned(size, 4096) };
-            if physical_address > 0 {
-                let mut mem = ContextMemory {
-                    physical_address: physical_address,
-                    virtual_address: ret,
-                    virtual_size: size,
-                    writeable: true,
-                    allocated: true
-                };
-                ret = mem.virtual_address + mem.virtual_size;
+            if physical_address == 0 {
+                return Err(Error::new(ENOMEM));
+            }
+            unsafe { ::memcpy(physical_address as *mut u8, source as *const u8, size) };
+
+            (physical_address, true)
+        }
+    };
+
+    let mmap = unsafe { &mut *current.mmap.get() };
+    let virtual_address = mmap.find_free(size);
 
-                unsafe {
-                    mem.map();
-                    (*current.heap.get()).memory.push(mem);
+    let mut mem = ContextMemory {
+        physical_address: physical_address,
+        virtual_address: virtual_address.unwrap_or(0),
+        virtual_size: size,
+        writeable: writeable,
+        allocated: allocated,
+        cow: None,
+    };
+
+    // Dropping the entry frees the allocation, if there is one
+    if virtual_address.is_none() {
+        return Err(Error::new(ENOMEM));
+    }
+
+    let addr = mem.virtual_address;
+    unsafe { mem.map() };
+    mmap.memory.push(mem);
+
+    Ok(addr)
+}
+
+/** <!-- @MANSTART{sys_munmap} -->
+NAME
+    sys_munmap - remove mappings created by sys_mmap
+
+SYNOPSIS
+    sys_munmap(addr: usize, size: usize) -> Result<usize>;
+
+DESCRIPTION
+    sys_munmap removes all mappings between addr and addr + size, rounded up to the page size.
+    Mappings can only be removed as a whole, so the range must not cover only part of one.
+
+RETURN VALUE
+    On success, Ok(0) is returned. On error, Err(err) is returned where err is one of the
+    following errors
+
+ERRORS
+    EINVAL
+        addr is not aligned, or the range covers only part of a mapping
+
+    ESRCH
+        Currently not running in a process context (rare, would only happen during kernel init)
+<!-- @MANEND --> */
+pub fn do_sys_munmap(addr: usize, size: usize) -> Result<usize> {
+    if addr % 4096 != 0 {
+        return Err(Error::new(EINVAL));
+    }
+    let end = addr + (size + 4095) / 4096 * 4096;
+
+    let contexts = ::env().contexts.lock();
+    let current = try!(contexts.current());
+    let mmap = unsafe { &mut *current.mmap.get() };
+
+    if mmap.memory.iter().any(|mem| overlaps(mem, addr, end) && ! inside(mem, addr, end)) {
+        return Err(Error::new(EINVAL));
+    }
+
+    for mem in mmap.memory.iter_mut() {
+        if inside(mem, addr, end) {
+            unsafe { mem.unmap() };
+            mem.virtual_size = 0;
+        }
+    }
+    unsafe { mmap.clean_mem() };
+
+    Ok(0)
+}
+
+/** <!-- @MANSTART{sys_mprotect} -->
+NAME
+    sys_mprotect - change the protection of memory
+
+SYNOPSIS
+    sys_mprotect(addr: usize, size: usize, prot: usize) -> Result<usize>;
+
+DESCRIPTION
+    sys_mprotect changes the protection of the memory between addr and addr + size, rounded up
+    to the page size, to prot. The range may cover the program image, the heap, and mappings
+    created by sys_mmap, but only as whole segments. Memory reserved by sys_brk or for the
+    program image, which is allocated when it is first used, is also a segment.
+
+    prot must contain PROT_READ, and PROT_WRITE makes the memory writeable.
+
+RETURN VALUE
+    On success, Ok(0) is returned. On error, Err(err) is returned where err is one of the
+    following errors
+
+ERRORS
+    EACCES
+        PROT_WRITE was requested for a shared mapping
+
+    EINVAL
+        addr is not aligned, prot does not contain PROT_READ, or the range covers only part of a
+        segment
+
+    ESRCH
+        Currently not running in a process context (rare, would only happen during kernel init)
+<!-- @MANEND --> */
+pub fn do_sys_mprotect(addr: usize, size: usize, prot: usize) -> Result<usize> {
+    if addr % 4096 != 0 || prot & PROT_READ != PROT_READ {
+        return Err(Error::new(EINVAL));
+    }
+    let end = addr + (size + 4095) / 4096 * 4096;
+    let writeable = prot & PROT_WRITE == PROT_WRITE;
+
+    let contexts = ::env().contexts.lock();
+    let current = try!(contexts.current());
+    let mut zones: [&mut ContextZone; 3] = unsafe {
+        [&mut *current.image.get(), &mut *current.heap.get(), &mut *current.mmap.get()]
+    };
+
+    for zone in zones.iter() {
+        for mem in zone.memory.iter() {
+            if overlaps(mem, addr, end) {
+                if ! inside(mem, addr, end) {
+                    return Err(Error::new(EINVAL));
+                }
+                if writeable && ! mem.allocated && mem.cow.is_none() {
+                    return Err(Error::new(EACCES));
                 }
-            } else {
-                debugln!("BRK: Alloc failed {}\n", size);
             }
         }
-    } else {
-        debugln!("BRK: Context not found");
+
+        for reserved in zone.reserved.iter() {
+            if reserved.virtual_address < end && addr < reserved.end()
+               && (reserved.virtual_address < addr || reserved.end() > end) {
+                return Err(Error::new(EINVAL));
+            }
+        }
+    }
+
+    for zone in zones.iter_mut() {
+        for mem in zone.memory.iter_mut() {
+            if inside(mem, addr, end) && mem.writeable != writeable {
+                unsafe { mem.unmap() };
+                mem.writeable = writeable;
+                unsafe { mem.map() };
+            }
+        }
+
+        // Pages that are allocated later get the new protection too
+        for reserved in zone.reserved.iter_mut() {
+            if addr <= reserved.virtual_address && reserved.end() <= end {
+                reserved.writeable = writeable;
+            }
+        }
     }
 
-    Ok(ret)
+    Ok(0)
+}
+
+/// Check if any page of `mem` is between `start` and `end`
+fn overlaps(mem: &ContextMemory, start: usize, end: usize) -> bool {
+    let mem_end = mem.virtual_address + (mem.virtual_size + 4095) / 4096 * 4096;
+    mem.virtual_size > 0 && mem.virtual_address < end && start < mem_end
+}
+
+/// Check if every page of `mem` is between `start` and `end`
+fn inside(mem: &ContextMemory, start: usize, end: usize) -> bool {
+    let mem_end = mem.virtual_address + (mem.virtual_size + 4095) / 4096 * 4096;
+    mem.virtual_size > 0 && start <= mem.virtual_address && mem_end <= end
 }
@@ -5,6 +5,7 @@ pub use self::debug::*;
 pub use self::file::*;
 pub use self::memory::*;
 pub use self::process::*;
+pub use self::signal::*;
 pub use self::time::*;
 
 use arch::regs::Regs;
@@ -15,6 +16,7 @@ pub mod execute;
 pub mod file;
 pub mod memory;
 pub mod process;
+pub mod signal;
 pub mod time;
 
 pub fn syscall_handle(regs: &mut Regs) {
@@ -43,6 +45,7 @@ pub fn syscall_handle(regs: &mut Regs) {
     regs.ax = Error::mux(match regs.ax {
         // Redox
         SYS_DEBUG => do_sys_debug(regs.bx as *const u8, regs.cx),
+        SYS_SETNS => do_sys_setns(regs.bx as *const *const u8),
         SYS_SUPERVISE => do_sys_supervise(regs.bx),
 
         // Unix
@@ -54,20 +57,39 @@ pub fn syscall_handle(regs: &mut Regs) {
         SYS_DUP => do_sys_dup(regs.bx),
         SYS_EXECVE => do_sys_execve(regs.bx as *const u8, regs.cx as *const *const u8),
         SYS_EXIT => do_sys_exit(regs.bx),
+        SYS_FCNTL => do_sys_fcntl(regs.bx, regs.cx, regs.dx),
         SYS_FPATH => do_sys_fpath(regs.bx, regs.cx as *mut u8, regs.dx),
         SYS_FSTAT => do_sys_fstat(regs.bx, regs.cx as *mut Stat),
         SYS_FSYNC => do_sys_fsync(regs.bx),
         SYS_FTRUNCATE => do_sys_ftruncate(regs.bx, regs.cx),
+        SYS_GETDENTS => do_sys_getdents(regs.bx, regs.cx as *mut Dirent, regs.dx),
+        SYS_GETEGID => do_sys_getegid(),
+        SYS_GETEUID => do_sys_geteuid(),
+        SYS_GETGID => do_sys_getgid(),
         SYS_GETPID => do_sys_getpid(),
+        SYS_GETPRIORITY => do_sys_getpriority(regs.bx),
+        SYS_GETUID => do_sys_getuid(),
         SYS_IOPL => do_sys_iopl(regs),
-        // TODO: link
+        SYS_KILL => do_sys_kill(regs.bx, regs.cx),
+        SYS_LINK => do_sys_link(regs.bx as *const u8, regs.cx as *const u8),
         SYS_LSEEK => do_sys_lseek(regs.bx, regs.cx as isize, regs.dx),
         SYS_MKDIR => do_sys_mkdir(regs.bx as *const u8, regs.cx),
+        SYS_MMAP => do_sys_mmap(regs.bx, regs.cx, regs.dx, regs.si, regs.di),
+        SYS_MPROTECT => do_sys_mprotect(regs.bx, regs.cx, regs.dx),
+        SYS_MUNMAP => do_sys_munmap(regs.bx, regs.cx),
         SYS_NANOSLEEP => do_sys_nanosleep(regs.bx as *const TimeSpec, regs.cx as *mut TimeSpec),
         SYS_OPEN => do_sys_open(regs.bx as *const u8, regs.cx),
         SYS_PIPE2 => do_sys_pipe2(regs.bx as *mut usize, regs.cx),
+        SYS_POLL => do_sys_poll(regs.bx as *mut PollFd, regs.cx, regs.dx as isize),
         SYS_READ => do_sys_read(regs.bx, regs.cx as *mut u8, regs.dx),
+        SYS_RENAME => do_sys_rename(regs.bx as *const u8, regs.cx as *const u8),
         SYS_RMDIR => do_sys_rmdir(regs.bx as *const u8),
+        SYS_SETGID => do_sys_setgid(regs.bx),
+        SYS_SETPRIORITY => do_sys_setpriority(regs.bx, regs.cx as isize),
+        SYS_SETUID => do_sys_setuid(regs.bx),
+        SYS_SIGACTION => do_sys_sigaction(regs.bx, regs.cx, regs.dx),
+        SYS_SIGPROCMASK => do_sys_sigprocmask(regs.bx, regs.cx),
+        SYS_SIGRETURN => do_sys_sigreturn(regs),
         SYS_STAT => do_sys_stat(regs.bx as *const u8, regs.cx as *mut Stat),
         SYS_UNLINK => do_sys_unlink(regs.bx as *const u8),
         SYS_WAITPID => do_sys_waitpid(regs.bx as isize, regs.cx as *mut usize, regs.dx),
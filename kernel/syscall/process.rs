@@ -1,15 +1,16 @@
-use arch::context::{context_clone, context_switch, ContextFile};
+use arch::context::{context_clone, context_switch, Context, ContextFile};
 use arch::regs::Regs;
 
 use collections::{BTreeMap, Vec};
-use collections::string::ToString;
+use collections::string::{String, ToString};
 
-use core::{mem, ptr};
-use core::ops::DerefMut;
+use core::{cmp, mem, ptr, u32};
+use core::ops::{Deref, DerefMut};
 
 use system::{c_array_to_slice, c_string_to_str};
 
-use system::error::{Error, Result, ECHILD, EINVAL, EACCES};
+use system::error::{Error, Result, ECHILD, EINVAL, EACCES, EPERM};
+use system::syscall::{PRIO_MAX, PRIO_MIN, SIGCHLD};
 
 use super::execute::execute;
 
@@ -31,6 +32,11 @@ pub fn do_sys_execve(path: *const u8, args: *const *const u8) -> Result<usize> {
 
 /// Exit context
 pub fn do_sys_exit(status: usize) -> ! {
+    context_exit((status & 0xFF) << 8)
+}
+
+/// Exit the current context, reporting `status` to the parent as it is returned by waitpid
+pub fn context_exit(status: usize) -> ! {
     {
         let mut contexts = ::env().contexts.lock();
 
@@ -48,6 +54,7 @@ pub fn do_sys_exit(status: usize) -> ! {
         for mut context in contexts.iter_mut() {
             // Add exit status to parent
             if context.pid == ppid {
+                context.sig_pending |= 1 << SIGCHLD;
                 context.statuses.send(pid, status);
                 for (pid, status) in statuses.iter() {
                     context.statuses.send(*pid, *status);
@@ -68,18 +75,270 @@ pub fn do_sys_exit(status: usize) -> ! {
     }
 }
 
+pub fn do_sys_getegid() -> Result<usize> {
+    let contexts = ::env().contexts.lock();
+    let current = try!(contexts.current());
+    Ok(current.egid as usize)
+}
+
+pub fn do_sys_geteuid() -> Result<usize> {
+    let contexts = ::env().contexts.lock();
+    let current = try!(contexts.current());
+    Ok(current.euid as usize)
+}
+
+pub fn do_sys_getgid() -> Result<usize> {
+    let contexts = ::env().contexts.lock();
+    let current = try!(contexts.current());
+    Ok(current.gid as usize)
+}
+
 pub fn do_sys_getpid() -> Result<usize> {
     let contexts = ::env().contexts.lock();
     let current = try!(contexts.current());
     Ok(current.pid)
 }
 
+pub fn do_sys_getuid() -> Result<usize> {
+    let contexts = ::env().contexts.lock();
+    let current = try!(contexts.current());
+    Ok(current.uid as usize)
+}
+
+/** <!-- @MANSTART{sys_getpriority} -->
+NAME
+    sys_getpriority - get the scheduling priority of a process
+
+SYNOPSIS
+    sys_getpriority(pid: usize) -> Result<usize>;
+
+DESCRIPTION
+    sys_getpriority gets the scheduling priority of the process pid, or of the calling process if
+    pid is 0. See sys_setpriority for the meaning of the priority.
+
+RETURN VALUE
+    On success, Ok(20 - priority) is returned, which is from 1 to 40, as a priority can be
+    negative. The system crate converts this back to the priority. On error, Err(err) is returned
+    where err is one of the following errors
+
+ERRORS
+    ESRCH
+        pid does not exist
+<!-- @MANEND --> */
+pub fn do_sys_getpriority(pid: usize) -> Result<usize> {
+    let contexts = ::env().contexts.lock();
+    let context = if pid == 0 {
+        try!(contexts.current())
+    } else {
+        try!(contexts.find(pid))
+    };
+
+    Ok((20 - context.priority) as usize)
+}
+
+/** <!-- @MANSTART{sys_setgid} -->
+NAME
+    sys_setgid - set the group of the calling process
+
+SYNOPSIS
+    sys_setgid(gid: usize) -> Result<usize>;
+
+DESCRIPTION
+    sys_setgid sets the real and effective group ids of the calling process to gid. Only a process
+    with an effective user id of 0, root, can change its group. Other processes can only set the
+    group they already have. The ids are inherited by children.
+
+RETURN VALUE
+    On success, Ok(0) is returned. On error, Err(err) is returned where err is one of the
+    following errors
+
+ERRORS
+    EINVAL
+        gid is not a valid id
+    EPERM
+        The calling process is not root, and gid is not its real group id
+<!-- @MANEND --> */
+pub fn do_sys_setgid(gid: usize) -> Result<usize> {
+    if gid > u32::MAX as usize {
+        return Err(Error::new(EINVAL));
+    }
+
+    let mut contexts = ::env().contexts.lock();
+    let mut current = try!(contexts.current_mut());
+    if current.euid != 0 && current.gid != gid as u32 {
+        return Err(Error::new(EPERM));
+    }
+
+    current.gid = gid as u32;
+    current.egid = gid as u32;
+
+    Ok(0)
+}
+
+/** <!-- @MANSTART{sys_setns} -->
+NAME
+    sys_setns - narrow the namespace of the calling process
+
+SYNOPSIS
+    sys_setns(names: *const *const u8) -> Result<usize>;
+
+DESCRIPTION
+    sys_setns sets the namespace of the calling process, the schemes it can use, to names, a null
+    terminated array of scheme names. Paths in other schemes are not found, and the list of
+    schemes, ":", cannot be opened, so schemes can neither be listed nor registered.
+
+    A namespace can only be narrowed. Every name must already be in the namespace of the process,
+    processes start with every scheme. The namespace is inherited by children, so a process can
+    sandbox a child by calling sys_setns between sys_clone and sys_execve.
+
+    Resources that are already open are not affected. A process with a namespace cannot change
+    its I/O privilege level with sys_iopl, even as root.
+
+RETURN VALUE
+    On success, Ok(0) is returned. On error, Err(err) is returned where err is one of the
+    following errors
+
+ERRORS
+    EINVAL
+        One of the names is empty, which would keep the list of schemes usable
+    EPERM
+        One of the names is not in the namespace of the calling process
+<!-- @MANEND --> */
+pub fn do_sys_setns(names: *const *const u8) -> Result<usize> {
+    let mut namespace = Vec::new();
+    for name in c_array_to_slice(names) {
+        let name = c_string_to_str(*name);
+        if name.is_empty() {
+            return Err(Error::new(EINVAL));
+        }
+        namespace.push(name.to_string());
+    }
+
+    let mut contexts = ::env().contexts.lock();
+    let mut current = try!(contexts.current_mut());
+    for name in namespace.iter() {
+        if !current.can_use(name) {
+            return Err(Error::new(EPERM));
+        }
+    }
+
+    current.namespace = Some(namespace);
+
+    Ok(0)
+}
+
+/** <!-- @MANSTART{sys_setpriority} -->
+NAME
+    sys_setpriority - set the scheduling priority of a process
+
+SYNOPSIS
+    sys_setpriority(pid: usize, prio: isize) -> Result<usize>;
+
+DESCRIPTION
+    sys_setpriority sets the scheduling priority of the process pid, or of the calling process if
+    pid is 0, to prio. Priorities go from PRIO_MIN (-20), the highest, to PRIO_MAX (19), the
+    lowest, and prio is clamped to this range. Processes start with the priority of their parent.
+
+    Processes that can run share the processor in proportion to a weight given by their priority,
+    with each priority getting about 1.25 times the time of the next. A process that sleeps is not
+    owed the time it did not use, so it cannot starve the others when it wakes.
+
+    The time used by each process is shown in context:.
+
+    Only root can raise a priority. Other processes can lower the priority of processes whose
+    real or effective user id matches their own.
+
+RETURN VALUE
+    On success, Ok(0) is returned. On error, Err(err) is returned where err is one of the
+    following errors
+
+ERRORS
+    EPERM
+        The calling process is not root, and prio is higher than the current priority of pid,
+        or pid belongs to another user
+    ESRCH
+        pid does not exist
+<!-- @MANEND --> */
+pub fn do_sys_setpriority(pid: usize, prio: isize) -> Result<usize> {
+    let mut contexts = ::env().contexts.lock();
+
+    let current_ptr: *const Context = {
+        let current = try!(contexts.current());
+        current.deref()
+    };
+
+    let mut context = if pid == 0 {
+        try!(contexts.current_mut())
+    } else {
+        try!(contexts.find_mut(pid))
+    };
+
+    let prio = cmp::max(PRIO_MIN, cmp::min(PRIO_MAX, prio));
+
+    let current = unsafe { &*current_ptr };
+    if current.euid != 0 && (! current.owns(context) || prio < context.priority) {
+        return Err(Error::new(EPERM));
+    }
+
+    context.priority = prio;
+
+    Ok(0)
+}
+
+/** <!-- @MANSTART{sys_setuid} -->
+NAME
+    sys_setuid - set the user of the calling process
+
+SYNOPSIS
+    sys_setuid(uid: usize) -> Result<usize>;
+
+DESCRIPTION
+    sys_setuid sets the real and effective user ids of the calling process to uid. Only a process
+    with an effective user id of 0, root, can change its user, and it cannot regain root once it
+    has changed it. Other processes can only set the user they already have. The ids are inherited
+    by children, see sys_clone.
+
+    Schemes are given the effective ids of the caller when a resource is opened, a directory is
+    made or a resource is unlinked, so they can check its permissions.
+
+RETURN VALUE
+    On success, Ok(0) is returned. On error, Err(err) is returned where err is one of the
+    following errors
+
+ERRORS
+    EINVAL
+        uid is not a valid id
+    EPERM
+        The calling process is not root, and uid is not its real user id
+<!-- @MANEND --> */
+pub fn do_sys_setuid(uid: usize) -> Result<usize> {
+    if uid > u32::MAX as usize {
+        return Err(Error::new(EINVAL));
+    }
+
+    let mut contexts = ::env().contexts.lock();
+    let mut current = try!(contexts.current_mut());
+    if current.euid != 0 && current.uid != uid as u32 {
+        return Err(Error::new(EPERM));
+    }
+
+    current.uid = uid as u32;
+    current.euid = uid as u32;
+
+    Ok(0)
+}
+
+/// Change the I/O privilege level. Only root can raise it, and it cannot be changed in a
+/// namespace, where ports would bypass the schemes it hides
 #[cfg(target_arch = "x86")]
 pub fn do_sys_iopl(regs: &mut Regs) -> Result<usize> {
     let level = regs.bx;
     if level <= 3 {
         let mut contexts = ::env().contexts.lock();
         let mut current = try!(contexts.current_mut());
+        if current.namespace.is_some() || (level > current.iopl && current.euid != 0) {
+            return Err(Error::new(EPERM));
+        }
         current.iopl = level;
 
         regs.flags &= 0xFFFFFFFF - 0x3000;
@@ -91,12 +350,17 @@ pub fn do_sys_iopl(regs: &mut Regs) -> Result<usize> {
     }
 }
 
+/// Change the I/O privilege level. Only root can raise it, and it cannot be changed in a
+/// namespace, where ports would bypass the schemes it hides
 #[cfg(target_arch = "x86_64")]
 pub fn do_sys_iopl(regs: &mut Regs) -> Result<usize> {
     let level = regs.bx;
     if level <= 3 {
         let mut contexts = ::env().contexts.lock();
         let mut current = try!(contexts.current_mut());
+        if current.namespace.is_some() || (level > current.iopl && current.euid != 0) {
+            return Err(Error::new(EPERM));
+        }
         current.iopl = level;
 
         regs.flags &= 0xFFFFFFFFFFFFFFFF - 0x3000;
@@ -171,6 +435,8 @@ pub fn do_sys_supervise(pid: usize) -> Result<usize> {
     unsafe {
         (*current.files.get()).push(ContextFile {
             fd: fd,
+            flags: 0,
+            path: String::new(),
             resource: box try!(SupervisorResource::new(procc)),
         });
     }
@@ -0,0 +1,296 @@
+use arch::context::{context_switch, Context, SignalAction, SignalFrame};
+use arch::memory;
+use arch::regs::Regs;
+
+use core::{mem, ptr};
+use core::ops::DerefMut;
+
+use system::error::{Error, Result, EINVAL, ENOMEM, EPERM};
+use system::syscall::{NSIG, SIGCONT, SIGKILL, SIGSEGV, SIGSTOP, SIGTSTP, SIGTTIN, SIGTTOU,
+                      SIG_BLOCK, SIG_DFL, SIG_IGN, SIG_SETMASK, SIG_UNBLOCK};
+
+use super::process::context_exit;
+
+/// Signals which stop a context by default
+const STOP_MASK: usize = 1 << SIGSTOP | 1 << SIGTSTP | 1 << SIGTTIN | 1 << SIGTTOU;
+
+/// Signals which cannot be caught, ignored, or blocked
+const UNCATCHABLE_MASK: usize = 1 << SIGKILL | 1 << SIGSTOP;
+
+/** <!-- @MANSTART{sys_kill} -->
+NAME
+    sys_kill - send a signal to a process
+
+SYNOPSIS
+    sys_kill(pid: usize, sig: usize) -> Result<usize>;
+
+DESCRIPTION
+    sys_kill sends the signal sig to the process pid. If sig is 0, no signal is sent, but pid is
+    still checked.
+
+    SIGKILL and SIGSTOP cannot be caught, ignored or blocked. SIGSTOP stops the process
+    immediately, and SIGCONT continues it, even if SIGCONT is blocked or ignored.
+
+    Other signals are left pending, and are delivered when the process returns to userspace. A
+    process blocked in a syscall is woken if the signal would terminate it, or if it has a handler
+    for the signal, in which case sys_poll and sys_nanosleep return EINTR.
+
+RETURN VALUE
+    On success, Ok(0) is returned. On error, Err(err) is returned where err is one of the
+    following errors
+
+ERRORS
+    EINVAL
+        sig is not a valid signal number
+
+    EPERM
+        pid is a kernel context or init, which cannot be signalled, or the caller is not root and
+        its real or effective user id does not match the real or effective user id of pid
+
+    ESRCH
+        pid does not exist
+<!-- @MANEND --> */
+pub fn do_sys_kill(pid: usize, sig: usize) -> Result<usize> {
+    if sig >= NSIG {
+        return Err(Error::new(EINVAL));
+    }
+
+    {
+        let contexts = ::env().contexts.lock();
+        let current = try!(contexts.current());
+        let context = try!(contexts.find(pid));
+        if context.ppid == 0 || ! current.owns(context) {
+            return Err(Error::new(EPERM));
+        }
+    }
+
+    if sig == 0 {
+        return Ok(0);
+    }
+
+    try!(context_signal(pid, sig));
+    Ok(0)
+}
+
+/// Send `sig` to the context `pid`, without checking that the sender may signal it. The kernel
+/// uses this directly where the current context is not the sender, such as for ^C in an IRQ
+pub fn context_signal(pid: usize, sig: usize) -> Result<()> {
+    if sig == 0 || sig >= NSIG {
+        return Err(Error::new(EINVAL));
+    }
+
+    let mut contexts = ::env().contexts.lock();
+
+    let context_ptr: *mut Context = {
+        let mut context = try!(contexts.find_mut(pid));
+        context.deref_mut()
+    };
+
+    // A parent waiting for its vfork child must stay blocked until the child execs or exits
+    let vfork_parent = contexts.iter().any(|other| other.vfork == Some(context_ptr));
+
+    let context = unsafe { &mut *context_ptr };
+
+    if sig == SIGSTOP {
+        context.stopped = true;
+        context.sig_pending &= !(1 << SIGCONT);
+        return Ok(());
+    } else if sig == SIGCONT {
+        context.stopped = false;
+        context.sig_pending &= !STOP_MASK;
+    } else if sig == SIGKILL {
+        context.stopped = false;
+    } else if STOP_MASK & (1 << sig) > 0 {
+        context.sig_pending &= !(1 << SIGCONT);
+    }
+
+    context.sig_pending |= 1 << sig;
+
+    if context.blocked && ! context.blocked_syscall && ! vfork_parent && context.signal_interrupt() {
+        context.blocked = false;
+    }
+
+    // Continued or woken contexts have to be queued to run again
+    contexts.woken.push(context.pid);
+
+    Ok(())
+}
+
+/** <!-- @MANSTART{sys_sigaction} -->
+NAME
+    sys_sigaction - set the action for a signal
+
+SYNOPSIS
+    sys_sigaction(sig: usize, handler: usize, restorer: usize) -> Result<usize>;
+
+DESCRIPTION
+    sys_sigaction sets the action taken when sig is delivered. handler is either SIG_DFL for the
+    default action, SIG_IGN to discard the signal, or the address of a function taking the signal
+    number.
+
+    The handler is run with sig blocked, and returns to restorer, which must call
+    sys_sigreturn. The system crate passes its own restorer, so sys_sigaction in userspace only
+    takes sig and handler.
+
+    Handlers are reset to SIG_DFL by sys_execve, while ignored signals stay ignored.
+
+RETURN VALUE
+    On success, Ok(handler) is returned, where handler is the previous handler. On error,
+    Err(err) is returned where err is one of the following errors
+
+ERRORS
+    EINVAL
+        sig is not a valid signal number, or is SIGKILL or SIGSTOP
+
+    ESRCH
+        Currently not running in a process context (rare, would only happen during kernel init)
+<!-- @MANEND --> */
+pub fn do_sys_sigaction(sig: usize, handler: usize, restorer: usize) -> Result<usize> {
+    if sig == 0 || sig >= NSIG || UNCATCHABLE_MASK & (1 << sig) > 0 {
+        return Err(Error::new(EINVAL));
+    }
+
+    let mut contexts = ::env().contexts.lock();
+    let mut current = try!(contexts.current_mut());
+
+    let old = current.sig_handlers[sig];
+    current.sig_handlers[sig] = handler;
+    if handler == SIG_IGN {
+        current.sig_pending &= !(1 << sig);
+    } else if handler != SIG_DFL {
+        current.sig_restorer = restorer;
+    }
+
+    Ok(old)
+}
+
+/** <!-- @MANSTART{sys_sigprocmask} -->
+NAME
+    sys_sigprocmask - change the blocked signals
+
+SYNOPSIS
+    sys_sigprocmask(how: usize, mask: usize) -> Result<usize>;
+
+DESCRIPTION
+    sys_sigprocmask changes the set of blocked signals, where bit n of mask stands for signal n.
+    If how is SIG_BLOCK, the signals in mask are added to the set. If how is SIG_UNBLOCK, they are
+    removed from it, and if how is SIG_SETMASK, the set is replaced by mask.
+
+    Blocked signals stay pending until they are unblocked. SIGKILL and SIGSTOP cannot be blocked.
+
+RETURN VALUE
+    On success, Ok(mask) is returned, where mask is the previous set. On error, Err(err) is
+    returned where err is one of the following errors
+
+ERRORS
+    EINVAL
+        how is not valid
+
+    ESRCH
+        Currently not running in a process context (rare, would only happen during kernel init)
+<!-- @MANEND --> */
+pub fn do_sys_sigprocmask(how: usize, mask: usize) -> Result<usize> {
+    let mut contexts = ::env().contexts.lock();
+    let mut current = try!(contexts.current_mut());
+
+    let old = current.sig_mask;
+    current.sig_mask = match how {
+        SIG_BLOCK => old | mask,
+        SIG_UNBLOCK => old & !mask,
+        SIG_SETMASK => mask,
+        _ => return Err(Error::new(EINVAL)),
+    } & !UNCATCHABLE_MASK;
+
+    Ok(old)
+}
+
+/// Return from a signal handler, restoring the state saved when it was entered
+pub fn do_sys_sigreturn(regs: &mut Regs) -> Result<usize> {
+    let mut contexts = ::env().contexts.lock();
+    let mut current = try!(contexts.current_mut());
+
+    if let Some(frame) = current.sig_frames.pop() {
+        *regs = frame.regs;
+        current.sig_mask = frame.mask;
+        unsafe { asm!("fxrstor [$0]" : : "r"(frame.fx) : "memory" : "intel", "volatile"); }
+
+        // The syscall result is written to ax, so return the value it had
+        Ok(regs.ax)
+    } else {
+        Err(Error::new(EINVAL))
+    }
+}
+
+/// Enter the handler for `sig`, saving the current state on the context
+fn signal_enter(regs: &mut Regs, sig: usize, handler: usize) -> Result<()> {
+    let mut contexts = ::env().contexts.lock();
+    let mut current = try!(contexts.current_mut());
+
+    // Skip the red zone, and align the argument as it would be for a call
+    let arg_addr = ((regs.sp - 128) & !15) - 16;
+    let ret_addr = arg_addr - mem::size_of::<usize>();
+    // The two words may be on different pages, so translate them separately
+    let ret_frame = try!(current.translate_mut(ret_addr, mem::size_of::<usize>()));
+    let arg_frame = try!(current.translate_mut(arg_addr, mem::size_of::<usize>()));
+
+    let fx = unsafe { memory::alloc(512) };
+    if fx == 0 {
+        return Err(Error::new(ENOMEM));
+    }
+
+    unsafe {
+        asm!("fxsave [$0]" : : "r"(fx) : "memory" : "intel", "volatile");
+
+        ptr::write(ret_frame as *mut usize, current.sig_restorer);
+        ptr::write(arg_frame as *mut usize, sig);
+    }
+
+    current.sig_frames.push(SignalFrame {
+        regs: *regs,
+        mask: current.sig_mask,
+        fx: fx,
+    });
+    current.sig_mask |= 1 << sig;
+
+    regs.ip = handler;
+    regs.sp = ret_addr;
+    regs.di = sig;
+
+    Ok(())
+}
+
+/// Act on the pending signals of the current context before it returns to userspace
+///
+/// Stopped contexts wait here until they are continued
+pub fn signal_handle(regs: &mut Regs) {
+    loop {
+        let next = {
+            let mut contexts = ::env().contexts.lock();
+            let mut current = match contexts.current_mut() {
+                Ok(current) => current,
+                Err(_) => return,
+            };
+
+            if current.stopped {
+                None
+            } else if let Some(sig) = current.signal_next() {
+                let action = current.signal_action(sig);
+                if action == SignalAction::Stop {
+                    current.stopped = true;
+                }
+                Some((sig, action))
+            } else {
+                return;
+            }
+        };
+
+        match next {
+            Some((sig, SignalAction::Terminate)) => context_exit(sig),
+            Some((sig, SignalAction::Handle(handler))) => if signal_enter(regs, sig, handler).is_err() {
+                context_exit(SIGSEGV);
+            },
+            Some(_) => (),
+            None => unsafe { context_switch(); },
+        }
+    }
+}
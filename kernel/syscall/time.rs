@@ -4,7 +4,7 @@ use common::time::Duration;
 
 use syscall::{CLOCK_MONOTONIC, CLOCK_REALTIME, TimeSpec};
 
-use system::error::{Error, Result, EFAULT, EINVAL};
+use system::error::{Error, Result, EFAULT, EINTR, EINVAL};
 
 pub fn do_sys_clock_gettime(clock: usize, tp: *mut TimeSpec) -> Result<usize> {
     if tp as usize > 0 {
@@ -34,26 +34,44 @@ pub fn do_sys_clock_gettime(clock: usize, tp: *mut TimeSpec) -> Result<usize> {
 
 pub fn do_sys_nanosleep(req: *const TimeSpec, rem: *mut TimeSpec) -> Result<usize> {
     if req as usize > 0 {
-        let mut contexts = ::env().contexts.lock();
-        let mut context = try!(contexts.current_mut());
+        let end = Duration::monotonic() + Duration::new(unsafe { (*req).tv_sec }, unsafe { (*req).tv_nsec });
 
-        context.blocked = true;
-        context.wake = Some(
-            Duration::monotonic() + Duration::new(unsafe { (*req).tv_sec }, unsafe { (*req).tv_nsec })
-        );
+        {
+            let mut contexts = ::env().contexts.lock();
+            let mut context = try!(contexts.current_mut());
+
+            context.blocked = true;
+            context.wake = Some(end);
+        }
 
         unsafe { context_switch(); }
 
+        // The wake time is only left set if a signal woke the context early
+        let mut contexts = ::env().contexts.lock();
+        let mut context = try!(contexts.current_mut());
+        let interrupted = context.wake.take().is_some();
+
+        let now = Duration::monotonic();
+        let left = if interrupted && end > now {
+            end - now
+        } else {
+            Duration::new(0, 0)
+        };
+
         if rem as usize > 0 {
             unsafe {
-                (*rem).tv_sec = 0;
+                (*rem).tv_sec = left.secs;
             }
             unsafe {
-                (*rem).tv_nsec = 0;
+                (*rem).tv_nsec = left.nanos;
             }
         }
 
-        Ok(0)
+        if interrupted {
+            Err(Error::new(EINTR))
+        } else {
+            Ok(0)
+        }
     } else {
         Err(Error::new(EFAULT))
     }
@@ -3,14 +3,18 @@ use core_collections::borrow::ToOwned;
 use io::{self, BufRead, BufReader, Read, Error, Result, Write, Seek, SeekFrom};
 use os::unix::io::{AsRawFd, FromRawFd, IntoRawFd, RawFd};
 use mem;
+use os::unix::fs::{MetadataExt, PermissionsExt};
 use path::{PathBuf, Path};
 use string::String;
 use sys_common::AsInner;
+use time::{Duration, SystemTime, UNIX_EPOCH};
 use vec::Vec;
 
-use system::syscall::{sys_open, sys_dup, sys_close, sys_fpath, sys_ftruncate, sys_read,
-              sys_write, sys_lseek, sys_fsync, sys_mkdir, sys_rmdir, sys_stat, sys_unlink};
-use system::syscall::{O_RDWR, O_RDONLY, O_WRONLY, O_APPEND, O_CREAT, O_TRUNC, MODE_DIR, MODE_FILE, SEEK_SET, SEEK_CUR, SEEK_END, Stat};
+use system::error::{ENOSYS, EXDEV};
+use system::syscall::{sys_open, sys_dup, sys_close, sys_fpath, sys_fstat, sys_ftruncate, sys_getdents, sys_read,
+              sys_write, sys_lseek, sys_fsync, sys_link, sys_mkdir, sys_rename, sys_rmdir,
+              sys_stat, sys_unlink};
+use system::syscall::{O_RDWR, O_RDONLY, O_WRONLY, O_APPEND, O_CREAT, O_TRUNC, MODE_DIR, MODE_FILE, MODE_PERM, SEEK_SET, SEEK_CUR, SEEK_END, Dirent, Stat, TimeSpec};
 
 /// A Unix-style file
 #[derive(Debug)]
@@ -54,6 +58,15 @@ impl File {
         }
     }
 
+    /// Get the metadata of the file
+    pub fn metadata(&self) -> Result<Metadata> {
+        let mut stat = Stat::default();
+        try!(sys_fstat(self.fd, &mut stat).map_err(|x| Error::from_sys(x)));
+        Ok(Metadata {
+            stat: stat
+        })
+    }
+
     /// Flush the file data and metadata
     pub fn sync_all(&mut self) -> Result<()> {
         sys_fsync(self.fd).and(Ok(())).map_err(|x| Error::from_sys(x))
@@ -218,10 +231,21 @@ impl OpenOptions {
     }
 }
 
+#[derive(Clone, Debug)]
 pub struct Metadata {
     stat: Stat
 }
 
+/// Convert a time from a stat. Schemes that do not keep the time leave it at zero, which is an
+/// error rather than the epoch
+fn system_time(time: TimeSpec) -> Result<SystemTime> {
+    if time.tv_sec == 0 && time.tv_nsec == 0 {
+        Err(Error::new(io::ErrorKind::Other, "Time is not available for this file"))
+    } else {
+        Ok(UNIX_EPOCH + Duration::new(time.tv_sec as u64, time.tv_nsec as u32))
+    }
+}
+
 impl Metadata {
     pub fn file_type(&self) -> FileType {
         FileType {
@@ -241,6 +265,121 @@ impl Metadata {
     pub fn len(&self) -> u64 {
         self.stat.st_size
     }
+
+    pub fn permissions(&self) -> Permissions {
+        Permissions {
+            mode: self.stat.st_mode & MODE_PERM
+        }
+    }
+
+    /// The time the file was last accessed
+    pub fn accessed(&self) -> Result<SystemTime> {
+        system_time(self.stat.st_atime)
+    }
+
+    /// The time the file was last modified
+    pub fn modified(&self) -> Result<SystemTime> {
+        system_time(self.stat.st_mtime)
+    }
+}
+
+impl MetadataExt for Metadata {
+    fn dev(&self) -> u64 {
+        self.stat.st_dev
+    }
+
+    fn ino(&self) -> u64 {
+        self.stat.st_ino
+    }
+
+    fn mode(&self) -> u32 {
+        self.stat.st_mode as u32
+    }
+
+    fn nlink(&self) -> u64 {
+        self.stat.st_nlink as u64
+    }
+
+    fn uid(&self) -> u32 {
+        self.stat.st_uid
+    }
+
+    fn gid(&self) -> u32 {
+        self.stat.st_gid
+    }
+
+    fn size(&self) -> u64 {
+        self.stat.st_size
+    }
+
+    fn atime(&self) -> i64 {
+        self.stat.st_atime.tv_sec
+    }
+
+    fn atime_nsec(&self) -> i64 {
+        self.stat.st_atime.tv_nsec as i64
+    }
+
+    fn mtime(&self) -> i64 {
+        self.stat.st_mtime.tv_sec
+    }
+
+    fn mtime_nsec(&self) -> i64 {
+        self.stat.st_mtime.tv_nsec as i64
+    }
+
+    fn ctime(&self) -> i64 {
+        self.stat.st_ctime.tv_sec
+    }
+
+    fn ctime_nsec(&self) -> i64 {
+        self.stat.st_ctime.tv_nsec as i64
+    }
+
+    fn blksize(&self) -> u64 {
+        self.stat.st_blksize as u64
+    }
+
+    fn blocks(&self) -> u64 {
+        self.stat.st_blocks
+    }
+}
+
+/// The permissions of a file
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct Permissions {
+    mode: u16
+}
+
+impl Permissions {
+    /// Check if no one can write to the file
+    pub fn readonly(&self) -> bool {
+        self.mode & 0o222 == 0
+    }
+
+    pub fn set_readonly(&mut self, readonly: bool) {
+        if readonly {
+            self.mode &= !0o222;
+        } else {
+            self.mode |= 0o222;
+        }
+    }
+}
+
+impl PermissionsExt for Permissions {
+    fn mode(&self) -> u32 {
+        self.mode as u32
+    }
+
+    fn set_mode(&mut self, mode: u32) {
+        self.mode = mode as u16 & MODE_PERM;
+    }
+
+    fn from_mode(mode: u32) -> Permissions {
+        Permissions {
+            mode: mode as u16 & MODE_PERM
+        }
+    }
 }
 
 pub struct DirEntry {
@@ -268,11 +407,45 @@ impl DirEntry {
 
 pub struct ReadDir {
     file: BufReader<File>,
+    /// Pending entries, in reverse order
+    dirents: Vec<Dirent>,
+    /// Whether the directory supports `sys_getdents`, unknown until the first read
+    getdents: Option<bool>,
 }
 
 impl Iterator for ReadDir {
     type Item = Result<DirEntry>;
     fn next(&mut self) -> Option<Result<DirEntry>> {
+        if self.getdents != Some(false) {
+            if self.dirents.is_empty() {
+                let mut dirents = [Dirent::default(); 16];
+                match sys_getdents(self.file.get_ref().as_raw_fd(), &mut dirents) {
+                    Ok(count) => {
+                        self.getdents = Some(true);
+                        for dirent in dirents[..count].iter().rev() {
+                            self.dirents.push(*dirent);
+                        }
+                    },
+                    Err(err) => if self.getdents.is_none() {
+                        // Schemes without getdents still list entries as lines
+                        self.getdents = Some(false);
+                    } else {
+                        return Some(Err(Error::from_sys(err)));
+                    }
+                }
+            }
+
+            if self.getdents == Some(true) {
+                return self.dirents.pop().map(|dirent| {
+                    Ok(DirEntry {
+                        path: dirent.name().to_owned(),
+                        dir: dirent.d_mode & MODE_DIR == MODE_DIR,
+                        file: dirent.d_mode & MODE_FILE == MODE_FILE,
+                    })
+                });
+            }
+        }
+
         let mut path = String::new();
         match self.file.read_line(&mut path) {
             Ok(0) => None,
@@ -309,10 +482,7 @@ pub fn canonicalize<P: AsRef<Path>>(path: P) -> Result<PathBuf> {
 }
 
 pub fn metadata<P: AsRef<Path>>(path: P) -> Result<Metadata> {
-    let mut stat = Stat {
-        st_mode: 0,
-        st_size: 0
-    };
+    let mut stat = Stat::default();
     let path_str = path.as_ref().as_os_str().as_inner();
     let mut path_c = path_str.to_owned();
     path_c.push_str("\0");
@@ -341,13 +511,40 @@ pub fn copy<P: AsRef<Path>, Q: AsRef<Path>>(from: P, to: Q) -> Result<u64> {
     io::copy(&mut infile, &mut outfile)
 }
 
+/// Create a new hard link to an existing file
+pub fn hard_link<P: AsRef<Path>, Q: AsRef<Path>>(src: P, dst: Q) -> Result<()> {
+    let mut src_c = src.as_ref().as_os_str().as_inner().to_owned();
+    src_c.push_str("\0");
+    let mut dst_c = dst.as_ref().as_os_str().as_inner().to_owned();
+    dst_c.push_str("\0");
+    unsafe {
+        sys_link(src_c.as_ptr(), dst_c.as_ptr()).and(Ok(()))
+    }.map_err(|x| Error::from_sys(x))
+}
+
+/// Rename a file, atomically replacing the destination if it exists. If the scheme can not
+/// rename, or the paths are on different schemes, the file is copied and the source removed
 pub fn rename<P: AsRef<Path>, Q: AsRef<Path>>(from: P, to: Q) -> Result<()> {
-    try!(copy(Path::new(from.as_ref()), to));
-    remove_file(from)
+    let mut from_c = from.as_ref().as_os_str().as_inner().to_owned();
+    from_c.push_str("\0");
+    let mut to_c = to.as_ref().as_os_str().as_inner().to_owned();
+    to_c.push_str("\0");
+    match unsafe { sys_rename(from_c.as_ptr(), to_c.as_ptr()) } {
+        Ok(_) => Ok(()),
+        Err(ref err) if err.errno == ENOSYS || err.errno == EXDEV => {
+            try!(copy(Path::new(from.as_ref()), to));
+            remove_file(from)
+        },
+        Err(err) => Err(Error::from_sys(err)),
+    }
 }
 
 pub fn read_dir<P: AsRef<Path>>(path: P) -> Result<ReadDir> {
-    File::open(path).map(|file| ReadDir { file: BufReader::new(file) })
+    File::open(path).map(|file| ReadDir {
+        file: BufReader::new(file),
+        dirents: Vec::new(),
+        getdents: None,
+    })
 }
 
 pub fn remove_dir<P: AsRef<Path>>(path: P) -> Result<()> {
@@ -9,7 +9,7 @@
 // except according to those terms.
 
 use fmt;
-use io::{self/*, Error, ErrorKind*/};
+use io::{self, Error, ErrorKind};
 use net::{ToSocketAddrs, SocketAddr/*, Ipv4Addr, Ipv6Addr*/};
 use sys_common::net as net_imp;
 use sys_common::{AsInner, FromInner, IntoInner};
@@ -52,7 +52,7 @@ impl UdpSocket {
         pub fn bind<A: ToSocketAddrs>(addr: A) -> io::Result<UdpSocket> {
         super::each_addr(addr, net_imp::UdpSocket::bind).map(UdpSocket)
     }
-/*
+
     /// Receives data from the socket. On success, returns the number of bytes
     /// read and the address from whence the data came.
         pub fn recv_from(&self, buf: &mut [u8]) -> io::Result<(usize, SocketAddr)> {
@@ -72,7 +72,7 @@ impl UdpSocket {
                                    "no addresses to send data to")),
         }
     }
-*/
+
     /// Returns the socket address that this socket was created from.
         pub fn local_addr(&self) -> io::Result<SocketAddr> {
         self.0.socket_addr()
@@ -293,14 +293,12 @@ impl UdpSocket {
         self.0.take_error()
     }
 
-/*
     /// Connects this UDP socket to a remote address, allowing the `send` and
     /// `recv` syscalls to be used to send data and also applies filters to only
     /// receive data from the specified address.
     pub fn connect<A: ToSocketAddrs>(&self, addr: A) -> io::Result<()> {
         super::each_addr(addr, |addr| self.0.connect(addr))
     }
-*/
 
     /// Sends data on the socket to the remote address to which it is connected.
     ///
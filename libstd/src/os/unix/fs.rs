@@ -0,0 +1,31 @@
+//! Unix specific extensions to the file system types
+
+/// The fields of a stat that are not part of `Metadata`
+pub trait MetadataExt {
+    /// The device of the scheme holding the file
+    fn dev(&self) -> u64;
+    fn ino(&self) -> u64;
+    /// The file type and permissions
+    fn mode(&self) -> u32;
+    fn nlink(&self) -> u64;
+    fn uid(&self) -> u32;
+    fn gid(&self) -> u32;
+    fn size(&self) -> u64;
+    fn atime(&self) -> i64;
+    fn atime_nsec(&self) -> i64;
+    fn mtime(&self) -> i64;
+    fn mtime_nsec(&self) -> i64;
+    fn ctime(&self) -> i64;
+    fn ctime_nsec(&self) -> i64;
+    /// The preferred size of reads and writes
+    fn blksize(&self) -> u64;
+    /// The number of 512 byte blocks allocated
+    fn blocks(&self) -> u64;
+}
+
+/// Access to the Unix permission bits
+pub trait PermissionsExt {
+    fn mode(&self) -> u32;
+    fn set_mode(&mut self, mode: u32);
+    fn from_mode(mode: u32) -> Self;
+}
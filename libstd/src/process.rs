@@ -9,7 +9,7 @@ use core_collections::borrow::ToOwned;
 use vec::Vec;
 
 use io::Error;
-use system::syscall::{sys_clone, sys_close, sys_dup, sys_execve, sys_exit, sys_pipe2, sys_read, sys_write, sys_waitpid, CLONE_VM, CLONE_VFORK, CLONE_SUPERVISE};
+use system::syscall::{sys_clone, sys_setns, sys_close, sys_dup, sys_execve, sys_exit, sys_pipe2, sys_read, sys_setgid, sys_setuid, sys_write, sys_waitpid, CLONE_VM, CLONE_VFORK, CLONE_SUPERVISE, WSIGMASK};
 use system::error::Error as SysError;
 
 pub struct ExitStatus {
@@ -22,7 +22,21 @@ impl ExitStatus {
     }
 
     pub fn code(&self) -> Option<i32> {
-        Some(self.status as i32)
+        if self.status & WSIGMASK == 0 {
+            Some(((self.status >> 8) & 0xFF) as i32)
+        } else {
+            None
+        }
+    }
+
+    /// The signal that terminated the process, if it was terminated by one
+    pub fn signal(&self) -> Option<i32> {
+        let sig = self.status & WSIGMASK;
+        if sig != 0 {
+            Some(sig as i32)
+        } else {
+            None
+        }
     }
 }
 
@@ -105,6 +119,9 @@ pub struct Command {
     stdin: Stdio,
     stdout: Stdio,
     stderr: Stdio,
+    uid: Option<u32>,
+    gid: Option<u32>,
+    namespace: Option<Vec<String>>,
 }
 
 impl fmt::Debug for Command {
@@ -125,6 +142,9 @@ impl Command {
             stdin: Stdio::inherit(),
             stdout: Stdio::inherit(),
             stderr: Stdio::inherit(),
+            uid: None,
+            gid: None,
+            namespace: None,
         }
     }
 
@@ -148,6 +168,25 @@ impl Command {
         self
     }
 
+    /// Run the command as the user `id`. Only root can change the user of a process
+    pub fn uid(&mut self, id: u32) -> &mut Command {
+        self.uid = Some(id);
+        self
+    }
+
+    /// Run the command in the group `id`. Only root can change the group of a process
+    pub fn gid(&mut self, id: u32) -> &mut Command {
+        self.gid = Some(id);
+        self
+    }
+
+    /// Run the command with only the schemes in `names`, which must be usable by this process and
+    /// not empty
+    pub fn namespace(&mut self, names: &[&str]) -> &mut Command {
+        self.namespace = Some(names.iter().map(|name| (*name).to_owned()).collect());
+        self
+    }
+
     pub fn spawn(&mut self) -> Result<Child> {
         self.exec(CLONE_VM | CLONE_VFORK)
     }
@@ -177,10 +216,24 @@ impl Command {
         }
         args_c.push(0 as *const u8);
 
+        let mut namespace_vec: Vec<String> = Vec::new();
+        let mut namespace_c: Vec<*const u8> = Vec::new();
+        if let Some(ref namespace) = self.namespace {
+            for name in namespace.iter() {
+                namespace_vec.push(name.to_owned() + "\0");
+            }
+            for name in namespace_vec.iter() {
+                namespace_c.push(name.as_ptr());
+            }
+            namespace_c.push(0 as *const u8);
+        }
+
         let child_res = res.deref_mut() as *mut usize;
         let child_stderr = self.stderr.inner;
         let child_stdout = self.stdout.inner;
         let child_stdin = self.stdin.inner;
+        let child_uid = self.uid;
+        let child_gid = self.gid;
         let child_code = Box::new(move || -> Result<usize> {
             match child_stderr {
                 StdioType::Piped(read, write) => {
@@ -236,6 +289,19 @@ impl Command {
                 _ => ()
             }
 
+            if !namespace_c.is_empty() {
+                try!(unsafe { sys_setns(namespace_c.as_ptr()) }.map_err(|x| Error::from_sys(x)));
+            }
+
+            // The group is changed first, as the user may no longer be allowed to change it
+            if let Some(gid) = child_gid {
+                try!(sys_setgid(gid as usize).map_err(|x| Error::from_sys(x)));
+            }
+
+            if let Some(uid) = child_uid {
+                try!(sys_setuid(uid as usize).map_err(|x| Error::from_sys(x)));
+            }
+
             unsafe { sys_execve(path_c.as_ptr(), args_c.as_ptr()) }.map_err(|x| Error::from_sys(x))
         });
 
@@ -1,22 +1,51 @@
-use cell::UnsafeCell;
+use cell::{Cell, UnsafeCell};
 use fs::File;
 use io::{Error, ErrorKind, Result, Read, Write};
 use iter::Iterator;
-use net::{SocketAddr, Shutdown};
+use net::{Ipv4Addr, Ipv6Addr, Shutdown, SocketAddr, SocketAddrV4, SocketAddrV6};
+use os::unix::io::AsRawFd;
+use string::String;
 use time::Duration;
-use vec::Vec;
+use vec::{self, Vec};
 
-pub struct LookupHost;
+use system::syscall::{sys_fcntl, sys_poll, PollFd, F_GETFL, F_SETFL, O_NONBLOCK, POLLIN};
+
+pub struct LookupHost(vec::IntoIter<SocketAddr>);
 
 impl Iterator for LookupHost {
     type Item = Result<SocketAddr>;
     fn next(&mut self) -> Option<Self::Item> {
-        None
+        self.0.next().map(Ok)
     }
 }
 
-pub fn lookup_host(_host: &str) -> Result<LookupHost> {
-    Err(Error::new(ErrorKind::Other, "Not implemented"))
+/// Resolve a host with the `dns:` scheme, which lists its addresses one per line
+pub fn lookup_host(host: &str) -> Result<LookupHost> {
+    let mut file = try!(File::open(format!("dns:{}", host)));
+    let mut string = String::new();
+    try!(file.read_to_string(&mut string));
+
+    let mut addrs = Vec::new();
+    for line in string.lines() {
+        if let Ok(ip) = line.trim().parse::<Ipv4Addr>() {
+            addrs.push(SocketAddr::V4(SocketAddrV4::new(ip, 0)));
+        } else if let Ok(ip) = line.trim().parse::<Ipv6Addr>() {
+            addrs.push(SocketAddr::V6(SocketAddrV6::new(ip, 0, 0, 0)));
+        }
+    }
+
+    Ok(LookupHost(addrs.into_iter()))
+}
+
+/// Parse the peer address in the path of a connected socket, such as `tcp:10.85.85.1:80/32768` or
+/// `tcp:[fe80::1]:80/32768`
+fn peer_addr(file: &File) -> Result<SocketAddr> {
+    let path = try!(file.path());
+    path.to_str()
+        .and_then(|path| path.splitn(2, ':').nth(1))
+        .and_then(|reference| reference.split('/').next())
+        .and_then(|addr| addr.parse::<SocketAddr>().ok())
+        .ok_or(Error::new(ErrorKind::InvalidData, "Invalid socket path"))
 }
 
 #[derive(Debug)]
@@ -25,12 +54,11 @@ pub struct TcpStream(UnsafeCell<File>);
 impl TcpStream {
     pub fn connect(addr: &SocketAddr) -> Result<TcpStream> {
         let path = format!("tcp:{}", addr);
-        println!("Open {}", path);
         Ok(TcpStream(UnsafeCell::new(try!(File::open(path)))))
     }
 
     pub fn duplicate(&self) -> Result<TcpStream> {
-        Err(Error::new(ErrorKind::Other, "Not implemented"))
+        unsafe { Ok(TcpStream(UnsafeCell::new(try!((*self.0.get()).dup())))) }
     }
 
     pub fn read(&self, buf: &mut [u8]) -> Result<usize> {
@@ -50,7 +78,7 @@ impl TcpStream {
     }
 
     pub fn peer_addr(&self) -> Result<SocketAddr> {
-        Err(Error::new(ErrorKind::Other, "Not implemented"))
+        unsafe { peer_addr(&*self.0.get()) }
     }
 
     pub fn socket_addr(&self) -> Result<SocketAddr> {
@@ -114,16 +142,20 @@ impl TcpStream {
 pub struct TcpListener(File);
 
 impl TcpListener {
-    pub fn bind(_addr: &SocketAddr) -> Result<TcpListener> {
-        Err(Error::new(ErrorKind::Other, "Not implemented"))
+    pub fn bind(addr: &SocketAddr) -> Result<TcpListener> {
+        let path = format!("tcp:/{}", addr.port());
+        Ok(TcpListener(try!(File::open(path))))
     }
 
     pub fn accept(&self) -> Result<(TcpStream, SocketAddr)> {
-        Err(Error::new(ErrorKind::Other, "Not implemented"))
+        let path = try!(self.0.path());
+        let file = try!(File::open(format!("{}/accept", path.display())));
+        let addr = try!(peer_addr(&file));
+        Ok((TcpStream(UnsafeCell::new(file)), addr))
     }
 
     pub fn duplicate(&self) -> Result<TcpListener> {
-        Err(Error::new(ErrorKind::Other, "Not implemented"))
+        Ok(TcpListener(try!(self.0.dup())))
     }
 
     pub fn take_error(&self) -> Result<Option<Error>> {
@@ -131,7 +163,12 @@ impl TcpListener {
     }
 
     pub fn socket_addr(&self) -> Result<SocketAddr> {
-        Err(Error::new(ErrorKind::Other, "Not implemented"))
+        let path = try!(self.0.path());
+        path.to_str()
+            .and_then(|path| path.rsplit('/').next())
+            .and_then(|port| port.parse::<u16>().ok())
+            .map(|port| SocketAddr::V4(SocketAddrV4::new(Ipv4Addr::new(0, 0, 0, 0), port)))
+            .ok_or(Error::new(ErrorKind::InvalidData, "Invalid socket path"))
     }
 
     pub fn nonblocking(&self) -> Result<bool> {
@@ -159,26 +196,142 @@ impl TcpListener {
     }
 }
 
+/// The length of the address that precedes each datagram on a bound socket
+const UDP_ADDR_LEN: usize = 6;
+/// The length of the address that precedes each datagram on a bound IPv6 socket
+const UDP_ADDR6_LEN: usize = 18;
+/// The TTL of datagrams sent over IPv4, which is set by the IP scheme
+const UDP_TTL: u32 = 128;
+/// The hop limit of datagrams sent over IPv6, which is set by the IP6 scheme
+const UDP_HOP_LIMIT: u32 = 64;
+
 #[derive(Debug)]
-pub struct UdpSocket(UnsafeCell<File>);
+pub struct UdpSocket {
+    file: UnsafeCell<File>,
+    /// Whether the socket was bound to an IPv6 address
+    v6: bool,
+    /// The peer set by `connect`
+    peer: Cell<Option<SocketAddr>>,
+    /// Whether sending to the broadcast address is allowed
+    broadcast: Cell<bool>,
+    /// How long `recv_from` waits for a datagram
+    read_timeout: Cell<Option<Duration>>,
+    /// Datagrams are sent without blocking, so this is only kept to be returned
+    write_timeout: Cell<Option<Duration>>,
+}
 
 impl UdpSocket {
     pub fn bind(addr: &SocketAddr) -> Result<UdpSocket> {
-        let path = format!("udp:{}", addr);
-        println!("Open {}", path);
-        Ok(UdpSocket(UnsafeCell::new(try!(File::open(path)))))
+        let (path, v6) = match *addr {
+            SocketAddr::V4(ref addr) => (format!("udp:/{}", addr.port()), false),
+            SocketAddr::V6(ref addr) => (format!("udp:/{}/6", addr.port()), true),
+        };
+        Ok(UdpSocket {
+            file: UnsafeCell::new(try!(File::open(path))),
+            v6: v6,
+            peer: Cell::new(None),
+            broadcast: Cell::new(false),
+            read_timeout: Cell::new(None),
+            write_timeout: Cell::new(None),
+        })
+    }
+
+    pub fn connect(&self, addr: &SocketAddr) -> Result<()> {
+        self.peer.set(Some(*addr));
+        Ok(())
     }
 
     pub fn duplicate(&self) -> Result<UdpSocket> {
-        Err(Error::new(ErrorKind::Other, "Not implemented"))
+        Ok(UdpSocket {
+            file: UnsafeCell::new(try!(unsafe { (*self.file.get()).dup() })),
+            v6: self.v6,
+            peer: Cell::new(self.peer.get()),
+            broadcast: Cell::new(self.broadcast.get()),
+            read_timeout: Cell::new(self.read_timeout.get()),
+            write_timeout: Cell::new(self.write_timeout.get()),
+        })
+    }
+
+    pub fn recv_from(&self, buf: &mut [u8]) -> Result<(usize, SocketAddr)> {
+        let addr_len = if self.v6 { UDP_ADDR6_LEN } else { UDP_ADDR_LEN };
+
+        if let Some(timeout) = self.read_timeout.get() {
+            let fd = unsafe { (*self.file.get()).as_raw_fd() };
+            let mut fds = [PollFd { fd: fd, events: POLLIN, revents: 0 }];
+            let millis = timeout.as_secs() * 1000 + (timeout.subsec_nanos() as u64 + 999999) / 1000000;
+            if try!(sys_poll(&mut fds, millis as isize).map_err(Error::from_sys)) == 0 {
+                return Err(Error::new(ErrorKind::WouldBlock, "Read timed out"));
+            }
+        }
+
+        let mut bytes = vec![0; addr_len + buf.len()];
+        let count = try!(unsafe { (*self.file.get()).read(&mut bytes) });
+        if count < addr_len {
+            return Err(Error::new(ErrorKind::InvalidData, "Invalid datagram"));
+        }
+
+        let port = (bytes[addr_len - 2] as u16) << 8 | bytes[addr_len - 1] as u16;
+        let addr = if self.v6 {
+            let mut segments = [0; 8];
+            for i in 0..8 {
+                segments[i] = (bytes[i * 2] as u16) << 8 | bytes[i * 2 + 1] as u16;
+            }
+            let ip = Ipv6Addr::new(segments[0], segments[1], segments[2], segments[3],
+                                   segments[4], segments[5], segments[6], segments[7]);
+            SocketAddr::V6(SocketAddrV6::new(ip, port, 0, 0))
+        } else {
+            SocketAddr::V4(SocketAddrV4::new(Ipv4Addr::new(bytes[0], bytes[1], bytes[2], bytes[3]), port))
+        };
+
+        let mut i = 0;
+        for (b, d) in buf.iter_mut().zip(bytes[addr_len .. count].iter()) {
+            *b = *d;
+            i += 1;
+        }
+
+        Ok((i, addr))
+    }
+
+    pub fn send_to(&self, buf: &[u8], addr: &SocketAddr) -> Result<usize> {
+        let mut bytes = Vec::with_capacity(UDP_ADDR6_LEN + buf.len());
+        match *addr {
+            SocketAddr::V4(ref addr) if !self.v6 => {
+                if addr.ip().is_broadcast() && !self.broadcast.get() {
+                    return Err(Error::new(ErrorKind::PermissionDenied, "Broadcast is not enabled"));
+                }
+                bytes.extend_from_slice(&addr.ip().octets());
+            },
+            SocketAddr::V6(ref addr) if self.v6 => for segment in addr.ip().segments().iter() {
+                bytes.push((*segment >> 8) as u8);
+                bytes.push(*segment as u8);
+            },
+            _ => return Err(Error::new(ErrorKind::InvalidInput, "Address family does not match the socket")),
+        }
+        bytes.push((addr.port() >> 8) as u8);
+        bytes.push(addr.port() as u8);
+        bytes.extend_from_slice(buf);
+
+        try!(unsafe { (*self.file.get()).write(&bytes) });
+        Ok(buf.len())
     }
 
     pub fn recv(&self, buf: &mut [u8]) -> Result<usize> {
-        unsafe { (*self.0.get()).read(buf) }
+        match self.peer.get() {
+            Some(peer) => loop {
+                let (count, addr) = try!(self.recv_from(buf));
+                if addr == peer {
+                    return Ok(count);
+                }
+            },
+            None => Err(Error::new(ErrorKind::NotConnected, "UDP socket is not connected")),
+        }
     }
 
     pub fn send(&self, buf: &[u8]) -> Result<usize> {
-        unsafe { (*self.0.get()).write(buf) }
+        match self.peer.get() {
+            Some(peer) => self.send_to(buf, &peer),
+            None => Err(Error::new(ErrorKind::NotConnected, "UDP socket is not connected")),
+        }
     }
 
     pub fn take_error(&self) -> Result<Option<Error>> {
@@ -186,15 +339,27 @@ impl UdpSocket {
     }
 
     pub fn socket_addr(&self) -> Result<SocketAddr> {
-        Err(Error::new(ErrorKind::Other, "Not implemented"))
+        let path = try!(unsafe { (*self.file.get()).path() });
+        let v6 = self.v6;
+        path.to_str()
+            .and_then(|path| path.split('/').nth(1))
+            .and_then(|port| port.parse::<u16>().ok())
+            .map(|port| if v6 {
+                SocketAddr::V6(SocketAddrV6::new(Ipv6Addr::new(0, 0, 0, 0, 0, 0, 0, 0), port, 0, 0))
+            } else {
+                SocketAddr::V4(SocketAddrV4::new(Ipv4Addr::new(0, 0, 0, 0), port))
+            })
+            .ok_or(Error::new(ErrorKind::InvalidData, "Invalid socket path"))
     }
 
     pub fn broadcast(&self) -> Result<bool> {
-        Err(Error::new(ErrorKind::Other, "Not implemented"))
+        Ok(self.broadcast.get())
     }
 
     pub fn nonblocking(&self) -> Result<bool> {
-        Err(Error::new(ErrorKind::Other, "Not implemented"))
+        let fd = unsafe { (*self.file.get()).as_raw_fd() };
+        let flags = try!(sys_fcntl(fd, F_GETFL, 0).map_err(Error::from_sys));
+        Ok(flags & O_NONBLOCK == O_NONBLOCK)
     }
 
     pub fn only_v6(&self) -> Result<bool> {
@@ -202,38 +367,60 @@ impl UdpSocket {
     }
 
     pub fn ttl(&self) -> Result<u32> {
-        Err(Error::new(ErrorKind::Other, "Not implemented"))
+        Ok(if self.v6 { UDP_HOP_LIMIT } else { UDP_TTL })
     }
 
     pub fn read_timeout(&self) -> Result<Option<Duration>> {
-        Err(Error::new(ErrorKind::Other, "Not implemented"))
+        Ok(self.read_timeout.get())
     }
 
     pub fn write_timeout(&self) -> Result<Option<Duration>> {
-        Err(Error::new(ErrorKind::Other, "Not implemented"))
+        Ok(self.write_timeout.get())
     }
 
-    pub fn set_broadcast(&self, _broadcast: bool) -> Result<()> {
-        Err(Error::new(ErrorKind::Other, "Not implemented"))
+    pub fn set_broadcast(&self, broadcast: bool) -> Result<()> {
+        self.broadcast.set(broadcast);
+        Ok(())
     }
 
-    pub fn set_nonblocking(&self, _nonblocking: bool) -> Result<()> {
-        Err(Error::new(ErrorKind::Other, "Not implemented"))
+    pub fn set_nonblocking(&self, nonblocking: bool) -> Result<()> {
+        let fd = unsafe { (*self.file.get()).as_raw_fd() };
+        let flags = try!(sys_fcntl(fd, F_GETFL, 0).map_err(Error::from_sys));
+        let flags = if nonblocking {
+            flags | O_NONBLOCK
+        } else {
+            flags & !O_NONBLOCK
+        };
+        try!(sys_fcntl(fd, F_SETFL, flags).map_err(Error::from_sys));
+        Ok(())
     }
 
     pub fn set_only_v6(&self, _only_v6: bool) -> Result<()> {
         Err(Error::new(ErrorKind::Other, "Not implemented"))
     }
 
-    pub fn set_ttl(&self, _ttl: u32) -> Result<()> {
-        Err(Error::new(ErrorKind::Other, "Not implemented"))
+    /// The TTL is fixed by the IP scheme, so only that value can be set
+    pub fn set_ttl(&self, ttl: u32) -> Result<()> {
+        if ttl == try!(self.ttl()) {
+            Ok(())
+        } else {
+            Err(Error::new(ErrorKind::InvalidInput, "The TTL of datagrams cannot be changed"))
+        }
     }
 
-    pub fn set_read_timeout(&self, _dur: Option<Duration>) -> Result<()> {
-        Err(Error::new(ErrorKind::Other, "Not implemented"))
+    pub fn set_read_timeout(&self, dur: Option<Duration>) -> Result<()> {
+        if dur == Some(Duration::new(0, 0)) {
+            return Err(Error::new(ErrorKind::InvalidInput, "Cannot set a zero timeout"));
+        }
+        self.read_timeout.set(dur);
+        Ok(())
     }
 
-    pub fn set_write_timeout(&self, _dur: Option<Duration>) -> Result<()> {
-        Err(Error::new(ErrorKind::Other, "Not implemented"))
+    pub fn set_write_timeout(&self, dur: Option<Duration>) -> Result<()> {
+        if dur == Some(Duration::new(0, 0)) {
+            return Err(Error::new(ErrorKind::InvalidInput, "Cannot set a zero timeout"));
+        }
+        self.write_timeout.set(dur);
+        Ok(())
     }
 }
@@ -204,7 +204,7 @@ impl Error for SystemTimeError {
     }
 }
 
-#[derive(Copy, Clone)]
+#[derive(Copy, Clone, PartialEq, Eq, PartialOrd, Ord, Debug, Hash)]
 pub struct SystemTime(Duration);
 
 impl SystemTime {
@@ -227,6 +227,22 @@ impl SystemTime {
 
 }
 
+impl Add<Duration> for SystemTime {
+    type Output = SystemTime;
+
+    fn add(self, dur: Duration) -> SystemTime {
+        SystemTime(self.0 + dur)
+    }
+}
+
+impl Sub<Duration> for SystemTime {
+    type Output = SystemTime;
+
+    fn sub(self, dur: Duration) -> SystemTime {
+        SystemTime(self.0 - dur)
+    }
+}
+
 pub const UNIX_EPOCH: SystemTime = SystemTime(Duration {
     secs: 0,
     nanos: 0